@@ -0,0 +1,191 @@
+use crate::error::{Error, Result};
+use crate::detector::FaceBox;
+use image::{DynamicImage, Rgb, RgbImage};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Box outline and label background color
+const BOX_COLOR: Rgb<u8> = Rgb([0, 255, 0]);
+/// Color of the faces only one side of a comparison found
+const UNMATCHED_COLOR: Rgb<u8> = Rgb([255, 0, 0]);
+/// Label text color
+const TEXT_COLOR: Rgb<u8> = Rgb([0, 0, 0]);
+
+/// Size of the built-in label font (px)
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+
+/// Glyph of the 3x5 label font, one row per byte (low 3 bits, MSB left)
+fn glyph(c: char) -> Option<[u8; 5]> {
+    Some(match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        _ => return None,
+    })
+}
+
+/// Writes copies of source images with their detected faces drawn on them
+pub struct Annotator {
+    /// Directory the annotated images go to, mirroring the input layout
+    dir: PathBuf,
+    /// Input directory the source paths are relative to
+    input_dir: PathBuf,
+}
+
+impl Annotator {
+    /// Create the output directory for images read from `input_dir`
+    pub fn new(dir: &Path, input_dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).map_err(|source| Error::Io {
+            context: format!("Failed to create annotated image directory: {:?}", dir),
+            source,
+        })?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            input_dir: input_dir.to_path_buf(),
+        })
+    }
+
+    /// Draw the faces of an image and save it under the same relative path
+    pub fn save(&self, path: &Path, img: &DynamicImage, faces: &[FaceBox]) -> Result<()> {
+        let canvas = draw_faces(img.to_rgb8(), faces.iter().map(|face| (face, BOX_COLOR)));
+        self.write(path, canvas)
+    }
+
+    /// Save two copies of an image next to each other, the faces of one detector
+    /// drawn on the left and those of another on the right
+    ///
+    /// Faces are paired with whether the other side matched them, unmatched ones
+    /// are drawn in red.
+    pub fn save_side_by_side(
+        &self,
+        path: &Path,
+        img: &DynamicImage,
+        left: &[(&FaceBox, bool)],
+        right: &[(&FaceBox, bool)],
+    ) -> Result<()> {
+        let draw = |faces: &[(&FaceBox, bool)]| {
+            let colored = faces
+                .iter()
+                .map(|(face, matched)| (*face, if *matched { BOX_COLOR } else { UNMATCHED_COLOR }));
+            draw_faces(img.to_rgb8(), colored)
+        };
+        let (left, right) = (draw(left), draw(right));
+
+        let mut canvas = RgbImage::new(left.width() * 2, left.height());
+        image::imageops::replace(&mut canvas, &left, 0, 0);
+        image::imageops::replace(&mut canvas, &right, left.width() as i64, 0);
+        self.write(path, canvas)
+    }
+
+    /// Save an annotated image under the relative path of its source
+    fn write(&self, path: &Path, canvas: RgbImage) -> Result<()> {
+        // The input may be a single file, which leaves no relative path but its name
+        let relative = match path.strip_prefix(&self.input_dir) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative,
+            _ => Path::new(path.file_name().unwrap_or(path.as_os_str())),
+        };
+        let output_path = self.dir.join(relative);
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent).map_err(|source| Error::Io {
+                context: format!("Failed to create directory: {:?}", parent),
+                source,
+            })?;
+        }
+
+        canvas
+            .save(&output_path)
+            .map_err(|source| Error::Image {
+                context: format!("Failed to save annotated image to: {:?}", output_path),
+                source,
+            })
+    }
+}
+
+/// Draw a box and confidence label in the given color for every face
+fn draw_faces<'a>(mut canvas: RgbImage, faces: impl IntoIterator<Item = (&'a FaceBox, Rgb<u8>)>) -> RgbImage {
+    // Scale lines and text with the image so they stay visible on large photos
+    let thickness = (canvas.width().min(canvas.height()) / 400).max(1) as i32;
+    let text_scale = thickness as u32 + 1;
+
+    for (face, color) in faces {
+        for t in 0..thickness {
+            draw_rect_outline(&mut canvas, face.x - t, face.y - t, face.width + 2 * t, face.height + 2 * t, color);
+        }
+
+        // Label above the box, or inside it when the box touches the top edge
+        let label = format!("{:.2}", face.confidence);
+        let label_width = label.len() as u32 * (GLYPH_WIDTH + 1) * text_scale + text_scale;
+        let label_height = (GLYPH_HEIGHT + 2) * text_scale;
+        let label_x = face.x - thickness;
+        let label_y = if face.y - thickness - label_height as i32 >= 0 {
+            face.y - thickness - label_height as i32
+        } else {
+            face.y
+        };
+
+        fill_rect(&mut canvas, label_x, label_y, label_width as i32, label_height as i32, color);
+        draw_text(&mut canvas, &label, label_x + text_scale as i32, label_y + text_scale as i32, text_scale, TEXT_COLOR);
+    }
+
+    canvas
+}
+
+/// Set a pixel, ignoring coordinates outside the image
+fn put_pixel(canvas: &mut RgbImage, x: i32, y: i32, color: Rgb<u8>) {
+    if x >= 0 && y >= 0 && (x as u32) < canvas.width() && (y as u32) < canvas.height() {
+        canvas.put_pixel(x as u32, y as u32, color);
+    }
+}
+
+/// One pixel wide rectangle outline
+fn draw_rect_outline(canvas: &mut RgbImage, x: i32, y: i32, width: i32, height: i32, color: Rgb<u8>) {
+    for dx in 0..width {
+        put_pixel(canvas, x + dx, y, color);
+        put_pixel(canvas, x + dx, y + height - 1, color);
+    }
+    for dy in 0..height {
+        put_pixel(canvas, x, y + dy, color);
+        put_pixel(canvas, x + width - 1, y + dy, color);
+    }
+}
+
+/// Filled rectangle
+fn fill_rect(canvas: &mut RgbImage, x: i32, y: i32, width: i32, height: i32, color: Rgb<u8>) {
+    for dy in 0..height {
+        for dx in 0..width {
+            put_pixel(canvas, x + dx, y + dy, color);
+        }
+    }
+}
+
+/// Draw text with the built-in font, each font pixel `scale` pixels wide
+fn draw_text(canvas: &mut RgbImage, text: &str, x: i32, y: i32, scale: u32, color: Rgb<u8>) {
+    let scale = scale as i32;
+    let advance = (GLYPH_WIDTH as i32 + 1) * scale;
+
+    for (i, c) in text.chars().enumerate() {
+        let Some(rows) = glyph(c) else {
+            continue;
+        };
+        let glyph_x = x + i as i32 * advance;
+
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH as i32 {
+                if bits & (1 << (GLYPH_WIDTH as i32 - 1 - col)) != 0 {
+                    fill_rect(canvas, glyph_x + col * scale, y + row as i32 * scale, scale, scale, color);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{init_detector, Args};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use face_cropper::{load_image, FaceBox};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageError, ImageFormat};
+use log::{error, info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Arguments of the `anonymize` subcommand
+#[derive(clap::Args, Debug)]
+pub struct AnonymizeArgs {
+    /// Image file, or directory to scan for images
+    pub input: PathBuf,
+
+    /// Output directory for the redacted images, mirroring the input layout (images whose
+    /// format can't be written, and animations, get a PNG copy with `.png` added to the name)
+    #[clap(short, long)]
+    pub output_dir: PathBuf,
+
+    /// How faces are redacted
+    #[clap(long, value_enum, default_value = "blur")]
+    pub method: RedactMethod,
+}
+
+/// Ways of making a face unrecognizable
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RedactMethod {
+    /// Gaussian blur
+    Blur,
+    /// Blocks of the average color
+    Pixelate,
+}
+
+/// Number of blocks across the shorter side of a pixelated face
+const PIXELATE_BLOCKS: u32 = 8;
+
+/// Detect faces and write a copy of every image with the faces blurred or pixelated
+pub fn anonymize(args: &Args, anonymize_args: &AnonymizeArgs) -> Result<()> {
+    info!("Initializing face detector: {}", args.detector);
+    let mut detector = init_detector(args)?;
+    let load = args.load_options();
+
+    let image_paths = args.find_images(&anonymize_args.input)?;
+    if image_paths.is_empty() {
+        warn!("No images found at {:?}", anonymize_args.input);
+        return Ok(());
+    }
+    info!("Found {} images", image_paths.len());
+
+    fs::create_dir_all(&anonymize_args.output_dir)
+        .context("Failed to create output directory")?;
+
+    let start_time = Instant::now();
+    let mut face_count = 0;
+    let mut unredacted = Vec::new();
+    for path in &image_paths {
+        let result = load_image(path, &load).map_err(anyhow::Error::from).and_then(|mut img| {
+            let faces = detector.detect_faces(&img, args.threshold)?;
+            for face in &faces {
+                redact(&mut img, face, anonymize_args.method);
+            }
+
+            let output_path = output_path(path, &anonymize_args.input, &anonymize_args.output_dir);
+            if let Some(parent) = output_path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+            }
+            save_redacted(&img, path, output_path)?;
+
+            Ok(faces.len())
+        });
+
+        match result {
+            Ok(count) => face_count += count,
+            Err(err) => {
+                error!("Failed to process {:?}: {}", path, err);
+                unredacted.push(path.display().to_string());
+            }
+        }
+    }
+
+    if !unredacted.is_empty() {
+        warn!("No redacted copy of {} images: {}", unredacted.len(), unredacted.join(", "));
+    }
+
+    info!(
+        "Finished anonymizing. Redacted {} faces in {} images in {} seconds",
+        face_count,
+        image_paths.len(),
+        start_time.elapsed().as_secs()
+    );
+
+    Ok(())
+}
+
+/// Where the redacted copy of an image goes, a single input file keeps only its name
+fn output_path(path: &Path, input: &Path, output_dir: &Path) -> PathBuf {
+    let relative = match path.strip_prefix(input) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative,
+        _ => Path::new(path.file_name().unwrap_or(path.as_os_str())),
+    };
+    output_dir.join(relative)
+}
+
+/// Write a redacted image in the format of its input, or as PNG (with `.png` added
+/// to its name) when that format can't be written or the input is an animation,
+/// returning where it went
+fn save_redacted(img: &DynamicImage, path: &Path, output_path: PathBuf) -> Result<PathBuf> {
+    let animated = is_animated(path);
+    let format = ImageFormat::from_path(path).ok().filter(|format| format.can_write() && !animated);
+    if let Some(format) = format {
+        match img.save_with_format(&output_path, format) {
+            Ok(()) => return Ok(output_path),
+            // e.g. WebP without its encoder, or a color type the format can't hold
+            Err(ImageError::Unsupported(_)) => {
+                let _ = fs::remove_file(&output_path);
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to save redacted image to: {:?}", output_path));
+            }
+        }
+    }
+
+    let mut name = output_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".png");
+    let png_path = output_path.with_file_name(name);
+    img.save_with_format(&png_path, ImageFormat::Png)
+        .with_context(|| format!("Failed to save redacted image to: {:?}", png_path))?;
+    if animated {
+        warn!("{:?} is animated, only its first frame was redacted, to {:?}", path, png_path);
+    } else {
+        warn!("{:?} can't be written in its own format, redacted to {:?}", path, png_path);
+    }
+
+    Ok(png_path)
+}
+
+/// Whether an image file is an animated GIF, WebP or PNG, of which only the first frame is loaded
+fn is_animated(path: &Path) -> bool {
+    use image::codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder};
+    use image::AnimationDecoder;
+    use std::io::Cursor;
+
+    let Ok(format @ (ImageFormat::Gif | ImageFormat::WebP | ImageFormat::Png)) = ImageFormat::from_path(path) else {
+        return false;
+    };
+    let Ok(data) = fs::read(path) else {
+        return false;
+    };
+    let data = Cursor::new(data);
+    match format {
+        ImageFormat::Gif => GifDecoder::new(data).is_ok_and(|decoder| decoder.into_frames().take(2).count() > 1),
+        ImageFormat::WebP => WebPDecoder::new(data).is_ok_and(|decoder| decoder.has_animation()),
+        _ => PngDecoder::new(data).is_ok_and(|decoder| decoder.is_apng()),
+    }
+}
+
+/// Blur or pixelate the area of a face in place
+fn redact(img: &mut DynamicImage, face: &FaceBox, method: RedactMethod) {
+    // Clamp the box to the image, faces on the border are partly outside it
+    let visible = face.clamp_to(img.width(), img.height());
+    if visible.area() == 0 {
+        return;
+    }
+
+    let (x1, y1) = (visible.x as u32, visible.y as u32);
+    let (width, height) = (visible.width as u32, visible.height as u32);
+    let region = img.crop_imm(x1, y1, width, height);
+    let redacted = match method {
+        // Strong enough that features don't survive, relative to the face size
+        RedactMethod::Blur => region.blur(width.max(height) as f32 / 8.0),
+        RedactMethod::Pixelate => {
+            let block = (width.min(height) / PIXELATE_BLOCKS).max(1);
+            region
+                .resize_exact((width / block).max(1), (height / block).max(1), FilterType::Triangle)
+                .resize_exact(width, height, FilterType::Nearest)
+        }
+    };
+
+    imageops::replace(img, &redacted, x1 as i64, y1 as i64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::gif::GifEncoder;
+    use image::{Frame, RgbaImage};
+
+    #[test]
+    fn unwritable_and_animated_inputs_are_redacted_to_png() {
+        let dir = std::env::temp_dir().join(format!("face_cropper-anonymize-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let animation = dir.join("animated.gif");
+        let frames = (0..2).map(|i| Frame::new(RgbaImage::from_pixel(4, 4, image::Rgba([i * 200, 0, 0, 255]))));
+        GifEncoder::new(fs::File::create(&animation).unwrap()).encode_frames(frames).unwrap();
+        let img = DynamicImage::new_rgb8(4, 4);
+
+        let saved = [
+            save_redacted(&img, Path::new("photo.jpg"), dir.join("photo.jpg")).unwrap(),
+            save_redacted(&img, Path::new("photo.heic"), dir.join("photo.heic")).unwrap(),
+            save_redacted(&img, &animation, dir.join("out.gif")).unwrap(),
+        ];
+        let written: Vec<bool> = saved.iter().map(|path| path.exists()).collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(saved, [dir.join("photo.jpg"), dir.join("photo.heic.png"), dir.join("out.gif.png")]);
+        assert_eq!(written, [true; 3]);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::error::{Error, Result};
+use crate::input::is_image;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::mpsc;
+
+/// Tar members read ahead of the pipeline
+const TAR_READ_AHEAD: usize = 16;
+
+/// Archive formats accepted as input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    /// Gzip compressed tar (`.tar.gz` or `.tgz`)
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// Format of an archive path by its extension, None for other paths
+    pub fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// A tar member's name and contents
+type TarMember = (String, io::Result<Vec<u8>>);
+
+/// Reads the member images of a ZIP or TAR archive without extracting it
+///
+/// ZIP members are read by name. TAR has no index, so a background thread streams
+/// the members in archive order and they have to be read in that order too,
+/// skipping the ones that aren't needed. A reader makes one pass over a TAR.
+pub struct ArchiveReader {
+    path: PathBuf,
+    kind: ArchiveKind,
+    source: Mutex<Source>,
+}
+
+enum Source {
+    Zip(zip::ZipArchive<BufReader<File>>),
+    Tar(mpsc::Receiver<TarMember>),
+}
+
+impl ArchiveReader {
+    /// Open an archive, its format given by the extension
+    pub fn open(path: &Path) -> Result<Self> {
+        let kind = ArchiveKind::of(path)
+            .ok_or_else(|| Error::Config(format!("Not a .zip, .tar or .tar.gz archive: {:?}", path)))?;
+
+        let source = match kind {
+            ArchiveKind::Zip => Source::Zip(
+                zip::ZipArchive::new(BufReader::new(open_file(path)?))
+                    .map_err(|err| Error::Config(format!("Failed to read archive {:?}: {}", path, err)))?,
+            ),
+            ArchiveKind::Tar | ArchiveKind::TarGz => {
+                let reader = tar_reader(path, kind)?;
+                let (tx, rx) = mpsc::sync_channel(TAR_READ_AHEAD);
+                std::thread::spawn(move || stream_tar(reader, tx));
+                Source::Tar(rx)
+            }
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            kind,
+            source: Mutex::new(source),
+        })
+    }
+
+    /// Names of the member images, in archive order
+    pub fn images(&self) -> Result<Vec<String>> {
+        let names = match &mut *self.source.lock().expect("archive lock poisoned") {
+            Source::Zip(archive) => {
+                let mut names = Vec::new();
+                for index in 0..archive.len() {
+                    let file = archive
+                        .by_index_raw(index)
+                        .map_err(|err| Error::Config(format!("Failed to read archive {:?}: {}", self.path, err)))?;
+                    if file.is_file() {
+                        names.push(file.name().to_string());
+                    }
+                }
+                names
+            }
+            // Listing takes a pass of its own, the streaming one stays where it is
+            Source::Tar(_) => {
+                let mut archive = tar::Archive::new(tar_reader(&self.path, self.kind)?);
+                let mut names = Vec::new();
+                for entry in archive.entries().map_err(|source| self.io_error(source))? {
+                    let entry = entry.map_err(|source| self.io_error(source))?;
+                    if entry.header().entry_type().is_file() {
+                        names.push(member_name(&entry.path().map_err(|source| self.io_error(source))?));
+                    }
+                }
+                names
+            }
+        };
+
+        Ok(names.into_iter().filter(|name| is_image(Path::new(name))).collect())
+    }
+
+    /// Contents of a member, TAR members in archive order
+    pub fn read(&self, member: &str) -> Result<Vec<u8>> {
+        let context = || format!("Failed to read {} from {:?}", member, self.path);
+        match &mut *self.source.lock().expect("archive lock poisoned") {
+            Source::Zip(archive) => {
+                let mut file = archive
+                    .by_name(member)
+                    .map_err(|err| Error::Config(format!("{}: {}", context(), err)))?;
+                let mut data = Vec::with_capacity(file.size() as usize);
+                file.read_to_end(&mut data)
+                    .map_err(|source| Error::Io { context: context(), source })?;
+                Ok(data)
+            }
+            Source::Tar(members) => {
+                for (name, data) in members.iter() {
+                    if name == member {
+                        return data.map_err(|source| Error::Io { context: context(), source });
+                    }
+                }
+                Err(Error::Config(format!("{}: not found after the members read before it", context())))
+            }
+        }
+    }
+
+    fn io_error(&self, source: io::Error) -> Error {
+        Error::Io {
+            context: format!("Failed to read archive {:?}", self.path),
+            source,
+        }
+    }
+}
+
+/// Writes files into a new ZIP or TAR archive, one after the other
+///
+/// ZIP members are stored uncompressed, the encoded crops don't shrink any
+/// further. The archive is only complete after [`ArchiveWriter::finish`].
+pub struct ArchiveWriter {
+    path: PathBuf,
+    sink: Mutex<Option<Sink>>,
+}
+
+enum Sink {
+    Zip(zip::ZipWriter<BufWriter<File>>),
+    Tar(tar::Builder<BufWriter<File>>),
+    TarGz(tar::Builder<GzEncoder<BufWriter<File>>>),
+}
+
+impl ArchiveWriter {
+    /// Create an archive, its format given by the extension
+    pub fn create(path: &Path) -> Result<Self> {
+        let kind = ArchiveKind::of(path)
+            .ok_or_else(|| Error::Config(format!("Not a .zip, .tar or .tar.gz archive: {:?}", path)))?;
+        let file = BufWriter::new(File::create(path).map_err(|source| Error::Io {
+            context: format!("Failed to create archive: {:?}", path),
+            source,
+        })?);
+
+        let sink = match kind {
+            ArchiveKind::Zip => Sink::Zip(zip::ZipWriter::new(file)),
+            ArchiveKind::Tar => Sink::Tar(tar::Builder::new(file)),
+            ArchiveKind::TarGz => Sink::TarGz(tar::Builder::new(GzEncoder::new(file, flate2::Compression::default()))),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            sink: Mutex::new(Some(sink)),
+        })
+    }
+
+    /// Add a file to the archive
+    pub fn append(&self, name: &str, data: &[u8]) -> Result<()> {
+        let mut sink = self.sink.lock().expect("archive lock poisoned");
+        let context = || format!("Failed to add {} to {:?}", name, self.path);
+        let result = match sink.as_mut() {
+            Some(Sink::Zip(zip)) => {
+                let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+                zip.start_file(name, options)
+                    .map_err(io::Error::other)
+                    .and_then(|_| zip.write_all(data))
+            }
+            Some(Sink::Tar(tar)) => tar.append_data(&mut tar_header(data.len()), name, data),
+            Some(Sink::TarGz(tar)) => tar.append_data(&mut tar_header(data.len()), name, data),
+            None => return Err(Error::Config(format!("{}: the archive is finished", context()))),
+        };
+
+        result.map_err(|source| Error::Io { context: context(), source })
+    }
+
+    /// Write the end of the archive, later appends fail
+    pub fn finish(&self) -> Result<()> {
+        let sink = self.sink.lock().expect("archive lock poisoned").take();
+        let result = match sink {
+            Some(Sink::Zip(mut zip)) => zip
+                .finish()
+                .map_err(io::Error::other)
+                .and_then(|mut file| file.flush()),
+            Some(Sink::Tar(tar)) => tar.into_inner().and_then(|mut file| file.flush()),
+            Some(Sink::TarGz(tar)) => tar
+                .into_inner()
+                .and_then(GzEncoder::finish)
+                .and_then(|mut file| file.flush()),
+            None => Ok(()),
+        };
+
+        result.map_err(|source| Error::Io {
+            context: format!("Failed to finish archive: {:?}", self.path),
+            source,
+        })
+    }
+}
+
+/// Header of a regular TAR member written now
+fn tar_header(size: usize) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size as u64);
+    header.set_mode(0o644);
+    header.set_mtime(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs()),
+    );
+    header
+}
+
+/// Decompressed TAR stream of an archive file
+fn tar_reader(path: &Path, kind: ArchiveKind) -> Result<Box<dyn Read + Send>> {
+    let file = BufReader::new(open_file(path)?);
+    Ok(match kind {
+        ArchiveKind::TarGz => Box::new(GzDecoder::new(file)),
+        _ => Box::new(file),
+    })
+}
+
+fn open_file(path: &Path) -> Result<File> {
+    File::open(path).map_err(|source| Error::Io {
+        context: format!("Failed to open archive: {:?}", path),
+        source,
+    })
+}
+
+/// Name of a TAR member without the `./` of archives made from a directory
+fn member_name(path: &Path) -> String {
+    let name = path.to_string_lossy();
+    name.strip_prefix("./").unwrap_or(&name).to_string()
+}
+
+/// Send the member images of a TAR stream until it ends or the reader is dropped
+fn stream_tar(reader: Box<dyn Read + Send>, tx: mpsc::SyncSender<TarMember>) {
+    let mut archive = tar::Archive::new(reader);
+    let Ok(entries) = archive.entries() else {
+        return;
+    };
+
+    for entry in entries {
+        let Ok(mut entry) = entry else {
+            return;
+        };
+        let Ok(name) = entry.path().map(|path| member_name(&path)) else {
+            continue;
+        };
+        if !entry.header().entry_type().is_file() || !is_image(Path::new(&name)) {
+            continue;
+        }
+
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        let data = entry.read_to_end(&mut data).map(|_| data);
+        if tx.send((name, data)).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "onnx")]
+use crate::detector::{load_session, DetectorError, Result};
+#[cfg(feature = "onnx")]
+use image::DynamicImage;
+#[cfg(feature = "onnx")]
+use ort::session::Session;
+#[cfg(feature = "onnx")]
+use std::path::Path;
+#[cfg(feature = "onnx")]
+use std::sync::Mutex;
+
+/// Estimated gender of a face
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Gender {
+    Female,
+    Male,
+}
+
+/// Facial expression, the classes of the FER+ model
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Expression {
+    Neutral,
+    Happy,
+    Surprised,
+    Sad,
+    Angry,
+    Disgusted,
+    Fearful,
+    Contemptuous,
+}
+
+impl Gender {
+    /// Name of the gender, as the manifest and --gender write it
+    pub fn name(self) -> &'static str {
+        match self {
+            Gender::Female => "female",
+            Gender::Male => "male",
+        }
+    }
+}
+
+impl std::str::FromStr for Gender {
+    type Err = String;
+
+    /// Parse `female` or `male`
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "female" => Ok(Gender::Female),
+            "male" => Ok(Gender::Male),
+            _ => Err(format!("Invalid gender: {} (expected female or male)", value)),
+        }
+    }
+}
+
+impl Expression {
+    /// Classes in the order of the model outputs
+    const ALL: [Expression; 8] = [
+        Expression::Neutral,
+        Expression::Happy,
+        Expression::Surprised,
+        Expression::Sad,
+        Expression::Angry,
+        Expression::Disgusted,
+        Expression::Fearful,
+        Expression::Contemptuous,
+    ];
+
+    /// Name of the expression, as the manifest and --filter-expression write it
+    pub fn name(self) -> &'static str {
+        match self {
+            Expression::Neutral => "neutral",
+            Expression::Happy => "happy",
+            Expression::Surprised => "surprised",
+            Expression::Sad => "sad",
+            Expression::Angry => "angry",
+            Expression::Disgusted => "disgusted",
+            Expression::Fearful => "fearful",
+            Expression::Contemptuous => "contemptuous",
+        }
+    }
+}
+
+impl std::str::FromStr for Expression {
+    type Err = String;
+
+    /// Parse the name of an expression, such as `happy`
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        Expression::ALL.into_iter().find(|expression| expression.name() == value).ok_or_else(|| {
+            format!(
+                "Invalid expression: {} (expected one of {})",
+                value,
+                Expression::ALL.map(Expression::name).join(", ")
+            )
+        })
+    }
+}
+
+/// What the NSFW filter classifies
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NsfwScope {
+    /// Each face crop, rejecting single crops
+    Crop,
+    /// The whole source image, rejecting all of its faces
+    Image,
+}
+
+impl std::str::FromStr for NsfwScope {
+    type Err = String;
+
+    /// Parse `crop` or `image`
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "crop" => Ok(NsfwScope::Crop),
+            "image" => Ok(NsfwScope::Image),
+            _ => Err(format!("Invalid NSFW scope: {} (expected crop or image)", value)),
+        }
+    }
+}
+
+/// Attributes estimated by the optional classification stages, recorded in the manifest
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FaceAttributes {
+    /// Estimated age (years)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub age: Option<u32>,
+    /// Age range the estimate falls in, e.g. "20-29"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub age_bucket: Option<String>,
+    /// Estimated gender
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gender: Option<Gender>,
+    /// Most likely facial expression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expression: Option<Expression>,
+    /// NSFW score of the crop (0-1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nsfw_score: Option<f32>,
+}
+
+/// Age range an age falls in: 0-2, 3-9, then decades up to 70+
+pub fn age_bucket(age: u32) -> &'static str {
+    match age {
+        0..=2 => "0-2",
+        3..=9 => "3-9",
+        10..=19 => "10-19",
+        20..=29 => "20-29",
+        30..=39 => "30-39",
+        40..=49 => "40-49",
+        50..=59 => "50-59",
+        60..=69 => "60-69",
+        _ => "70+",
+    }
+}
+
+/// Default age/gender model, relative to the working directory
+#[cfg(feature = "onnx")]
+pub const DEFAULT_AGE_GENDER_MODEL: &str = "model/genderage.onnx";
+
+/// Age and gender estimation with the insightface `genderage` model
+///
+/// Expects a 96x96 NCHW RGB input of raw pixel values and a single output of
+/// two gender scores (female, male) followed by the age divided by 100. The
+/// session is shared between worker threads behind a lock.
+#[cfg(feature = "onnx")]
+#[derive(Debug)]
+pub struct AgeGenderEstimator {
+    session: Mutex<Session>,
+}
+
+#[cfg(feature = "onnx")]
+impl AgeGenderEstimator {
+    /// Input size of the model (px)
+    const INPUT_SIZE: u32 = 96;
+
+    /// Load the model, or the default one when no path is given
+    pub fn new(model_path: Option<&Path>) -> Result<Self> {
+        let model_path = model_path.unwrap_or(Path::new(DEFAULT_AGE_GENDER_MODEL));
+        if !model_path.exists() {
+            return Err(DetectorError::ModelNotFound {
+                path: model_path.to_path_buf(),
+                hint: "Download genderage.onnx (insightface buffalo_l) and place it there, \
+                    or pass --age-gender-model <path>",
+            });
+        }
+
+        Ok(Self {
+            session: Mutex::new(load_session(model_path)?),
+        })
+    }
+
+    /// Estimated age (years) and gender of a face crop
+    pub fn estimate(&self, face: &DynamicImage) -> Result<(u32, Gender)> {
+        let outputs = run_classifier(&self.session, rgb_input(face, Self::INPUT_SIZE, 0.0, 1.0))?;
+        let [female, male, age, ..] = outputs[..] else {
+            return Err(DetectorError::Backend(format!(
+                "Unsupported age/gender model: expected 3 outputs, got {}",
+                outputs.len()
+            )));
+        };
+
+        let gender = if male > female { Gender::Male } else { Gender::Female };
+        Ok(((age * 100.0).round().max(0.0) as u32, gender))
+    }
+}
+
+/// Default expression model, relative to the working directory
+#[cfg(feature = "onnx")]
+pub const DEFAULT_EXPRESSION_MODEL: &str = "model/emotion-ferplus-8.onnx";
+
+/// Expression classification with the FER+ model of the ONNX model zoo
+///
+/// Expects a 64x64 grayscale input of raw pixel values and one score per
+/// [`Expression`], in the order of its variants.
+#[cfg(feature = "onnx")]
+#[derive(Debug)]
+pub struct ExpressionClassifier {
+    session: Mutex<Session>,
+}
+
+#[cfg(feature = "onnx")]
+impl ExpressionClassifier {
+    /// Input size of the model (px)
+    const INPUT_SIZE: u32 = 64;
+
+    /// Load the model, or the default one when no path is given
+    pub fn new(model_path: Option<&Path>) -> Result<Self> {
+        let model_path = model_path.unwrap_or(Path::new(DEFAULT_EXPRESSION_MODEL));
+        if !model_path.exists() {
+            return Err(DetectorError::ModelNotFound {
+                path: model_path.to_path_buf(),
+                hint: "Download emotion-ferplus-8.onnx (ONNX model zoo) and place it there, \
+                    or pass --expression-model <path>",
+            });
+        }
+
+        Ok(Self {
+            session: Mutex::new(load_session(model_path)?),
+        })
+    }
+
+    /// Most likely expression of a face crop
+    pub fn classify(&self, face: &DynamicImage) -> Result<Expression> {
+        let scores = run_classifier(&self.session, gray_input(face, Self::INPUT_SIZE))?;
+        if scores.len() != Expression::ALL.len() {
+            return Err(DetectorError::Backend(format!(
+                "Unsupported expression model: expected {} outputs, got {}",
+                Expression::ALL.len(),
+                scores.len()
+            )));
+        }
+
+        let best = (0..scores.len())
+            .max_by(|&a, &b| scores[a].total_cmp(&scores[b]))
+            .unwrap_or(0);
+        Ok(Expression::ALL[best])
+    }
+}
+
+/// Default NSFW model, relative to the working directory
+#[cfg(feature = "onnx")]
+pub const DEFAULT_NSFW_MODEL: &str = "model/nsfw_mobilenet2.onnx";
+
+/// NSFW scoring with the nsfw_model MobileNet (GantMan) exported to ONNX
+///
+/// Expects a 224x224 NHWC RGB input scaled to 0-1 and five class probabilities
+/// (drawings, hentai, neutral, porn, sexy). The score is the probability of the
+/// three explicit classes.
+#[cfg(feature = "onnx")]
+#[derive(Debug)]
+pub struct NsfwClassifier {
+    session: Mutex<Session>,
+}
+
+#[cfg(feature = "onnx")]
+impl NsfwClassifier {
+    /// Input size of the model (px)
+    const INPUT_SIZE: u32 = 224;
+
+    /// Load the model, or the default one when no path is given
+    pub fn new(model_path: Option<&Path>) -> Result<Self> {
+        let model_path = model_path.unwrap_or(Path::new(DEFAULT_NSFW_MODEL));
+        if !model_path.exists() {
+            return Err(DetectorError::ModelNotFound {
+                path: model_path.to_path_buf(),
+                hint: "Convert the nsfw_model MobileNet v2 (224x224) to ONNX and place it there, \
+                    or pass --nsfw-model <path>",
+            });
+        }
+
+        Ok(Self {
+            session: Mutex::new(load_session(model_path)?),
+        })
+    }
+
+    /// Probability (0-1) that an image is explicit
+    pub fn score(&self, image: &DynamicImage) -> Result<f32> {
+        let scores = run_classifier(&self.session, nhwc_input(image, Self::INPUT_SIZE))?;
+        let [_drawings, hentai, _neutral, porn, sexy] = scores[..] else {
+            return Err(DetectorError::Backend(format!(
+                "Unsupported NSFW model: expected 5 outputs, got {}",
+                scores.len()
+            )));
+        };
+
+        Ok(hentai + porn + sexy)
+    }
+}
+
+/// A model input: shape and data
+#[cfg(feature = "onnx")]
+type ModelInput = ([usize; 4], Vec<f32>);
+
+/// RGB model input, normalizing pixels as (pixel - mean) / std
+#[cfg(feature = "onnx")]
+fn rgb_input(face: &DynamicImage, size: u32, mean: f32, std: f32) -> ModelInput {
+    let rgb = face
+        .resize_exact(size, size, image::imageops::FilterType::Triangle)
+        .to_rgb8();
+
+    let plane = (size * size) as usize;
+    let mut input = vec![0.0f32; 3 * plane];
+    for (i, pixel) in rgb.pixels().enumerate() {
+        for channel in 0..3 {
+            input[channel * plane + i] = (pixel[channel] as f32 - mean) / std;
+        }
+    }
+
+    ([1, 3, size as usize, size as usize], input)
+}
+
+/// Channels-last RGB model input scaled to 0-1
+#[cfg(feature = "onnx")]
+fn nhwc_input(image: &DynamicImage, size: u32) -> ModelInput {
+    let rgb = image
+        .resize_exact(size, size, image::imageops::FilterType::Triangle)
+        .to_rgb8();
+
+    ([1, size as usize, size as usize, 3], rgb.as_raw().iter().map(|&value| value as f32 / 255.0).collect())
+}
+
+/// Single channel model input of raw gray values
+#[cfg(feature = "onnx")]
+fn gray_input(face: &DynamicImage, size: u32) -> ModelInput {
+    let gray = face
+        .resize_exact(size, size, image::imageops::FilterType::Triangle)
+        .to_luma8();
+
+    ([1, 1, size as usize, size as usize], gray.pixels().map(|pixel| pixel[0] as f32).collect())
+}
+
+/// Run a single-input model and return its first output
+#[cfg(feature = "onnx")]
+fn run_classifier(session: &Mutex<Session>, input: ModelInput) -> Result<Vec<f32>> {
+    let tensor = ort::value::Tensor::from_array(input)
+        .map_err(|err| DetectorError::Backend(format!("Failed to build ONNX input tensor: {}", err)))?;
+
+    let mut session = session.lock().expect("classifier lock poisoned");
+    let input_name = session.inputs()[0].name().to_string();
+    let outputs = session
+        .run(ort::inputs![input_name => tensor])
+        .map_err(|err| DetectorError::Backend(format!("ONNX inference failed: {}", err)))?;
+    let (_, values) = outputs[0]
+        .try_extract_tensor::<f32>()
+        .map_err(|err| DetectorError::Backend(format!("Failed to read classifier output: {}", err)))?;
+
+    Ok(values.to_vec())
+}
@@ -0,0 +1,121 @@
+use crate::Args;
+use anyhow::{Context, Result};
+use face_cropper::{available_detectors, create_detector, crop_face, encode_crop, load_image};
+use image::DynamicImage;
+use log::{error, info, warn};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Arguments of the `bench` subcommand
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// Directory of sample images
+    pub input: PathBuf,
+
+    /// Detectors to compare, comma separated (default: every detector in this build)
+    #[clap(long, value_delimiter = ',')]
+    pub detectors: Vec<String>,
+
+    /// Images taken from the sample directory, they are all kept decoded in memory
+    #[clap(long, default_value_t = 100)]
+    pub limit: usize,
+}
+
+/// Time spent in each stage over all sample images
+#[derive(Debug, Default)]
+struct StageTimes {
+    detect: Duration,
+    crop: Duration,
+    encode: Duration,
+}
+
+/// Run every detector over the sample images and print throughput and per-stage timings
+///
+/// Images are decoded once and shared by the detectors, decoding counts towards
+/// each detector's images/sec all the same.
+pub fn bench(args: &Args, bench_args: &BenchArgs) -> Result<()> {
+    let detectors = if bench_args.detectors.is_empty() {
+        available_detectors()
+    } else {
+        bench_args.detectors.clone()
+    };
+    let load = args.load_options();
+    let crop = args.crop_options()?;
+    let config = args.detector_config();
+
+    let mut paths = args.find_images(&bench_args.input)?;
+    paths.truncate(bench_args.limit);
+    if paths.is_empty() {
+        warn!("No images found in {:?}", bench_args.input);
+        return Ok(());
+    }
+
+    let decode_start = Instant::now();
+    let images: Vec<DynamicImage> = paths
+        .iter()
+        .filter_map(|path| match load_image(path, &load) {
+            Ok(img) => Some(img),
+            Err(err) => {
+                error!("Failed to load {:?}: {}", path, err);
+                None
+            }
+        })
+        .collect();
+    let decode = decode_start.elapsed();
+    info!("Decoded {} images in {:.2?}", images.len(), decode);
+
+    println!(
+        "{:<12} {:>7} {:>7} {:>11} {:>10} {:>10} {:>10} {:>10}",
+        "detector", "images", "faces", "images/sec", "decode ms", "detect ms", "crop ms", "encode ms"
+    );
+
+    for name in &detectors {
+        let mut detector = match create_detector(name, &config) {
+            Ok(detector) => detector,
+            Err(err) => {
+                error!("Skipping detector {}: {}", name, err);
+                continue;
+            }
+        };
+
+        let mut times = StageTimes::default();
+        let mut faces_found = 0;
+        for img in &images {
+            let started = Instant::now();
+            let faces = detector
+                .detect_faces(img, args.threshold)
+                .with_context(|| format!("Detector {} failed", name))?;
+            times.detect += started.elapsed();
+            faces_found += faces.len();
+
+            for face in &faces {
+                let started = Instant::now();
+                let face_crop = crop_face(img, face, &crop);
+                times.crop += started.elapsed();
+
+                if let Some(face_crop) = face_crop {
+                    let started = Instant::now();
+                    encode_crop(&face_crop.image, &crop)?;
+                    times.encode += started.elapsed();
+                }
+            }
+        }
+
+        let total = decode + times.detect + times.crop + times.encode;
+        // Stage columns are averages per image
+        let per_image = |time: Duration| time.as_secs_f64() * 1000.0 / images.len().max(1) as f64;
+        println!(
+            "{:<12} {:>7} {:>7} {:>11.2} {:>10.1} {:>10.1} {:>10.1} {:>10.1}",
+            name,
+            images.len(),
+            faces_found,
+            images.len() as f64 / total.as_secs_f64().max(f64::EPSILON),
+            per_image(decode),
+            per_image(times.detect),
+            per_image(times.crop),
+            per_image(times.encode)
+        );
+    }
+
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use std::fs::{self, File};
 use std::io::{self};
 use std::path::Path;
+#[cfg(target_family = "windows")]
 use std::process::Command;
 
 
@@ -72,8 +73,7 @@ fn download_file(url: &str, dest: &str) -> io::Result<()> {
             drop(file);
             fs::remove_file(&temp_dest)?;
 
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
+            return Err(io::Error::other(
                 format!("Failed to download: {}", err)
             ));
         }
@@ -111,10 +111,10 @@ fn extract_zip(zip_path: &str, extract_to: &str) -> io::Result<()> {
         if file.name().ends_with('/') {
             fs::create_dir_all(&outpath)?;
         } else {
-            if let Some(p) = outpath.parent() {
-                if !p.exists() {
-                    fs::create_dir_all(p)?;
-                }
+            if let Some(p) = outpath.parent()
+                && !p.exists()
+            {
+                fs::create_dir_all(p)?;
             }
             let mut outfile = File::create(&outpath)?;
             io::copy(&mut file, &mut outfile)?;
@@ -0,0 +1,89 @@
+use crate::{init_detector, Args};
+use anyhow::{Context, Result};
+use face_cropper::save_faces;
+use image::{DynamicImage, RgbImage};
+use log::{info, warn};
+use nokhwa::pixel_format::RgbFormat;
+use nokhwa::utils::{CameraIndex, RequestedFormat, RequestedFormatType};
+use nokhwa::Camera;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Capture frames from a webcam and save the faces found in them
+///
+/// Runs until `--max-faces` faces were saved (or forever with `--max-faces 0`).
+/// Frames themselves aren't kept, manifest entries name them `camera:<N>/frame_<index>`.
+pub fn capture(args: &Args, index: u32, output_dir: &Path) -> Result<()> {
+    if args.resume {
+        return Err(anyhow::anyhow!("--resume isn't supported for camera input"));
+    }
+    if args.annotations.is_some() {
+        warn!("Annotations are only written for image directories, ignoring --annotations");
+    }
+
+    info!("Initializing face detector: {}", args.detector);
+    let mut detector = init_detector(args)?;
+
+    let format = RequestedFormat::new::<RgbFormat>(RequestedFormatType::AbsoluteHighestFrameRate);
+    let mut camera = Camera::new(CameraIndex::Index(index), format)
+        .with_context(|| format!("Failed to open camera {}", index))?;
+    camera
+        .open_stream()
+        .with_context(|| format!("Failed to start capturing from camera {}", index))?;
+    info!("Capturing from {} ({})", camera.info().human_name(), camera.camera_format());
+
+    let manifest_path = output_dir.join("manifest.jsonl");
+    let mut manifest = BufWriter::new(
+        File::create(&manifest_path)
+            .with_context(|| format!("Failed to create manifest: {:?}", manifest_path))?,
+    );
+
+    let crop = args.crop_options()?;
+    let face_counter = AtomicUsize::new(0);
+    let start_time = Instant::now();
+
+    let mut frame_idx: u64 = 0;
+    while args.max_faces == 0 || face_counter.load(Ordering::SeqCst) < args.max_faces {
+        let frame = camera.frame().context("Failed to capture frame")?;
+        let rgb = frame.decode_image::<RgbFormat>().context("Failed to decode frame")?;
+        let (width, height) = rgb.dimensions();
+        let img = RgbImage::from_raw(width, height, rgb.into_raw())
+            .map(DynamicImage::ImageRgb8)
+            .context("Camera returned a truncated frame")?;
+
+        let faces = detector.detect_faces(&img, args.threshold)?;
+        let source = format!("camera:{}/frame_{:06}", index, frame_idx);
+        let processed = save_faces(Path::new(&source), None, &img, faces, output_dir, &crop, &face_counter)?;
+
+        for entry in &processed.entries {
+            serde_json::to_writer(&mut manifest, entry)?;
+            writeln!(manifest)?;
+        }
+        manifest.flush().context("Failed to write manifest")?;
+
+        frame_idx += 1;
+        if frame_idx.is_multiple_of(30) {
+            let elapsed = start_time.elapsed().as_secs_f64();
+            info!(
+                "Captured {} frames ({:.1} frames/sec), found {} faces",
+                frame_idx,
+                frame_idx as f64 / elapsed,
+                face_counter.load(Ordering::SeqCst)
+            );
+        }
+    }
+
+    camera.stop_stream().context("Failed to stop camera stream")?;
+
+    info!(
+        "Finished capturing. Extracted {} faces from {} frames in {} seconds",
+        face_counter.load(Ordering::SeqCst),
+        frame_idx,
+        start_time.elapsed().as_secs()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,279 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the state file kept in the output directory
+pub const STATE_FILE: &str = "state.jsonl";
+
+/// One line of the state file, appended after every finished batch
+#[derive(Debug, Serialize, Deserialize)]
+struct Checkpoint {
+    /// Images finished in this batch, relative to the input directory
+    processed: Vec<String>,
+    /// Next free face index
+    face_counter: usize,
+    /// Length of the manifest once this batch's entries were written
+    manifest_bytes: u64,
+    /// Fingerprints of the finished images, recorded by incremental runs
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    fingerprints: HashMap<String, Fingerprint>,
+}
+
+/// What an input file looked like when it was processed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// Modification time (ns since the epoch), unknown when the file was modified
+    /// during the run that processed it
+    mtime: Option<u64>,
+    size: u64,
+    /// Hex SHA-256 digest of the contents that were processed, unknown when they
+    /// weren't hashed
+    sha256: Option<String>,
+}
+
+impl Fingerprint {
+    /// Fingerprint of a file processed by the run started at `run_started`, from the
+    /// digest of the bytes the pipeline read
+    ///
+    /// The times are read after processing, so they are only kept when the file
+    /// wasn't modified since the run started and still has the contents that were hashed.
+    pub fn of_processed(path: &Path, sha256: Option<String>, run_started: SystemTime) -> Self {
+        let started = run_started.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos() as u64);
+        let times = file_times(path).filter(|&(mtime, _)| mtime < started);
+        Self {
+            mtime: times.map(|(mtime, _)| mtime),
+            size: times.map_or(0, |(_, size)| size),
+            sha256,
+        }
+    }
+
+    /// Whether a file still has the processed content, hashing it only when its
+    /// times changed or aren't known; never when the digest isn't known
+    fn matches(&self, path: &Path) -> bool {
+        let Some(sha256) = &self.sha256 else {
+            return false;
+        };
+        if self.mtime.is_some() && file_times(path) == self.mtime.map(|mtime| (mtime, self.size)) {
+            return true;
+        }
+        fs::read(path).is_ok_and(|data| format!("{:x}", Sha256::digest(&data)) == *sha256)
+    }
+}
+
+/// Modification time (ns since the epoch) and size of a file
+fn file_times(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64;
+    Some((mtime, metadata.len()))
+}
+
+/// Progress of an earlier run, rebuilt from its state file
+#[derive(Debug, Default)]
+pub struct RunState {
+    /// Images that don't need processing again, relative to the input directory
+    pub processed: HashSet<String>,
+    /// Next free face index
+    pub face_counter: usize,
+    /// Manifest length at the last checkpoint, anything after it is from an unfinished batch
+    pub manifest_bytes: u64,
+    /// Fingerprints of the processed images that have one, by relative path
+    fingerprints: HashMap<String, Fingerprint>,
+    /// Length of the valid part of the state file
+    state_bytes: u64,
+}
+
+impl RunState {
+    /// Load the state of an earlier run, or an empty state if there is none
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut state = Self::default();
+        if !path.exists() {
+            return Ok(state);
+        }
+
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open state file: {:?}", path))?;
+
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader
+                .read_line(&mut line)
+                .with_context(|| format!("Failed to read state file: {:?}", path))?;
+
+            // A run killed mid-write leaves a partial last line behind
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            let Ok(checkpoint) = serde_json::from_str::<Checkpoint>(&line) else {
+                break;
+            };
+
+            state.processed.extend(checkpoint.processed);
+            state.fingerprints.extend(checkpoint.fingerprints);
+            state.face_counter = checkpoint.face_counter;
+            state.manifest_bytes = checkpoint.manifest_bytes;
+            state.state_bytes += read as u64;
+        }
+
+        Ok(state)
+    }
+
+    /// Whether an image was processed and hasn't changed since, images processed
+    /// without a fingerprint (by a --resume run) count as unchanged
+    pub fn is_current(&self, relative: &str, path: &Path) -> bool {
+        self.processed.contains(relative)
+            && self
+                .fingerprints
+                .get(relative)
+                .is_none_or(|fingerprint| fingerprint.matches(path))
+    }
+
+    /// Forget the processed images under `input_dir` that changed since, returning
+    /// them; the ones that were deleted are kept, nothing will replace their crops
+    pub fn take_changed(&mut self, input_dir: &Path) -> HashSet<String> {
+        let changed: HashSet<String> = self
+            .processed
+            .iter()
+            .filter(|relative| {
+                let path = input_dir.join(relative);
+                path.exists() && !self.is_current(relative, &path)
+            })
+            .cloned()
+            .collect();
+        self.processed.retain(|relative| !changed.contains(relative));
+        changed
+    }
+}
+
+/// Appends a checkpoint line to the state file after every batch
+pub struct CheckpointWriter {
+    file: File,
+}
+
+impl CheckpointWriter {
+    /// Open the state file, continuing after `resume_from` or starting over without it
+    pub fn open(path: &Path, resume_from: Option<&RunState>) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(resume_from.is_none())
+            .open(path)
+            .with_context(|| format!("Failed to open state file: {:?}", path))?;
+
+        // Drop a partial line left by an interrupted write before appending
+        if let Some(state) = resume_from {
+            file.set_len(state.state_bytes)?;
+            file.seek(SeekFrom::End(0))?;
+        }
+
+        Ok(Self { file })
+    }
+
+    /// Record a finished batch
+    pub fn record(
+        &mut self,
+        processed: Vec<String>,
+        fingerprints: HashMap<String, Fingerprint>,
+        face_counter: usize,
+        manifest_bytes: u64
+    ) -> Result<()> {
+        let checkpoint = Checkpoint {
+            processed,
+            face_counter,
+            manifest_bytes,
+            fingerprints,
+        };
+
+        let mut line = serde_json::to_vec(&checkpoint)?;
+        line.push(b'\n');
+        self.file.write_all(&line).context("Failed to write state file")?;
+        self.file.sync_data().context("Failed to sync state file")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_stops_at_a_truncated_last_line() {
+        let dir = std::env::temp_dir().join(format!("face_cropper-state-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(STATE_FILE);
+
+        let mut writer = CheckpointWriter::open(&path, None).unwrap();
+        writer.record(vec!["a.jpg".to_string()], HashMap::new(), 4, 100).unwrap();
+        writer.record(vec!["b.jpg".to_string()], HashMap::new(), 6, 150).unwrap();
+        let complete = fs::metadata(&path).unwrap().len();
+        drop(writer);
+        // A run killed while writing its third checkpoint
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(br#"{"processed":["c.jpg"],"face_cou"#).unwrap();
+
+        let state = RunState::load(&path).unwrap();
+        assert_eq!(state.processed, HashSet::from(["a.jpg".to_string(), "b.jpg".to_string()]));
+        assert_eq!((state.face_counter, state.manifest_bytes, state.state_bytes), (6, 150, complete));
+
+        // Resuming drops the partial line before appending
+        let mut writer = CheckpointWriter::open(&path, Some(&state)).unwrap();
+        writer.record(vec!["c.jpg".to_string()], HashMap::new(), 7, 180).unwrap();
+        let resumed = RunState::load(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(resumed.processed.len(), 3);
+        assert_eq!(resumed.face_counter, 7);
+    }
+
+    #[test]
+    fn fingerprints_trust_only_times_from_before_the_run() {
+        let dir = std::env::temp_dir().join(format!("face_cropper-fingerprint-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.jpg");
+        fs::write(&path, b"before").unwrap();
+        let digest = Some(format!("{:x}", Sha256::digest(b"before")));
+
+        let before = Fingerprint::of_processed(&path, digest.clone(), SystemTime::now());
+        let during = Fingerprint::of_processed(&path, digest.clone(), UNIX_EPOCH);
+        let unhashed = Fingerprint::of_processed(&path, None, SystemTime::now());
+        assert!(before.mtime.is_some() && during.mtime.is_none());
+        assert!(before.matches(&path) && during.matches(&path));
+        assert!(!unhashed.matches(&path));
+
+        fs::write(&path, b"after!").unwrap();
+        let changed = (before.matches(&path), during.matches(&path));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(changed, (false, false));
+    }
+
+    #[test]
+    fn take_changed_forgets_images_that_changed_or_were_never_hashed() {
+        let dir = std::env::temp_dir().join(format!("face_cropper-changed-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["same.jpg", "edited.jpg", "unhashed.jpg", "resumed.jpg"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let now = SystemTime::now();
+        let digest = |data: &[u8]| Some(format!("{:x}", Sha256::digest(data)));
+
+        let mut state = RunState::default();
+        state.processed.extend(["same.jpg", "edited.jpg", "unhashed.jpg", "resumed.jpg", "deleted.jpg"].map(String::from));
+        state.fingerprints.extend([
+            ("same.jpg".to_string(), Fingerprint::of_processed(&dir.join("same.jpg"), digest(b"same.jpg"), now)),
+            ("edited.jpg".to_string(), Fingerprint::of_processed(&dir.join("edited.jpg"), digest(b"edited.jpg"), now)),
+            ("unhashed.jpg".to_string(), Fingerprint::of_processed(&dir.join("unhashed.jpg"), None, now)),
+            ("deleted.jpg".to_string(), Fingerprint::of_processed(&dir.join("deleted.jpg"), digest(b"deleted.jpg"), now)),
+        ]);
+        fs::write(dir.join("edited.jpg"), "edited, longer").unwrap();
+
+        let changed = state.take_changed(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(changed, HashSet::from(["edited.jpg".to_string(), "unhashed.jpg".to_string()]));
+        assert_eq!(state.processed.len(), 3);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{find_crops, gallery, relative_path, Args};
+use face_cropper::LoadOptions;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// Arguments of the `cluster` subcommand
+#[derive(clap::Args, Debug)]
+pub struct ClusterArgs {
+    /// Directory of face crops, e.g. the output of a previous run (crops review rejected are left out)
+    pub crops: PathBuf,
+
+    /// Output directory, crops are copied into one subdirectory per identity
+    #[clap(short, long)]
+    pub output_dir: PathBuf,
+
+    /// ArcFace ONNX model computing the embeddings
+    #[clap(long)]
+    pub embedding_model: Option<PathBuf>,
+
+    /// Cosine similarity a face needs to a cluster's mean embedding to join it
+    #[clap(long, default_value = "0.5")]
+    pub similarity: f32,
+
+    /// Clusters with fewer faces go to `unclustered/`
+    #[clap(long, default_value = "2")]
+    pub min_cluster_size: usize,
+
+    /// Also write an index.html to the output directory showing the crops as thumbnails, grouped by cluster
+    #[clap(long)]
+    pub gallery: bool,
+}
+
+/// One line of `clusters.jsonl`
+#[derive(Debug, Serialize)]
+struct ClusterEntry {
+    /// Crop path, relative to the crops directory
+    source: String,
+    /// Cluster directory the crop was copied to
+    cluster: String,
+    /// L2-normalized face embedding
+    embedding: Vec<f32>,
+}
+
+/// Group face crops by identity and copy each group into its own directory
+pub fn cluster(args: &Args, cluster_args: &ClusterArgs) -> Result<()> {
+    let crops = find_crops(&cluster_args.crops);
+    if crops.is_empty() {
+        warn!("No face crops found at {:?}", cluster_args.crops);
+        return Ok(());
+    }
+    info!("Found {} face crops", crops.len());
+
+    let start_time = Instant::now();
+    let embedded = embed_crops(&crops, cluster_args, &args.load_options())?;
+    let clusters = group(&embedded, cluster_args.similarity);
+
+    fs::create_dir_all(&cluster_args.output_dir)
+        .context("Failed to create output directory")?;
+    let index_path = cluster_args.output_dir.join("clusters.jsonl");
+    let mut index = BufWriter::new(
+        File::create(&index_path)
+            .with_context(|| format!("Failed to create cluster index: {:?}", index_path))?,
+    );
+
+    let mut cluster_count = 0;
+    // Gallery groups, the faces of every too small cluster in a last one
+    let (mut groups, mut unclustered) = (Vec::new(), Vec::new());
+    for members in &clusters {
+        let name = if members.len() >= cluster_args.min_cluster_size {
+            cluster_count += 1;
+            format!("cluster_{:03}", cluster_count - 1)
+        } else {
+            "unclustered".to_string()
+        };
+        let dir = cluster_args.output_dir.join(&name);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create directory: {:?}", dir))?;
+
+        // Unclustered faces have no identity to cap
+        let kept = match args.max_per_identity {
+            Some(max) if name != "unclustered" => members.len().min(max as usize),
+            _ => members.len(),
+        };
+        if kept < members.len() {
+            info!("Keeping {} of the {} faces in {}", kept, members.len(), name);
+        }
+
+        let mut copied = Vec::with_capacity(kept);
+        for &member in &members[..kept] {
+            let (path, embedding) = &embedded[member];
+            let file_name = path.file_name().context("Crop path has no file name")?;
+            fs::copy(path, dir.join(file_name))
+                .with_context(|| format!("Failed to copy {:?} to {:?}", path, dir))?;
+            copied.push(format!("{}/{}", name, file_name.to_string_lossy()));
+
+            let entry = ClusterEntry {
+                source: relative_path(path, &cluster_args.crops),
+                cluster: name.clone(),
+                embedding: embedding.clone(),
+            };
+            serde_json::to_writer(&mut index, &entry)?;
+            writeln!(index)?;
+        }
+        if name == "unclustered" {
+            unclustered.append(&mut copied);
+        } else {
+            groups.push((name, copied));
+        }
+    }
+    index.flush().context("Failed to write cluster index")?;
+
+    if cluster_args.gallery {
+        if !unclustered.is_empty() {
+            groups.push(("unclustered".to_string(), unclustered));
+        }
+        let gallery_path = gallery::write_gallery(&cluster_args.output_dir, "Face clusters", &groups)?;
+        info!("Wrote a gallery of the clusters to {:?}", gallery_path);
+    }
+
+    info!(
+        "Finished clustering. Grouped {} faces into {} identities in {} seconds",
+        embedded.len(),
+        cluster_count,
+        start_time.elapsed().as_secs()
+    );
+
+    Ok(())
+}
+
+/// Embed every crop that can be read, skipping the others
+#[cfg(feature = "onnx")]
+fn embed_crops(crops: &[PathBuf], cluster_args: &ClusterArgs, load: &LoadOptions) -> Result<Vec<(PathBuf, Vec<f32>)>> {
+    let mut embedder = face_cropper::FaceEmbedder::new(cluster_args.embedding_model.as_deref())?;
+
+    let mut embedded = Vec::with_capacity(crops.len());
+    for (i, path) in crops.iter().enumerate() {
+        match face_cropper::load_image(path, load).and_then(|img| Ok(embedder.embed(&img)?)) {
+            Ok(embedding) => embedded.push((path.clone(), embedding)),
+            Err(err) => log::error!("Failed to embed {:?}: {}", path, err),
+        }
+
+        if (i + 1).is_multiple_of(500) {
+            info!("Embedded {}/{} crops", i + 1, crops.len());
+        }
+    }
+
+    Ok(embedded)
+}
+
+#[cfg(not(feature = "onnx"))]
+fn embed_crops(_crops: &[PathBuf], _cluster_args: &ClusterArgs, _load: &LoadOptions) -> Result<Vec<(PathBuf, Vec<f32>)>> {
+    Err(anyhow::anyhow!(
+        "Clustering runs ArcFace on ONNX Runtime, which needs a build with `--features onnx`"
+    ))
+}
+
+/// Greedily assign each embedding to the most similar cluster mean, or start a new cluster
+///
+/// Returns the member indices of every cluster, largest cluster first.
+fn group(embedded: &[(PathBuf, Vec<f32>)], similarity: f32) -> Vec<Vec<usize>> {
+    let mut sums: Vec<Vec<f32>> = Vec::new();
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+
+    for (i, (_, embedding)) in embedded.iter().enumerate() {
+        let best = sums
+            .iter()
+            .enumerate()
+            .map(|(c, sum)| (c, mean_similarity(sum, embedding)))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        match best {
+            Some((c, score)) if score >= similarity => {
+                sums[c].iter_mut().zip(embedding).for_each(|(s, v)| *s += v);
+                clusters[c].push(i);
+            }
+            _ => {
+                sums.push(embedding.clone());
+                clusters.push(vec![i]);
+            }
+        }
+    }
+
+    clusters.sort_by_key(|members| std::cmp::Reverse(members.len()));
+    clusters
+}
+
+/// Cosine similarity between a normalized embedding and the mean of a cluster, given as its sum
+fn mean_similarity(sum: &[f32], embedding: &[f32]) -> f32 {
+    let norm = sum.iter().map(|v| v * v).sum::<f32>().sqrt().max(f32::EPSILON);
+    sum.iter().zip(embedding).map(|(s, v)| s * v).sum::<f32>() / norm
+}
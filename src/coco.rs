@@ -0,0 +1,142 @@
+use crate::detector::FaceBox;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Keypoint names of the face category, matching the `Landmarks` order
+const FACE_KEYPOINTS: [&str; 5] = ["left_eye", "right_eye", "nose", "mouth_left", "mouth_right"];
+
+/// COCO-format detection dataset with a single "face" category
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CocoDataset {
+    pub images: Vec<CocoImage>,
+    pub annotations: Vec<CocoAnnotation>,
+    pub categories: Vec<CocoCategory>,
+}
+
+/// A source image entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CocoImage {
+    pub id: u64,
+    pub file_name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A face bounding box annotation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CocoAnnotation {
+    pub id: u64,
+    pub image_id: u64,
+    pub category_id: u64,
+    pub bbox: [f32; 4], // [x, y, width, height]
+    pub area: f32,
+    pub iscrowd: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keypoints: Vec<f32>, // [x, y, visibility] per keypoint
+    #[serde(default)]
+    pub num_keypoints: u32,
+}
+
+/// An annotation category
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CocoCategory {
+    pub id: u64,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keypoints: Vec<String>,
+}
+
+impl CocoDataset {
+    /// Category id used for faces
+    pub const FACE_CATEGORY: u64 = 1;
+
+    /// Create an empty dataset with the face category
+    pub fn new() -> Self {
+        Self {
+            images: Vec::new(),
+            annotations: Vec::new(),
+            categories: vec![CocoCategory {
+                id: Self::FACE_CATEGORY,
+                name: "face".to_string(),
+                keypoints: FACE_KEYPOINTS.iter().map(|name| name.to_string()).collect(),
+            }],
+        }
+    }
+
+    /// Add a source image, returning its id
+    pub fn add_image(&mut self, file_name: &str, width: u32, height: u32) -> u64 {
+        let id = self.images.last().map_or(1, |image| image.id + 1);
+        self.images.push(CocoImage {
+            id,
+            file_name: file_name.to_string(),
+            width,
+            height,
+        });
+        id
+    }
+
+    /// Add a detected face to an image previously returned by `add_image`
+    pub fn add_face(&mut self, image_id: u64, face: &FaceBox) {
+        let (keypoints, num_keypoints) = match &face.landmarks {
+            Some(landmarks) => (
+                landmarks.iter().flat_map(|&(x, y)| [x, y, 2.0]).collect(),
+                landmarks.len() as u32,
+            ),
+            None => (Vec::new(), 0),
+        };
+
+        self.annotations.push(CocoAnnotation {
+            id: self.annotations.last().map_or(1, |annotation| annotation.id + 1),
+            image_id,
+            category_id: Self::FACE_CATEGORY,
+            bbox: [face.x as f32, face.y as f32, face.width as f32, face.height as f32],
+            area: face.area() as f32,
+            iscrowd: 0,
+            score: Some(face.confidence),
+            keypoints,
+            num_keypoints,
+        });
+    }
+
+    /// Remove the images with these file names and their annotations
+    pub fn remove_images(&mut self, file_names: &HashSet<String>) {
+        let removed: HashSet<u64> = self
+            .images
+            .iter()
+            .filter(|image| file_names.contains(&image.file_name))
+            .map(|image| image.id)
+            .collect();
+        self.images.retain(|image| !removed.contains(&image.id));
+        self.annotations.retain(|annotation| !removed.contains(&annotation.image_id));
+    }
+}
+
+impl CocoAnnotation {
+    /// The annotation as a face box, keypoints become landmarks when all five are present
+    pub fn to_face(&self) -> FaceBox {
+        let landmarks = (self.keypoints.len() == FACE_KEYPOINTS.len() * 3).then(|| {
+            let mut landmarks = [(0.0, 0.0); 5];
+            for (landmark, keypoint) in landmarks.iter_mut().zip(self.keypoints.chunks(3)) {
+                *landmark = (keypoint[0], keypoint[1]);
+            }
+            landmarks
+        });
+
+        FaceBox {
+            x: self.bbox[0].round() as i32,
+            y: self.bbox[1].round() as i32,
+            width: self.bbox[2].round() as i32,
+            height: self.bbox[3].round() as i32,
+            confidence: self.score.unwrap_or(1.0),
+            landmarks,
+        }
+    }
+}
+
+impl Default for CocoDataset {
+    fn default() -> Self {
+        Self::new()
+    }
+}
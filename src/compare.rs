@@ -0,0 +1,214 @@
+use crate::Args;
+use anyhow::{Context, Result};
+use face_cropper::{create_detector, load_image, Annotator, DetectedBox, FaceBox};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+/// Arguments of the `compare` subcommand
+#[derive(clap::Args, Debug)]
+pub struct CompareArgs {
+    /// Image file, or directory to scan for images
+    pub input: PathBuf,
+
+    /// First detector (any name --detector accepts)
+    #[clap(long)]
+    pub a: String,
+
+    /// Second detector
+    #[clap(long)]
+    pub b: String,
+
+    /// Overlap (IoU) at which a face of A and a face of B are the same face
+    #[clap(long, default_value_t = 0.5)]
+    pub iou: f32,
+
+    /// File to write the report to (default: stdout)
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Also write each image with A's faces on the left and B's on the right to this
+    /// directory, faces the other detector missed in red
+    #[clap(long)]
+    pub side_by_side: Option<PathBuf>,
+}
+
+/// The report written by `compare`
+#[derive(Debug, Serialize)]
+struct CompareReport {
+    detector_a: String,
+    detector_b: String,
+    iou_threshold: f32,
+    images: usize,
+    matched: usize,
+    only_a: usize,
+    only_b: usize,
+    /// Mean IoU of the matched pairs
+    mean_iou: f32,
+    /// Mean of B's confidence minus A's over the matched pairs
+    mean_confidence_delta: f32,
+    per_image: Vec<ImageComparison>,
+}
+
+/// Differences between the detectors on one image
+#[derive(Debug, Serialize)]
+struct ImageComparison {
+    source: String,
+    only_a: Vec<DetectedBox>,
+    only_b: Vec<DetectedBox>,
+    matched: Vec<MatchedPair>,
+}
+
+/// A face both detectors found
+#[derive(Debug, Serialize)]
+struct MatchedPair {
+    a: DetectedBox,
+    b: DetectedBox,
+    iou: f32,
+    /// B's confidence minus A's
+    confidence_delta: f32,
+}
+
+/// Run two detectors over the same images and report where they differ
+pub fn compare(args: &Args, compare_args: &CompareArgs) -> Result<()> {
+    let config = args.detector_config();
+    let load = args.load_options();
+    let mut detector_a = create_detector(&compare_args.a, &config)
+        .with_context(|| format!("Failed to initialize face detector: {}", compare_args.a))?;
+    let mut detector_b = create_detector(&compare_args.b, &config)
+        .with_context(|| format!("Failed to initialize face detector: {}", compare_args.b))?;
+    let annotator = compare_args
+        .side_by_side
+        .as_deref()
+        .map(|dir| Annotator::new(dir, &compare_args.input))
+        .transpose()?;
+
+    let image_paths = args.find_images(&compare_args.input)?;
+    if image_paths.is_empty() {
+        warn!("No images found at {:?}", compare_args.input);
+        return Ok(());
+    }
+    info!("Comparing {} and {} on {} images", compare_args.a, compare_args.b, image_paths.len());
+
+    let mut per_image = Vec::with_capacity(image_paths.len());
+    for path in &image_paths {
+        let img = match load_image(path, &load) {
+            Ok(img) => img,
+            Err(err) => {
+                error!("Failed to process {:?}: {}", path, err);
+                continue;
+            }
+        };
+        let detected = detector_a
+            .detect_faces(&img, args.threshold)
+            .and_then(|faces_a| Ok((faces_a, detector_b.detect_faces(&img, args.threshold)?)));
+        let (faces_a, faces_b) = match detected {
+            Ok(faces) => faces,
+            Err(err) => {
+                error!("Failed to process {:?}: {}", path, err);
+                continue;
+            }
+        };
+
+        let pairs = match_faces(&faces_a, &faces_b, compare_args.iou);
+        if let Some(annotator) = &annotator {
+            let left: Vec<_> = (0..faces_a.len())
+                .map(|i| (&faces_a[i], pairs.iter().any(|(a, _, _)| *a == i)))
+                .collect();
+            let right: Vec<_> = (0..faces_b.len())
+                .map(|j| (&faces_b[j], pairs.iter().any(|(_, b, _)| *b == j)))
+                .collect();
+            if let Err(err) = annotator.save_side_by_side(path, &img, &left, &right) {
+                error!("Failed to annotate {:?}: {}", path, err);
+            }
+        }
+
+        per_image.push(ImageComparison {
+            source: path.to_string_lossy().into_owned(),
+            only_a: (0..faces_a.len())
+                .filter(|i| !pairs.iter().any(|(a, _, _)| a == i))
+                .map(|i| DetectedBox::from(&faces_a[i]))
+                .collect(),
+            only_b: (0..faces_b.len())
+                .filter(|j| !pairs.iter().any(|(_, b, _)| b == j))
+                .map(|j| DetectedBox::from(&faces_b[j]))
+                .collect(),
+            matched: pairs
+                .iter()
+                .map(|&(a, b, iou)| MatchedPair {
+                    a: DetectedBox::from(&faces_a[a]),
+                    b: DetectedBox::from(&faces_b[b]),
+                    iou,
+                    confidence_delta: faces_b[b].confidence - faces_a[a].confidence,
+                })
+                .collect(),
+        });
+    }
+
+    let report = summarize(compare_args, per_image);
+    info!(
+        "{} faces matched, {} only found by {}, {} only found by {}",
+        report.matched, report.only_a, compare_args.a, report.only_b, compare_args.b
+    );
+
+    let mut output: BufWriter<Box<dyn Write>> = BufWriter::new(match &compare_args.output {
+        Some(path) => Box::new(
+            File::create(path).with_context(|| format!("Failed to create report file: {:?}", path))?,
+        ),
+        None => Box::new(io::stdout().lock()),
+    });
+    serde_json::to_writer_pretty(&mut output, &report)?;
+    writeln!(output)?;
+    output.flush().context("Failed to write report")
+}
+
+/// Pair faces of A and B by overlap, best overlaps first, each face in at most one pair
+///
+/// Returns the indices of the paired faces with their IoU.
+fn match_faces(faces_a: &[FaceBox], faces_b: &[FaceBox], iou_threshold: f32) -> Vec<(usize, usize, f32)> {
+    let mut candidates = Vec::new();
+    for (i, a) in faces_a.iter().enumerate() {
+        for (j, b) in faces_b.iter().enumerate() {
+            let iou = a.iou(b);
+            if iou >= iou_threshold {
+                candidates.push((i, j, iou));
+            }
+        }
+    }
+    candidates.sort_by(|x, y| y.2.total_cmp(&x.2));
+
+    let mut pairs: Vec<(usize, usize, f32)> = Vec::new();
+    for (i, j, iou) in candidates {
+        if !pairs.iter().any(|&(a, b, _)| a == i || b == j) {
+            pairs.push((i, j, iou));
+        }
+    }
+    pairs
+}
+
+/// Totals of the per-image comparisons
+fn summarize(compare_args: &CompareArgs, per_image: Vec<ImageComparison>) -> CompareReport {
+    let pairs: Vec<&MatchedPair> = per_image.iter().flat_map(|image| &image.matched).collect();
+    let mean = |values: Vec<f32>| {
+        if values.is_empty() {
+            0.0
+        } else {
+            values.iter().sum::<f32>() / values.len() as f32
+        }
+    };
+
+    CompareReport {
+        detector_a: compare_args.a.clone(),
+        detector_b: compare_args.b.clone(),
+        iou_threshold: compare_args.iou,
+        images: per_image.len(),
+        matched: pairs.len(),
+        only_a: per_image.iter().map(|image| image.only_a.len()).sum(),
+        only_b: per_image.iter().map(|image| image.only_b.len()).sum(),
+        mean_iou: mean(pairs.iter().map(|pair| pair.iou).collect()),
+        mean_confidence_delta: mean(pairs.iter().map(|pair| pair.confidence_delta).collect()),
+        per_image,
+    }
+}
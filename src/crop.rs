@@ -0,0 +1,116 @@
+use crate::Args;
+use anyhow::{Context, Result};
+use face_cropper::{load_image, save_faces, CocoDataset, FaceBox, ImageDetections};
+use log::{error, info};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Arguments of the `crop` subcommand
+#[derive(clap::Args, Debug)]
+pub struct CropArgs {
+    /// Boxes to crop: output of the `detect` subcommand (JSON lines) or a COCO annotations file
+    pub annotations: PathBuf,
+
+    /// Directory the image paths in the annotations are relative to
+    #[clap(long, default_value = ".")]
+    pub image_root: PathBuf,
+
+    /// Output directory for cropped faces
+    #[clap(short, long)]
+    pub output_dir: PathBuf,
+}
+
+/// Crop the faces listed in an annotations file, without running the detector
+pub fn crop(args: &Args, crop_args: &CropArgs) -> Result<()> {
+    let mut images = read_annotations(&crop_args.annotations)?;
+    images.retain(|(_, faces)| !faces.is_empty());
+    let face_total: usize = images.iter().map(|(_, faces)| faces.len()).sum();
+    info!("Loaded {} faces in {} images from {:?}", face_total, images.len(), crop_args.annotations);
+
+    fs::create_dir_all(&crop_args.output_dir)
+        .context("Failed to create output directory")?;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.max(1))
+        .build()
+        .context("Failed to create worker thread pool")?;
+
+    let load = args.load_options();
+    let mut crop = args.crop_options()?;
+    if args.mirror_structure {
+        crop.mirror_root = Some(crop_args.image_root.clone());
+    }
+    let face_counter = AtomicUsize::new(0);
+    let start_time = Instant::now();
+
+    let results: Vec<_> = pool.install(|| {
+        images
+            .into_par_iter()
+            .map(|(source, faces)| {
+                let path = crop_args.image_root.join(&source);
+                let result = load_image(&path, &load)
+                    .and_then(|img| save_faces(&path, None, &img, faces, &crop_args.output_dir, &crop, &face_counter));
+                (path, result)
+            })
+            .collect()
+    });
+
+    // Crops are written as they are made, the manifest is written in annotation order
+    let manifest_path = crop_args.output_dir.join("manifest.jsonl");
+    let mut manifest = BufWriter::new(
+        File::create(&manifest_path)
+            .with_context(|| format!("Failed to create manifest: {:?}", manifest_path))?,
+    );
+    for (path, result) in results {
+        match result {
+            Ok(processed) => {
+                for entry in &processed.entries {
+                    serde_json::to_writer(&mut manifest, entry)?;
+                    writeln!(manifest)?;
+                }
+            }
+            Err(err) => error!("Failed to process {:?}: {}", path, err),
+        }
+    }
+    manifest.flush().context("Failed to write manifest")?;
+
+    info!(
+        "Finished cropping. Saved {} faces in {} seconds",
+        face_counter.load(Ordering::SeqCst),
+        start_time.elapsed().as_secs()
+    );
+
+    Ok(())
+}
+
+/// Read the faces of every image from a COCO file or `detect` JSON lines
+fn read_annotations(path: &Path) -> Result<Vec<(String, Vec<FaceBox>)>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read annotations: {:?}", path))?;
+
+    // A COCO file is a single JSON object, `detect` output has one object per line
+    if let Ok(coco) = serde_json::from_str::<CocoDataset>(&text) {
+        let mut faces: HashMap<u64, Vec<FaceBox>> = HashMap::new();
+        for annotation in &coco.annotations {
+            if annotation.category_id == CocoDataset::FACE_CATEGORY {
+                faces.entry(annotation.image_id).or_default().push(annotation.to_face());
+            }
+        }
+
+        return Ok(coco
+            .images
+            .iter()
+            .filter_map(|image| faces.remove(&image.id).map(|faces| (image.file_name.clone(), faces)))
+            .collect());
+    }
+
+    let images = ImageDetections::read_jsonl(text.as_bytes())
+        .with_context(|| format!("Invalid annotations in {:?}", path))?;
+
+    Ok(images.into_iter().map(|detections| (detections.source, detections.faces.iter().map(FaceBox::from).collect())).collect())
+}
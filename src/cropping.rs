@@ -0,0 +1,1175 @@
+#[cfg(feature = "onnx")]
+use crate::attributes::{self, AgeGenderEstimator, ExpressionClassifier, NsfwClassifier};
+use crate::attributes::{Expression, FaceAttributes, Gender, NsfwScope};
+use crate::detector::{FaceBox, Landmarks};
+use crate::quality::{self, Exposure, PhashIndex};
+#[cfg(feature = "onnx")]
+use crate::upscale::SuperResolution;
+use crate::error::{Error, Result};
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Margins added around a detected box on each side, as fractions of its width
+/// (left, right) or height (top, bottom)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Margins {
+    pub top: f32,
+    pub bottom: f32,
+    pub left: f32,
+    pub right: f32,
+}
+
+/// Which faces of an image are kept first when only some of them are cropped
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FaceRank {
+    /// Largest box first
+    #[default]
+    Area,
+    /// Most confident detection first, the larger box on ties
+    Confidence,
+}
+
+/// How crop pixels outside the source image are filled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PadFill {
+    Clamp,
+    Black,
+    Reflect,
+}
+
+impl PadFill {
+    /// Source pixel coordinate to use for `v` on an axis of `len` pixels, None for black
+    fn coord(self, v: i64, len: u32) -> Option<u32> {
+        let len = len as i64;
+        if (0..len).contains(&v) {
+            return Some(v as u32);
+        }
+
+        match self {
+            PadFill::Black => None,
+            PadFill::Clamp => Some(v.clamp(0, len - 1) as u32),
+            PadFill::Reflect => {
+                // Mirror at the edges, repeating the edge pixel: ...1 0 | 0 1 ... len-1 | len-1 ...
+                let period = 2 * len;
+                let m = v.rem_euclid(period);
+                Some(if m < len { m } else { period - 1 - m } as u32)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for PadFill {
+    type Err = String;
+
+    /// Parse `clamp`, `black` or `reflect`
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "clamp" => Ok(PadFill::Clamp),
+            "black" => Ok(PadFill::Black),
+            "reflect" => Ok(PadFill::Reflect),
+            _ => Err(format!("Invalid pad fill: {} (expected clamp, black or reflect)", value)),
+        }
+    }
+}
+
+/// Filters crops can be resized with, from sharpest to fastest
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResizeFilter {
+    #[default]
+    Lanczos3,
+    Bilinear,
+    /// Averages the source pixels under each output pixel
+    Box,
+    Nearest,
+}
+
+impl std::str::FromStr for ResizeFilter {
+    type Err = String;
+
+    /// Parse `lanczos3`, `bilinear`, `box` or `nearest`
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "lanczos3" => Ok(ResizeFilter::Lanczos3),
+            "bilinear" => Ok(ResizeFilter::Bilinear),
+            "box" => Ok(ResizeFilter::Box),
+            "nearest" => Ok(ResizeFilter::Nearest),
+            _ => Err(format!("Invalid resize filter: {} (expected lanczos3, bilinear, box or nearest)", value)),
+        }
+    }
+}
+
+/// Image formats crops can be saved in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Jpg,
+    Png,
+    Webp,
+}
+
+impl OutputFormat {
+    /// File extension of the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Jpg => "jpg",
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    /// Parse `jpg`, `png` or `webp`
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "jpg" => Ok(OutputFormat::Jpg),
+            "png" => Ok(OutputFormat::Png),
+            "webp" => Ok(OutputFormat::Webp),
+            _ => Err(format!("Invalid output format: {} (expected jpg, png or webp)", value)),
+        }
+    }
+}
+
+/// Outcome of processing one image
+#[derive(Debug)]
+pub struct ProcessedImage {
+    /// Source image dimensions (px)
+    pub width: u32,
+    pub height: u32,
+    /// Every face the detector found (in all processed frames of an animated image)
+    pub faces: Vec<FaceBox>,
+    /// Manifest entries of the crops that were saved
+    pub entries: Vec<ManifestEntry>,
+    /// The whole image was rejected, by the NSFW filter or for being smaller than
+    /// the load options allow (then without a size), nothing of it was saved
+    pub rejected: bool,
+    /// Hex SHA-256 digest of the source bytes that were processed, when the
+    /// pipeline hashes its sources
+    pub source_sha256: Option<String>,
+}
+
+/// One line of `manifest.jsonl`, recording where a saved face came from
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Source image path
+    pub source: String,
+    /// Identity label, the name of the source image's directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Frame of an animated source image the face is in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame: Option<u32>,
+    /// Source image dimensions (px)
+    pub image_width: u32,
+    pub image_height: u32,
+    /// Detected face box in source coordinates: [x, y, width, height]
+    pub bbox: [i32; 4],
+    /// Detection confidence
+    pub confidence: f32,
+    /// Landmarks in source coordinates, if the detector provides them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub landmarks: Option<Landmarks>,
+    /// Region cut out of the source image: [x, y, width, height]
+    pub crop: [i32; 4],
+    /// Rotation applied to the crop (degrees, non-zero when aligning)
+    pub crop_angle: f32,
+    /// Landmarks in the coordinate frame of the saved crop
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop_landmarks: Option<Landmarks>,
+    /// Sharpness of the saved crop (variance of the Laplacian)
+    pub sharpness: f64,
+    /// Mean luminance (0-255) and RMS contrast of the saved crop, 0 in manifests
+    /// of runs from before they were recorded
+    #[serde(default)]
+    pub brightness: f64,
+    #[serde(default)]
+    pub contrast: f64,
+    /// Output filename, relative to the output directory
+    pub output: String,
+    /// Output crop size (px)
+    pub output_size: u32,
+    /// Estimated attributes, when the classification stages ran
+    #[serde(flatten)]
+    pub attributes: FaceAttributes,
+}
+
+impl ManifestEntry {
+    /// The detected face the crop was made from, in source coordinates
+    pub fn to_face(&self) -> FaceBox {
+        FaceBox {
+            x: self.bbox[0],
+            y: self.bbox[1],
+            width: self.bbox[2],
+            height: self.bbox[3],
+            confidence: self.confidence,
+            landmarks: self.landmarks,
+        }
+    }
+}
+
+/// Options controlling how detected faces are cropped
+#[derive(Debug)]
+pub struct CropOptions {
+    /// Square size for output faces (px), 0 to keep crops at their size in the source image
+    pub size: u32,
+    /// Sizes every crop is saved at instead, each into a subdirectory named after
+    /// it (`128/`, `256/`, ...); filters and attributes still look at `size`
+    pub sizes: Vec<u32>,
+    /// Filter crops are resized to the output size with
+    pub filter: ResizeFilter,
+    /// Super-resolution model crops smaller than the output size are upscaled with
+    /// before the final resize, None to only resample them with `filter`
+    #[cfg(feature = "onnx")]
+    pub upscaler: Option<SuperResolution>,
+    /// Image format of the saved crops
+    pub format: OutputFormat,
+    /// Encoder quality for jpg and webp (1-100)
+    pub quality: u8,
+    /// Rotate crops so the eyes are horizontal
+    pub align: bool,
+    /// Padding around the detected box, as a fraction of its size
+    pub padding: f32,
+    /// Margins on each side of the detected box instead of the even padding; the crop
+    /// is then the square covering the box with its margins
+    pub margins: Option<Margins>,
+    /// Where the line between the eyes sits, as a fraction of the crop height from
+    /// the top; crops of faces with landmarks are then centered on the eyes instead
+    /// of the box, None to always center them on the box
+    pub eye_y: Option<f32>,
+    /// How pixels outside the image are filled, None to shrink the crop instead
+    pub pad_fill: Option<PadFill>,
+    /// Directory whose layout the crops mirror, None to save them all in the output directory
+    pub mirror_root: Option<PathBuf>,
+    /// Label faces with the name of their source image's directory and save them into
+    /// a subdirectory of that name, as in VGGFace or LFW style datasets
+    pub label_from_dir: bool,
+    /// Cap on the crops saved per label during this run (needs `label_from_dir`)
+    pub identity_cap: Option<IdentityCap>,
+    /// Crops per numbered subdirectory (00000/, 00001/, ...) by face index, None for one flat directory
+    pub shard_size: Option<usize>,
+    /// Range of face sizes to crop (px, shorter side of the box in the source image)
+    pub min_face_px: Option<u32>,
+    pub max_face_px: Option<u32>,
+    /// Drop crops less sharp than this
+    pub min_sharpness: Option<f64>,
+    /// Range of crop brightness to keep (mean luminance, 0-255)
+    pub min_brightness: Option<f64>,
+    pub max_brightness: Option<f64>,
+    /// Drop crops with less RMS contrast than this
+    pub min_contrast: Option<f64>,
+    /// Faces cropped per image (or frame) at most, the first ones by `rank` that pass the filters
+    pub max_faces: Option<usize>,
+    /// Order faces are kept in under `max_faces`
+    pub rank: FaceRank,
+    /// Hashes of the faces saved during this run, when skipping near-duplicates
+    pub dedupe: Option<PhashIndex>,
+    /// Age and gender estimation for every crop
+    #[cfg(feature = "onnx")]
+    pub age_gender: Option<AgeGenderEstimator>,
+    /// Keep only faces estimated at least this old (needs `age_gender`)
+    pub min_age: Option<u32>,
+    /// Keep only faces of this estimated gender (needs `age_gender`)
+    pub gender: Option<Gender>,
+    /// Expression classification for every crop
+    #[cfg(feature = "onnx")]
+    pub expression: Option<ExpressionClassifier>,
+    /// Keep only faces with one of these expressions, empty for all (needs `expression`)
+    pub expressions: Vec<Expression>,
+    /// NSFW scoring of crops or whole images, see `nsfw_scope`
+    #[cfg(feature = "onnx")]
+    pub nsfw: Option<NsfwClassifier>,
+    /// NSFW score (0-1) above which crops or images are rejected
+    pub nsfw_threshold: f32,
+    /// Whether the NSFW filter scores each crop or the whole image
+    pub nsfw_scope: NsfwScope,
+    /// Skip writing crops entirely
+    pub skip: bool,
+}
+
+impl Default for CropOptions {
+    fn default() -> Self {
+        Self {
+            size: 128,
+            sizes: Vec::new(),
+            filter: ResizeFilter::Lanczos3,
+            #[cfg(feature = "onnx")]
+            upscaler: None,
+            format: OutputFormat::Jpg,
+            quality: 75,
+            align: false,
+            padding: 0.5,
+            margins: None,
+            eye_y: None,
+            pad_fill: None,
+            mirror_root: None,
+            label_from_dir: false,
+            identity_cap: None,
+            shard_size: None,
+            min_face_px: None,
+            max_face_px: None,
+            min_sharpness: None,
+            min_brightness: None,
+            max_brightness: None,
+            min_contrast: None,
+            max_faces: None,
+            rank: FaceRank::Area,
+            dedupe: None,
+            #[cfg(feature = "onnx")]
+            age_gender: None,
+            min_age: None,
+            gender: None,
+            #[cfg(feature = "onnx")]
+            expression: None,
+            expressions: Vec::new(),
+            #[cfg(feature = "onnx")]
+            nsfw: None,
+            nsfw_threshold: 0.8,
+            nsfw_scope: NsfwScope::Crop,
+            skip: false,
+        }
+    }
+}
+
+/// Crops saved per identity label, so no identity gets more than a maximum
+#[derive(Debug)]
+pub struct IdentityCap {
+    max: usize,
+    counts: Mutex<HashMap<String, usize>>,
+}
+
+impl IdentityCap {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a crop of `label` unless the label has reached the maximum, returns whether it was counted
+    pub fn admit(&self, label: &str) -> bool {
+        let mut counts = self.counts.lock().expect("identity cap lock poisoned");
+        let count = counts.entry(label.to_string()).or_insert(0);
+        if *count >= self.max {
+            return false;
+        }
+
+        *count += 1;
+        true
+    }
+
+    /// Give back a crop of `label` counted by [`admit`](Self::admit) that wasn't saved
+    pub fn release(&self, label: &str) {
+        let mut counts = self.counts.lock().expect("identity cap lock poisoned");
+        if let Some(count) = counts.get_mut(label) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+/// A face cut out of its source image, resized to the output size
+#[derive(Debug)]
+pub struct FaceCrop {
+    /// The resized crop
+    pub image: DynamicImage,
+    /// Region cut out of the source image: [x, y, width, height]
+    pub rect: [i32; 4],
+    /// Rotation applied around the region center (radians)
+    pub angle: f32,
+    /// Landmarks in the coordinate frame of the crop
+    pub landmarks: Option<Landmarks>,
+}
+
+/// Crop, resize and save the detected faces of an image, or of one frame of an
+/// animated image
+pub fn save_faces(
+    path: &Path,
+    frame: Option<u32>,
+    img: &DynamicImage,
+    faces: Vec<FaceBox>,
+    output_dir: &Path,
+    crop: &CropOptions,
+    face_counter: &AtomicUsize
+) -> Result<ProcessedImage> {
+    // A failed image leaves no crops behind, none of them get a manifest entry
+    let written = std::cell::RefCell::new(Vec::new());
+    let result = save_faces_with(path, frame, img, faces, crop, face_counter, |relative, data| {
+        let output_path = output_dir.join(relative);
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent).map_err(|source| Error::Io {
+                context: format!("Failed to create directory: {:?}", parent),
+                source,
+            })?;
+        }
+
+        write_atomic(&output_path, &data).map_err(|source| Error::Io {
+            context: format!("Failed to save cropped face to: {:?}", output_path),
+            source,
+        })?;
+        written.borrow_mut().push(output_path);
+        Ok(())
+    });
+
+    if result.is_err() {
+        for output_path in written.into_inner() {
+            let _ = fs::remove_file(output_path);
+        }
+    }
+    result
+}
+
+/// Write a file through a temporary file next to it, so that it never exists half written
+pub fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{}.tmp", name));
+
+    let result = fs::write(&temp_path, data).and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Crop and resize the detected faces of an image like [`save_faces`], handing
+/// each encoded crop with its output path to `write`
+pub fn save_faces_with(
+    path: &Path,
+    frame: Option<u32>,
+    img: &DynamicImage,
+    faces: Vec<FaceBox>,
+    crop: &CropOptions,
+    face_counter: &AtomicUsize,
+    write: impl Fn(&Path, Vec<u8>) -> Result<()>
+) -> Result<ProcessedImage> {
+    // Rejected images are dropped before anything of them is written
+    if image_rejected(path, img, crop)? {
+        return Ok(ProcessedImage {
+            width: img.width(),
+            height: img.height(),
+            faces: Vec::new(),
+            entries: Vec::new(),
+            rejected: true,
+            source_sha256: None,
+        });
+    }
+
+    // Detection-only runs keep the faces but save nothing
+    if crop.skip {
+        return Ok(ProcessedImage {
+            width: img.width(),
+            height: img.height(),
+            faces,
+            entries: Vec::new(),
+            rejected: false,
+            source_sha256: None,
+        });
+    }
+
+    // Labelled crops go to a directory named after the label, mirrored ones to the
+    // source image's subdirectory
+    let label = crop
+        .label_from_dir
+        .then(|| path.parent().and_then(Path::file_name))
+        .flatten()
+        .map(|name| name.to_string_lossy().into_owned());
+    let relative_dir = match &label {
+        Some(label) => Path::new(label),
+        None => crop
+            .mirror_root
+            .as_deref()
+            .and_then(|root| path.strip_prefix(root).ok())
+            .and_then(Path::parent)
+            .unwrap_or(Path::new("")),
+    };
+
+    // With a limit the best ranked faces are cropped first, so they are the ones kept
+    let mut order: Vec<&FaceBox> = faces.iter().collect();
+    if crop.max_faces.is_some() {
+        match crop.rank {
+            FaceRank::Area => order.sort_by_key(|face| std::cmp::Reverse(face.area())),
+            FaceRank::Confidence => order.sort_by(|a, b| {
+                b.confidence.total_cmp(&a.confidence).then(b.area().cmp(&a.area()))
+            }),
+        }
+    }
+
+    // Process each detected face
+    let mut entries = Vec::new();
+    let mut cropped = 0;
+    let mut claims = Claims {
+        cap: crop.identity_cap.as_ref().zip(label.as_deref()),
+        dedupe: crop.dedupe.as_ref(),
+        hashes: Vec::new(),
+        kept: false,
+    };
+
+    for face in order {
+        if crop.max_faces.is_some_and(|max| cropped >= max) {
+            debug!("Reached the face limit for {:?}, skipping its other faces", path);
+            break;
+        }
+        // The identity's slot is taken before the filters, so a face of a full identity
+        // isn't recorded for --dedupe-phash, and given back if the face isn't saved
+        if !claims.admit() {
+            debug!("Identity {:?} has reached its crop limit, skipping face in {:?}", label, path);
+            continue;
+        }
+        let Some(SelectedCrop { crop: face_crop, sharpness, exposure, attributes, phash }) = select_crop(path, img, face, crop)?
+        else {
+            claims.reject();
+            continue;
+        };
+        claims.record(phash);
+        cropped += 1;
+
+        // Generate output filename with face index, confidence and frame
+        let face_index = face_counter.fetch_add(1, Ordering::SeqCst);
+        let filename = format!(
+            "face_{:06}_{:.3}{}.{}",
+            face_index,
+            face.confidence,
+            frame.map(|frame| format!("_frame{:04}", frame)).unwrap_or_default(),
+            crop.format.extension()
+        );
+        let output_path = match crop.shard_size {
+            Some(shard_size) => relative_dir.join(format!("{:05}", face_index / shard_size.max(1))).join(&filename),
+            None => relative_dir.join(&filename),
+        };
+
+        // With several sizes each is cut again (reusing the crop the filters saw)
+        // and saved under its own subdirectory, every file getting its own entry
+        let sized_crops: Vec<(PathBuf, FaceCrop)> = if crop.sizes.is_empty() {
+            vec![(output_path, face_crop)]
+        } else {
+            let mut filtered = Some(face_crop);
+            crop.sizes
+                .iter()
+                .filter_map(|&sized| {
+                    let face_crop = filtered
+                        .take_if(|filtered| filtered.image.width() == sized)
+                        .or_else(|| crop_face_at(img, face, crop, sized))?;
+                    Some((Path::new(&sized.to_string()).join(&output_path), face_crop))
+                })
+                .collect()
+        };
+
+        for (output_path, face_crop) in sized_crops {
+            // Save the cropped and resized face
+            write(&output_path, encode_crop(&face_crop.image, crop)?)?;
+
+            debug!("Saved face from {:?} to {:?}", path, output_path);
+
+            entries.push(ManifestEntry {
+                source: path.to_string_lossy().into_owned(),
+                label: label.clone(),
+                frame,
+                image_width: img.width(),
+                image_height: img.height(),
+                bbox: [face.x, face.y, face.width, face.height],
+                confidence: face.confidence,
+                landmarks: face.landmarks,
+                crop: face_crop.rect,
+                crop_angle: face_crop.angle.to_degrees(),
+                crop_landmarks: face_crop.landmarks,
+                sharpness,
+                brightness: exposure.brightness,
+                contrast: exposure.contrast,
+                output: output_path.to_string_lossy().into_owned(),
+                output_size: face_crop.image.width(),
+                attributes: attributes.clone(),
+            });
+        }
+    }
+    claims.kept = true;
+
+    Ok(ProcessedImage {
+        width: img.width(),
+        height: img.height(),
+        faces,
+        entries,
+        rejected: false,
+        source_sha256: None,
+    })
+}
+
+/// Identity slots and --dedupe-phash hashes taken by the faces of one image, given
+/// back unless the image was saved, so its removed crops don't count
+struct Claims<'a> {
+    cap: Option<(&'a IdentityCap, &'a str)>,
+    dedupe: Option<&'a PhashIndex>,
+    /// --dedupe-phash hash of every face holding a slot, in order
+    hashes: Vec<Option<u64>>,
+    kept: bool,
+}
+
+impl Claims<'_> {
+    /// Take a slot of the identity for the next face, returns whether it had one left
+    fn admit(&mut self) -> bool {
+        let admitted = self.cap.is_none_or(|(cap, label)| cap.admit(label));
+        if admitted {
+            self.hashes.push(None);
+        }
+        admitted
+    }
+
+    /// Record the hash of the face admitted last, once it passed the filters
+    fn record(&mut self, phash: Option<u64>) {
+        if let Some(last) = self.hashes.last_mut() {
+            *last = phash;
+        }
+    }
+
+    /// Give back the slot of the face admitted last, it didn't pass the filters
+    fn reject(&mut self) {
+        if let Some(hash) = self.hashes.pop() {
+            self.release(hash);
+        }
+    }
+
+    fn release(&self, hash: Option<u64>) {
+        if let Some((cap, label)) = self.cap {
+            cap.release(label);
+        }
+        if let Some((dedupe, hash)) = self.dedupe.zip(hash) {
+            dedupe.remove(hash);
+        }
+    }
+}
+
+impl Drop for Claims<'_> {
+    fn drop(&mut self) {
+        if !self.kept {
+            for &hash in &self.hashes {
+                self.release(hash);
+            }
+        }
+    }
+}
+
+/// A crop that passed the filters of the crop options
+pub(crate) struct SelectedCrop {
+    pub crop: FaceCrop,
+    pub sharpness: f64,
+    pub exposure: Exposure,
+    pub attributes: FaceAttributes,
+    /// Hash recorded for --dedupe-phash
+    pub phash: Option<u64>,
+}
+
+/// Crop a face unless the crop options filter it out (size, sharpness, exposure,
+/// attributes, duplicates), returning the crop with its sharpness, exposure and
+/// estimated attributes
+pub(crate) fn select_crop(
+    path: &Path,
+    img: &DynamicImage,
+    face: &FaceBox,
+    crop: &CropOptions
+) -> Result<Option<SelectedCrop>> {
+    // Sizes are checked in the source image, upscaled tiny detections make useless crops
+    let face_px = face.width.min(face.height).max(0) as u32;
+    if crop.min_face_px.is_some_and(|min| face_px < min) || crop.max_face_px.is_some_and(|max| face_px > max) {
+        debug!("Skipping {}px face in {:?}", face_px, path);
+        return Ok(None);
+    }
+
+    let Some(face_crop) = crop_face(img, face, crop) else {
+        return Ok(None);
+    };
+
+    let sharpness = quality::sharpness(&face_crop.image);
+    if let Some(min_sharpness) = crop.min_sharpness
+        && sharpness < min_sharpness
+    {
+        debug!("Dropping blurry face in {:?} (sharpness {:.1})", path, sharpness);
+        return Ok(None);
+    }
+
+    let exposure = quality::exposure(&face_crop.image);
+    if crop.min_brightness.is_some_and(|min| exposure.brightness < min)
+        || crop.max_brightness.is_some_and(|max| exposure.brightness > max)
+        || crop.min_contrast.is_some_and(|min| exposure.contrast < min)
+    {
+        debug!(
+            "Dropping badly exposed face in {:?} (brightness {:.1}, contrast {:.1})",
+            path, exposure.brightness, exposure.contrast
+        );
+        return Ok(None);
+    }
+
+    let attributes = estimate_attributes(&face_crop, crop)?;
+    if crop.min_age.is_some_and(|min| attributes.age.is_none_or(|age| age < min))
+        || crop.gender.is_some_and(|gender| attributes.gender != Some(gender))
+        || (!crop.expressions.is_empty()
+            && !attributes.expression.is_some_and(|expression| crop.expressions.contains(&expression)))
+        || attributes.nsfw_score.is_some_and(|score| score > crop.nsfw_threshold)
+    {
+        debug!("Skipping face in {:?} filtered by its attributes ({:?})", path, attributes);
+        return Ok(None);
+    }
+
+    let phash = crop.dedupe.as_ref().map(|_| quality::phash(&face_crop.image));
+    if let Some((dedupe, hash)) = crop.dedupe.as_ref().zip(phash)
+        && !dedupe.insert(hash)
+    {
+        debug!("Skipping near-duplicate face in {:?}", path);
+        return Ok(None);
+    }
+
+    if crop.align && face.landmarks.is_none() {
+        debug!("No landmarks for face in {:?}, saving it unaligned", path);
+    }
+
+    Ok(Some(SelectedCrop { crop: face_crop, sharpness, exposure, attributes, phash }))
+}
+
+/// Run the classification stages set in the crop options on a crop
+#[cfg(feature = "onnx")]
+fn estimate_attributes(face_crop: &FaceCrop, crop: &CropOptions) -> Result<FaceAttributes> {
+    let mut attributes = FaceAttributes::default();
+    if let Some(estimator) = &crop.age_gender {
+        let (age, gender) = estimator.estimate(&face_crop.image)?;
+        attributes.age = Some(age);
+        attributes.age_bucket = Some(attributes::age_bucket(age).to_string());
+        attributes.gender = Some(gender);
+    }
+    if let Some(classifier) = &crop.expression {
+        attributes.expression = Some(classifier.classify(&face_crop.image)?);
+    }
+    if let Some(classifier) = &crop.nsfw
+        && crop.nsfw_scope == NsfwScope::Crop
+    {
+        attributes.nsfw_score = Some(classifier.score(&face_crop.image)?);
+    }
+
+    Ok(attributes)
+}
+
+/// Whether the NSFW filter rejects a whole source image
+#[cfg(feature = "onnx")]
+pub(crate) fn image_rejected(path: &Path, img: &DynamicImage, crop: &CropOptions) -> Result<bool> {
+    let Some(classifier) = crop.nsfw.as_ref().filter(|_| crop.nsfw_scope == NsfwScope::Image) else {
+        return Ok(false);
+    };
+
+    let score = classifier.score(img)?;
+    if score > crop.nsfw_threshold {
+        log::info!("Rejecting {:?} as NSFW (score {:.2})", path, score);
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Without ONNX Runtime there is no NSFW filter
+#[cfg(not(feature = "onnx"))]
+pub(crate) fn image_rejected(_path: &Path, _img: &DynamicImage, _crop: &CropOptions) -> Result<bool> {
+    Ok(false)
+}
+
+/// Without ONNX Runtime there are no classification stages
+#[cfg(not(feature = "onnx"))]
+fn estimate_attributes(_face_crop: &FaceCrop, _crop: &CropOptions) -> Result<FaceAttributes> {
+    Ok(FaceAttributes::default())
+}
+
+/// Encode a crop in the configured output format
+pub fn encode_crop(img: &DynamicImage, crop: &CropOptions) -> Result<Vec<u8>> {
+    let format = match crop.format {
+        OutputFormat::Jpg => ImageOutputFormat::Jpeg(crop.quality),
+        OutputFormat::Png => ImageOutputFormat::Png,
+        OutputFormat::Webp => {
+            // libwebp only takes RGB(A)
+            let (width, height) = (img.width(), img.height());
+            let data = match img {
+                DynamicImage::ImageRgba8(rgba) => {
+                    webp_encode(webp::Encoder::from_rgba(rgba, width, height), crop.quality)
+                }
+                _ => webp_encode(webp::Encoder::from_rgb(&img.to_rgb8(), width, height), crop.quality),
+            };
+            return Ok(data);
+        }
+    };
+
+    let mut buffer = Cursor::new(Vec::new());
+    img.write_to(&mut buffer, format).map_err(|source| Error::Image {
+        context: "Failed to encode crop".to_string(),
+        source,
+    })?;
+    Ok(buffer.into_inner())
+}
+
+/// Run the WebP encoder, lossless at quality 100
+fn webp_encode(encoder: webp::Encoder, quality: u8) -> Vec<u8> {
+    let encoded = if quality >= 100 {
+        encoder.encode_lossless()
+    } else {
+        encoder.encode(f32::from(quality))
+    };
+
+    encoded.to_vec()
+}
+
+/// Cut a padded square crop around a face and resize it to the output size (unless that is 0)
+///
+/// Returns `None` when the face lies entirely outside the image.
+pub fn crop_face(img: &DynamicImage, face: &FaceBox, crop: &CropOptions) -> Option<FaceCrop> {
+    crop_face_at(img, face, crop, crop.size)
+}
+
+/// Cut the crop of [`crop_face`] but resize it to `size` instead of the configured size
+fn crop_face_at(img: &DynamicImage, face: &FaceBox, crop: &CropOptions, size: u32) -> Option<FaceCrop> {
+    // Rotate around the crop center so the eyes end up horizontal
+    let angle = match (crop.align, face.landmarks) {
+        (true, Some(landmarks)) => eye_angle(&landmarks),
+        _ => 0.0,
+    };
+
+    // Crop face with some padding, keeping the region inside the image unless it's filled in
+    let region = match crop.margins {
+        Some(margins) => face
+            .expand_sides(margins.left, margins.top, margins.right, margins.bottom)
+            .to_outer_square(),
+        None => face.expand(crop.padding),
+    };
+    let region = match (crop.eye_y, face.landmarks) {
+        (Some(eye_y), Some(landmarks)) => eye_square(&region, &landmarks, eye_y, angle),
+        _ => region,
+    };
+    let region = if crop.pad_fill.is_some() {
+        region
+    } else {
+        region.clamp_to(img.width(), img.height())
+    };
+
+    // Ensure we have a valid crop region
+    if region.area() == 0 {
+        return None;
+    }
+
+    // Get square crop (use the smaller dimension)
+    let square = region.to_square();
+    let (x_crop, y_crop, size_to_use) = (square.x, square.y, square.width);
+
+    let half = size_to_use as f32 / 2.0;
+    let crop_center = (x_crop as f32 + half, y_crop as f32 + half);
+
+    // Create the crop
+    let fill = crop.pad_fill.unwrap_or(PadFill::Black);
+    let inside = x_crop >= 0
+        && y_crop >= 0
+        && x_crop + size_to_use <= img.width() as i32
+        && y_crop + size_to_use <= img.height() as i32;
+    let cropped = if angle != 0.0 {
+        rotated_crop(img, crop_center, size_to_use as u32, angle, fill)
+    } else if !inside {
+        padded_crop(img, x_crop, y_crop, size_to_use as u32, fill)
+    } else {
+        img.crop_imm(
+            x_crop as u32,
+            y_crop as u32,
+            size_to_use as u32,
+            size_to_use as u32
+        )
+    };
+
+    // Small faces go through the super-resolution model first, when there is one
+    #[cfg(feature = "onnx")]
+    let cropped = match &crop.upscaler {
+        Some(upscaler) if size > size_to_use as u32 => upscaler.upscale(&cropped).unwrap_or_else(|err| {
+            log::warn!("Super-resolution failed, resampling the crop instead: {}", err);
+            cropped
+        }),
+        _ => cropped,
+    };
+
+    // Resize to the requested size, size 0 keeps the native resolution
+    let (resized, crop_scale) = if size == 0 {
+        (cropped, 1.0)
+    } else {
+        (resize_crop(cropped, size, crop.filter)?, size as f32 / size_to_use as f32)
+    };
+
+    // Map landmarks into the coordinate frame of the saved crop
+    let (sin, cos) = angle.sin_cos();
+    let crop_landmarks = face.landmarks.map(|points| {
+        points.map(|(lx, ly)| {
+            let (dx, dy) = (lx - crop_center.0, ly - crop_center.1);
+            (
+                (dx * cos + dy * sin + half) * crop_scale,
+                (dy * cos - dx * sin + half) * crop_scale,
+            )
+        })
+    });
+
+    Some(FaceCrop {
+        image: resized,
+        rect: [x_crop, y_crop, size_to_use, size_to_use],
+        angle,
+        landmarks: crop_landmarks,
+    })
+}
+
+/// Resize a crop to a `size` square with fast_image_resize (SIMD)
+///
+/// Gray, RGB and RGBA crops keep their pixel type, others become RGB.
+fn resize_crop(img: DynamicImage, size: u32, filter: ResizeFilter) -> Option<DynamicImage> {
+    use fast_image_resize::{images::Image, FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer};
+
+    let (width, height) = (img.width(), img.height());
+    let (pixel_type, data) = match img {
+        DynamicImage::ImageLuma8(gray) => (PixelType::U8, gray.into_raw()),
+        DynamicImage::ImageRgba8(rgba) => (PixelType::U8x4, rgba.into_raw()),
+        other => (PixelType::U8x3, other.into_rgb8().into_raw()),
+    };
+    let algorithm = match filter {
+        ResizeFilter::Lanczos3 => ResizeAlg::Convolution(FilterType::Lanczos3),
+        ResizeFilter::Bilinear => ResizeAlg::Convolution(FilterType::Bilinear),
+        ResizeFilter::Box => ResizeAlg::Convolution(FilterType::Box),
+        ResizeFilter::Nearest => ResizeAlg::Nearest,
+    };
+
+    let source = Image::from_vec_u8(width, height, data, pixel_type).ok()?;
+    let mut resized = Image::new(size, size, pixel_type);
+    Resizer::new()
+        .resize(&source, &mut resized, &ResizeOptions::new().resize_alg(algorithm))
+        .ok()?;
+
+    let data = resized.into_vec();
+    match pixel_type {
+        PixelType::U8 => image::GrayImage::from_raw(size, size, data).map(DynamicImage::ImageLuma8),
+        PixelType::U8x4 => image::RgbaImage::from_raw(size, size, data).map(DynamicImage::ImageRgba8),
+        _ => RgbImage::from_raw(size, size, data).map(DynamicImage::ImageRgb8),
+    }
+}
+
+/// Square of the shorter side of `region`, placed so the midpoint between the eyes
+/// is centered horizontally and `eye_y` of the way down the crop once it is
+/// rotated by `angle`
+fn eye_square(region: &FaceBox, landmarks: &Landmarks, eye_y: f32, angle: f32) -> FaceBox {
+    let side = region.width.min(region.height);
+    let eyes = (
+        (landmarks[0].0 + landmarks[1].0) / 2.0,
+        (landmarks[0].1 + landmarks[1].1) / 2.0,
+    );
+
+    // The crop center lies below the eyes, along the face's vertical axis
+    let below = (0.5 - eye_y) * side as f32;
+    let (sin, cos) = angle.sin_cos();
+    let half = side as f32 / 2.0;
+
+    FaceBox {
+        x: (eyes.0 - below * sin - half).round() as i32,
+        y: (eyes.1 + below * cos - half).round() as i32,
+        width: side,
+        height: side,
+        ..region.clone()
+    }
+}
+
+/// Angle (radians) of the line from the left eye to the right eye
+fn eye_angle(landmarks: &Landmarks) -> f32 {
+    let (left_x, left_y) = landmarks[0];
+    let (right_x, right_y) = landmarks[1];
+    (right_y - left_y).atan2(right_x - left_x)
+}
+
+/// Cut a square reaching past the image border out of the image, filling the outside pixels
+fn padded_crop(img: &DynamicImage, x: i32, y: i32, side: u32, fill: PadFill) -> DynamicImage {
+    let (xs, ys) = (x as i64..x as i64 + side as i64, y as i64..y as i64 + side as i64);
+    let window = Window::new(img, xs, ys, fill);
+
+    let padded = RgbImage::from_fn(side, side, |u, v| {
+        window
+            .as_ref()
+            .and_then(|window| window.pixel(x as i64 + u as i64, y as i64 + v as i64))
+            .unwrap_or(Rgb([0, 0, 0]))
+    });
+
+    DynamicImage::ImageRgb8(padded)
+}
+
+/// Cut a square of side `side` centered on `center` out of the image, rotated by
+/// `angle` radians so that a line at that angle in the source becomes horizontal
+fn rotated_crop(img: &DynamicImage, center: (f32, f32), side: u32, angle: f32, fill: PadFill) -> DynamicImage {
+    let (sin, cos) = angle.sin_cos();
+    let half = side as f32 / 2.0;
+
+    // The rotated square lies within this distance of its center, bilinear
+    // sampling reads one more pixel on each side
+    let reach = half * (sin.abs() + cos.abs()) + 1.5;
+    let span = |center: f32| (center - reach).floor() as i64..(center + reach).ceil() as i64 + 1;
+    let window = Window::new(img, span(center.0), span(center.1), fill);
+
+    let rotated = RgbImage::from_fn(side, side, |u, v| {
+        let dx = u as f32 + 0.5 - half;
+        let dy = v as f32 + 0.5 - half;
+        bilinear(center.0 + dx * cos - dy * sin, center.1 + dx * sin + dy * cos, |px, py| {
+            window.as_ref().and_then(|window| window.pixel(px, py))
+        })
+    });
+
+    DynamicImage::ImageRgb8(rotated)
+}
+
+/// The source pixels a crop reads, copied as RGB so a crop doesn't convert the
+/// whole image
+struct Window {
+    pixels: RgbImage,
+    /// Position of the copied pixels in the image
+    x: u32,
+    y: u32,
+    image_width: u32,
+    image_height: u32,
+    fill: PadFill,
+}
+
+impl Window {
+    /// Copy the pixels that positions in `xs`x`ys` read with the fill, None if
+    /// the fill makes all of them black
+    fn new(img: &DynamicImage, xs: std::ops::Range<i64>, ys: std::ops::Range<i64>, fill: PadFill) -> Option<Self> {
+        let (x1, x2) = Self::span(xs, img.width(), fill)?;
+        let (y1, y2) = Self::span(ys, img.height(), fill)?;
+
+        Some(Self {
+            pixels: img.crop_imm(x1, y1, x2 - x1, y2 - y1).to_rgb8(),
+            x: x1,
+            y: y1,
+            image_width: img.width(),
+            image_height: img.height(),
+            fill,
+        })
+    }
+
+    /// First and one past the last source coordinate positions in `range` read
+    /// on an axis of `len` pixels
+    fn span(range: std::ops::Range<i64>, len: u32, fill: PadFill) -> Option<(u32, u32)> {
+        range
+            .filter_map(|v| fill.coord(v, len))
+            .fold(None, |span, coord| match span {
+                None => Some((coord, coord + 1)),
+                Some((first, end)) => Some((first.min(coord), end.max(coord + 1))),
+            })
+    }
+
+    /// The pixel at an image position, None where the fill is black
+    fn pixel(&self, x: i64, y: i64) -> Option<Rgb<u8>> {
+        let px = self.fill.coord(x, self.image_width)?;
+        let py = self.fill.coord(y, self.image_height)?;
+        self.pixels.get_pixel_checked(px.checked_sub(self.x)?, py.checked_sub(self.y)?).copied()
+    }
+}
+
+/// Bilinearly sample an image at a sub-pixel position, outside pixels are filled per `fill`
+pub(crate) fn sample_bilinear(img: &RgbImage, x: f32, y: f32, fill: PadFill) -> Rgb<u8> {
+    bilinear(x, y, |px, py| {
+        Some(*img.get_pixel(fill.coord(px, img.width())?, fill.coord(py, img.height())?))
+    })
+}
+
+/// Bilinearly interpolate the pixels `pixel` returns around a sub-pixel position,
+/// None counting as black
+fn bilinear(x: f32, y: f32, pixel: impl Fn(i64, i64) -> Option<Rgb<u8>>) -> Rgb<u8> {
+    // Pixel centers sit at +0.5
+    let x = x - 0.5;
+    let y = y - 0.5;
+    let x0 = x.floor();
+    let y0 = y.floor();
+    let fx = x - x0;
+    let fy = y - y0;
+
+    let pixel = |px: f32, py: f32| -> [f32; 3] {
+        pixel(px as i64, py as i64).map_or([0.0; 3], |p| [f32::from(p[0]), f32::from(p[1]), f32::from(p[2])])
+    };
+
+    let top_left = pixel(x0, y0);
+    let top_right = pixel(x0 + 1.0, y0);
+    let bottom_left = pixel(x0, y0 + 1.0);
+    let bottom_right = pixel(x0 + 1.0, y0 + 1.0);
+
+    let mut out = [0u8; 3];
+    for channel in 0..3 {
+        let top = top_left[channel] * (1.0 - fx) + top_right[channel] * fx;
+        let bottom = bottom_left[channel] * (1.0 - fx) + bottom_right[channel] * fx;
+        out[channel] = (top * (1.0 - fy) + bottom * fy).round().clamp(0.0, 255.0) as u8;
+    }
+
+    Rgb(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An image with a different color at every pixel
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| Rgb([(x * 7) as u8, (y * 5) as u8, ((x + y) * 3) as u8])))
+    }
+
+    #[test]
+    fn failed_image_gives_back_its_identity_slots_and_hashes() {
+        let img = gradient(100, 100);
+        let faces = vec![FaceBox { x: 20, y: 20, width: 40, height: 40, confidence: 0.9, landmarks: None }];
+        let crop = CropOptions {
+            label_from_dir: true,
+            identity_cap: Some(IdentityCap::new(1)),
+            dedupe: Some(PhashIndex::new(4)),
+            ..CropOptions::default()
+        };
+        let counter = AtomicUsize::new(0);
+        let path = Path::new("alice/photo.png");
+        let save = |write: &dyn Fn(&Path, Vec<u8>) -> Result<()>| {
+            save_faces_with(path, None, &img, faces.clone(), &crop, &counter, write)
+        };
+
+        let failed = save(&|_, _| Err(Error::Storage("disk full".to_string())));
+        assert!(failed.is_err());
+        // Retrying the image saves its face, neither the cap nor the hash of the failed attempt counts
+        let saved = save(&|_, _| Ok(())).unwrap();
+        assert_eq!(saved.entries.len(), 1);
+        // Now the identity is full and the face is known
+        assert!(save(&|_, _| Ok(())).unwrap().entries.is_empty());
+        assert!(!crop.identity_cap.as_ref().unwrap().admit("alice"));
+    }
+
+    #[test]
+    fn padded_crop_matches_sampling_the_whole_image() {
+        let img = gradient(40, 30);
+        let source = img.to_rgb8();
+        for fill in [PadFill::Black, PadFill::Clamp, PadFill::Reflect] {
+            for (x, y, side) in [(-10, -5, 25), (30, 20, 20), (-50, -50, 20), (-60, 10, 100)] {
+                let padded = padded_crop(&img, x, y, side, fill).to_rgb8();
+                for (u, v, pixel) in padded.enumerate_pixels() {
+                    let px = fill.coord(x as i64 + u as i64, source.width());
+                    let py = fill.coord(y as i64 + v as i64, source.height());
+                    let expected = px.zip(py).map_or(Rgb([0, 0, 0]), |(px, py)| *source.get_pixel(px, py));
+                    assert_eq!(*pixel, expected, "{:?} at ({}, {}) of ({}, {}, {})", fill, u, v, x, y, side);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rotated_crop_matches_sampling_the_whole_image() {
+        let img = gradient(40, 30);
+        let source = img.to_rgb8();
+        for fill in [PadFill::Black, PadFill::Clamp, PadFill::Reflect] {
+            for (center, side, angle) in [((20.0, 15.0), 10, 0.3), ((2.0, 3.0), 16, -0.7), ((38.5, 29.0), 24, 1.2), ((-20.0, 15.0), 8, 0.5)] {
+                let rotated = rotated_crop(&img, center, side, angle, fill).to_rgb8();
+                let (sin, cos) = angle.sin_cos();
+                let half = side as f32 / 2.0;
+                for (u, v, pixel) in rotated.enumerate_pixels() {
+                    let (dx, dy) = (u as f32 + 0.5 - half, v as f32 + 0.5 - half);
+                    let expected = sample_bilinear(&source, center.0 + dx * cos - dy * sin, center.1 + dx * sin + dy * cos, fill);
+                    assert_eq!(*pixel, expected, "{:?} at ({}, {}) around {:?}", fill, u, v, center);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use anyhow::{Context, Result};
+use face_cropper::{ExtractionSummary, ProcessedImage};
+use rusqlite::{Connection, params};
+use std::path::Path;
+
+/// Tables of the metadata database, created on first use
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    started_at TEXT NOT NULL,
+    finished_at TEXT,
+    input TEXT NOT NULL,
+    output TEXT NOT NULL,
+    detector TEXT NOT NULL,
+    threshold REAL NOT NULL,
+    images INTEGER,
+    failed INTEGER,
+    next_face_index INTEGER,
+    elapsed_secs REAL
+);
+CREATE TABLE IF NOT EXISTS images (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    path TEXT NOT NULL,
+    width INTEGER,
+    height INTEGER,
+    error TEXT
+);
+CREATE INDEX IF NOT EXISTS images_path ON images(path);
+CREATE TABLE IF NOT EXISTS detections (
+    id INTEGER PRIMARY KEY,
+    image_id INTEGER NOT NULL REFERENCES images(id),
+    x INTEGER NOT NULL,
+    y INTEGER NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    confidence REAL NOT NULL,
+    landmarks TEXT
+);
+CREATE TABLE IF NOT EXISTS crops (
+    id INTEGER PRIMARY KEY,
+    detection_id INTEGER REFERENCES detections(id),
+    output TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    crop_x INTEGER NOT NULL,
+    crop_y INTEGER NOT NULL,
+    crop_width INTEGER NOT NULL,
+    crop_height INTEGER NOT NULL,
+    crop_angle REAL NOT NULL,
+    sharpness REAL NOT NULL,
+    attributes TEXT
+);
+";
+
+/// What a run was started with, recorded in its `runs` row
+pub struct RunInfo<'a> {
+    pub input: &'a Path,
+    pub output: &'a Path,
+    pub detector: &'a str,
+    pub threshold: f32,
+}
+
+/// Records a run's images, detections and crops in a SQLite database
+///
+/// Rows are written in one transaction per batch, committed together with the
+/// manifest and checkpoint.
+pub struct Database {
+    conn: Connection,
+    run_id: i64,
+}
+
+impl Database {
+    /// Open or create the database and start a new run in it
+    pub fn open(path: &Path, run: &RunInfo) -> Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open database: {:?}", path))?;
+        conn.execute_batch(SCHEMA)
+            .with_context(|| format!("Failed to create tables in: {:?}", path))?;
+
+        conn.execute(
+            "INSERT INTO runs (started_at, input, output, detector, threshold) VALUES (datetime('now'), ?1, ?2, ?3, ?4)",
+            params![
+                run.input.to_string_lossy(),
+                run.output.to_string_lossy(),
+                run.detector,
+                run.threshold
+            ],
+        )?;
+        let run_id = conn.last_insert_rowid();
+        conn.execute_batch("BEGIN")?;
+
+        Ok(Self { conn, run_id })
+    }
+
+    /// Record an image with its detections and saved crops, or the error it failed with
+    pub fn record_image(&mut self, path: &Path, result: std::result::Result<&ProcessedImage, String>) -> Result<()> {
+        let processed = match result {
+            Ok(processed) => processed,
+            Err(error) => {
+                self.conn.execute(
+                    "INSERT INTO images (run_id, path, error) VALUES (?1, ?2, ?3)",
+                    params![self.run_id, path.to_string_lossy(), error],
+                )?;
+                return Ok(());
+            }
+        };
+
+        self.conn.execute(
+            "INSERT INTO images (run_id, path, width, height) VALUES (?1, ?2, ?3, ?4)",
+            params![self.run_id, path.to_string_lossy(), processed.width, processed.height],
+        )?;
+        let image_id = self.conn.last_insert_rowid();
+
+        let mut detection_ids = Vec::with_capacity(processed.faces.len());
+        for face in &processed.faces {
+            let landmarks = face.landmarks.map(|points| serde_json::to_string(&points)).transpose()?;
+            self.conn.execute(
+                "INSERT INTO detections (image_id, x, y, width, height, confidence, landmarks)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![image_id, face.x, face.y, face.width, face.height, face.confidence, landmarks],
+            )?;
+            detection_ids.push(([face.x, face.y, face.width, face.height], self.conn.last_insert_rowid()));
+        }
+
+        for entry in &processed.entries {
+            // A crop belongs to the detection with its box
+            let detection_id = detection_ids
+                .iter()
+                .find(|(bbox, _)| *bbox == entry.bbox)
+                .map(|(_, id)| *id);
+            let attributes = serde_json::to_value(&entry.attributes)?;
+            let attributes = attributes
+                .as_object()
+                .is_some_and(|fields| !fields.is_empty())
+                .then(|| attributes.to_string());
+
+            let [crop_x, crop_y, crop_width, crop_height] = entry.crop;
+            self.conn.execute(
+                "INSERT INTO crops (detection_id, output, size, crop_x, crop_y, crop_width, crop_height, crop_angle, sharpness, attributes)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    detection_id,
+                    entry.output,
+                    entry.output_size,
+                    crop_x,
+                    crop_y,
+                    crop_width,
+                    crop_height,
+                    entry.crop_angle,
+                    entry.sharpness,
+                    attributes
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Commit the rows of the finished batch
+    pub fn commit(&mut self) -> Result<()> {
+        self.conn
+            .execute_batch("COMMIT; BEGIN")
+            .context("Failed to write to the database")
+    }
+
+    /// Commit the last rows and record the totals of the run
+    pub fn finish(self, summary: &ExtractionSummary) -> Result<()> {
+        self.conn.execute(
+            "UPDATE runs SET finished_at = datetime('now'), images = ?2, failed = ?3, next_face_index = ?4, elapsed_secs = ?5
+             WHERE id = ?1",
+            params![
+                self.run_id,
+                summary.images as i64,
+                summary.failed as i64,
+                summary.next_face_index as i64,
+                summary.elapsed.as_secs_f64()
+            ],
+        )?;
+        self.conn
+            .execute_batch("COMMIT")
+            .context("Failed to write to the database")
+    }
+}
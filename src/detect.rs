@@ -0,0 +1,161 @@
+use crate::{init_detector, Args};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use face_cropper::{load_image, Annotator, DetectedBox, ImageDetections, Landmarks};
+use log::{error, info, warn};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+/// Arguments of the `detect` subcommand
+#[derive(clap::Args, Debug)]
+pub struct DetectArgs {
+    /// Image file, or directory to scan for images
+    pub input: PathBuf,
+
+    /// File to write the boxes to (default: stdout)
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// How boxes are written
+    #[clap(long, value_enum, default_value = "json")]
+    pub output_format: BoxFormat,
+}
+
+/// Output formats of the `detect` subcommand
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoxFormat {
+    /// One JSON object per image (JSON Lines)
+    Json,
+    /// One row per face, with a header
+    Csv,
+}
+
+/// Header of the CSV output, landmark columns are empty when the detector has none
+const CSV_HEADER: &str = "source,image_width,image_height,x,y,width,height,confidence,\
+    left_eye_x,left_eye_y,right_eye_x,right_eye_y,nose_x,nose_y,\
+    mouth_left_x,mouth_left_y,mouth_right_x,mouth_right_y";
+
+/// Detect faces and write their boxes without saving any crops
+pub fn detect(args: &Args, detect_args: &DetectArgs) -> Result<()> {
+    info!("Initializing face detector: {}", args.detector);
+    let mut detector = init_detector(args)?;
+    let load = args.load_options();
+    let annotator = args
+        .save_annotated
+        .as_deref()
+        .map(|dir| Annotator::new(dir, &detect_args.input))
+        .transpose()?;
+
+    let image_paths = args.find_images(&detect_args.input)?;
+    if image_paths.is_empty() {
+        warn!("No images found at {:?}", detect_args.input);
+        return Ok(());
+    }
+    info!("Found {} images", image_paths.len());
+
+    let mut output: BufWriter<Box<dyn Write>> = BufWriter::new(match &detect_args.output {
+        Some(path) => Box::new(
+            File::create(path).with_context(|| format!("Failed to create output file: {:?}", path))?,
+        ),
+        None => Box::new(io::stdout().lock()),
+    });
+
+    if detect_args.output_format == BoxFormat::Csv {
+        writeln!(output, "{}", CSV_HEADER)?;
+    }
+
+    let mut face_count = 0;
+    for path in &image_paths {
+        let img = match load_image(path, &load) {
+            Ok(img) => img,
+            Err(err) => {
+                error!("Failed to process {:?}: {}", path, err);
+                continue;
+            }
+        };
+        let faces = match detector.detect_faces(&img, args.threshold) {
+            Ok(faces) => faces,
+            Err(err) => {
+                error!("Failed to process {:?}: {}", path, err);
+                continue;
+            }
+        };
+        face_count += faces.len();
+
+        if let Some(annotator) = &annotator
+            && let Err(err) = annotator.save(path, &img, &faces)
+        {
+            error!("Failed to annotate {:?}: {}", path, err);
+        }
+
+        let source = path.to_string_lossy().into_owned();
+        match detect_args.output_format {
+            BoxFormat::Json => {
+                let detections = ImageDetections {
+                    source,
+                    width: img.width(),
+                    height: img.height(),
+                    faces: faces.iter().map(DetectedBox::from).collect(),
+                };
+                detections.write_jsonl(&mut output)?;
+            }
+            BoxFormat::Csv => {
+                for face in &faces {
+                    writeln!(
+                        output,
+                        "{},{},{},{},{},{},{},{},{}",
+                        csv_field(&source),
+                        img.width(),
+                        img.height(),
+                        face.x,
+                        face.y,
+                        face.width,
+                        face.height,
+                        face.confidence,
+                        landmark_columns(face.landmarks.as_ref())
+                    )?;
+                }
+            }
+        }
+    }
+
+    output.flush().context("Failed to write boxes")?;
+    info!("Detected {} faces in {} images", face_count, image_paths.len());
+
+    Ok(())
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// The ten landmark columns of a CSV row
+fn landmark_columns(landmarks: Option<&Landmarks>) -> String {
+    match landmarks {
+        Some(landmarks) => landmarks
+            .iter()
+            .map(|(x, y)| format!("{},{}", x, y))
+            .collect::<Vec<_>>()
+            .join(","),
+        None => ",".repeat(9),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_field_quotes_only_when_needed() {
+        assert_eq!(csv_field("photos/a.jpg"), "photos/a.jpg");
+        assert_eq!(csv_field("a,b.jpg"), "\"a,b.jpg\"");
+        assert_eq!(csv_field("say \"cheese\".jpg"), "\"say \"\"cheese\"\".jpg\"");
+        assert_eq!(csv_field("two\nlines.jpg"), "\"two\nlines.jpg\"");
+    }
+}
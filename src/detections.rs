@@ -0,0 +1,89 @@
+use crate::detector::{FaceBox, Landmarks};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+
+/// Faces detected in one image, a line of a detections file
+///
+/// Detections files are JSON Lines with one object per image, as the `detect`
+/// subcommand writes them and the `crop` subcommand reads them back:
+/// `{"source", "width", "height", "faces": [{"x", "y", "width", "height", "confidence", "landmarks"?}]}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageDetections {
+    /// Path of the source image
+    pub source: String,
+    /// Source image dimensions (px)
+    pub width: u32,
+    pub height: u32,
+    /// Faces in source image coordinates
+    pub faces: Vec<DetectedBox>,
+}
+
+/// A face box as stored in detections files and service responses
+///
+/// Serializable whether or not the `serde` feature derives it on [`FaceBox`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetectedBox {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub confidence: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub landmarks: Option<Landmarks>,
+}
+
+impl From<&FaceBox> for DetectedBox {
+    fn from(face: &FaceBox) -> Self {
+        Self {
+            x: face.x,
+            y: face.y,
+            width: face.width,
+            height: face.height,
+            confidence: face.confidence,
+            landmarks: face.landmarks,
+        }
+    }
+}
+
+impl From<&DetectedBox> for FaceBox {
+    fn from(face: &DetectedBox) -> Self {
+        Self {
+            x: face.x,
+            y: face.y,
+            width: face.width,
+            height: face.height,
+            confidence: face.confidence,
+            landmarks: face.landmarks,
+        }
+    }
+}
+
+impl ImageDetections {
+    /// Write the detections as one line of a detections file
+    pub fn write_jsonl(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        serde_json::to_writer(&mut *writer, self)?;
+        writeln!(writer)
+    }
+
+    /// Read every image of a detections file, skipping blank lines
+    pub fn read_jsonl(reader: impl BufRead) -> Result<Vec<Self>> {
+        let mut images = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.map_err(|source| Error::Io {
+                context: format!("Failed to read detections line {}", i + 1),
+                source,
+            })?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let detections = serde_json::from_str(&line).map_err(|err| Error::Io {
+                context: format!("Invalid detections on line {}", i + 1),
+                source: err.into(),
+            })?;
+            images.push(detections);
+        }
+
+        Ok(images)
+    }
+}
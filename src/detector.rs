@@ -1,136 +1,1102 @@
-use anyhow::{Context, Result};
-use image::DynamicImage;
+use image::{DynamicImage, Pixel};
 use rustface::{Detector, ImageData};
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, RwLock};
+
+#[cfg(feature = "blazeface")]
+mod blazeface;
+mod cloud;
+#[cfg(feature = "onnx")]
+mod mtcnn;
+mod mock;
+mod plugin;
+#[cfg(feature = "opencv")]
+mod yunet;
+mod downscaled;
+mod ensemble;
+mod roi;
+mod rotated;
+mod suppressed;
+mod tiled;
+mod tta;
+#[cfg(feature = "blazeface")]
+pub use blazeface::BlazeFaceDetector;
+pub use cloud::GoogleVisionDetector;
+#[cfg(feature = "rekognition")]
+pub use cloud::RekognitionDetector;
+pub use downscaled::DownscaledDetector;
+pub use ensemble::{EnsembleDetector, Fusion};
+#[cfg(feature = "onnx")]
+pub use mtcnn::MtcnnDetector;
+pub use mock::MockDetector;
+pub use plugin::{PluginDetector, PluginFace};
+pub use roi::{Roi, RoiDetector};
+pub use rotated::RotatedDetector;
+pub use suppressed::SuppressedDetector;
+pub use tiled::TiledDetector;
+pub use tta::TtaDetector;
+#[cfg(feature = "opencv")]
+pub use yunet::YuNetDetector;
+
+/// Why a detector couldn't be created or failed to run
+#[derive(Debug, thiserror::Error)]
+pub enum DetectorError {
+    /// A model file doesn't exist, `hint` says where to get it
+    #[error("Model not found at: {}\n{hint}", path.display())]
+    ModelNotFound { path: PathBuf, hint: &'static str },
+
+    /// The model couldn't be downloaded or saved to the cache
+    #[error(
+        "{reason}\n\
+        Please download the model manually from:\n\
+        https://github.com/atomashpolskiy/rustface/tree/master/model\n\
+        and place it at: {} (or build with --features embedded-model)",
+        path.display()
+    )]
+    Download { path: PathBuf, reason: String },
+
+    /// A model file was read but couldn't be parsed
+    #[error("Failed to read face detection model")]
+    Decode(#[source] std::io::Error),
+
+    /// The inference backend failed or produced unexpected output
+    #[error("{0}")]
+    Backend(String),
+
+    /// The detector name or configuration is invalid
+    #[error("{0}")]
+    InvalidParams(String),
+
+    /// Encoded image data handed to a detector couldn't be decoded
+    #[error("Failed to decode image")]
+    InvalidImage(#[source] image::ImageError),
+}
+
+/// Result of detector operations
+pub type Result<T> = std::result::Result<T, DetectorError>;
 
 /// Represents a detected face with bounding box and confidence
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FaceBox {
     pub x: i32,      // Left coordinate
     pub y: i32,      // Top coordinate
     pub width: i32,  // Width of bounding box
     pub height: i32, // Height of bounding box
     pub confidence: f32, // Detection confidence (0.0-1.0)
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub landmarks: Option<Landmarks>, // Facial landmarks, if the backend provides them
+}
+
+impl FaceBox {
+    /// Area of the box (px²), 0 for empty boxes
+    pub fn area(&self) -> i32 {
+        self.width.max(0) * self.height.max(0)
+    }
+
+    /// Overlap of two boxes, keeping this box's confidence but no landmarks
+    ///
+    /// Returns `None` when the boxes don't overlap.
+    pub fn intersect(&self, other: &FaceBox) -> Option<FaceBox> {
+        let x1 = self.x.max(other.x);
+        let y1 = self.y.max(other.y);
+        let x2 = (self.x + self.width).min(other.x + other.width);
+        let y2 = (self.y + self.height).min(other.y + other.height);
+        if x2 <= x1 || y2 <= y1 {
+            return None;
+        }
+
+        Some(FaceBox {
+            x: x1,
+            y: y1,
+            width: x2 - x1,
+            height: y2 - y1,
+            confidence: self.confidence,
+            landmarks: None,
+        })
+    }
+
+    /// Intersection over union of two boxes
+    pub fn iou(&self, other: &FaceBox) -> f32 {
+        let intersection = self.intersect(other).map_or(0, |overlap| overlap.area()) as f32;
+        let union = (self.area() + other.area()) as f32 - intersection;
+
+        if union <= 0.0 { 0.0 } else { intersection / union }
+    }
+
+    /// Grow the box by `factor` times its width and height, split evenly between both sides
+    pub fn expand(&self, factor: f32) -> FaceBox {
+        let grow_w = (self.width as f32 * factor) as i32;
+        let grow_h = (self.height as f32 * factor) as i32;
+
+        FaceBox {
+            x: self.x - grow_w / 2,
+            y: self.y - grow_h / 2,
+            width: self.width + grow_w,
+            height: self.height + grow_h,
+            ..self.clone()
+        }
+    }
+
+    /// Grow the box on each side by a fraction of its width (left, right) or height (top, bottom)
+    pub fn expand_sides(&self, left: f32, top: f32, right: f32, bottom: f32) -> FaceBox {
+        let (width, height) = (self.width as f32, self.height as f32);
+        let (left, right) = ((width * left) as i32, (width * right) as i32);
+        let (top, bottom) = ((height * top) as i32, (height * bottom) as i32);
+
+        FaceBox {
+            x: self.x - left,
+            y: self.y - top,
+            width: self.width + left + right,
+            height: self.height + top + bottom,
+            ..self.clone()
+        }
+    }
+
+    /// The part of the box inside a `width`x`height` image, empty when it lies outside
+    pub fn clamp_to(&self, width: u32, height: u32) -> FaceBox {
+        let x1 = self.x.clamp(0, width as i32);
+        let y1 = self.y.clamp(0, height as i32);
+        let x2 = (self.x + self.width).clamp(0, width as i32);
+        let y2 = (self.y + self.height).clamp(0, height as i32);
+
+        FaceBox {
+            x: x1,
+            y: y1,
+            width: (x2 - x1).max(0),
+            height: (y2 - y1).max(0),
+            ..self.clone()
+        }
+    }
+
+    /// Square of the longer side, centered on the box so it covers all of it
+    pub fn to_outer_square(&self) -> FaceBox {
+        let side = self.width.max(self.height);
+
+        FaceBox {
+            x: self.x + self.width / 2 - side / 2,
+            y: self.y + self.height / 2 - side / 2,
+            width: side,
+            height: side,
+            ..self.clone()
+        }
+    }
+
+    /// Square of the shorter side, centered on the box
+    pub fn to_square(&self) -> FaceBox {
+        let side = self.width.min(self.height);
+
+        FaceBox {
+            x: self.x + self.width / 2 - side / 2,
+            y: self.y + self.height / 2 - side / 2,
+            width: side,
+            height: side,
+            ..self.clone()
+        }
+    }
+}
+
+/// Five facial landmarks as (x, y) image coordinates, in the order:
+/// left eye, right eye, nose tip, left mouth corner, right mouth corner
+pub type Landmarks = [(f32, f32); 5];
+
+/// Detector tuning parameters, unset fields keep the backend's default
+///
+/// Backends ignore the fields that don't apply to them.
+#[derive(Debug, Clone, Default)]
+pub struct DetectorConfig {
+    pub min_face_size: Option<u32>,        // Smallest face to look for (px)
+    pub pyramid_scale_factor: Option<f32>, // Scale step between image pyramid levels (0.01-0.99)
+    pub slide_window_step: Option<u32>,    // Sliding window step in both directions (px)
+    pub score_threshold: Option<f64>,      // Backend-internal score cut-off, before --threshold
+    pub model_path: Option<PathBuf>,       // Model file to load instead of the default
+    pub extra_models: Vec<PathBuf>,        // Further SeetaFace models rustface runs after the first
+    pub input_size: Option<u32>,           // Network input size (px, multiple of 32)
+    pub nms_iou: Option<f32>,              // IoU above which overlapping boxes are merged
+    pub tta: bool,                         // Also detect on flipped and rotated copies
+    pub rotations: Vec<f32>,               // Detect on copies turned clockwise by these angles (degrees), empty for none
+    pub roi: Option<Roi>,                  // Only detect inside this region of each image
+    pub tile_megapixels: Option<f32>,      // Detect on overlapping tiles of images above this size (MP)
+    pub detect_max_dim: Option<u32>,       // Shrink images to this longest side before detecting (px)
+    pub device: Device,                    // Where ONNX models run, falling back to the CPU
+    pub gpu_id: u32,                       // GPU the device runs on (CUDA, DirectML)
+    pub requests_per_second: Option<f32>,  // Request rate to cloud detectors, all instances together
+    pub fusion: Fusion,                    // How ensembles merge the boxes of their detectors
+}
+
+/// Hardware ONNX Runtime detectors run on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Device {
+    #[default]
+    Cpu,
+    /// NVIDIA GPUs (needs the CUDA build of ONNX Runtime)
+    Cuda,
+    /// Apple Neural Engine and GPU
+    Coreml,
+    /// DirectX 12 GPUs on Windows
+    Directml,
+}
+
+impl std::str::FromStr for Device {
+    type Err = String;
+
+    /// Parse `cpu`, `cuda`, `coreml` or `directml`
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "cpu" => Ok(Device::Cpu),
+            "cuda" => Ok(Device::Cuda),
+            "coreml" => Ok(Device::Coreml),
+            "directml" => Ok(Device::Directml),
+            _ => Err(format!("Invalid device: {} (expected cpu, cuda, coreml or directml)", value)),
+        }
+    }
 }
 
 /// Trait for face detector implementations
 pub trait FaceDetector {
-    /// Initialize a new detector
-    fn new() -> Result<Self> where Self: Sized;
+    /// Initialize a new detector with the given configuration
+    fn new(config: &DetectorConfig) -> Result<Self> where Self: Sized;
 
     /// Detect faces in an image
     fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>>;
 
-    /// Optional method to set detector-specific parameters
-    fn set_params(&mut self, _params: &str) -> Result<()> {
-        // Default implementation does nothing
-        Ok(())
+    /// Detect faces in several images at once, returning one result per image
+    ///
+    /// The default implementation runs `detect_faces` on each image in turn;
+    /// backends that can batch inference (GPU/ONNX) should override it.
+    fn detect_faces_batch(&mut self, images: &[DynamicImage], threshold: f32) -> Result<Vec<Vec<FaceBox>>> {
+        images
+            .iter()
+            .map(|image| self.detect_faces(image, threshold))
+            .collect()
+    }
+
+    /// Decode an encoded image held in memory (e.g. an upload) and detect faces in it
+    ///
+    /// Any format [`decode_image`](crate::decode_image) reads is accepted, and the
+    /// image is turned upright by its EXIF orientation like a loaded file.
+    fn detect_from_bytes(&mut self, data: &[u8], threshold: f32) -> Result<Vec<FaceBox>> {
+        let image = crate::input::decode_image(data, &crate::input::LoadOptions::default())
+            .map_err(DetectorError::InvalidImage)?;
+        self.detect_faces(&image, threshold)
+    }
+
+    /// Detect faces in a grayscale buffer of `width * height` bytes, row by row
+    /// (e.g. the Y plane of a video frame)
+    ///
+    /// The default implementation copies the buffer into an image for
+    /// `detect_faces`; backends detecting on grayscale (rustface) read it as it is.
+    fn detect_luma8(&mut self, data: &[u8], width: u32, height: u32, threshold: f32) -> Result<Vec<FaceBox>> {
+        check_luma8(data, width, height)?;
+        let image = image::GrayImage::from_raw(width, height, data.to_vec()).expect("buffer size was checked");
+        self.detect_faces(&DynamicImage::ImageLuma8(image), threshold)
+    }
+}
+
+/// Check that a grayscale buffer holds exactly `width * height` pixels
+fn check_luma8(data: &[u8], width: u32, height: u32) -> Result<()> {
+    if data.len() as u64 != u64::from(width) * u64::from(height) {
+        return Err(DetectorError::InvalidParams(format!(
+            "Grayscale buffer of {} bytes doesn't match a {}x{} image",
+            data.len(),
+            width,
+            height
+        )));
+    }
+    Ok(())
+}
+
+/// Detectors created by name, so the wrappers can go around any of them
+impl FaceDetector for Box<dyn FaceDetector> {
+    fn new(_config: &DetectorConfig) -> Result<Self> {
+        Err(DetectorError::InvalidParams(
+            "A boxed detector needs a backend, create it by name with create_detector".to_string(),
+        ))
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        (**self).detect_faces(image, threshold)
+    }
+
+    fn detect_faces_batch(&mut self, images: &[DynamicImage], threshold: f32) -> Result<Vec<Vec<FaceBox>>> {
+        (**self).detect_faces_batch(images, threshold)
+    }
+
+    fn detect_from_bytes(&mut self, data: &[u8], threshold: f32) -> Result<Vec<FaceBox>> {
+        (**self).detect_from_bytes(data, threshold)
+    }
+
+    fn detect_luma8(&mut self, data: &[u8], width: u32, height: u32, threshold: f32) -> Result<Vec<FaceBox>> {
+        (**self).detect_luma8(data, width, height, threshold)
     }
 }
 
 /// RustFace (SeetaFace) detector implementation
 pub struct RustFaceDetector {
-    detector: Box<dyn Detector>,
+    /// One detector per SeetaFace model, run in order on every image
+    detectors: Vec<Box<dyn Detector>>,
+    min_face_size: u32,
+    nms_iou: f32,
+    /// Grayscale pixels of the last image, reused so frames don't each allocate one
+    gray: Vec<u8>,
 }
 
-impl FaceDetector for RustFaceDetector {
-    fn new() -> Result<Self> {
-        // Download the model file if it doesn't exist
-        let model_path = "model/seeta_fd_frontal_v1.0.bin";
-
-        if !Path::new(model_path).exists() {
-            println!("Downloading face detection model...");
-
-            // Create the model directory
-            std::fs::create_dir_all("model")?;
-
-            // Try multiple URLs for the model
-            let model_urls = [
-                // Direct link from the raw GitHub content
-                "https://github.com/atomashpolskiy/rustface/raw/master/model/seeta_fd_frontal_v1.0.bin",
-                // Alternative raw content URL
-                "https://raw.githubusercontent.com/atomashpolskiy/rustface/master/model/seeta_fd_frontal_v1.0.bin",
-            ];
-
-            let mut downloaded = false;
-            let mut last_error = None;
-
-            for url in &model_urls {
-                println!("Trying to download from: {}", url);
-
-                match ureq::get(url).call() {
-                    Ok(response) => {
-                        let mut reader = response.into_reader();
-                        let mut file = std::fs::File::create(model_path)?;
-                        std::io::copy(&mut reader, &mut file)?;
-                        println!("Model downloaded successfully from {}", url);
-                        downloaded = true;
-                        break;
-                    }
-                    Err(err) => {
-                        println!("Failed to download from {}: {}", url, err);
-                        last_error = Some(err);
-                        continue;
-                    }
-                }
-            }
+/// File name of the SeetaFace frontal model
+#[cfg(not(feature = "embedded-model"))]
+const SEETA_MODEL_FILE: &str = "seeta_fd_frontal_v1.0.bin";
+
+/// SHA-256 of the SeetaFace frontal model, checked for cached and downloaded copies
+#[cfg(not(feature = "embedded-model"))]
+const SEETA_MODEL_SHA256: &str = "c4619d066ed35e84d9a8e842860b0dff567aba0cbb139881075538761db3ff5d";
+
+/// SeetaFace model built into the binary
+#[cfg(feature = "embedded-model")]
+static EMBEDDED_SEETA_MODEL: &[u8] = include_bytes!("../model/seeta_fd_frontal_v1.0.bin");
+
+/// Directory downloaded models are cached in (`~/.cache/face-extractor` on Linux)
+pub fn model_cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("face-extractor")
+}
+
+/// Read the SeetaFace model built into the binary
+#[cfg(feature = "embedded-model")]
+fn load_seeta_model() -> Result<rustface::Model> {
+    rustface::read_model(EMBEDDED_SEETA_MODEL).map_err(DetectorError::Decode)
+}
+
+/// Load the SeetaFace model from the cache, downloading it if it's missing or corrupt
+#[cfg(not(feature = "embedded-model"))]
+fn load_seeta_model() -> Result<rustface::Model> {
+    let cache_dir = model_cache_dir();
+    let model_path = cache_dir.join(SEETA_MODEL_FILE);
+
+    let cached = match std::fs::read(&model_path) {
+        Ok(data) if sha256_hex(&data) == SEETA_MODEL_SHA256 => {
+            log::debug!("Model already exists at: {}", model_path.display());
+            Some(data)
+        }
+        Ok(_) => {
+            log::warn!("Cached model at {} is corrupt, downloading it again", model_path.display());
+            None
+        }
+        Err(_) => None,
+    };
+
+    let data = match cached {
+        Some(data) => data,
+        None => {
+            let download_error = |reason: String| DetectorError::Download {
+                path: model_path.clone(),
+                reason,
+            };
+            let data = download_seeta_model().map_err(download_error)?;
+
+            // Write to a temporary file first so an interrupted download never looks complete
+            std::fs::create_dir_all(&cache_dir).map_err(|err| {
+                download_error(format!("Failed to create model cache directory {}: {}", cache_dir.display(), err))
+            })?;
+            let partial_path = model_path.with_extension("part");
+            std::fs::write(&partial_path, &data)
+                .and_then(|_| std::fs::rename(&partial_path, &model_path))
+                .map_err(|err| download_error(format!("Failed to save model to {}: {}", model_path.display(), err)))?;
+            data
+        }
+    };
+
+    rustface::read_model(&data[..]).map_err(DetectorError::Decode)
+}
+
+/// Whether a model path can be a SeetaFace model, rather than an ONNX or JSON
+/// model or an MTCNN directory of the other detectors
+fn is_seeta_model(path: &Path) -> bool {
+    let extension = path.extension().map(|extension| extension.to_ascii_lowercase());
+    !path.is_dir() && !matches!(extension.as_ref().and_then(|e| e.to_str()), Some("onnx" | "json"))
+}
+
+/// Read a SeetaFace model file given in the config
+fn read_seeta_model(path: &Path) -> Result<rustface::Model> {
+    if !path.exists() {
+        return Err(DetectorError::ModelNotFound {
+            path: path.to_path_buf(),
+            hint: "Pass the path of a SeetaFace detection model (.bin), such as the frontal model of \
+                https://github.com/atomashpolskiy/rustface/tree/master/model",
+        });
+    }
+    log::info!("Loading SeetaFace model from: {}", path.display());
+    let data = std::fs::read(path).map_err(DetectorError::Decode)?;
+
+    rustface::read_model(&data[..]).map_err(DetectorError::Decode)
+}
+
+/// Download the SeetaFace model, returning it once its checksum is verified
+#[cfg(not(feature = "embedded-model"))]
+fn download_seeta_model() -> std::result::Result<Vec<u8>, String> {
+    use std::io::Read;
+
+    log::info!("Downloading face detection model...");
+
+    // Try multiple URLs for the model
+    let model_urls = [
+        // Direct link from the raw GitHub content
+        "https://github.com/atomashpolskiy/rustface/raw/master/model/seeta_fd_frontal_v1.0.bin",
+        // Alternative raw content URL
+        "https://raw.githubusercontent.com/atomashpolskiy/rustface/master/model/seeta_fd_frontal_v1.0.bin",
+    ];
 
-            if !downloaded {
-                return Err(anyhow::anyhow!(
-                    "Failed to download model from all sources. Last error: {:?}\n\
-                    Please download the model manually from:\n\
-                    https://github.com/atomashpolskiy/rustface/tree/master/model\n\
-                    and place it at: {}", 
-                    last_error,
-                    model_path
-                ));
+    let mut last_error = None;
+
+    for url in &model_urls {
+        log::info!("Trying to download from: {}", url);
+
+        let mut data = Vec::new();
+        let result = ureq::get(url)
+            .call()
+            .map_err(|err| err.to_string())
+            .and_then(|response| {
+                response
+                    .into_reader()
+                    .read_to_end(&mut data)
+                    .map_err(|err| err.to_string())
+            });
+
+        match result {
+            Ok(_) if sha256_hex(&data) == SEETA_MODEL_SHA256 => {
+                log::info!("Model downloaded successfully from {}", url);
+                return Ok(data);
+            }
+            Ok(_) => {
+                log::warn!("Model downloaded from {} doesn't match the expected checksum", url);
+                last_error = Some("checksum mismatch".to_string());
             }
-        } else {
-            println!("Model already exists at: {}", model_path);
+            Err(err) => {
+                log::warn!("Failed to download from {}: {}", url, err);
+                last_error = Some(err);
+            }
+        }
+    }
+
+    Err(format!(
+        "Failed to download model from all sources. Last error: {:?}",
+        last_error
+    ))
+}
+
+/// Lowercase hex SHA-256 digest
+#[cfg(not(feature = "embedded-model"))]
+fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl FaceDetector for RustFaceDetector {
+    fn new(config: &DetectorConfig) -> Result<Self> {
+        // rustface panics on out of range values, so check them here
+        let min_face_size = config.min_face_size.unwrap_or(20);
+        if min_face_size < 20 {
+            return Err(DetectorError::InvalidParams(format!("Minimum face size must be at least 20 px, got {}", min_face_size)));
+        }
+        if let Some(factor) = config.pyramid_scale_factor
+            && !(0.01..=0.99).contains(&factor)
+        {
+            return Err(DetectorError::InvalidParams(format!("Pyramid scale factor must be between 0.01 and 0.99, got {}", factor)));
         }
+        if config.slide_window_step == Some(0) {
+            return Err(DetectorError::InvalidParams("Slide window step must be positive".to_string()));
+        }
+        if let Some(score) = config.score_threshold
+            && score <= 0.0
+        {
+            return Err(DetectorError::InvalidParams(format!("Detector score threshold must be positive, got {}", score)));
+        }
+        let nms_iou = nms_iou(config)?;
 
-        // Create the detector
-        let detector = rustface::create_detector(model_path)
-            .context("Failed to create face detector")?;
+        // The model at the model path replaces the frontal one and the extra models run after it
+        let seeta_path = config.model_path.as_deref();
+        if let Some(path) = seeta_path
+            && !is_seeta_model(path)
+        {
+            return Err(DetectorError::InvalidParams(format!(
+                "{} isn't a SeetaFace model, rustface only runs SeetaFace .bin models",
+                path.display()
+            )));
+        }
+        let mut models = vec![match seeta_path {
+            Some(path) => read_seeta_model(path)?,
+            None => load_seeta_model()?,
+        }];
+        for path in &config.extra_models {
+            models.push(read_seeta_model(path)?);
+        }
 
-        Ok(Self { detector })
+        let detectors = models
+            .into_iter()
+            .map(|model| {
+                let mut detector = rustface::create_detector_with_model(model);
+                detector.set_min_face_size(min_face_size);
+                if let Some(factor) = config.pyramid_scale_factor {
+                    detector.set_pyramid_scale_factor(factor);
+                }
+                if let Some(step) = config.slide_window_step {
+                    detector.set_slide_window_step(step, step);
+                }
+                if let Some(score) = config.score_threshold {
+                    detector.set_score_thresh(score);
+                }
+                detector
+            })
+            .collect();
+
+        Ok(Self { detectors, min_face_size, nms_iou, gray: Vec::new() })
     }
 
     fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
-        let gray_image = image.to_luma8();
+        // The buffer is taken out while rustface reads it and put back for the next image
+        let mut gray = std::mem::take(&mut self.gray);
+        fill_gray(&mut gray, image);
+        let result = self.detect_gray(&gray, image.width(), image.height(), threshold);
+        self.gray = gray;
+        result
+    }
+
+    fn detect_luma8(&mut self, data: &[u8], width: u32, height: u32, threshold: f32) -> Result<Vec<FaceBox>> {
+        check_luma8(data, width, height)?;
+        self.detect_gray(data, width, height, threshold)
+    }
+}
+
+/// Replace the contents of `gray` with the grayscale pixels of an image, the same
+/// as `to_luma8` gives but without allocating once the buffer is large enough
+fn fill_gray(gray: &mut Vec<u8>, image: &DynamicImage) {
+    gray.clear();
+    match image {
+        DynamicImage::ImageLuma8(luma) => gray.extend_from_slice(luma.as_raw()),
+        DynamicImage::ImageRgb8(rgb) => gray.extend(rgb.pixels().map(|pixel| pixel.to_luma()[0])),
+        DynamicImage::ImageRgba8(rgba) => gray.extend(rgba.pixels().map(|pixel| pixel.to_luma()[0])),
+        other => gray.extend_from_slice(other.to_luma8().as_raw()),
+    }
+}
 
+impl RustFaceDetector {
+    /// Detect faces in grayscale pixels, the only input rustface works on
+    fn detect_gray(&mut self, data: &[u8], width: u32, height: u32, threshold: f32) -> Result<Vec<FaceBox>> {
         // Convert to rustface ImageData format
-        let (width, height) = gray_image.dimensions();
-        let mut image_data = ImageData::new(gray_image.as_raw(), width, height);
+        let image_data = ImageData::new(data, width, height);
 
-        // Detect faces
-        let faces = self.detector.detect(&mut image_data);
+        // Detect faces with every model, their boxes are merged below
+        let faces: Vec<_> = self.detectors.iter_mut().flat_map(|detector| detector.detect(&image_data)).collect();
 
-        // Convert to our FaceBox format, filtering by threshold
+        // Convert to our FaceBox format, filtering by threshold. rustface stores the
+        // minimum face size but doesn't apply it, so smaller boxes are dropped here
         let mut result = Vec::new();
         for face in faces {
-            if face.score() >= f64::from(threshold) {
-                let bbox = face.bbox();
+            let bbox = face.bbox();
+            if face.score() >= f64::from(threshold) && bbox.width().min(bbox.height()) >= self.min_face_size {
                 result.push(FaceBox {
-                    x: bbox.x() as i32,
-                    y: bbox.y() as i32,
+                    x: bbox.x(),
+                    y: bbox.y(),
                     width: bbox.width() as i32,
                     height: bbox.height() as i32,
                     confidence: face.score() as f32,
+                    landmarks: None,
                 });
             }
         }
 
-        Ok(result)
+        Ok(non_max_suppression(result, self.nms_iou))
+    }
+}
+
+/// ONNX Runtime detector for RetinaFace/SCRFD style models
+///
+/// Expects the anchor-free output layout produced by the insightface exports:
+/// one score and one bbox-distance tensor per feature stride, optionally
+/// followed by keypoint tensors (`*_kps` models) which provide landmarks.
+#[cfg(feature = "onnx")]
+pub struct OnnxDetector {
+    input_size: u32,
+    nms_iou: f32,
+    session: ort::session::Session,
+}
+
+#[cfg(feature = "onnx")]
+impl OnnxDetector {
+    /// Model loaded when the config doesn't name one
+    const DEFAULT_MODEL: &'static str = "model/scrfd_2.5g_bnkps.onnx";
+
+    /// Letterbox an image into the square network input, returning CHW data and the scale used
+    fn preprocess(&self, image: &DynamicImage) -> (Vec<f32>, f32) {
+        let input_size = self.input_size;
+
+        let rgb = image.to_rgb8();
+        let (width, height) = rgb.dimensions();
+        let scale = (input_size as f32 / width as f32).min(input_size as f32 / height as f32);
+        let new_width = ((width as f32 * scale).round() as u32).clamp(1, input_size);
+        let new_height = ((height as f32 * scale).round() as u32).clamp(1, input_size);
+        let resized = image::imageops::resize(
+            &rgb,
+            new_width,
+            new_height,
+            image::imageops::FilterType::Triangle
+        );
+
+        // Normalized as (pixel - 127.5) / 128, padding is black
+        let plane = (input_size * input_size) as usize;
+        let mut input = vec![-127.5 / 128.0; 3 * plane];
+        for (x, y, pixel) in resized.enumerate_pixels() {
+            let idx = (y * input_size + x) as usize;
+            for channel in 0..3 {
+                input[channel * plane + idx] = (f32::from(pixel[channel]) - 127.5) / 128.0;
+            }
+        }
+
+        (input, scale)
+    }
+
+    /// Run the network on preprocessed images and decode boxes for each of them
+    fn infer(&mut self, inputs: Vec<f32>, scales: &[f32], threshold: f32) -> Result<Vec<Vec<FaceBox>>> {
+        let input_size = self.input_size;
+        let nms_iou = self.nms_iou;
+        let batch = scales.len();
+
+        let tensor = ort::value::Tensor::from_array((
+            [batch, 3, input_size as usize, input_size as usize],
+            inputs,
+        ))
+        .map_err(|err| DetectorError::Backend(format!("Failed to build ONNX input tensor: {}", err)))?;
+
+        let session = &mut self.session;
+        let input_name = session.inputs()[0].name().to_string();
+        let outputs = session
+            .run(ort::inputs![input_name => tensor])
+            .map_err(|err| DetectorError::Backend(format!("ONNX inference failed: {}", err)))?;
+
+        // 6/9 outputs: strides 8/16/32, 10/15 outputs: strides 8..128
+        let strides: &[u32] = match outputs.len() {
+            6 | 9 => &[8, 16, 32],
+            10 | 15 => &[8, 16, 32, 64, 128],
+            n => return Err(DetectorError::Backend(format!("Unsupported ONNX model: unexpected output count {}", n))),
+        };
+        let levels = strides.len();
+        let has_keypoints = outputs.len() == levels * 3;
+
+        let mut results = vec![Vec::new(); batch];
+        for (level, &stride) in strides.iter().enumerate() {
+            let (_, scores) = outputs[level]
+                .try_extract_tensor::<f32>()
+                .map_err(|err| DetectorError::Backend(format!("Failed to read score output: {}", err)))?;
+            let (_, distances) = outputs[level + levels]
+                .try_extract_tensor::<f32>()
+                .map_err(|err| DetectorError::Backend(format!("Failed to read bbox output: {}", err)))?;
+            let keypoints = if has_keypoints {
+                let (_, keypoints) = outputs[level + levels * 2]
+                    .try_extract_tensor::<f32>()
+                    .map_err(|err| DetectorError::Backend(format!("Failed to read keypoint output: {}", err)))?;
+                Some(keypoints)
+            } else {
+                None
+            };
+
+            let grid_width = input_size.div_ceil(stride) as usize;
+            let cells = grid_width * input_size.div_ceil(stride) as usize;
+            let per_image = scores.len() / batch;
+            let anchors_per_cell = (per_image / cells).max(1);
+
+            for (image_idx, (result, &scale)) in results.iter_mut().zip(scales).enumerate() {
+                let offset = image_idx * per_image;
+
+                for (i, &score) in scores[offset..offset + per_image].iter().enumerate() {
+                    if score < threshold {
+                        continue;
+                    }
+
+                    let cell = i / anchors_per_cell;
+                    let cx = ((cell % grid_width) as u32 * stride) as f32;
+                    let cy = ((cell / grid_width) as u32 * stride) as f32;
+                    let d = &distances[(offset + i) * 4..(offset + i) * 4 + 4];
+                    let stride = stride as f32;
+
+                    // Distances are relative to the anchor center, scaled by the stride
+                    let x1 = (cx - d[0] * stride) / scale;
+                    let y1 = (cy - d[1] * stride) / scale;
+                    let x2 = (cx + d[2] * stride) / scale;
+                    let y2 = (cy + d[3] * stride) / scale;
+
+                    // Keypoints are (dx, dy) offsets from the anchor center, same scaling
+                    let landmarks = keypoints.map(|keypoints| {
+                        let k = &keypoints[(offset + i) * 10..(offset + i) * 10 + 10];
+                        let mut points = [(0.0, 0.0); 5];
+                        for (j, point) in points.iter_mut().enumerate() {
+                            *point = (
+                                (cx + k[j * 2] * stride) / scale,
+                                (cy + k[j * 2 + 1] * stride) / scale,
+                            );
+                        }
+                        points
+                    });
+
+                    result.push(FaceBox {
+                        x: x1.round() as i32,
+                        y: y1.round() as i32,
+                        width: (x2 - x1).round() as i32,
+                        height: (y2 - y1).round() as i32,
+                        confidence: score,
+                        landmarks,
+                    });
+                }
+            }
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|faces| non_max_suppression(faces, nms_iou))
+            .collect())
+    }
+}
+
+#[cfg(feature = "onnx")]
+impl FaceDetector for OnnxDetector {
+    fn new(config: &DetectorConfig) -> Result<Self> {
+        let input_size = config.input_size.unwrap_or(640);
+        if input_size == 0 || !input_size.is_multiple_of(32) {
+            return Err(DetectorError::InvalidParams(format!("Input size must be a positive multiple of 32, got {}", input_size)));
+        }
+        let nms_iou = nms_iou(config)?;
+
+        let model_path = config
+            .model_path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(Self::DEFAULT_MODEL));
+        if !model_path.exists() {
+            return Err(DetectorError::ModelNotFound {
+                path: model_path,
+                hint: "Download a SCRFD/RetinaFace model (e.g. from the insightface model zoo) and \
+                    place it there, or pass --model-path <path>",
+            });
+        }
+
+        let session = load_session_on(&model_path, config.device, config.gpu_id)?;
+
+        Ok(Self {
+            input_size,
+            nms_iou,
+            session,
+        })
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        let (input, scale) = self.preprocess(image);
+        let mut results = self.infer(input, &[scale], threshold)?;
+
+        Ok(results.pop().unwrap_or_default())
+    }
+
+    fn detect_faces_batch(&mut self, images: &[DynamicImage], threshold: f32) -> Result<Vec<Vec<FaceBox>>> {
+        // Models exported with a fixed batch size of 1 have to be run image by image
+        if images.len() <= 1 || !supports_batching(&self.session) {
+            return images
+                .iter()
+                .map(|image| self.detect_faces(image, threshold))
+                .collect();
+        }
+
+        let mut inputs = Vec::new();
+        let mut scales = Vec::with_capacity(images.len());
+        for image in images {
+            let (input, scale) = self.preprocess(image);
+            inputs.extend(input);
+            scales.push(scale);
+        }
+
+        self.infer(inputs, &scales, threshold)
     }
 }
 
+/// Load an ONNX model into a new session on the CPU
+#[cfg(feature = "onnx")]
+pub(crate) fn load_session(model_path: &std::path::Path) -> Result<ort::session::Session> {
+    load_session_on(model_path, Device::Cpu, 0)
+}
+
+/// Load an ONNX model into a new session on a device
+///
+/// A device ONNX Runtime can't use is reported with a warning and the session
+/// runs on the CPU instead.
+#[cfg(feature = "onnx")]
+pub(crate) fn load_session_on(model_path: &std::path::Path, device: Device, gpu_id: u32) -> Result<ort::session::Session> {
+    use ort::ep::ExecutionProvider;
+
+    log::info!("Loading ONNX model from: {}", model_path.display());
+    let mut builder = ort::session::Session::builder()
+        .map_err(|err| DetectorError::Backend(format!("Failed to create ONNX session: {}", err)))?;
+
+    let registered = match device {
+        Device::Cpu => Ok(()),
+        Device::Cuda => ort::ep::CUDA::default()
+            .with_device_id(gpu_id as i32)
+            .register(&mut builder),
+        Device::Coreml => ort::ep::CoreML::default().register(&mut builder),
+        Device::Directml => ort::ep::DirectML::default()
+            .with_device_id(gpu_id as i32)
+            .register(&mut builder),
+    };
+    if let Err(err) = registered {
+        log::warn!("Can't run {} on {:?} ({}), falling back to the CPU", model_path.display(), device, err);
+    }
+
+    builder
+        .commit_from_file(model_path)
+        .map_err(|err| DetectorError::Backend(format!("Failed to load ONNX model {}: {}", model_path.display(), err)))
+}
+
+/// Whether a model accepts more than one image per run (dynamic batch dimension)
+#[cfg(feature = "onnx")]
+fn supports_batching(session: &ort::session::Session) -> bool {
+    let batch_dim = session.inputs()[0]
+        .dtype()
+        .tensor_shape()
+        .and_then(|shape| shape.first().copied());
+
+    batch_dim == Some(-1)
+}
+
+/// Scale a box and its landmarks by `scale`, then move them by (`dx`, `dy`)
+pub(crate) fn transform_box(face: FaceBox, scale: f32, dx: f32, dy: f32) -> FaceBox {
+    FaceBox {
+        x: (face.x as f32 * scale + dx).round() as i32,
+        y: (face.y as f32 * scale + dy).round() as i32,
+        width: (face.width as f32 * scale).round() as i32,
+        height: (face.height as f32 * scale).round() as i32,
+        confidence: face.confidence,
+        landmarks: face
+            .landmarks
+            .map(|points| points.map(|(x, y)| (x * scale + dx, y * scale + dy))),
+    }
+}
+
+/// The configured NMS IoU threshold, 0.4 by default
+pub(crate) fn nms_iou(config: &DetectorConfig) -> Result<f32> {
+    let nms_iou = config.nms_iou.unwrap_or(0.4);
+    if !(0.0..=1.0).contains(&nms_iou) {
+        return Err(DetectorError::InvalidParams(format!("NMS IoU must be between 0 and 1, got {}", nms_iou)));
+    }
+
+    Ok(nms_iou)
+}
+
+/// Greedy NMS: keep the most confident box out of each group of overlapping boxes
+///
+/// Boxes overlapping a more confident kept box by more than `iou_threshold` are dropped.
+pub fn non_max_suppression(mut faces: Vec<FaceBox>, iou_threshold: f32) -> Vec<FaceBox> {
+    faces.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+    let mut kept: Vec<FaceBox> = Vec::new();
+    for face in faces {
+        if kept.iter().all(|k| k.iou(&face) <= iou_threshold) {
+            kept.push(face);
+        }
+    }
+
+    kept
+}
+
+/// Creates a detector from the config, as registered with [`register_detector`]
+pub type DetectorFactory = dyn Fn(&DetectorConfig) -> Result<Box<dyn FaceDetector>> + Send + Sync;
+
+/// Detectors registered at runtime, by lowercase name
+static REGISTRY: LazyLock<RwLock<HashMap<String, Arc<DetectorFactory>>>> = LazyLock::new(Default::default);
+
+/// Make a detector of another crate available to create_detector under `name`
+///
+/// Its detectors are wrapped like the built-in ones: NMS at the configured IoU,
+/// then tiling, downscaling, TTA, rotations and the region as the config asks, so
+/// a factory creating other detectors by name gets those wrapped once more.
+///
+/// Registered names are looked up before the built-in ones, so a backend can be
+/// replaced, and registering a name again replaces its factory. Names are case
+/// insensitive like the built-in ones.
+pub fn register_detector<F>(name: &str, factory: F)
+where
+    F: Fn(&DetectorConfig) -> Result<Box<dyn FaceDetector>> + Send + Sync + 'static,
+{
+    REGISTRY
+        .write()
+        .expect("detector registry poisoned")
+        .insert(name.to_lowercase(), Arc::new(factory));
+}
+
+/// Detector names accepted by create_detector in this build, registered ones last
+pub fn available_detectors() -> Vec<String> {
+    let mut names: Vec<String> = [
+        ("rustface", true),
+        ("onnx", cfg!(feature = "onnx")),
+        ("mtcnn", cfg!(feature = "onnx")),
+        ("blazeface", cfg!(feature = "blazeface")),
+        ("yunet", cfg!(feature = "opencv")),
+        ("rekognition", cfg!(feature = "rekognition")),
+        ("google-vision", true),
+        ("mock", true),
+    ]
+    .into_iter()
+    .filter(|(_, available)| *available)
+    .map(|(name, _)| name.to_string())
+    .collect();
+
+    let mut registered: Vec<String> = REGISTRY
+        .read()
+        .expect("detector registry poisoned")
+        .keys()
+        .filter(|name| !names.contains(name))
+        .cloned()
+        .collect();
+    registered.sort();
+    names.extend(registered);
+    names
+}
+
 // Factory function to create detectors by name
-pub fn create_detector(name: &str) -> Result<Box<dyn FaceDetector>> {
+pub fn create_detector(name: &str, config: &DetectorConfig) -> Result<Box<dyn FaceDetector>> {
+    if let Some(names) = name.strip_prefix("ensemble:") {
+        // The model path is another member's when it isn't a SeetaFace one, rustface
+        // then keeps its built-in model
+        let seeta_config = DetectorConfig { model_path: None, ..config.clone() };
+        let members = names
+            .split(',')
+            .map(|member| match member.trim() {
+                member if member.eq_ignore_ascii_case("rustface")
+                    && config.model_path.as_deref().is_some_and(|path| !is_seeta_model(path)) =>
+                {
+                    create_detector(member, &seeta_config)
+                }
+                member => create_detector(member, config),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        return Ok(Box::new(EnsembleDetector::from_detectors(members, config)?));
+    }
+    // Plugins and registered detectors get the NMS the built-in backends run themselves
+    if let Some(path) = name.strip_prefix("plugin:") {
+        let plugin = PluginDetector::load(Path::new(path), config)?;
+        return wrap(Box::new(SuppressedDetector::wrap(plugin, config)?), config);
+    }
+
+    // Cloned out so the factory can create registered detectors itself
+    let factory = REGISTRY
+        .read()
+        .expect("detector registry poisoned")
+        .get(&name.to_lowercase())
+        .cloned();
+    if let Some(factory) = factory {
+        return wrap(Box::new(SuppressedDetector::wrap(factory(config)?, config)?), config);
+    }
+
     match name.to_lowercase().as_str() {
-        "rustface" => Ok(Box::new(RustFaceDetector::new()?)),
+        "rustface" => boxed::<RustFaceDetector>(config),
+        #[cfg(feature = "onnx")]
+        "onnx" => boxed::<OnnxDetector>(config),
+        #[cfg(feature = "onnx")]
+        "mtcnn" => boxed::<MtcnnDetector>(config),
+        #[cfg(feature = "blazeface")]
+        "blazeface" => boxed::<BlazeFaceDetector>(config),
+        #[cfg(feature = "opencv")]
+        "yunet" => boxed::<YuNetDetector>(config),
+        #[cfg(feature = "rekognition")]
+        "rekognition" => boxed::<RekognitionDetector>(config),
+        "google-vision" => boxed::<GoogleVisionDetector>(config),
+        "mock" => boxed::<MockDetector>(config),
         // Add other detectors here as needed
-        _ => Err(anyhow::anyhow!("Unknown detector: {}", name)),
+        _ => Err(DetectorError::InvalidParams(format!("Unknown detector: {}", name))),
     }
 }
 
+/// Create a detector and wrap it as far as the config asks for it
+fn boxed<D: FaceDetector + 'static>(config: &DetectorConfig) -> Result<Box<dyn FaceDetector>> {
+    wrap(Box::new(D::new(config)?), config)
+}
+
+/// Wrap a detector for tiling, downscaling, test-time augmentation, a rotation
+/// sweep and a region of interest (outermost) as far as the config asks for them
+fn wrap(mut detector: Box<dyn FaceDetector>, config: &DetectorConfig) -> Result<Box<dyn FaceDetector>> {
+    if config.tile_megapixels.is_some() {
+        detector = Box::new(TiledDetector::wrap(detector, config)?);
+    }
+    if config.detect_max_dim.is_some() {
+        detector = Box::new(DownscaledDetector::wrap(detector, config)?);
+    }
+    if config.tta {
+        detector = Box::new(TtaDetector::wrap(detector, config)?);
+    }
+    if !config.rotations.is_empty() {
+        detector = Box::new(RotatedDetector::wrap(detector, config)?);
+    }
+    if config.roi.is_some() {
+        detector = Box::new(RoiDetector::wrap(detector, config)?);
+    }
+
+    Ok(detector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn face(x: i32, y: i32, width: i32, height: i32, confidence: f32) -> FaceBox {
+        FaceBox { x, y, width, height, confidence, landmarks: None }
+    }
+
+    fn rect(face: &FaceBox) -> (i32, i32, i32, i32) {
+        (face.x, face.y, face.width, face.height)
+    }
+
+    #[test]
+    fn iou_of_identical_disjoint_and_overlapping_boxes() {
+        let a = face(0, 0, 10, 10, 1.0);
+        assert_eq!(a.iou(&a), 1.0);
+        assert_eq!(a.iou(&face(20, 20, 10, 10, 1.0)), 0.0);
+        // 50px² shared out of 150px² covered
+        assert!((a.iou(&face(5, 0, 10, 10, 1.0)) - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(face(0, 0, 0, 0, 1.0).iou(&face(0, 0, 0, 0, 1.0)), 0.0);
+    }
+
+    #[test]
+    fn expand_sides_grows_each_side_by_its_own_fraction() {
+        let grown = face(10, 10, 100, 50, 0.8).expand_sides(0.1, 0.2, 0.3, 0.4);
+        assert_eq!(rect(&grown), (0, 0, 140, 80));
+        assert_eq!(grown.confidence, 0.8);
+    }
+
+    #[test]
+    fn outer_square_covers_the_box() {
+        assert_eq!(rect(&face(10, 20, 40, 20, 1.0).to_outer_square()), (10, 10, 40, 40));
+        assert_eq!(rect(&face(10, 20, 20, 40, 1.0).to_outer_square()), (0, 20, 40, 40));
+    }
+
+    #[test]
+    fn clamp_to_keeps_the_part_inside_the_image() {
+        assert_eq!(rect(&face(-10, -10, 50, 50, 1.0).clamp_to(30, 20)), (0, 0, 30, 20));
+        assert_eq!(rect(&face(5, 5, 10, 10, 1.0).clamp_to(30, 20)), (5, 5, 10, 10));
+        let outside = face(100, 100, 10, 10, 1.0).clamp_to(50, 50);
+        assert_eq!((outside.width, outside.height), (0, 0));
+    }
+
+    #[test]
+    fn nms_keeps_the_most_confident_of_overlapping_boxes() {
+        let faces = vec![
+            face(1, 0, 10, 10, 0.8),
+            face(50, 50, 10, 10, 0.7),
+            face(0, 0, 10, 10, 0.9),
+        ];
+        let kept = non_max_suppression(faces, 0.5);
+        let kept: Vec<_> = kept.iter().map(|face| (rect(face), face.confidence)).collect();
+        assert_eq!(kept, vec![((0, 0, 10, 10), 0.9), ((50, 50, 10, 10), 0.7)]);
+
+        // Boxes overlapping less than the threshold are all kept
+        let faces = vec![face(0, 0, 10, 10, 0.9), face(5, 0, 10, 10, 0.8)];
+        assert_eq!(non_max_suppression(faces, 0.5).len(), 2);
+    }
+}
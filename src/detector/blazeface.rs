@@ -0,0 +1,193 @@
+use super::{nms_iou, non_max_suppression, DetectorConfig, DetectorError, FaceBox, FaceDetector, Result};
+use image::DynamicImage;
+use std::path::PathBuf;
+use tract_onnx::prelude::*;
+use tract_onnx::tract_hir::infer::Factoid;
+use tract_onnx::tract_hir::internal::DimLike;
+
+/// Values per anchor in the regressor output: box center and size, then six keypoints
+const REGRESSORS: usize = 16;
+
+/// MediaPipe BlazeFace detector, run with tract so no native runtime is needed
+///
+/// Made for the short-range (front camera) model: a 128x128 RGB input in [-1, 1],
+/// NCHW or NHWC, and per anchor 16 box and keypoint regressors plus one score
+/// logit, as two outputs or two per anchor layer. BlazeFace finds one mouth
+/// point, it stands in for both mouth corners of the landmarks.
+pub struct BlazeFaceDetector {
+    model: TypedRunnableModel<TypedModel>,
+    input_size: u32,
+    channels_last: bool,
+    /// Anchor centers in input pixels
+    anchors: Vec<(f32, f32)>,
+    nms_iou: f32,
+}
+
+impl BlazeFaceDetector {
+    /// Model loaded when the config doesn't name one
+    const DEFAULT_MODEL: &'static str = "model/blazeface.onnx";
+
+    /// Letterbox an image into the square network input, returning its data and the scale used
+    fn preprocess(&self, image: &DynamicImage) -> (Vec<f32>, f32) {
+        let input_size = self.input_size;
+
+        let rgb = image.to_rgb8();
+        let (width, height) = rgb.dimensions();
+        let scale = (input_size as f32 / width as f32).min(input_size as f32 / height as f32);
+        let new_width = ((width as f32 * scale).round() as u32).clamp(1, input_size);
+        let new_height = ((height as f32 * scale).round() as u32).clamp(1, input_size);
+        let resized = image::imageops::resize(
+            &rgb,
+            new_width,
+            new_height,
+            image::imageops::FilterType::Triangle
+        );
+
+        // Normalized to [-1, 1], padding is black
+        let plane = (input_size * input_size) as usize;
+        let mut input = vec![-1.0; 3 * plane];
+        for (x, y, pixel) in resized.enumerate_pixels() {
+            let idx = (y * input_size + x) as usize;
+            for channel in 0..3 {
+                let value = f32::from(pixel[channel]) / 127.5 - 1.0;
+                if self.channels_last {
+                    input[idx * 3 + channel] = value;
+                } else {
+                    input[channel * plane + idx] = value;
+                }
+            }
+        }
+
+        (input, scale)
+    }
+}
+
+impl FaceDetector for BlazeFaceDetector {
+    fn new(config: &DetectorConfig) -> Result<Self> {
+        let nms_iou = nms_iou(config)?;
+        let model_path = config
+            .model_path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(Self::DEFAULT_MODEL));
+        if !model_path.exists() {
+            return Err(DetectorError::ModelNotFound {
+                path: model_path,
+                hint: "Convert the MediaPipe BlazeFace short-range model to ONNX (e.g. with tf2onnx \
+                    or from the PINTO model zoo) and place it there, or pass --model-path <path>",
+            });
+        }
+
+        log::info!("Loading BlazeFace model from: {}", model_path.display());
+        let backend = |err: TractError| {
+            DetectorError::Backend(format!("Failed to load BlazeFace model {}: {}", model_path.display(), err))
+        };
+        let model = tract_onnx::onnx().model_for_path(&model_path).map_err(backend)?;
+
+        // The layout and size come from the model where it fixes them
+        let fact = model.input_fact(0).map_err(backend)?;
+        let dim = |axis| {
+            fact.shape
+                .dim(axis)
+                .and_then(|dim| dim.concretize())
+                .and_then(|dim| dim.to_usize().ok())
+        };
+        let channels_last = dim(3) == Some(3);
+        let model_size = dim(if channels_last { 1 } else { 2 }).map(|size| size as u32);
+        let input_size = config.input_size.or(model_size).unwrap_or(128);
+        if input_size == 0 || !input_size.is_multiple_of(16) {
+            return Err(DetectorError::InvalidParams(format!("Input size must be a positive multiple of 16, got {}", input_size)));
+        }
+
+        let size = input_size as usize;
+        let shape = if channels_last { [1, size, size, 3] } else { [1, 3, size, size] };
+        let model = model
+            .with_input_fact(0, f32::fact(shape).into())
+            .and_then(|model| model.into_optimized())
+            .and_then(|model| model.into_runnable())
+            .map_err(backend)?;
+
+        Ok(Self {
+            model,
+            input_size,
+            channels_last,
+            anchors: anchors(input_size),
+            nms_iou,
+        })
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        let (input, scale) = self.preprocess(image);
+        let size = self.input_size as usize;
+        let shape = if self.channels_last { [1, size, size, 3] } else { [1, 3, size, size] };
+        let backend = |err: TractError| DetectorError::Backend(format!("BlazeFace inference failed: {}", err));
+
+        let input = Tensor::from_shape(&shape, &input).map_err(backend)?;
+        let outputs = self.model.run(tvec!(input.into())).map_err(backend)?;
+
+        // Regressors and scores, concatenated over the anchor layers in output order
+        let mut regressors = Vec::with_capacity(self.anchors.len() * REGRESSORS);
+        let mut logits = Vec::with_capacity(self.anchors.len());
+        for output in &outputs {
+            let view = output.to_array_view::<f32>().map_err(backend)?;
+            match view.shape().last() {
+                Some(&REGRESSORS) => regressors.extend(view.iter().copied()),
+                Some(1) => logits.extend(view.iter().copied()),
+                _ => {
+                    return Err(DetectorError::Backend(format!(
+                        "Unsupported BlazeFace model: unexpected output shape {:?}",
+                        view.shape()
+                    )));
+                }
+            }
+        }
+        if logits.len() != self.anchors.len() || regressors.len() != self.anchors.len() * REGRESSORS {
+            return Err(DetectorError::Backend(format!(
+                "Unsupported BlazeFace model: {} scores for {} anchors",
+                logits.len(),
+                self.anchors.len()
+            )));
+        }
+
+        let mut faces = Vec::new();
+        for (i, (&logit, &(anchor_x, anchor_y))) in logits.iter().zip(&self.anchors).enumerate() {
+            let score = 1.0 / (1.0 + (-logit.clamp(-100.0, 100.0)).exp());
+            if score < threshold {
+                continue;
+            }
+
+            // Offsets and sizes are in input pixels, relative to the anchor center
+            let r = &regressors[i * REGRESSORS..(i + 1) * REGRESSORS];
+            let (cx, cy, width, height) = (anchor_x + r[0], anchor_y + r[1], r[2], r[3]);
+            let keypoint = |k: usize| ((anchor_x + r[4 + k * 2]) / scale, (anchor_y + r[5 + k * 2]) / scale);
+
+            // Keypoints: the eye on the image's left, the other eye, nose, mouth, then the ears
+            let mouth = keypoint(3);
+            faces.push(FaceBox {
+                x: ((cx - width / 2.0) / scale).round() as i32,
+                y: ((cy - height / 2.0) / scale).round() as i32,
+                width: (width / scale).round() as i32,
+                height: (height / scale).round() as i32,
+                confidence: score,
+                landmarks: Some([keypoint(0), keypoint(1), keypoint(2), mouth, mouth]),
+            });
+        }
+
+        Ok(non_max_suppression(faces, self.nms_iou))
+    }
+}
+
+/// Anchor centers of the short-range model: two per cell of the stride 8 grid,
+/// six per cell of the stride 16 grid
+fn anchors(input_size: u32) -> Vec<(f32, f32)> {
+    let mut anchors = Vec::new();
+    for (stride, per_cell) in [(8, 2), (16, 6)] {
+        let grid = input_size / stride;
+        for y in 0..grid {
+            for x in 0..grid {
+                let center = ((x as f32 + 0.5) * stride as f32, (y as f32 + 0.5) * stride as f32);
+                anchors.extend(std::iter::repeat_n(center, per_cell));
+            }
+        }
+    }
+    anchors
+}
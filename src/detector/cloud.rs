@@ -0,0 +1,326 @@
+use super::{DetectorConfig, DetectorError, FaceBox, FaceDetector, Landmarks, Result};
+use base64::Engine;
+use image::{DynamicImage, ImageOutputFormat};
+use serde::Deserialize;
+use std::io::Cursor;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Requests per second to a service when the config doesn't set a rate
+const DEFAULT_RATE: f32 = 5.0;
+
+/// Attempts after the first one for throttled requests, server and network errors
+const RETRIES: u32 = 3;
+
+/// Delay before the first retry, doubled for every further one
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Google Cloud Vision endpoint for image annotation
+const GOOGLE_VISION_URL: &str = "https://vision.googleapis.com/v1/images:annotate";
+
+/// Spaces requests to a service evenly, shared by all detectors talking to it
+struct RateLimiter {
+    /// Earliest start of the next request
+    next: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    const fn new() -> Self {
+        Self { next: Mutex::new(None) }
+    }
+
+    /// Wait for the next free slot, slots are `1 / rate` seconds apart
+    fn wait(&self, rate: f32) {
+        let slot = {
+            let mut next = self.next.lock().expect("rate limiter poisoned");
+            let now = Instant::now();
+            let slot = next.map_or(now, |next| next.max(now));
+            *next = Some(slot + Duration::from_secs_f32(1.0 / rate));
+            slot
+        };
+        std::thread::sleep(slot.saturating_duration_since(Instant::now()));
+    }
+}
+
+static GOOGLE_VISION_LIMIT: RateLimiter = RateLimiter::new();
+#[cfg(feature = "rekognition")]
+static REKOGNITION_LIMIT: RateLimiter = RateLimiter::new();
+
+/// The configured request rate, 5 per second by default
+fn request_rate(config: &DetectorConfig) -> Result<f32> {
+    let rate = config.requests_per_second.unwrap_or(DEFAULT_RATE);
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(DetectorError::InvalidParams(format!("Request rate must be positive, got {}", rate)));
+    }
+
+    Ok(rate)
+}
+
+/// The image as a JPEG for upload
+fn encode_jpeg(image: &DynamicImage) -> Result<Vec<u8>> {
+    let mut data = Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(image.to_rgb8())
+        .write_to(&mut data, ImageOutputFormat::Jpeg(90))
+        .map_err(|err| DetectorError::Backend(format!("Failed to encode image for upload: {}", err)))?;
+
+    Ok(data.into_inner())
+}
+
+/// Google Cloud Vision face detection over its REST API
+///
+/// Needs an API key in `GOOGLE_API_KEY`. Requests are rate limited and retried
+/// on throttling, server and network errors.
+pub struct GoogleVisionDetector {
+    agent: ureq::Agent,
+    api_key: String,
+    rate: f32,
+}
+
+/// Body of an `images:annotate` response, only the parts used here
+#[derive(Debug, Deserialize)]
+struct AnnotateResponse {
+    #[serde(default)]
+    responses: Vec<AnnotateImageResponse>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct AnnotateImageResponse {
+    face_annotations: Vec<FaceAnnotation>,
+    error: Option<AnnotateError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnnotateError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FaceAnnotation {
+    /// The tight box around the skin of the face
+    fd_bounding_poly: BoundingPoly,
+    detection_confidence: f32,
+    #[serde(default)]
+    landmarks: Vec<FaceLandmark>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BoundingPoly {
+    #[serde(default)]
+    vertices: Vec<Point>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FaceLandmark {
+    #[serde(rename = "type")]
+    kind: String,
+    position: Point,
+}
+
+/// Coordinates left out of a response are 0
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Point {
+    x: f32,
+    y: f32,
+}
+
+impl FaceAnnotation {
+    fn to_face_box(&self) -> FaceBox {
+        let vertices = &self.fd_bounding_poly.vertices;
+        let min_x = vertices.iter().map(|point| point.x).fold(f32::INFINITY, f32::min);
+        let min_y = vertices.iter().map(|point| point.y).fold(f32::INFINITY, f32::min);
+        let max_x = vertices.iter().map(|point| point.x).fold(f32::NEG_INFINITY, f32::max);
+        let max_y = vertices.iter().map(|point| point.y).fold(f32::NEG_INFINITY, f32::max);
+
+        // Landmarks are named from the subject's side, so their right comes first
+        let landmark = |kind: &str| {
+            self.landmarks
+                .iter()
+                .find(|landmark| landmark.kind == kind)
+                .map(|landmark| (landmark.position.x, landmark.position.y))
+        };
+        let landmarks: Option<Landmarks> = (|| {
+            Some([
+                landmark("RIGHT_EYE")?,
+                landmark("LEFT_EYE")?,
+                landmark("NOSE_TIP")?,
+                landmark("MOUTH_RIGHT")?,
+                landmark("MOUTH_LEFT")?,
+            ])
+        })();
+
+        FaceBox {
+            x: min_x.round() as i32,
+            y: min_y.round() as i32,
+            width: (max_x - min_x).round() as i32,
+            height: (max_y - min_y).round() as i32,
+            confidence: self.detection_confidence,
+            landmarks,
+        }
+    }
+}
+
+impl FaceDetector for GoogleVisionDetector {
+    fn new(config: &DetectorConfig) -> Result<Self> {
+        let api_key = std::env::var("GOOGLE_API_KEY").map_err(|_| {
+            DetectorError::InvalidParams("The google-vision detector needs an API key in GOOGLE_API_KEY".to_string())
+        })?;
+
+        Ok(Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(30))
+                .timeout_read(Duration::from_secs(60))
+                .build(),
+            api_key,
+            rate: request_rate(config)?,
+        })
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        let content = base64::engine::general_purpose::STANDARD.encode(encode_jpeg(image)?);
+        let body = serde_json::json!({
+            "requests": [{
+                "image": { "content": content },
+                "features": [{ "type": "FACE_DETECTION", "maxResults": 100 }],
+            }],
+        })
+        .to_string();
+
+        let mut attempt = 0;
+        let response: AnnotateResponse = loop {
+            GOOGLE_VISION_LIMIT.wait(self.rate);
+            let request = self
+                .agent
+                .post(GOOGLE_VISION_URL)
+                .query("key", &self.api_key)
+                .set("Content-Type", "application/json");
+            let (reason, retryable) = match request.send_string(&body) {
+                Ok(response) => match response.into_string() {
+                    Ok(text) => match serde_json::from_str(&text) {
+                        Ok(parsed) => break parsed,
+                        Err(err) => (format!("Invalid response: {}", err), false),
+                    },
+                    Err(err) => (err.to_string(), true),
+                },
+                Err(ureq::Error::Status(code, response)) => (
+                    format!("HTTP {}: {}", code, response.into_string().unwrap_or_default()),
+                    code == 429 || code >= 500,
+                ),
+                Err(err) => (err.to_string(), true),
+            };
+
+            if !retryable || attempt >= RETRIES {
+                return Err(DetectorError::Backend(format!("Google Vision request failed: {}", reason)));
+            }
+            attempt += 1;
+            log::warn!("Google Vision request failed ({}), retry {}/{}", reason, attempt, RETRIES);
+            std::thread::sleep(RETRY_DELAY * 2u32.pow(attempt - 1));
+        };
+
+        let result = response.responses.into_iter().next().unwrap_or_default();
+        if let Some(error) = result.error {
+            return Err(DetectorError::Backend(format!("Google Vision failed: {}", error.message)));
+        }
+
+        Ok(result
+            .face_annotations
+            .iter()
+            .map(FaceAnnotation::to_face_box)
+            .filter(|face| face.confidence >= threshold)
+            .collect())
+    }
+}
+
+/// AWS Rekognition face detection
+///
+/// Credentials and region come from the standard AWS environment variables and
+/// config files. Requests are rate limited, the SDK retries throttled and
+/// failed ones. Rekognition's confidence (0-100) is scaled to 0-1.
+#[cfg(feature = "rekognition")]
+pub struct RekognitionDetector {
+    runtime: tokio::runtime::Runtime,
+    client: aws_sdk_rekognition::Client,
+    rate: f32,
+}
+
+#[cfg(feature = "rekognition")]
+impl FaceDetector for RekognitionDetector {
+    fn new(config: &DetectorConfig) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| DetectorError::Backend(format!("Failed to start the Rekognition runtime: {}", err)))?;
+        let aws_config = runtime.block_on(
+            aws_config::defaults(aws_config::BehaviorVersion::latest())
+                .retry_config(aws_config::retry::RetryConfig::standard().with_max_attempts(RETRIES + 1))
+                .load(),
+        );
+
+        Ok(Self {
+            client: aws_sdk_rekognition::Client::new(&aws_config),
+            runtime,
+            rate: request_rate(config)?,
+        })
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        use aws_sdk_rekognition::types::{Image, LandmarkType};
+
+        let upload = Image::builder()
+            .bytes(aws_sdk_rekognition::primitives::Blob::new(encode_jpeg(image)?))
+            .build();
+        REKOGNITION_LIMIT.wait(self.rate);
+        let output = self
+            .runtime
+            .block_on(self.client.detect_faces().image(upload).send())
+            .map_err(|err| {
+                DetectorError::Backend(format!(
+                    "Rekognition request failed: {}",
+                    aws_sdk_rekognition::error::DisplayErrorContext(err)
+                ))
+            })?;
+
+        // Boxes and landmarks are fractions of the image size
+        let (width, height) = (image.width() as f32, image.height() as f32);
+        let mut faces = Vec::new();
+        for detail in output.face_details() {
+            let Some(bbox) = detail.bounding_box() else {
+                continue;
+            };
+            let confidence = detail.confidence().unwrap_or(0.0) / 100.0;
+            if confidence < threshold {
+                continue;
+            }
+
+            let landmark = |kind: LandmarkType| {
+                detail
+                    .landmarks()
+                    .iter()
+                    .find(|landmark| landmark.r#type() == Some(&kind))
+                    .and_then(|landmark| Some((landmark.x()? * width, landmark.y()? * height)))
+            };
+            let landmarks: Option<Landmarks> = (|| {
+                Some([
+                    landmark(LandmarkType::EyeLeft)?,
+                    landmark(LandmarkType::EyeRight)?,
+                    landmark(LandmarkType::Nose)?,
+                    landmark(LandmarkType::MouthLeft)?,
+                    landmark(LandmarkType::MouthRight)?,
+                ])
+            })();
+
+            faces.push(FaceBox {
+                x: (bbox.left().unwrap_or(0.0) * width).round() as i32,
+                y: (bbox.top().unwrap_or(0.0) * height).round() as i32,
+                width: (bbox.width().unwrap_or(0.0) * width).round() as i32,
+                height: (bbox.height().unwrap_or(0.0) * height).round() as i32,
+                confidence,
+                landmarks,
+            });
+        }
+
+        Ok(faces)
+    }
+}
@@ -0,0 +1,62 @@
+use super::{transform_box, DetectorConfig, DetectorError, FaceBox, FaceDetector, Result};
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+/// Longest side images are shrunk to when the config doesn't set one (px)
+const DEFAULT_MAX_DIM: u32 = 1600;
+
+/// Detects on a shrunk copy of large images and scales the boxes back up
+///
+/// Detection cost grows with the pixel count while most faces stay detectable at
+/// a fraction of a 40MP photo's resolution. Faces smaller than the detector's
+/// minimum size after shrinking are missed, so keep the limit well above it.
+pub struct DownscaledDetector<D> {
+    inner: D,
+    max_dim: u32,
+}
+
+impl<D: FaceDetector> DownscaledDetector<D> {
+    /// Shrink large images before they reach an existing detector
+    pub fn wrap(inner: D, config: &DetectorConfig) -> Result<Self> {
+        let max_dim = config.detect_max_dim.unwrap_or(DEFAULT_MAX_DIM);
+        if max_dim == 0 {
+            return Err(DetectorError::InvalidParams("Detection size limit must be positive".to_string()));
+        }
+
+        Ok(Self { inner, max_dim })
+    }
+}
+
+impl<D: FaceDetector> FaceDetector for DownscaledDetector<D> {
+    fn new(config: &DetectorConfig) -> Result<Self> {
+        Self::wrap(D::new(config)?, config)
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        if image.width().max(image.height()) <= self.max_dim {
+            return self.inner.detect_faces(image, threshold);
+        }
+
+        let small = image.resize(self.max_dim, self.max_dim, FilterType::Triangle);
+        let scale = image.width() as f32 / small.width() as f32;
+
+        Ok(self
+            .inner
+            .detect_faces(&small, threshold)?
+            .into_iter()
+            .map(|face| transform_box(face, scale, 0.0, 0.0))
+            .collect())
+    }
+
+    fn detect_faces_batch(&mut self, images: &[DynamicImage], threshold: f32) -> Result<Vec<Vec<FaceBox>>> {
+        // Keep batching when nothing needs shrinking
+        if images.iter().all(|image| image.width().max(image.height()) <= self.max_dim) {
+            return self.inner.detect_faces_batch(images, threshold);
+        }
+
+        images
+            .iter()
+            .map(|image| self.detect_faces(image, threshold))
+            .collect()
+    }
+}
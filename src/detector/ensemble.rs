@@ -0,0 +1,176 @@
+use super::{nms_iou, non_max_suppression, DetectorConfig, DetectorError, FaceBox, FaceDetector, Result};
+use image::DynamicImage;
+
+/// How an ensemble merges the boxes its detectors found for the same face
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Fusion {
+    /// Weighted box fusion: average overlapping boxes, weighted by confidence
+    #[default]
+    Wbf,
+    /// Keep the most confident of overlapping boxes
+    Nms,
+}
+
+impl std::str::FromStr for Fusion {
+    type Err = String;
+
+    /// Parse `wbf` or `nms`
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "wbf" => Ok(Fusion::Wbf),
+            "nms" => Ok(Fusion::Nms),
+            _ => Err(format!("Invalid fusion: {} (expected wbf or nms)", value)),
+        }
+    }
+}
+
+/// Several detectors run on every image, their boxes fused into one set
+///
+/// Finds the faces any member finds, at the cost of running all of them.
+/// Confidences are not rescaled between backends, so weighted fusion favours
+/// the backend with the larger scores (rustface scores aren't 0-1).
+pub struct EnsembleDetector {
+    members: Vec<Box<dyn FaceDetector>>,
+    fusion: Fusion,
+    fusion_iou: f32,
+}
+
+impl EnsembleDetector {
+    /// An ensemble of already created detectors, fused as the config says
+    pub fn from_detectors(members: Vec<Box<dyn FaceDetector>>, config: &DetectorConfig) -> Result<Self> {
+        if members.is_empty() {
+            return Err(DetectorError::InvalidParams("An ensemble needs at least one detector".to_string()));
+        }
+
+        Ok(Self {
+            members,
+            fusion: config.fusion,
+            fusion_iou: nms_iou(config)?,
+        })
+    }
+
+    /// Merge the boxes all members found on one image
+    fn fuse(&self, faces: Vec<FaceBox>) -> Vec<FaceBox> {
+        match self.fusion {
+            Fusion::Nms => non_max_suppression(faces, self.fusion_iou),
+            Fusion::Wbf => weighted_box_fusion(faces, self.fusion_iou),
+        }
+    }
+}
+
+impl FaceDetector for EnsembleDetector {
+    fn new(_config: &DetectorConfig) -> Result<Self> {
+        Err(DetectorError::InvalidParams(
+            "An ensemble needs its detectors, create it as ensemble:<detector>,<detector>".to_string(),
+        ))
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        let mut results = self.detect_faces_batch(std::slice::from_ref(image), threshold)?;
+
+        Ok(results.pop().unwrap_or_default())
+    }
+
+    fn detect_faces_batch(&mut self, images: &[DynamicImage], threshold: f32) -> Result<Vec<Vec<FaceBox>>> {
+        let mut merged: Vec<Vec<FaceBox>> = vec![Vec::new(); images.len()];
+        for member in &mut self.members {
+            for (faces, found) in merged.iter_mut().zip(member.detect_faces_batch(images, threshold)?) {
+                faces.extend(found);
+            }
+        }
+
+        Ok(merged.into_iter().map(|faces| self.fuse(faces)).collect())
+    }
+}
+
+/// Weighted box fusion
+///
+/// Boxes are taken most confident first, each joins the cluster whose fused box it
+/// overlaps most by more than `iou_threshold` or starts a new one. A cluster's box
+/// is the confidence-weighted mean of its boxes, its confidence their mean, and
+/// its landmarks those of its most confident box that has them.
+fn weighted_box_fusion(mut faces: Vec<FaceBox>, iou_threshold: f32) -> Vec<FaceBox> {
+    faces.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+    let mut clusters: Vec<(FaceBox, Vec<FaceBox>)> = Vec::new();
+    for face in faces {
+        let best = clusters
+            .iter()
+            .enumerate()
+            .map(|(i, (fused, _))| (i, fused.iou(&face)))
+            .filter(|&(_, iou)| iou > iou_threshold)
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        match best {
+            Some((i, _)) => {
+                let (fused, members) = &mut clusters[i];
+                members.push(face);
+                *fused = fuse_cluster(members);
+            }
+            None => clusters.push((face.clone(), vec![face])),
+        }
+    }
+
+    clusters.into_iter().map(|(fused, _)| fused).collect()
+}
+
+/// The fused box of a cluster, its boxes most confident first
+fn fuse_cluster(faces: &[FaceBox]) -> FaceBox {
+    // Negative scores would flip the weights, so the weights start at zero
+    let weights: Vec<f32> = faces.iter().map(|face| face.confidence.max(0.0)).collect();
+    let total: f32 = weights.iter().sum();
+    let mean = |value: fn(&FaceBox) -> f32| {
+        if total > 0.0 {
+            faces.iter().zip(&weights).map(|(face, weight)| value(face) * weight).sum::<f32>() / total
+        } else {
+            faces.iter().map(value).sum::<f32>() / faces.len() as f32
+        }
+    };
+
+    let x1 = mean(|face| face.x as f32);
+    let y1 = mean(|face| face.y as f32);
+    let x2 = mean(|face| (face.x + face.width) as f32);
+    let y2 = mean(|face| (face.y + face.height) as f32);
+
+    FaceBox {
+        x: x1.round() as i32,
+        y: y1.round() as i32,
+        width: (x2 - x1).round() as i32,
+        height: (y2 - y1).round() as i32,
+        confidence: faces.iter().map(|face| face.confidence).sum::<f32>() / faces.len() as f32,
+        landmarks: faces.iter().find_map(|face| face.landmarks),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn face(x: i32, y: i32, width: i32, height: i32, confidence: f32) -> FaceBox {
+        FaceBox { x, y, width, height, confidence, landmarks: None }
+    }
+
+    #[test]
+    fn wbf_averages_overlapping_boxes_by_confidence() {
+        let landmarks = [(1.0, 1.0); 5];
+        let faces = vec![
+            face(4, 0, 10, 10, 0.25),
+            face(100, 100, 20, 20, 0.6),
+            FaceBox { landmarks: Some(landmarks), ..face(0, 0, 10, 10, 0.75) },
+        ];
+        let fused = weighted_box_fusion(faces, 0.3);
+
+        assert_eq!(fused.len(), 2);
+        let merged = &fused[0];
+        assert_eq!((merged.x, merged.y, merged.width, merged.height), (1, 0, 10, 10));
+        assert!((merged.confidence - 0.5).abs() < 1e-6);
+        assert_eq!(merged.landmarks, Some(landmarks));
+        assert_eq!((fused[1].x, fused[1].width, fused[1].confidence), (100, 20, 0.6));
+    }
+
+    #[test]
+    fn wbf_without_positive_scores_takes_the_plain_mean() {
+        let fused = weighted_box_fusion(vec![face(0, 0, 10, 10, -1.0), face(2, 0, 10, 10, -1.0)], 0.3);
+        assert_eq!(fused.len(), 1);
+        assert_eq!((fused[0].x, fused[0].width), (1, 10));
+    }
+}
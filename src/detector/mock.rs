@@ -0,0 +1,84 @@
+use super::{DetectorConfig, DetectorError, FaceBox, FaceDetector, Landmarks, Result};
+use image::DynamicImage;
+use serde::Deserialize;
+
+/// Cells per side of the grid faces are placed on when no box file is given
+const GRID: u32 = 2;
+
+/// Landmarks as fractions of a face box, roughly where they sit on a frontal face
+const LANDMARKS: Landmarks = [(0.34, 0.46), (0.66, 0.46), (0.5, 0.64), (0.37, 0.82), (0.63, 0.82)];
+
+/// A box of a mock box file, as fractions of the image size
+#[derive(Debug, Clone, Deserialize)]
+struct MockBox {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    #[serde(default = "full_confidence")]
+    confidence: f32,
+}
+
+fn full_confidence() -> f32 {
+    1.0
+}
+
+/// Detector returning the same boxes for every image, for testing without a model
+///
+/// Without a model path it finds a face in the middle of each cell of a 2x2 grid,
+/// otherwise the boxes of the JSON file at the model path: an array of
+/// `{"x", "y", "width", "height"}` objects in fractions of the image size, with
+/// an optional `confidence` (default 1). Every face gets landmarks at typical
+/// positions so alignment can be tested too.
+pub struct MockDetector {
+    boxes: Vec<MockBox>,
+}
+
+impl FaceDetector for MockDetector {
+    fn new(config: &DetectorConfig) -> Result<Self> {
+        let Some(path) = &config.model_path else {
+            let cell = 1.0 / GRID as f32;
+            let boxes = (0..GRID * GRID)
+                .map(|i| MockBox {
+                    x: ((i % GRID) as f32 + 0.25) * cell,
+                    y: ((i / GRID) as f32 + 0.25) * cell,
+                    width: cell / 2.0,
+                    height: cell / 2.0,
+                    confidence: full_confidence(),
+                })
+                .collect();
+            return Ok(Self { boxes });
+        };
+
+        let data = std::fs::read(path).map_err(|err| {
+            DetectorError::InvalidParams(format!("Failed to read mock boxes from {}: {}", path.display(), err))
+        })?;
+        let boxes: Vec<MockBox> = serde_json::from_slice(&data).map_err(|err| {
+            DetectorError::InvalidParams(format!("Invalid mock boxes in {}: {}", path.display(), err))
+        })?;
+
+        Ok(Self { boxes })
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        let (width, height) = (image.width() as f32, image.height() as f32);
+
+        Ok(self
+            .boxes
+            .iter()
+            .filter(|mock| mock.confidence >= threshold)
+            .map(|mock| {
+                let (x, y) = (mock.x * width, mock.y * height);
+                let (box_width, box_height) = (mock.width * width, mock.height * height);
+                FaceBox {
+                    x: x.round() as i32,
+                    y: y.round() as i32,
+                    width: box_width.round() as i32,
+                    height: box_height.round() as i32,
+                    confidence: mock.confidence,
+                    landmarks: Some(LANDMARKS.map(|(lx, ly)| (x + lx * box_width, y + ly * box_height))),
+                }
+            })
+            .collect())
+    }
+}
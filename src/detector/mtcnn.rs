@@ -0,0 +1,407 @@
+use super::{
+    load_session_on, nms_iou, non_max_suppression, supports_batching, DetectorConfig, DetectorError, FaceBox, FaceDetector,
+    Landmarks, Result,
+};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, RgbImage};
+use ort::session::Session;
+use std::path::PathBuf;
+
+/// Face probability a candidate needs to pass the P-Net, R-Net and O-Net stages
+const STAGE_THRESHOLDS: [f32; 3] = [0.6, 0.7, 0.7];
+
+/// P-Net window size and stride on each pyramid level (px)
+const PNET_CELL: f32 = 12.0;
+const PNET_STRIDE: f32 = 2.0;
+
+/// MTCNN cascade (P-Net, R-Net, O-Net) run through ONNX Runtime
+///
+/// Loads `pnet.onnx`, `rnet.onnx` and `onet.onnx` from the model directory. Expects
+/// the layout of the facenet-pytorch exports: NCHW RGB input normalized as
+/// (pixel - 127.5) / 128, and per network a 2-channel face probability output, a
+/// 4-channel box regression output and, for the O-Net, 10 landmark values (the five
+/// x coordinates followed by the five y coordinates, relative to the box). Outputs
+/// are told apart by their channel count, so their order doesn't matter.
+pub struct MtcnnDetector {
+    pnet: Session,
+    rnet: Session,
+    onet: Session,
+    min_face_size: f32,
+    scale_factor: f32,
+    nms_iou: f32,
+}
+
+/// A face candidate passed between the stages, in source image coordinates
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    x1: f32,
+    y1: f32,
+    x2: f32,
+    y2: f32,
+    score: f32,
+    reg: [f32; 4], // Box regression predicted by the last stage
+}
+
+/// How box overlap is normalized during NMS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Overlap {
+    Union,
+    Min,
+}
+
+/// Output tensor copied out of a session run
+struct Output {
+    shape: Vec<i64>,
+    data: Vec<f32>,
+}
+
+impl MtcnnDetector {
+    /// Model directory used when the config doesn't name one
+    const DEFAULT_MODEL_DIR: &'static str = "model/mtcnn";
+
+    /// Run the P-Net over an image pyramid, returning squared candidate boxes
+    fn propose(&mut self, rgb: &RgbImage) -> Result<Vec<Candidate>> {
+        let (width, height) = rgb.dimensions();
+
+        // Scale so the smallest face fills the P-Net cell, then shrink until the image does
+        let mut scale = PNET_CELL / self.min_face_size;
+        let mut min_side = width.min(height) as f32 * scale;
+        let mut candidates = Vec::new();
+        while min_side >= PNET_CELL {
+            let scaled_width = (width as f32 * scale + 1.0) as u32;
+            let scaled_height = (height as f32 * scale + 1.0) as u32;
+            let resized = imageops::resize(rgb, scaled_width, scaled_height, FilterType::Triangle);
+
+            let outputs = run(&mut self.pnet, to_chw(&resized), [1, 3, scaled_height as usize, scaled_width as usize])?;
+            let prob = output_with_channels(&outputs, 2, "P-Net")?;
+            let reg = output_with_channels(&outputs, 4, "P-Net")?;
+            let (map_height, map_width) = match prob.shape[..] {
+                [_, _, h, w] => (h as usize, w as usize),
+                _ => return Err(DetectorError::Backend(format!("Unexpected P-Net output shape: {:?}", prob.shape))),
+            };
+            let plane = map_height * map_width;
+
+            let mut level = Vec::new();
+            for y in 0..map_height {
+                for x in 0..map_width {
+                    let i = y * map_width + x;
+                    let score = prob.data[plane + i];
+                    if score < STAGE_THRESHOLDS[0] {
+                        continue;
+                    }
+
+                    level.push(Candidate {
+                        x1: ((PNET_STRIDE * x as f32 + 1.0) / scale).floor(),
+                        y1: ((PNET_STRIDE * y as f32 + 1.0) / scale).floor(),
+                        x2: ((PNET_STRIDE * x as f32 + PNET_CELL) / scale).floor(),
+                        y2: ((PNET_STRIDE * y as f32 + PNET_CELL) / scale).floor(),
+                        score,
+                        reg: [reg.data[i], reg.data[plane + i], reg.data[2 * plane + i], reg.data[3 * plane + i]],
+                    });
+                }
+            }
+            candidates.extend(nms(level, 0.5, Overlap::Union));
+
+            scale *= self.scale_factor;
+            min_side *= self.scale_factor;
+        }
+
+        Ok(nms(candidates, 0.7, Overlap::Union)
+            .into_iter()
+            .map(|candidate| square(regress(candidate)))
+            .collect())
+    }
+
+    /// Run the R-Net on the proposals, keeping and refining the ones it accepts
+    fn refine(&mut self, rgb: &RgbImage, candidates: Vec<Candidate>) -> Result<Vec<Candidate>> {
+        if candidates.is_empty() {
+            return Ok(candidates);
+        }
+
+        let outputs = run_crops(&mut self.rnet, rgb, &candidates, 24)?;
+        let prob = output_with_channels(&outputs, 2, "R-Net")?;
+        let reg = output_with_channels(&outputs, 4, "R-Net")?;
+
+        let mut kept = Vec::new();
+        for (i, candidate) in candidates.into_iter().enumerate() {
+            let score = prob.data[i * 2 + 1];
+            if score >= STAGE_THRESHOLDS[1] {
+                kept.push(Candidate {
+                    score,
+                    reg: [reg.data[i * 4], reg.data[i * 4 + 1], reg.data[i * 4 + 2], reg.data[i * 4 + 3]],
+                    ..candidate
+                });
+            }
+        }
+
+        Ok(nms(kept, 0.7, Overlap::Union)
+            .into_iter()
+            .map(|candidate| square(regress(candidate)))
+            .collect())
+    }
+
+    /// Run the O-Net on the refined boxes, producing the final faces with landmarks
+    fn output(&mut self, rgb: &RgbImage, candidates: Vec<Candidate>) -> Result<Vec<(Candidate, Landmarks)>> {
+        if candidates.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let outputs = run_crops(&mut self.onet, rgb, &candidates, 48)?;
+        let prob = output_with_channels(&outputs, 2, "O-Net")?;
+        let reg = output_with_channels(&outputs, 4, "O-Net")?;
+        let points = output_with_channels(&outputs, 10, "O-Net")?;
+
+        let mut faces = Vec::new();
+        for (i, candidate) in candidates.into_iter().enumerate() {
+            let score = prob.data[i * 2 + 1];
+            if score < STAGE_THRESHOLDS[2] {
+                continue;
+            }
+
+            // Landmarks are relative to the box the O-Net saw, before regression
+            let width = candidate.x2 - candidate.x1 + 1.0;
+            let height = candidate.y2 - candidate.y1 + 1.0;
+            let p = &points.data[i * 10..i * 10 + 10];
+            let mut landmarks = [(0.0, 0.0); 5];
+            for (j, landmark) in landmarks.iter_mut().enumerate() {
+                *landmark = (width * p[j] + candidate.x1 - 1.0, height * p[j + 5] + candidate.y1 - 1.0);
+            }
+
+            let candidate = regress(Candidate {
+                score,
+                reg: [reg.data[i * 4], reg.data[i * 4 + 1], reg.data[i * 4 + 2], reg.data[i * 4 + 3]],
+                ..candidate
+            });
+            faces.push((candidate, landmarks));
+        }
+
+        // Same greedy NMS as `nms`, carrying the landmarks along
+        faces.sort_by(|a, b| b.0.score.total_cmp(&a.0.score));
+        let mut kept: Vec<(Candidate, Landmarks)> = Vec::new();
+        for face in faces {
+            if kept.iter().all(|k| overlap(&k.0, &face.0, Overlap::Min) <= 0.7) {
+                kept.push(face);
+            }
+        }
+
+        Ok(kept)
+    }
+}
+
+impl FaceDetector for MtcnnDetector {
+    fn new(config: &DetectorConfig) -> Result<Self> {
+        let min_face_size = config.min_face_size.unwrap_or(20);
+        if min_face_size < 12 {
+            return Err(DetectorError::InvalidParams(format!("Minimum face size must be at least 12 px, got {}", min_face_size)));
+        }
+        let scale_factor = config.pyramid_scale_factor.unwrap_or(0.709);
+        if !(0.01..=0.99).contains(&scale_factor) {
+            return Err(DetectorError::InvalidParams(format!("Pyramid scale factor must be between 0.01 and 0.99, got {}", scale_factor)));
+        }
+
+        let model_dir = config
+            .model_path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(Self::DEFAULT_MODEL_DIR));
+        let load = |name: &str| {
+            let path = model_dir.join(name);
+            if !path.exists() {
+                return Err(DetectorError::ModelNotFound {
+                    path,
+                    hint: "Export pnet.onnx, rnet.onnx and onet.onnx (e.g. from facenet-pytorch) into \
+                        one directory and pass it with --model-path <dir>",
+                });
+            }
+            load_session_on(&path, config.device, config.gpu_id)
+        };
+
+        Ok(Self {
+            pnet: load("pnet.onnx")?,
+            rnet: load("rnet.onnx")?,
+            onet: load("onet.onnx")?,
+            min_face_size: min_face_size as f32,
+            scale_factor,
+            nms_iou: nms_iou(config)?,
+        })
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        let rgb = image.to_rgb8();
+
+        let candidates = self.propose(&rgb)?;
+        let candidates = self.refine(&rgb, candidates)?;
+        let faces = self.output(&rgb, candidates)?;
+
+        let faces = faces
+            .into_iter()
+            .filter(|(candidate, _)| candidate.score >= threshold)
+            .map(|(candidate, landmarks)| FaceBox {
+                x: candidate.x1.round() as i32,
+                y: candidate.y1.round() as i32,
+                width: (candidate.x2 - candidate.x1).round() as i32,
+                height: (candidate.y2 - candidate.y1).round() as i32,
+                confidence: candidate.score,
+                landmarks: Some(landmarks),
+            })
+            .collect();
+
+        Ok(non_max_suppression(faces, self.nms_iou))
+    }
+}
+
+/// Run a network on one NCHW input and copy its outputs
+fn run(session: &mut Session, input: Vec<f32>, shape: [usize; 4]) -> Result<Vec<Output>> {
+    let tensor = ort::value::Tensor::from_array((shape, input))
+        .map_err(|err| DetectorError::Backend(format!("Failed to build ONNX input tensor: {}", err)))?;
+
+    let input_name = session.inputs()[0].name().to_string();
+    let outputs = session
+        .run(ort::inputs![input_name => tensor])
+        .map_err(|err| DetectorError::Backend(format!("ONNX inference failed: {}", err)))?;
+
+    outputs
+        .values()
+        .map(|value| {
+            let (shape, data) = value
+                .try_extract_tensor::<f32>()
+                .map_err(|err| DetectorError::Backend(format!("Failed to read MTCNN output: {}", err)))?;
+            Ok(Output {
+                shape: shape.to_vec(),
+                data: data.to_vec(),
+            })
+        })
+        .collect()
+}
+
+/// Crop the candidates to `size`x`size` and run a network on them, batched when the model allows
+fn run_crops(session: &mut Session, rgb: &RgbImage, candidates: &[Candidate], size: u32) -> Result<Vec<Output>> {
+    let crops: Vec<Vec<f32>> = candidates
+        .iter()
+        .map(|candidate| to_chw(&crop_candidate(rgb, candidate, size)))
+        .collect();
+
+    let side = size as usize;
+    if supports_batching(session) {
+        return run(session, crops.concat(), [crops.len(), 3, side, side]);
+    }
+
+    // Fixed batch size of 1: run the crops one by one and stack the results
+    let mut stacked: Vec<Output> = Vec::new();
+    for crop in crops {
+        let outputs = run(session, crop, [1, 3, side, side])?;
+        if stacked.is_empty() {
+            stacked = outputs;
+        } else {
+            for (stack, output) in stacked.iter_mut().zip(outputs) {
+                stack.shape[0] += 1;
+                stack.data.extend(output.data);
+            }
+        }
+    }
+
+    Ok(stacked)
+}
+
+/// Find the output with the given number of channels (dimension 1)
+fn output_with_channels<'a>(outputs: &'a [Output], channels: i64, network: &str) -> Result<&'a Output> {
+    outputs
+        .iter()
+        .find(|output| output.shape.get(1) == Some(&channels))
+        .ok_or_else(|| DetectorError::Backend(format!("Unsupported {} model: no output with {} channels", network, channels)))
+}
+
+/// Cut a candidate box out of the image and resize it, areas outside the image are black
+fn crop_candidate(rgb: &RgbImage, candidate: &Candidate, size: u32) -> RgbImage {
+    let (width, height) = rgb.dimensions();
+    let x1 = candidate.x1.trunc() as i64;
+    let y1 = candidate.y1.trunc() as i64;
+    let x2 = candidate.x2.trunc() as i64;
+    let y2 = candidate.y2.trunc() as i64;
+    let box_width = (x2 - x1 + 1).max(1) as u32;
+    let box_height = (y2 - y1 + 1).max(1) as u32;
+
+    let mut patch = RgbImage::new(box_width, box_height);
+    let left = x1.max(0);
+    let top = y1.max(0);
+    let right = (x2 + 1).min(i64::from(width));
+    let bottom = (y2 + 1).min(i64::from(height));
+    if right > left && bottom > top {
+        let inside = imageops::crop_imm(rgb, left as u32, top as u32, (right - left) as u32, (bottom - top) as u32);
+        imageops::replace(&mut patch, &*inside, left - x1, top - y1);
+    }
+
+    imageops::resize(&patch, size, size, FilterType::Triangle)
+}
+
+/// Normalize an RGB image into CHW network input
+fn to_chw(rgb: &RgbImage) -> Vec<f32> {
+    let plane = (rgb.width() * rgb.height()) as usize;
+    let mut input = vec![0.0; 3 * plane];
+    for (i, pixel) in rgb.pixels().enumerate() {
+        for channel in 0..3 {
+            input[channel * plane + i] = (f32::from(pixel[channel]) - 127.5) / 128.0;
+        }
+    }
+
+    input
+}
+
+/// Apply a candidate's box regression
+fn regress(candidate: Candidate) -> Candidate {
+    let width = candidate.x2 - candidate.x1 + 1.0;
+    let height = candidate.y2 - candidate.y1 + 1.0;
+    let [dx1, dy1, dx2, dy2] = candidate.reg;
+
+    Candidate {
+        x1: candidate.x1 + dx1 * width,
+        y1: candidate.y1 + dy1 * height,
+        x2: candidate.x2 + dx2 * width,
+        y2: candidate.y2 + dy2 * height,
+        ..candidate
+    }
+}
+
+/// Grow a box into a square around its center
+fn square(candidate: Candidate) -> Candidate {
+    let width = candidate.x2 - candidate.x1;
+    let height = candidate.y2 - candidate.y1;
+    let side = width.max(height);
+    let x1 = candidate.x1 + width * 0.5 - side * 0.5;
+    let y1 = candidate.y1 + height * 0.5 - side * 0.5;
+
+    Candidate {
+        x1,
+        y1,
+        x2: x1 + side,
+        y2: y1 + side,
+        ..candidate
+    }
+}
+
+/// Overlap of two boxes, relative to their union or to the smaller box
+fn overlap(a: &Candidate, b: &Candidate, mode: Overlap) -> f32 {
+    let area = |c: &Candidate| (c.x2 - c.x1 + 1.0) * (c.y2 - c.y1 + 1.0);
+    let width = (a.x2.min(b.x2) - a.x1.max(b.x1) + 1.0).max(0.0);
+    let height = (a.y2.min(b.y2) - a.y1.max(b.y1) + 1.0).max(0.0);
+    let intersection = width * height;
+
+    let denominator = match mode {
+        Overlap::Union => area(a) + area(b) - intersection,
+        Overlap::Min => area(a).min(area(b)),
+    };
+    if denominator <= 0.0 { 0.0 } else { intersection / denominator }
+}
+
+/// Greedy NMS: keep the most confident candidate out of each group of overlapping ones
+fn nms(mut candidates: Vec<Candidate>, threshold: f32, mode: Overlap) -> Vec<Candidate> {
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    let mut kept: Vec<Candidate> = Vec::new();
+    for candidate in candidates {
+        if kept.iter().all(|k| overlap(k, &candidate, mode) <= threshold) {
+            kept.push(candidate);
+        }
+    }
+
+    kept
+}
@@ -0,0 +1,178 @@
+use super::{DetectorConfig, DetectorError, FaceBox, FaceDetector, Landmarks, Result};
+use image::DynamicImage;
+use libloading::Library;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::path::{Path, PathBuf};
+
+/// Faces there is room for in the first call to a plugin, grown when it finds more
+const INITIAL_CAPACITY: usize = 64;
+
+/// A face as a plugin reports it, in image pixels
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PluginFace {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub confidence: f32,
+    /// Nonzero when `landmarks` is filled in
+    pub has_landmarks: u32,
+    /// Five (x, y) pairs in the order of [`Landmarks`]
+    pub landmarks: [f32; 10],
+}
+
+/// `void *fc_detector_init(const char *model_path)`, NULL on failure
+type InitFn = unsafe extern "C" fn(model_path: *const c_char) -> *mut c_void;
+
+/// `int fc_detector_detect(void *state, const uint8_t *rgb, uint32_t width, uint32_t height,
+/// float threshold, fc_face *faces, size_t capacity, size_t *count)`, 0 on success
+type DetectFn = unsafe extern "C" fn(
+    state: *mut c_void,
+    rgb: *const u8,
+    width: u32,
+    height: u32,
+    threshold: f32,
+    faces: *mut PluginFace,
+    capacity: usize,
+    count: *mut usize,
+) -> c_int;
+
+/// `void fc_detector_free(void *state)`
+type FreeFn = unsafe extern "C" fn(state: *mut c_void);
+
+/// Optional `const char *fc_detector_last_error(void *state)`, the reason the last call failed
+type LastErrorFn = unsafe extern "C" fn(state: *mut c_void) -> *const c_char;
+
+/// A detector loaded from a shared library over a small C ABI
+///
+/// The library exports `fc_detector_init`, `fc_detector_detect` and
+/// `fc_detector_free` (see the README for their contract). Each detector gets
+/// its own state from `fc_detector_init`, so a plugin only has to be safe to use
+/// from several threads with separate states.
+pub struct PluginDetector {
+    state: *mut c_void,
+    detect: DetectFn,
+    free: FreeFn,
+    last_error: Option<LastErrorFn>,
+    /// Buffer the plugin writes faces to, reused between images
+    faces: Vec<PluginFace>,
+    path: PathBuf,
+    // Dropped after the state is freed so the functions above stay loaded
+    _library: Library,
+}
+
+impl PluginDetector {
+    /// Load the plugin at `path` and initialize it with the configured model path
+    pub fn load(path: &Path, config: &DetectorConfig) -> Result<Self> {
+        let failed = |reason: String| DetectorError::Backend(format!("Failed to load detector plugin {}: {}", path.display(), reason));
+
+        log::info!("Loading detector plugin from: {}", path.display());
+        // SAFETY: loading runs the library's initializers, trusting it is a detector plugin
+        let library = unsafe { Library::new(path) }.map_err(|err| failed(err.to_string()))?;
+
+        // SAFETY: the symbols are declared with the signatures of the plugin ABI
+        let (init, detect, free, last_error) = unsafe {
+            let init = *library.get::<InitFn>(b"fc_detector_init\0").map_err(|err| failed(err.to_string()))?;
+            let detect = *library.get::<DetectFn>(b"fc_detector_detect\0").map_err(|err| failed(err.to_string()))?;
+            let free = *library.get::<FreeFn>(b"fc_detector_free\0").map_err(|err| failed(err.to_string()))?;
+            let last_error = library.get::<LastErrorFn>(b"fc_detector_last_error\0").ok().map(|symbol| *symbol);
+            (init, detect, free, last_error)
+        };
+
+        let model_path = config
+            .model_path
+            .as_ref()
+            .map(|model_path| CString::new(model_path.to_string_lossy().into_owned()))
+            .transpose()
+            .map_err(|_| DetectorError::InvalidParams("Model path contains a NUL byte".to_string()))?;
+        // SAFETY: the model path is NULL or a NUL-terminated string that outlives the call
+        let state = unsafe { init(model_path.as_ref().map_or(std::ptr::null(), |model_path| model_path.as_ptr())) };
+        if state.is_null() {
+            return Err(failed("fc_detector_init failed".to_string()));
+        }
+
+        Ok(Self {
+            state,
+            detect,
+            free,
+            last_error,
+            faces: vec![PluginFace::default(); INITIAL_CAPACITY],
+            path: path.to_path_buf(),
+            _library: library,
+        })
+    }
+
+    /// Why the last call failed, as far as the plugin says
+    fn error(&self) -> DetectorError {
+        // SAFETY: the plugin returns NULL or a NUL-terminated string valid until its next call
+        let reason = self
+            .last_error
+            .map(|last_error| unsafe { last_error(self.state) })
+            .filter(|reason| !reason.is_null())
+            .map(|reason| unsafe { CStr::from_ptr(reason) }.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "no reason given".to_string());
+
+        DetectorError::Backend(format!("Detector plugin {} failed: {}", self.path.display(), reason))
+    }
+}
+
+impl FaceDetector for PluginDetector {
+    fn new(_config: &DetectorConfig) -> Result<Self> {
+        Err(DetectorError::InvalidParams(
+            "A plugin detector needs its library, create it as plugin:<path>".to_string(),
+        ))
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        let rgb = image.to_rgb8();
+
+        // A plugin finding more faces than there is room for reports how many,
+        // the image is then detected again with room for all of them
+        let mut count = 0;
+        loop {
+            // SAFETY: the pixels are width * height * 3 bytes and the buffer has room for `capacity` faces
+            let status = unsafe {
+                (self.detect)(
+                    self.state,
+                    rgb.as_ptr(),
+                    rgb.width(),
+                    rgb.height(),
+                    threshold,
+                    self.faces.as_mut_ptr(),
+                    self.faces.len(),
+                    &mut count,
+                )
+            };
+            if status != 0 {
+                return Err(self.error());
+            }
+            if count <= self.faces.len() {
+                break;
+            }
+            self.faces.resize(count, PluginFace::default());
+        }
+
+        Ok(self.faces[..count]
+            .iter()
+            .map(|face| FaceBox {
+                x: face.x.round() as i32,
+                y: face.y.round() as i32,
+                width: face.width.round() as i32,
+                height: face.height.round() as i32,
+                confidence: face.confidence,
+                landmarks: (face.has_landmarks != 0).then(|| {
+                    let points: Landmarks = std::array::from_fn(|i| (face.landmarks[i * 2], face.landmarks[i * 2 + 1]));
+                    points
+                }),
+            })
+            .collect())
+    }
+}
+
+impl Drop for PluginDetector {
+    fn drop(&mut self) {
+        // SAFETY: the state came from fc_detector_init and is freed once
+        unsafe { (self.free)(self.state) };
+    }
+}
@@ -0,0 +1,114 @@
+use super::{transform_box, DetectorConfig, DetectorError, FaceBox, FaceDetector, Result};
+use image::DynamicImage;
+
+/// Region of interest of an image: x, y, width and height, in pixels or as fractions of the image size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Roi {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// Whether the values are fractions of the image size rather than pixels
+    pub fractional: bool,
+}
+
+impl Roi {
+    /// The region on a `width`x`height` image in whole pixels, clipped to the image;
+    /// None when nothing of it lies on the image
+    pub fn pixels(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        let (scale_x, scale_y) = if self.fractional { (width as f32, height as f32) } else { (1.0, 1.0) };
+        let left = (self.x * scale_x).round().clamp(0.0, width as f32) as u32;
+        let top = (self.y * scale_y).round().clamp(0.0, height as f32) as u32;
+        let right = ((self.x + self.width) * scale_x).round().clamp(0.0, width as f32) as u32;
+        let bottom = ((self.y + self.height) * scale_y).round().clamp(0.0, height as f32) as u32;
+
+        (right > left && bottom > top).then(|| (left, top, right - left, bottom - top))
+    }
+}
+
+impl std::str::FromStr for Roi {
+    type Err = String;
+
+    /// Parse `x,y,w,h`, as fractions of the image size when every value is at most 1
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("Invalid region: {} (expected x,y,w,h in pixels or fractions of the image, e.g. 0.25,0,0.5,1)", value);
+        let values: Vec<f32> = value
+            .split(',')
+            .map(|number| number.trim().parse::<f32>())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| invalid())?;
+        let [x, y, width, height] = values[..] else {
+            return Err(invalid());
+        };
+        if values.iter().any(|v| !v.is_finite() || *v < 0.0) || width == 0.0 || height == 0.0 {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+            fractional: values.iter().all(|&v| v <= 1.0),
+        })
+    }
+}
+
+/// Detects only inside a region of each image
+///
+/// The wrapped detector sees the region cut out of the image, and its boxes are
+/// moved back into image coordinates, so crops may still reach past the region.
+/// Images the region misses entirely have no faces.
+pub struct RoiDetector<D> {
+    inner: D,
+    roi: Roi,
+}
+
+impl<D: FaceDetector> RoiDetector<D> {
+    /// Restrict an existing detector to the configured region
+    pub fn wrap(inner: D, config: &DetectorConfig) -> Result<Self> {
+        let roi = config
+            .roi
+            .ok_or_else(|| DetectorError::InvalidParams("Region detection needs a region".to_string()))?;
+
+        Ok(Self { inner, roi })
+    }
+}
+
+impl<D: FaceDetector> FaceDetector for RoiDetector<D> {
+    fn new(config: &DetectorConfig) -> Result<Self> {
+        Self::wrap(D::new(config)?, config)
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        let mut results = self.detect_faces_batch(std::slice::from_ref(image), threshold)?;
+
+        Ok(results.pop().unwrap_or_default())
+    }
+
+    fn detect_faces_batch(&mut self, images: &[DynamicImage], threshold: f32) -> Result<Vec<Vec<FaceBox>>> {
+        let regions: Vec<Option<(u32, u32, u32, u32)>> =
+            images.iter().map(|image| self.roi.pixels(image.width(), image.height())).collect();
+        // The regions of all images that have one go to the wrapped detector as one batch
+        let cut: Vec<DynamicImage> = images
+            .iter()
+            .zip(&regions)
+            .filter_map(|(image, region)| region.map(|(x, y, width, height)| image.crop_imm(x, y, width, height)))
+            .collect();
+        let cut_faces = if cut.is_empty() { Vec::new() } else { self.inner.detect_faces_batch(&cut, threshold)? };
+        let mut cut_faces = cut_faces.into_iter();
+
+        Ok(regions
+            .iter()
+            .map(|region| match region {
+                Some((x, y, _, _)) => cut_faces
+                    .next()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|face| transform_box(face, 1.0, *x as f32, *y as f32))
+                    .collect(),
+                None => Vec::new(),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,141 @@
+use super::{nms_iou, non_max_suppression, DetectorConfig, DetectorError, FaceBox, FaceDetector, Result};
+use crate::cropping::{sample_bilinear, PadFill};
+use image::{DynamicImage, RgbImage};
+
+/// Detects on copies of the image turned by each of a set of angles
+///
+/// Boxes found on a turned copy are mapped back onto the image by their center,
+/// keeping their size (width and height swapped when the copy was turned closer
+/// to 90° than to 0°), and landmarks are mapped back exactly so aligned crops come
+/// out upright. The boxes of all angles are merged with NMS. Quarter turns are
+/// lossless; other angles are resampled onto a canvas large enough for the whole
+/// image, costing a bilinear pass per angle.
+pub struct RotatedDetector<D> {
+    inner: D,
+    /// Clockwise turns in degrees, in [0, 360)
+    angles: Vec<f32>,
+    nms_iou: f32,
+}
+
+impl<D: FaceDetector> RotatedDetector<D> {
+    /// Sweep an existing detector over the configured rotations
+    pub fn wrap(inner: D, config: &DetectorConfig) -> Result<Self> {
+        let mut angles: Vec<f32> = Vec::with_capacity(config.rotations.len());
+        for &angle in &config.rotations {
+            if !angle.is_finite() {
+                return Err(DetectorError::InvalidParams(format!("Invalid rotation: {}", angle)));
+            }
+            let angle = angle.rem_euclid(360.0);
+            if !angles.contains(&angle) {
+                angles.push(angle);
+            }
+        }
+        if angles.is_empty() {
+            return Err(DetectorError::InvalidParams("Rotation sweep needs at least one angle".to_string()));
+        }
+
+        Ok(Self {
+            inner,
+            angles,
+            nms_iou: nms_iou(config)?,
+        })
+    }
+}
+
+impl<D: FaceDetector> FaceDetector for RotatedDetector<D> {
+    fn new(config: &DetectorConfig) -> Result<Self> {
+        Self::wrap(D::new(config)?, config)
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        let mut results = self.detect_faces_batch(std::slice::from_ref(image), threshold)?;
+
+        Ok(results.pop().unwrap_or_default())
+    }
+
+    fn detect_faces_batch(&mut self, images: &[DynamicImage], threshold: f32) -> Result<Vec<Vec<FaceBox>>> {
+        // All turned copies of all images go to the wrapped detector as one batch
+        let turned: Vec<DynamicImage> = images
+            .iter()
+            .flat_map(|image| self.angles.iter().map(move |&angle| rotate(image, angle)))
+            .collect();
+        let turned_faces = self.inner.detect_faces_batch(&turned, threshold)?;
+
+        Ok(images
+            .iter()
+            .zip(turned_faces.chunks(self.angles.len()))
+            .map(|(image, faces)| {
+                let merged = self
+                    .angles
+                    .iter()
+                    .zip(faces)
+                    .flat_map(|(&angle, faces)| faces.iter().map(move |face| map_back(face, angle, image)))
+                    .collect();
+                non_max_suppression(merged, self.nms_iou)
+            })
+            .collect())
+    }
+}
+
+/// Size of an image turned by `angle` degrees, the bounding box of the turned image
+fn turned_size(width: u32, height: u32, angle: f32) -> (u32, u32) {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let (sin, cos) = (sin.abs(), cos.abs());
+    let (width, height) = (width as f32, height as f32);
+    // The rounding error of sin/cos at quarter turns mustn't add a pixel
+    let fit = |size: f32| (size - 1e-3).ceil().max(1.0) as u32;
+
+    (fit(width * cos + height * sin), fit(width * sin + height * cos))
+}
+
+/// An image turned clockwise by `angle` degrees about its center, corners outside it black
+fn rotate(image: &DynamicImage, angle: f32) -> DynamicImage {
+    match angle {
+        0.0 => return image.clone(),
+        90.0 => return image.rotate90(),
+        180.0 => return image.rotate180(),
+        270.0 => return image.rotate270(),
+        _ => {}
+    }
+
+    let source = image.to_rgb8();
+    let (width, height) = turned_size(image.width(), image.height(), angle);
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let (source_cx, source_cy) = (image.width() as f32 / 2.0, image.height() as f32 / 2.0);
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+
+    let turned = RgbImage::from_fn(width, height, |u, v| {
+        let dx = u as f32 + 0.5 - cx;
+        let dy = v as f32 + 0.5 - cy;
+        sample_bilinear(&source, source_cx + dx * cos + dy * sin, source_cy - dx * sin + dy * cos, PadFill::Black)
+    });
+
+    DynamicImage::ImageRgb8(turned)
+}
+
+/// Map a box found on the copy of `image` turned by `angle` back onto the image
+fn map_back(face: &FaceBox, angle: f32, image: &DynamicImage) -> FaceBox {
+    let (width, height) = turned_size(image.width(), image.height(), angle);
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let (source_cx, source_cy) = (image.width() as f32 / 2.0, image.height() as f32 / 2.0);
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    let point = |(x, y): (f32, f32)| {
+        let (dx, dy) = (x - cx, y - cy);
+        (source_cx + dx * cos + dy * sin, source_cy - dx * sin + dy * cos)
+    };
+
+    let (center_x, center_y) = point((
+        face.x as f32 + face.width as f32 / 2.0,
+        face.y as f32 + face.height as f32 / 2.0,
+    ));
+    let (w, h) = if sin.abs() > cos.abs() { (face.height, face.width) } else { (face.width, face.height) };
+
+    FaceBox {
+        x: (center_x - w as f32 / 2.0).round() as i32,
+        y: (center_y - h as f32 / 2.0).round() as i32,
+        width: w,
+        height: h,
+        confidence: face.confidence,
+        landmarks: face.landmarks.map(|points| points.map(point)),
+    }
+}
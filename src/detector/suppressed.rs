@@ -0,0 +1,42 @@
+use super::{nms_iou, non_max_suppression, DetectorConfig, FaceBox, FaceDetector, Result};
+use image::DynamicImage;
+
+/// Merges the overlapping boxes of a detector that doesn't run NMS itself
+///
+/// The built-in backends suppress their own boxes at the configured IoU; plugins
+/// and registered detectors are wrapped in this so `--nms-iou` applies to them too.
+/// Boxes already suppressed at the same IoU pass through unchanged.
+pub struct SuppressedDetector<D> {
+    inner: D,
+    nms_iou: f32,
+}
+
+impl<D: FaceDetector> SuppressedDetector<D> {
+    /// Suppress the boxes of an existing detector at the configured IoU
+    pub fn wrap(inner: D, config: &DetectorConfig) -> Result<Self> {
+        Ok(Self { inner, nms_iou: nms_iou(config)? })
+    }
+}
+
+impl<D: FaceDetector> FaceDetector for SuppressedDetector<D> {
+    fn new(config: &DetectorConfig) -> Result<Self> {
+        Self::wrap(D::new(config)?, config)
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        Ok(non_max_suppression(self.inner.detect_faces(image, threshold)?, self.nms_iou))
+    }
+
+    fn detect_faces_batch(&mut self, images: &[DynamicImage], threshold: f32) -> Result<Vec<Vec<FaceBox>>> {
+        Ok(self
+            .inner
+            .detect_faces_batch(images, threshold)?
+            .into_iter()
+            .map(|faces| non_max_suppression(faces, self.nms_iou))
+            .collect())
+    }
+
+    fn detect_luma8(&mut self, data: &[u8], width: u32, height: u32, threshold: f32) -> Result<Vec<FaceBox>> {
+        Ok(non_max_suppression(self.inner.detect_luma8(data, width, height, threshold)?, self.nms_iou))
+    }
+}
@@ -0,0 +1,121 @@
+use super::{nms_iou, non_max_suppression, transform_box, DetectorConfig, DetectorError, FaceBox, FaceDetector, Result};
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+/// Tile limit used when the config doesn't set one (megapixels)
+const DEFAULT_TILE_MEGAPIXELS: f32 = 16.0;
+
+/// Fraction of a tile shared with each neighbour, faces up to this size always fit in a tile
+const TILE_OVERLAP: f32 = 0.25;
+
+/// Boxes this close to a tile edge inside the image are cut off and left to the neighbouring tile (px)
+const EDGE_MARGIN: i32 = 2;
+
+/// Detects on overlapping tiles of images above a pixel limit
+///
+/// Smaller images go to the wrapped detector as they are. Larger ones are split
+/// into square tiles of at most the limit, which overlap so that every face up to
+/// a quarter of the tile size lies entirely in one of them. Boxes cut by an inner
+/// tile edge are dropped, and a detection pass over the image scaled down to the
+/// limit finds the faces too large for a tile. The boxes of all passes are merged
+/// with NMS.
+pub struct TiledDetector<D> {
+    inner: D,
+    max_pixels: u64,
+    nms_iou: f32,
+}
+
+impl<D: FaceDetector> TiledDetector<D> {
+    /// Tile the images of an existing detector
+    pub fn wrap(inner: D, config: &DetectorConfig) -> Result<Self> {
+        let megapixels = config.tile_megapixels.unwrap_or(DEFAULT_TILE_MEGAPIXELS);
+        if megapixels.is_nan() || megapixels <= 0.0 {
+            return Err(DetectorError::InvalidParams(format!("Tile size must be positive, got {} MP", megapixels)));
+        }
+
+        Ok(Self {
+            inner,
+            max_pixels: (f64::from(megapixels) * 1e6) as u64,
+            nms_iou: nms_iou(config)?,
+        })
+    }
+}
+
+impl<D: FaceDetector> FaceDetector for TiledDetector<D> {
+    fn new(config: &DetectorConfig) -> Result<Self> {
+        Self::wrap(D::new(config)?, config)
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        let (width, height) = (u64::from(image.width()), u64::from(image.height()));
+        if width * height <= self.max_pixels {
+            return self.inner.detect_faces(image, threshold);
+        }
+
+        let side = ((self.max_pixels as f64).sqrt() as u32).max(1);
+        let overlap = (side as f32 * TILE_OVERLAP) as u32;
+        let mut faces = Vec::new();
+
+        for ty in tile_offsets(image.height(), side, overlap) {
+            for tx in tile_offsets(image.width(), side, overlap) {
+                let tile_width = side.min(image.width() - tx);
+                let tile_height = side.min(image.height() - ty);
+                let tile = image.crop_imm(tx, ty, tile_width, tile_height);
+
+                // Edges on the image border are real, the others cut faces in half
+                let cut_left = tx > 0;
+                let cut_top = ty > 0;
+                let cut_right = tx + tile_width < image.width();
+                let cut_bottom = ty + tile_height < image.height();
+
+                for face in self.inner.detect_faces(&tile, threshold)? {
+                    let cut = (cut_left && face.x <= EDGE_MARGIN)
+                        || (cut_top && face.y <= EDGE_MARGIN)
+                        || (cut_right && face.x + face.width >= tile_width as i32 - EDGE_MARGIN)
+                        || (cut_bottom && face.y + face.height >= tile_height as i32 - EDGE_MARGIN);
+                    if !cut {
+                        faces.push(transform_box(face, 1.0, tx as f32, ty as f32));
+                    }
+                }
+            }
+        }
+
+        // Faces larger than the overlap only show up whole on the scaled down image
+        let scale = (self.max_pixels as f64 / (width * height) as f64).sqrt() as f32;
+        let overview = image.resize(
+            ((image.width() as f32 * scale) as u32).max(1),
+            ((image.height() as f32 * scale) as u32).max(1),
+            FilterType::Triangle
+        );
+        let scale_back = image.width() as f32 / overview.width() as f32;
+        for face in self.inner.detect_faces(&overview, threshold)? {
+            faces.push(transform_box(face, scale_back, 0.0, 0.0));
+        }
+
+        Ok(non_max_suppression(faces, self.nms_iou))
+    }
+
+    fn detect_faces_batch(&mut self, images: &[DynamicImage], threshold: f32) -> Result<Vec<Vec<FaceBox>>> {
+        // Keep batching when nothing needs tiling
+        if images.iter().all(|image| u64::from(image.width()) * u64::from(image.height()) <= self.max_pixels) {
+            return self.inner.detect_faces_batch(images, threshold);
+        }
+
+        images
+            .iter()
+            .map(|image| self.detect_faces(image, threshold))
+            .collect()
+    }
+}
+
+/// Start positions of `side` long tiles covering `len` px, the last one flush with the end
+fn tile_offsets(len: u32, side: u32, overlap: u32) -> Vec<u32> {
+    if len <= side {
+        return vec![0];
+    }
+
+    let step = (side - overlap).max(1);
+    let mut offsets: Vec<u32> = (0..len - side).step_by(step as usize).collect();
+    offsets.push(len - side);
+    offsets
+}
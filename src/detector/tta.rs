@@ -0,0 +1,177 @@
+use super::{nms_iou, non_max_suppression, DetectorConfig, FaceBox, FaceDetector, Landmarks, Result};
+use image::DynamicImage;
+
+/// Copies of an image a test-time augmentation pass detects on
+#[derive(Debug, Clone, Copy)]
+enum Variant {
+    Original,
+    /// Mirrored left to right
+    Flipped,
+    /// Turned 90° clockwise
+    Rotated90,
+    /// Turned 90° counterclockwise
+    Rotated270,
+}
+
+const VARIANTS: [Variant; 4] = [Variant::Original, Variant::Flipped, Variant::Rotated90, Variant::Rotated270];
+
+impl Variant {
+    /// The transformed copy of an image
+    fn apply(self, image: &DynamicImage) -> DynamicImage {
+        match self {
+            Variant::Original => image.clone(),
+            Variant::Flipped => image.fliph(),
+            Variant::Rotated90 => image.rotate90(),
+            Variant::Rotated270 => image.rotate270(),
+        }
+    }
+
+    /// Map a box found on the transformed copy back onto the `width`x`height` source image
+    fn map_back(self, face: FaceBox, width: u32, height: u32) -> FaceBox {
+        let (width, height) = (width as i32, height as i32);
+        let (x, y, w, h) = match self {
+            Variant::Original => return face,
+            Variant::Flipped => (width - face.x - face.width, face.y, face.width, face.height),
+            Variant::Rotated90 => (face.y, height - face.x - face.width, face.height, face.width),
+            Variant::Rotated270 => (width - face.y - face.height, face.x, face.height, face.width),
+        };
+
+        FaceBox {
+            x,
+            y,
+            width: w,
+            height: h,
+            confidence: face.confidence,
+            landmarks: face.landmarks.map(|points| self.map_landmarks(points, width as f32, height as f32)),
+        }
+    }
+
+    /// Map landmarks back onto the source image, keeping their left/right meaning
+    fn map_landmarks(self, points: Landmarks, width: f32, height: f32) -> Landmarks {
+        match self {
+            Variant::Original => points,
+            // The mirrored left eye is the right one, and so on
+            Variant::Flipped => {
+                let [left_eye, right_eye, nose, left_mouth, right_mouth] = points.map(|(x, y)| (width - x, y));
+                [right_eye, left_eye, nose, right_mouth, left_mouth]
+            }
+            Variant::Rotated90 => points.map(|(x, y)| (y, height - x)),
+            Variant::Rotated270 => points.map(|(x, y)| (width - y, x)),
+        }
+    }
+}
+
+/// Test-time augmentation around another detector
+///
+/// Detects on the image, a mirrored copy and copies turned 90° either way, maps
+/// the boxes back onto the image and merges them with NMS. Finds faces the
+/// wrapped detector misses at their original orientation, at four times the cost.
+pub struct TtaDetector<D> {
+    inner: D,
+    nms_iou: f32,
+}
+
+impl<D: FaceDetector> TtaDetector<D> {
+    /// Augment an existing detector
+    pub fn wrap(inner: D, config: &DetectorConfig) -> Result<Self> {
+        Ok(Self {
+            inner,
+            nms_iou: nms_iou(config)?,
+        })
+    }
+}
+
+impl<D: FaceDetector> FaceDetector for TtaDetector<D> {
+    fn new(config: &DetectorConfig) -> Result<Self> {
+        Self::wrap(D::new(config)?, config)
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        let mut results = self.detect_faces_batch(std::slice::from_ref(image), threshold)?;
+
+        Ok(results.pop().unwrap_or_default())
+    }
+
+    fn detect_faces_batch(&mut self, images: &[DynamicImage], threshold: f32) -> Result<Vec<Vec<FaceBox>>> {
+        // All variants of all images go to the wrapped detector as one batch
+        let variants: Vec<DynamicImage> = images
+            .iter()
+            .flat_map(|image| VARIANTS.map(|variant| variant.apply(image)))
+            .collect();
+        let variant_faces = self.inner.detect_faces_batch(&variants, threshold)?;
+
+        Ok(images
+            .iter()
+            .zip(variant_faces.chunks(VARIANTS.len()))
+            .map(|(image, faces)| {
+                let merged = VARIANTS
+                    .iter()
+                    .zip(faces)
+                    .flat_map(|(variant, faces)| {
+                        faces
+                            .iter()
+                            .map(|face| variant.map_back(face.clone(), image.width(), image.height()))
+                    })
+                    .collect();
+                non_max_suppression(merged, self.nms_iou)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    /// Bounding box of the white pixels of an image
+    fn white_box(image: &DynamicImage) -> (i32, i32, i32, i32) {
+        let luma = image.to_luma8();
+        let white: Vec<(u32, u32)> = luma.enumerate_pixels().filter(|(_, _, pixel)| pixel[0] > 0).map(|(x, y, _)| (x, y)).collect();
+        let (x1, y1) = (white.iter().map(|p| p.0).min().unwrap(), white.iter().map(|p| p.1).min().unwrap());
+        let (x2, y2) = (white.iter().map(|p| p.0).max().unwrap(), white.iter().map(|p| p.1).max().unwrap());
+        (x1 as i32, y1 as i32, (x2 - x1 + 1) as i32, (y2 - y1 + 1) as i32)
+    }
+
+    fn face(x: i32, y: i32, width: i32, height: i32) -> FaceBox {
+        FaceBox { x, y, width, height, confidence: 0.9, landmarks: None }
+    }
+
+    #[test]
+    fn boxes_found_on_every_variant_map_back_onto_the_source() {
+        let (width, height) = (120, 80);
+        let mut source = GrayImage::new(width, height);
+        for y in 10..40 {
+            for x in 20..35 {
+                source.put_pixel(x, y, Luma([255]));
+            }
+        }
+        let source = DynamicImage::ImageLuma8(source);
+
+        for variant in VARIANTS {
+            let (x, y, w, h) = white_box(&variant.apply(&source));
+            let mapped = variant.map_back(face(x, y, w, h), width, height);
+            assert_eq!((mapped.x, mapped.y, mapped.width, mapped.height), (20, 10, 15, 30), "{:?}", variant);
+            assert_eq!(mapped.confidence, 0.9);
+        }
+    }
+
+    #[test]
+    fn landmarks_map_back_keeping_left_and_right() {
+        let (width, height) = (100.0, 60.0);
+        let source: Landmarks = [(30.0, 20.0), (60.0, 20.0), (45.0, 30.0), (35.0, 40.0), (55.0, 40.0)];
+
+        // The detector names the eyes by position, so on the mirrored copy they swap
+        let [left_eye, right_eye, nose, left_mouth, right_mouth] = source.map(|(x, y)| (width - x, y));
+        let flipped = [right_eye, left_eye, nose, right_mouth, left_mouth];
+        assert_eq!(Variant::Flipped.map_landmarks(flipped, width, height), source);
+
+        let rotated90 = source.map(|(x, y)| (height - y, x));
+        assert_eq!(Variant::Rotated90.map_landmarks(rotated90, width, height), source);
+
+        let rotated270 = source.map(|(x, y)| (y, width - x));
+        assert_eq!(Variant::Rotated270.map_landmarks(rotated270, width, height), source);
+
+        assert_eq!(Variant::Original.map_landmarks(source, width, height), source);
+    }
+}
@@ -0,0 +1,100 @@
+use super::{nms_iou, DetectorConfig, DetectorError, FaceBox, FaceDetector, Result};
+use image::DynamicImage;
+use opencv::core::{Mat, Ptr, Size, Vec3b, VecN};
+use opencv::objdetect::FaceDetectorYN;
+use opencv::prelude::*;
+use std::path::PathBuf;
+
+/// Candidates kept before NMS, OpenCV's default
+const TOP_K: i32 = 5000;
+
+/// OpenCV YuNet detector (FaceDetectorYN)
+///
+/// Runs on the whole image at its own size, so no input size applies. Every face
+/// comes with the five landmarks of the RetinaFace layout.
+pub struct YuNetDetector {
+    detector: Ptr<FaceDetectorYN>,
+}
+
+impl YuNetDetector {
+    /// Model loaded when the config doesn't name one
+    const DEFAULT_MODEL: &'static str = "model/face_detection_yunet_2023mar.onnx";
+}
+
+impl FaceDetector for YuNetDetector {
+    fn new(config: &DetectorConfig) -> Result<Self> {
+        let nms_iou = nms_iou(config)?;
+        let model_path = config
+            .model_path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(Self::DEFAULT_MODEL));
+        if !model_path.exists() {
+            return Err(DetectorError::ModelNotFound {
+                path: model_path,
+                hint: "Download face_detection_yunet_2023mar.onnx from \
+                    https://github.com/opencv/opencv_zoo/tree/main/models/face_detection_yunet \
+                    and place it there, or pass --model-path <path>",
+            });
+        }
+
+        log::info!("Loading YuNet model from: {}", model_path.display());
+        // The input size and score threshold are set again for every image
+        let detector = FaceDetectorYN::create(
+            &model_path.to_string_lossy(),
+            "",
+            Size::new(320, 320),
+            0.5,
+            nms_iou,
+            TOP_K,
+            0,
+            0,
+        )
+        .map_err(|err| DetectorError::Backend(format!("Failed to load YuNet model {}: {}", model_path.display(), err)))?;
+
+        Ok(Self { detector })
+    }
+
+    fn detect_faces(&mut self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        let backend = |err: opencv::Error| DetectorError::Backend(format!("YuNet detection failed: {}", err));
+
+        // OpenCV wants BGR pixels
+        let rgb = image.to_rgb8();
+        let (width, height) = rgb.dimensions();
+        let pixels: Vec<Vec3b> = rgb
+            .pixels()
+            .map(|pixel| VecN([pixel[2], pixel[1], pixel[0]]))
+            .collect();
+        let input = Mat::new_rows_cols_with_data(height as i32, width as i32, &pixels)
+            .and_then(|mat| mat.try_clone())
+            .map_err(backend)?;
+
+        self.detector
+            .set_input_size(Size::new(width as i32, height as i32))
+            .map_err(backend)?;
+        self.detector.set_score_threshold(threshold).map_err(backend)?;
+        let mut detections = Mat::default();
+        self.detector.detect(&input, &mut detections).map_err(backend)?;
+
+        // One row per face: box, right eye, left eye, nose, right and left mouth
+        // corner (the subject's, so the image's left comes first), then the score
+        let mut faces = Vec::with_capacity(detections.rows().max(0) as usize);
+        for row in 0..detections.rows() {
+            let values = detections.at_row::<f32>(row).map_err(backend)?;
+            if values.len() < 15 {
+                return Err(DetectorError::Backend(format!("Unexpected YuNet output with {} columns", values.len())));
+            }
+
+            let point = |i: usize| (values[4 + i * 2], values[5 + i * 2]);
+            faces.push(FaceBox {
+                x: values[0].round() as i32,
+                y: values[1].round() as i32,
+                width: values[2].round() as i32,
+                height: values[3].round() as i32,
+                confidence: values[14],
+                landmarks: Some([point(0), point(1), point(2), point(3), point(4)]),
+            });
+        }
+
+        Ok(faces)
+    }
+}
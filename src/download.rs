@@ -0,0 +1,154 @@
+use crate::error::{Error, Result};
+use log::warn;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Delay before the first retry, doubled for every further one
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest delay between two retries
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Delay before retry number `attempt` (from 1)
+fn retry_delay(attempt: u32) -> Duration {
+    2u32.checked_pow(attempt.saturating_sub(1))
+        .and_then(|factor| RETRY_DELAY.checked_mul(factor))
+        .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+}
+
+/// URL of a path that is an http(s) URL
+pub fn url_of(path: &Path) -> Option<&str> {
+    path.to_str()
+        .filter(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// Read an input list: one image URL or path per line, skipping blank lines and `#` comments
+///
+/// Relative paths are relative to the directory of the list. Anything after a tab
+/// is ignored, so a failures.txt of an earlier run can be read as a list.
+pub fn read_input_list(list: &Path) -> Result<Vec<PathBuf>> {
+    let contents = std::fs::read_to_string(list).map_err(|source| Error::Io {
+        context: format!("Failed to read input list: {:?}", list),
+        source,
+    })?;
+    let base = list.parent().unwrap_or(Path::new(""));
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let line = line.split('\t').next().unwrap_or(line);
+            let path = PathBuf::from(line);
+            if url_of(&path).is_some() || path.is_absolute() {
+                path
+            } else {
+                base.join(path)
+            }
+        })
+        .collect())
+}
+
+/// Downloads images over HTTP(S), several at a time and retrying failures
+#[derive(Debug, Clone)]
+pub struct HttpFetcher {
+    agent: ureq::Agent,
+    /// Downloads running at once
+    pub concurrency: usize,
+    /// Attempts after the first one for network errors, timeouts and 429/5xx responses,
+    /// waiting 0.5s before the first and doubling up to 30s between further ones
+    pub retries: u32,
+}
+
+impl Default for HttpFetcher {
+    fn default() -> Self {
+        Self::new(8, 3)
+    }
+}
+
+impl HttpFetcher {
+    /// Create a fetcher with 30 second timeouts
+    pub fn new(concurrency: usize, retries: u32) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(30))
+                .timeout_read(Duration::from_secs(30))
+                .build(),
+            concurrency,
+            retries,
+        }
+    }
+
+    /// Download one URL
+    pub fn fetch(&self, url: &str) -> Result<Vec<u8>> {
+        let mut attempt = 0;
+        loop {
+            let (reason, retryable) = match self.agent.get(url).call() {
+                Ok(response) => {
+                    let mut data = Vec::new();
+                    match response.into_reader().read_to_end(&mut data) {
+                        Ok(_) => return Ok(data),
+                        Err(err) => (err.to_string(), true),
+                    }
+                }
+                Err(ureq::Error::Status(code, _)) => (format!("HTTP {}", code), code == 429 || code >= 500),
+                Err(err) => (err.to_string(), true),
+            };
+
+            if !retryable || attempt >= self.retries {
+                return Err(Error::Storage(format!("Failed to download {}: {}", url, reason)));
+            }
+
+            attempt += 1;
+            warn!("Download of {} failed ({}), retry {}/{}", url, reason, attempt, self.retries);
+            std::thread::sleep(retry_delay(attempt));
+        }
+    }
+
+    /// Download URLs on up to `concurrency` threads, results in the order of `urls`
+    pub fn fetch_all(&self, urls: &[&str]) -> Vec<Result<Vec<u8>>> {
+        let results: Vec<Mutex<Option<Result<Vec<u8>>>>> = urls.iter().map(|_| Mutex::new(None)).collect();
+        let next = AtomicUsize::new(0);
+
+        std::thread::scope(|scope| {
+            for _ in 0..self.concurrency.clamp(1, urls.len().max(1)) {
+                scope.spawn(|| {
+                    loop {
+                        let index = next.fetch_add(1, Ordering::SeqCst);
+                        let Some(url) = urls.get(index) else {
+                            break;
+                        };
+                        *results[index].lock().expect("download slot poisoned") = Some(self.fetch(url));
+                    }
+                });
+            }
+        });
+
+        results
+            .into_iter()
+            .map(|slot| {
+                slot.into_inner()
+                    .expect("download slot poisoned")
+                    .expect("every URL was downloaded")
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_cap() {
+        assert_eq!(retry_delay(1), Duration::from_millis(500));
+        assert_eq!(retry_delay(2), Duration::from_secs(1));
+        assert_eq!(retry_delay(4), Duration::from_secs(4));
+        assert_eq!(retry_delay(7), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(33), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+}
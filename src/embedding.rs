@@ -0,0 +1,75 @@
+use crate::detector::{load_session, DetectorError, Result};
+use image::imageops::FilterType;
+use image::DynamicImage;
+use ort::session::Session;
+use std::path::Path;
+
+/// Default ArcFace model, relative to the working directory
+pub const DEFAULT_EMBEDDING_MODEL: &str = "model/arcface_r100.onnx";
+
+/// Input size of ArcFace models (px)
+const INPUT_SIZE: u32 = 112;
+
+/// Face embeddings from an ArcFace model run through ONNX Runtime
+///
+/// Expects the insightface export layout: a 112x112 NCHW RGB input normalized as
+/// (pixel - 127.5) / 127.5 and a single embedding output (usually 512 values).
+/// Works best on aligned crops, as ArcFace was trained on faces with their eyes level.
+pub struct FaceEmbedder {
+    session: Session,
+}
+
+impl FaceEmbedder {
+    /// Load the model, or the default one when no path is given
+    pub fn new(model_path: Option<&Path>) -> Result<Self> {
+        let model_path = model_path.unwrap_or(Path::new(DEFAULT_EMBEDDING_MODEL));
+        if !model_path.exists() {
+            return Err(DetectorError::ModelNotFound {
+                path: model_path.to_path_buf(),
+                hint: "Download an ArcFace model and place it there, or pass --embedding-model <path>",
+            });
+        }
+
+        Ok(Self {
+            session: load_session(model_path)?,
+        })
+    }
+
+    /// L2-normalized embedding of a face crop, compare two with [`cosine_similarity`]
+    pub fn embed(&mut self, face: &DynamicImage) -> Result<Vec<f32>> {
+        let rgb = face
+            .resize_exact(INPUT_SIZE, INPUT_SIZE, FilterType::Triangle)
+            .to_rgb8();
+
+        let plane = (INPUT_SIZE * INPUT_SIZE) as usize;
+        let mut input = vec![0.0f32; 3 * plane];
+        for (i, pixel) in rgb.pixels().enumerate() {
+            for channel in 0..3 {
+                input[channel * plane + i] = (pixel[channel] as f32 - 127.5) / 127.5;
+            }
+        }
+
+        let tensor = ort::value::Tensor::from_array((
+            [1, 3, INPUT_SIZE as usize, INPUT_SIZE as usize],
+            input,
+        ))
+        .map_err(|err| DetectorError::Backend(format!("Failed to build ONNX input tensor: {}", err)))?;
+
+        let input_name = self.session.inputs()[0].name().to_string();
+        let outputs = self
+            .session
+            .run(ort::inputs![input_name => tensor])
+            .map_err(|err| DetectorError::Backend(format!("ONNX inference failed: {}", err)))?;
+        let (_, values) = outputs[0]
+            .try_extract_tensor::<f32>()
+            .map_err(|err| DetectorError::Backend(format!("Failed to read embedding output: {}", err)))?;
+
+        let norm = values.iter().map(|v| v * v).sum::<f32>().sqrt().max(f32::EPSILON);
+        Ok(values.iter().map(|v| v / norm).collect())
+    }
+}
+
+/// Cosine similarity of two L2-normalized embeddings (1 for the same direction)
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
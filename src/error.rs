@@ -0,0 +1,60 @@
+use crate::detector::DetectorError;
+use std::path::PathBuf;
+
+/// Errors of the extraction library
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Creating or running a detector failed
+    #[error(transparent)]
+    Detector(#[from] DetectorError),
+
+    /// A file or directory couldn't be read or written
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// An image couldn't be decoded, encoded or saved
+    #[error("{context}")]
+    Image {
+        context: String,
+        #[source]
+        source: image::ImageError,
+    },
+
+    /// A download or a request to remote storage failed
+    #[error("{0}")]
+    Storage(String),
+
+    /// The pipeline was configured incompletely or inconsistently
+    #[error("{0}")]
+    Config(String),
+
+    /// Images failed and the [`ErrorPolicy`](crate::ErrorPolicy) stopped the run
+    #[error("Stopping after {failed} failed images, the last one {path:?}: {reason}")]
+    TooManyFailures { failed: usize, path: PathBuf, reason: String },
+
+    /// An [`ExtractionObserver`](crate::ExtractionObserver) stopped the run
+    #[error("{0}")]
+    Observer(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Result of library operations
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Short name of the kind of failure, as written to failures.txt
+    pub fn category(&self) -> &'static str {
+        match self {
+            Error::Detector(_) => "detect",
+            Error::Io { .. } => "io",
+            Error::Image { .. } => "image",
+            Error::Storage(_) => "storage",
+            Error::Config(_) => "config",
+            Error::TooManyFailures { .. } => "failures",
+            Error::Observer(_) => "observer",
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use crate::attributes::FaceAttributes;
+use crate::cropping::{image_rejected, select_crop, CropOptions, FaceCrop, SelectedCrop};
+use crate::detector::{FaceBox, FaceDetector};
+use crate::input::{load_image, LoadOptions};
+use crate::quality::Exposure;
+use crate::error::Result;
+use image::DynamicImage;
+use std::path::{Path, PathBuf};
+
+/// A face cropped out of a source image, with where it came from
+#[derive(Debug)]
+pub struct ExtractedFace {
+    /// Source image path
+    pub source: PathBuf,
+    /// Source image dimensions (px)
+    pub image_width: u32,
+    pub image_height: u32,
+    /// Detected face in source coordinates
+    pub face: FaceBox,
+    /// The resized crop and the region it was cut from
+    pub crop: FaceCrop,
+    /// Sharpness of the crop (variance of the Laplacian)
+    pub sharpness: f64,
+    /// Mean luminance (0-255) and RMS contrast of the crop
+    pub exposure: Exposure,
+    /// Attributes estimated by the classification stages set in the crop options
+    pub attributes: FaceAttributes,
+}
+
+/// Detects and crops faces without writing anything, leaving storage to the caller
+pub struct FaceExtractor {
+    detector: Box<dyn FaceDetector>,
+    load: LoadOptions,
+    crop: CropOptions,
+    threshold: f32,
+}
+
+impl FaceExtractor {
+    /// Extractor running `detector` and cropping as set in `crop` (its output options are unused)
+    pub fn new(detector: Box<dyn FaceDetector>, crop: CropOptions) -> Self {
+        Self {
+            detector,
+            load: LoadOptions::default(),
+            crop,
+            threshold: 0.5,
+        }
+    }
+
+    /// How source images are loaded
+    pub fn with_load_options(mut self, load: LoadOptions) -> Self {
+        self.load = load;
+        self
+    }
+
+    /// Confidence threshold for detected faces
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Lazily load, detect and crop the given images, one face at a time
+    ///
+    /// Images are only read once the faces of the previous one were consumed.
+    /// An image that fails to load or detect yields a single error item.
+    pub fn extract<'a, P: AsRef<Path>>(
+        &'a mut self,
+        paths: impl IntoIterator<Item = P> + 'a
+    ) -> impl Iterator<Item = Result<ExtractedFace>> + 'a {
+        paths.into_iter().flat_map(move |path| {
+            let path = path.as_ref();
+            match load_image(path, &self.load).and_then(|img| self.extract_image(path, &img)) {
+                Ok(faces) => faces.into_iter().map(Ok).collect::<Vec<_>>(),
+                Err(err) => vec![Err(err)],
+            }
+        })
+    }
+
+    /// Detect and crop the faces of an image already in memory, `source` is recorded as its path
+    pub fn extract_image(&mut self, source: &Path, img: &DynamicImage) -> Result<Vec<ExtractedFace>> {
+        if image_rejected(source, img, &self.crop)? {
+            return Ok(Vec::new());
+        }
+        let faces = self.detector.detect_faces(img, self.threshold)?;
+
+        let mut extracted = Vec::new();
+        for face in faces {
+            if let Some(SelectedCrop { crop, sharpness, exposure, attributes, .. }) = select_crop(source, img, &face, &self.crop)? {
+                extracted.push(ExtractedFace {
+                    source: source.to_path_buf(),
+                    image_width: img.width(),
+                    image_height: img.height(),
+                    face,
+                    crop,
+                    sharpness,
+                    exposure,
+                    attributes,
+                });
+            }
+        }
+
+        Ok(extracted)
+    }
+}
@@ -0,0 +1,193 @@
+use anyhow::{Context, Result};
+use face_cropper::{write_atomic, ManifestEntry};
+use image::ImageFormat;
+use log::warn;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the gallery page, written next to the crops it shows
+pub const GALLERY_FILE: &str = "index.html";
+
+/// Directory next to the gallery page its thumbnails are written to
+pub const THUMBS_DIR: &str = "thumbs";
+
+/// Edge of the square a thumbnail is fitted into (px)
+const THUMBNAIL_SIZE: u32 = 112;
+
+/// Crops under one heading of the gallery, paths relative to the page
+pub type Group = (String, Vec<String>);
+
+/// Crops of a manifest grouped by label, or by source image when they have none,
+/// groups and crops in manifest order
+pub fn manifest_groups(entries: &[ManifestEntry]) -> Vec<Group> {
+    let mut groups: Vec<Group> = Vec::new();
+    // Group of every title, without labels there is one per source image
+    let mut indices: HashMap<&str, usize> = HashMap::new();
+    for entry in entries {
+        let title = entry.label.as_ref().unwrap_or(&entry.source);
+        match indices.get(title.as_str()) {
+            Some(&index) => groups[index].1.push(entry.output.clone()),
+            None => {
+                indices.insert(title, groups.len());
+                groups.push((title.clone(), vec![entry.output.clone()]));
+            }
+        }
+    }
+
+    groups
+}
+
+/// Write a page to `dir` showing every group's crops as a grid of thumbnails
+///
+/// Thumbnails are small JPEG copies of the crops in `thumbs/`, kept from an
+/// earlier gallery while they are newer than their crop. They load lazily, so a
+/// gallery of a large run only fetches the ones scrolled to, and each links to
+/// its full-size crop. Groups are collapsible and headed with their crop count.
+/// Returns the path of the page.
+pub fn write_gallery(dir: &Path, title: &str, groups: &[Group]) -> Result<PathBuf> {
+    let crop_count: usize = groups.iter().map(|(_, crops)| crops.len()).sum();
+    let crops: Vec<&String> = groups.iter().flat_map(|(_, crops)| crops).collect();
+    let thumbnails: HashMap<&str, String> = crops
+        .par_iter()
+        .filter_map(|crop| match write_thumbnail(dir, crop) {
+            Ok(thumbnail) => Some((crop.as_str(), thumbnail)),
+            Err(err) => {
+                warn!("No thumbnail of {:?}, the gallery shows the crop itself: {:#}", crop, err);
+                None
+            }
+        })
+        .collect();
+
+    let mut page = String::new();
+    let _ = write!(
+        page,
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 1em 2em; background: #fafafa; }}
+summary {{ font-weight: bold; margin: 1em 0 0.5em; cursor: pointer; overflow-wrap: anywhere; }}
+.grid {{ display: flex; flex-wrap: wrap; gap: 4px; }}
+.grid img {{ width: {size}px; height: {size}px; object-fit: contain; background: #ddd; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<p>{crop_count} faces in {group_count} groups</p>
+"#,
+        title = escape(title),
+        size = THUMBNAIL_SIZE,
+        crop_count = crop_count,
+        group_count = groups.len(),
+    );
+
+    for (group, crops) in groups {
+        let _ = writeln!(page, "<details open>\n<summary>{} ({})</summary>\n<div class=\"grid\">", escape(group), crops.len());
+        for crop in crops {
+            let (href, name) = (escape(&url_path(crop)), escape(crop));
+            let src = thumbnails.get(crop.as_str()).map_or_else(|| href.clone(), |thumbnail| escape(&url_path(thumbnail)));
+            let _ = writeln!(page, r#"<a href="{href}"><img src="{src}" loading="lazy" alt="{name}" title="{name}"></a>"#);
+        }
+        page.push_str("</div>\n</details>\n");
+    }
+    page.push_str("</body>\n</html>\n");
+
+    let path = dir.join(GALLERY_FILE);
+    write_atomic(&path, page.as_bytes()).with_context(|| format!("Failed to write gallery: {:?}", path))?;
+
+    Ok(path)
+}
+
+/// Write the thumbnail of a crop unless an up to date one exists, returning its
+/// path relative to `dir`
+fn write_thumbnail(dir: &Path, crop: &str) -> Result<String> {
+    let thumbnail = Path::new(THUMBS_DIR).join(crop).with_extension("jpg");
+    let (crop_path, thumbnail_path) = (dir.join(crop), dir.join(&thumbnail));
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    let current = modified(&thumbnail_path).zip(modified(&crop_path)).is_some_and(|(thumb, crop)| thumb >= crop);
+
+    if !current {
+        let img = image::open(&crop_path).with_context(|| format!("Failed to open crop: {:?}", crop_path))?;
+        if let Some(parent) = thumbnail_path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create directory: {:?}", parent))?;
+        }
+        // JPEG can't hold alpha, and every crop format can be read but not all written
+        img.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+            .to_rgb8()
+            .save_with_format(&thumbnail_path, ImageFormat::Jpeg)
+            .with_context(|| format!("Failed to write thumbnail: {:?}", thumbnail_path))?;
+    }
+
+    Ok(thumbnail.to_string_lossy().into_owned())
+}
+
+/// Text with the characters HTML gives a meaning escaped
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A relative path as a URL path, percent-encoding everything but unreserved characters and separators
+fn url_path(path: &str) -> String {
+    let mut url = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => url.push(byte as char),
+            b'\\' => url.push('/'),
+            _ => {
+                let _ = write!(url, "%{:02X}", byte);
+            }
+        }
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_replaces_html_special_characters() {
+        assert_eq!(escape("Tom & Jerry's <b>\"show\"</b>"), "Tom &amp; Jerry&#39;s &lt;b&gt;&quot;show&quot;&lt;/b&gt;");
+        assert_eq!(escape("plain"), "plain");
+    }
+
+    #[test]
+    fn url_path_percent_encodes_all_but_unreserved_characters() {
+        assert_eq!(url_path("alice/face_000001_0.990.jpg"), "alice/face_000001_0.990.jpg");
+        assert_eq!(url_path("bob smith/#1?.jpg"), "bob%20smith/%231%3F.jpg");
+        assert_eq!(url_path("dir\\zoë.jpg"), "dir/zo%C3%AB.jpg");
+    }
+
+    #[test]
+    fn gallery_shows_thumbnails_linked_to_the_crops() {
+        let dir = std::env::temp_dir().join(format!("face_cropper-gallery-{}", std::process::id()));
+        fs::create_dir_all(dir.join("alice")).unwrap();
+        image::RgbImage::new(448, 224).save(dir.join("alice/face_000000.png")).unwrap();
+        let groups = vec![("alice".to_string(), vec!["alice/face_000000.png".to_string(), "alice/missing.png".to_string()])];
+
+        let page = fs::read_to_string(write_gallery(&dir, "Faces", &groups).unwrap()).unwrap();
+        let thumbnail = image::open(dir.join("thumbs/alice/face_000000.jpg")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!((thumbnail.width(), thumbnail.height()), (THUMBNAIL_SIZE, THUMBNAIL_SIZE / 2));
+        assert!(page.contains(r#"<a href="alice/face_000000.png"><img src="thumbs/alice/face_000000.jpg""#));
+        // A crop that can't be read is shown as it is
+        assert!(page.contains(r#"<a href="alice/missing.png"><img src="alice/missing.png""#));
+    }
+}
@@ -0,0 +1,644 @@
+use crate::error::{Error, Result};
+use image::DynamicImage;
+use log::debug;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use walkdir::WalkDir;
+
+/// Which frames of animated GIF, WebP and PNG images are processed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrameSelection {
+    /// Only the first frame, like a still image
+    #[default]
+    First,
+    /// Every frame
+    All,
+    /// Every Nth frame, starting with the first
+    Every(usize),
+}
+
+impl FrameSelection {
+    /// Whether the frame at `index` is processed
+    fn takes(self, index: usize) -> bool {
+        match self {
+            FrameSelection::First => index == 0,
+            FrameSelection::All => true,
+            FrameSelection::Every(step) => index.is_multiple_of(step),
+        }
+    }
+}
+
+impl std::str::FromStr for FrameSelection {
+    type Err = String;
+
+    /// Parse `first`, `all` or `every=N`
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "first" => Ok(FrameSelection::First),
+            "all" => Ok(FrameSelection::All),
+            _ => value
+                .strip_prefix("every=")
+                .and_then(|step| step.parse().ok())
+                .filter(|&step| step > 0)
+                .map(FrameSelection::Every)
+                .ok_or_else(|| format!("Invalid frame selection: {} (expected first, all or every=N with N > 0)", value)),
+        }
+    }
+}
+
+/// Options controlling how source images are loaded
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    /// Rotate images upright according to their EXIF orientation
+    pub exif_rotate: bool,
+    /// Frames of animated images that [`load_frames`] decodes ([`load_image`]
+    /// always decodes the first)
+    pub frames: FrameSelection,
+    /// Images whose shorter side is below this (px) give [`load_frames`] no frames,
+    /// told from their header where the format allows it so they aren't decoded
+    pub min_dim: Option<u32>,
+    /// Images whose header declares more pixels than this fail to decode before any
+    /// of them is allocated, so a decompression bomb can't exhaust the memory (RAW
+    /// files once rawloader has read the sensor data, before it is converted); the
+    /// frames [`load_frames`] keeps of an animation count together, each as a full canvas
+    pub max_pixels: Option<u64>,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            exif_rotate: true,
+            frames: FrameSelection::First,
+            min_dim: None,
+            max_pixels: None,
+        }
+    }
+}
+
+/// Decoded frames of an image, with their index when the image is animated
+pub type Frames = Vec<(Option<u32>, DynamicImage)>;
+
+/// Load an image file
+pub fn load_image(path: &Path, load: &LoadOptions) -> Result<DynamicImage> {
+    let data = fs::read(path).map_err(|source| Error::Io {
+        context: format!("Failed to open image: {:?}", path),
+        source,
+    })?;
+    decode_loaded(path, &data, load)
+}
+
+/// Load the frames of an image file that the load options select
+///
+/// Still images and animations of a single frame give one frame without an index.
+pub fn load_frames(path: &Path, load: &LoadOptions) -> Result<Frames> {
+    let data = read_image(path)?;
+    decode_loaded_frames(path, &data, load)
+}
+
+/// Read the encoded bytes of a local image file
+pub(crate) fn read_image(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|source| Error::Io {
+        context: format!("Failed to open image: {:?}", path),
+        source,
+    })
+}
+
+/// Decode the selected frames of an image read or downloaded from `path`, none
+/// when it is smaller than the load options allow
+pub(crate) fn decode_loaded_frames(path: &Path, data: &[u8], load: &LoadOptions) -> Result<Frames> {
+    // Formats image can't read the header of (HEIF, RAW) are checked once decoded
+    let probed = load.min_dim.and_then(|_| probe_dimensions(data));
+    if let Some((width, height)) = probed
+        && too_small(width, height, load)
+    {
+        debug!("Skipping {:?}, it is only {}x{}", path, width, height);
+        return Ok(Vec::new());
+    }
+
+    let frames = decode_selected_frames(path, data, load)?;
+    if probed.is_none()
+        && let Some((_, img)) = frames.first()
+        && too_small(img.width(), img.height(), load)
+    {
+        debug!("Skipping {:?}, it is only {}x{}", path, img.width(), img.height());
+        return Ok(Vec::new());
+    }
+
+    Ok(frames)
+}
+
+/// Dimensions an image's header declares, without decoding its pixels
+fn probe_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    image::io::Reader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+/// Whether an image is below the minimum size of the load options
+fn too_small(width: u32, height: u32, load: &LoadOptions) -> bool {
+    load.min_dim.is_some_and(|min| width.min(height) < min)
+}
+
+/// Fail for an image whose header declares more pixels than the load options allow
+///
+/// Images whose header image can't read (HEIF, RAW) pass here; their decoders
+/// check the size they read with [`check_dimensions`] before decoding the pixels.
+fn check_pixels(data: &[u8], load: &LoadOptions) -> image::ImageResult<()> {
+    if load.max_pixels.is_none() {
+        return Ok(());
+    }
+    match probe_dimensions(data) {
+        Some((width, height)) => check_dimensions(u64::from(width), u64::from(height), load),
+        None => Ok(()),
+    }
+}
+
+/// Fail for a `width`x`height` image above the pixel limit of the load options
+fn check_dimensions(width: u64, height: u64, load: &LoadOptions) -> image::ImageResult<()> {
+    check_pixel_count(width.saturating_mul(height), load)
+}
+
+/// Fail for more decoded pixels than the load options allow
+fn check_pixel_count(pixels: u64, load: &LoadOptions) -> image::ImageResult<()> {
+    use image::error::{ImageError, LimitError, LimitErrorKind};
+
+    match load.max_pixels {
+        Some(max_pixels) if pixels > max_pixels => {
+            Err(ImageError::Limits(LimitError::from_kind(LimitErrorKind::DimensionError)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Decode the selected frames of an image, whatever its size
+fn decode_selected_frames(path: &Path, data: &[u8], load: &LoadOptions) -> Result<Frames> {
+    if load.frames != FrameSelection::First {
+        let frames = check_pixels(data, load)
+            .and_then(|_| decode_animation(data, load))
+            .map_err(|source| Error::Image {
+            context: format!("Failed to open image: {:?}", path),
+            source,
+        })?;
+        if let Some(frames) = frames {
+            return Ok(frames);
+        }
+    }
+
+    Ok(vec![(None, decode_loaded(path, data, load)?)])
+}
+
+/// The selected frames of an animated GIF, WebP or PNG, None for other images
+///
+/// Frames are composited onto the full canvas, as they would be displayed. The
+/// kept frames are held until all are decoded, so together they count against
+/// the pixel limit.
+fn decode_animation(data: &[u8], load: &LoadOptions) -> image::ImageResult<Option<Frames>> {
+    use image::codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder};
+    use image::{AnimationDecoder, ImageFormat};
+
+    let decoded = match image::guess_format(data) {
+        Ok(ImageFormat::Gif) => GifDecoder::new(Cursor::new(data))?.into_frames(),
+        Ok(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(Cursor::new(data))?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            decoder.into_frames()
+        }
+        Ok(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(Cursor::new(data))?;
+            if !decoder.is_apng() {
+                return Ok(None);
+            }
+            decoder.apng().into_frames()
+        }
+        _ => return Ok(None),
+    };
+
+    let mut selected = Vec::new();
+    let mut count = 0;
+    let mut pixels: u64 = 0;
+    for (index, frame) in decoded.enumerate() {
+        let frame = frame?;
+        count += 1;
+        if load.frames.takes(index) {
+            let canvas = frame.into_buffer();
+            pixels = pixels.saturating_add(u64::from(canvas.width()) * u64::from(canvas.height()));
+            check_pixel_count(pixels, load)?;
+            selected.push((Some(index as u32), DynamicImage::ImageRgba8(canvas)));
+        }
+    }
+
+    // A single frame is a still image, without an index
+    match count {
+        0 => Ok(None),
+        1 => Ok(Some(selected.into_iter().map(|(_, img)| (None, img)).collect())),
+        _ => Ok(Some(selected)),
+    }
+}
+
+/// Decode the contents of an image read or downloaded from `path`
+pub(crate) fn decode_loaded(path: &Path, data: &[u8], load: &LoadOptions) -> Result<DynamicImage> {
+    // Most RAW formats are TIFF containers, so they are told apart by extension
+    #[cfg(feature = "raw")]
+    let decoded = if is_raw(path) { decode_raw(data, load) } else { decode_image(data, load) };
+    #[cfg(not(feature = "raw"))]
+    let decoded = decode_image(data, load);
+
+    decoded.map_err(|source| Error::Image {
+        context: format!("Failed to open image: {:?}", path),
+        source,
+    })
+}
+
+/// Decode an encoded image, turning it upright if it carries an EXIF orientation
+///
+/// Animated GIF, WebP and PNG images and multi-page TIFFs decode to their first
+/// frame, see [`load_frames`] for the others.
+pub fn decode_image(data: &[u8], load: &LoadOptions) -> image::ImageResult<DynamicImage> {
+    check_pixels(data, load)?;
+
+    // libheif turns HEIF images upright itself
+    #[cfg(feature = "heic")]
+    if is_heif(data) {
+        return decode_heif(data, load);
+    }
+
+    // zune-jpeg decodes most JPEGs faster, anything it can't is left to image
+    #[cfg(feature = "fast-jpeg")]
+    let img = match decode_jpeg(data) {
+        Some(img) => img,
+        None => image::load_from_memory(data)?,
+    };
+    #[cfg(not(feature = "fast-jpeg"))]
+    let img = image::load_from_memory(data)?;
+    if !load.exif_rotate {
+        return Ok(img);
+    }
+
+    let orientation = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+        });
+
+    // Values 2-8 are mirror/rotate combinations of the stored pixels, see the EXIF spec
+    Ok(match orientation {
+        Some(2) => img.fliph(),
+        Some(3) => img.rotate180(),
+        Some(4) => img.flipv(),
+        Some(5) => img.rotate90().fliph(),
+        Some(6) => img.rotate90(),
+        Some(7) => img.rotate270().fliph(),
+        Some(8) => img.rotate270(),
+        _ => img,
+    })
+}
+
+/// Decode a JPEG with zune-jpeg, None for other data, unusual color spaces (CMYK)
+/// and files it fails on
+#[cfg(feature = "fast-jpeg")]
+fn decode_jpeg(data: &[u8]) -> Option<DynamicImage> {
+    use zune_jpeg::zune_core::colorspace::ColorSpace;
+    use zune_jpeg::zune_core::options::DecoderOptions;
+
+    if !data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return None;
+    }
+
+    let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGB);
+    let mut decoder = zune_jpeg::JpegDecoder::new_with_options(data, options);
+    let pixels = decoder
+        .decode()
+        .map_err(|err| log::debug!("zune-jpeg failed, decoding with image instead: {:?}", err))
+        .ok()?;
+    let info = decoder.info()?;
+    let (width, height) = (u32::from(info.width), u32::from(info.height));
+
+    match decoder.get_output_colorspace()? {
+        ColorSpace::RGB => image::RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        ColorSpace::Luma => image::GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        _ => None,
+    }
+}
+
+/// Whether data is a HEIF container (HEIC photos), by the brand of its `ftyp` box
+#[cfg(feature = "heic")]
+fn is_heif(data: &[u8]) -> bool {
+    data.get(4..8) == Some(b"ftyp".as_slice())
+        && data.get(8..12).is_some_and(|brand| {
+            [b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1"]
+                .iter()
+                .any(|known| brand == known.as_slice())
+        })
+}
+
+/// Decode the primary image of a HEIF container to RGB, checking its size against
+/// the pixel limit before decoding it
+#[cfg(feature = "heic")]
+fn decode_heif(data: &[u8], load: &LoadOptions) -> image::ImageResult<DynamicImage> {
+    use image::error::{DecodingError, ImageFormatHint};
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let failed = |err: libheif_rs::HeifError| {
+        image::ImageError::Decoding(DecodingError::new(ImageFormatHint::Name("HEIF".to_string()), err))
+    };
+    let context = HeifContext::read_from_bytes(data).map_err(failed)?;
+    let handle = context.primary_image_handle().map_err(failed)?;
+    check_dimensions(u64::from(handle.width()), u64::from(handle.height()), load)?;
+    let decoded = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)
+        .map_err(failed)?;
+
+    let planes = decoded.planes();
+    let plane = planes.interleaved.ok_or_else(|| {
+        image::ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Name("HEIF".to_string()),
+            "no interleaved RGB plane",
+        ))
+    })?;
+
+    // Rows are padded to the stride
+    let (width, height) = (plane.width, plane.height);
+    let row_bytes = width as usize * 3;
+    let pixels: Vec<u8> = plane
+        .data
+        .chunks(plane.stride)
+        .take(height as usize)
+        .flat_map(|row| &row[..row_bytes])
+        .copied()
+        .collect();
+    image::RgbImage::from_raw(width, height, pixels)
+        .map(DynamicImage::ImageRgb8)
+        .ok_or_else(|| {
+            image::ImageError::Decoding(DecodingError::new(
+                ImageFormatHint::Name("HEIF".to_string()),
+                "truncated image data",
+            ))
+        })
+}
+
+/// Whether a path has the extension of a camera RAW format
+#[cfg(feature = "raw")]
+fn is_raw(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| RAW_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// Decode a camera RAW file to RGB with a bilinear demosaic
+///
+/// Black and white levels, white balance, the sensor crop and the orientation
+/// come from the file. There is no camera color matrix, noise reduction or
+/// highlight recovery, so colors are good enough for detection and cropping
+/// rather than a proper development.
+#[cfg(feature = "raw")]
+fn decode_raw(data: &[u8], load: &LoadOptions) -> image::ImageResult<DynamicImage> {
+    use image::error::{DecodingError, ImageFormatHint};
+    use rayon::prelude::*;
+
+    let failed = |reason: String| {
+        image::ImageError::Decoding(DecodingError::new(ImageFormatHint::Name("RAW".to_string()), reason))
+    };
+    let raw = rawloader::decode(&mut Cursor::new(data)).map_err(|err| failed(err.to_string()))?;
+    let (width, height, cpp) = (raw.width, raw.height, raw.cpp);
+    // rawloader holds the sensor data by now, but the pixels aren't converted yet
+    check_dimensions(width as u64, height as u64, load)?;
+    if cpp != 1 && cpp != 3 {
+        return Err(failed(format!("unsupported {} components per pixel", cpp)));
+    }
+    let values: Vec<f32> = match &raw.data {
+        rawloader::RawImageData::Integer(values) => values.iter().map(|&value| value as f32).collect(),
+        rawloader::RawImageData::Float(values) => values.clone(),
+    };
+    if values.len() < width * height * cpp {
+        return Err(failed("truncated image data".to_string()));
+    }
+
+    // Files without white balance get a daylight one, gains are relative to green
+    let wb = if raw.wb_coeffs[..3].iter().all(|coeff| coeff.is_finite() && *coeff > 0.0) {
+        raw.wb_coeffs
+    } else {
+        raw.neutralwb()
+    };
+    // The fourth color of a CFA is a second green (or emerald) and counts as green
+    let channel = |color: usize| if color == 3 { 1 } else { color };
+    let level = |value: f32, channel: usize| {
+        let black = raw.blacklevels[channel] as f32;
+        let white = (raw.whitelevels[channel] as f32).max(black + 1.0);
+        ((value - black) / (white - black)).max(0.0) * wb[channel] / wb[1]
+    };
+    let monochrome = raw.is_monochrome();
+    let color_at = |row: usize, col: usize| if monochrome { 1 } else { channel(raw.cfa.color_at(row, col)) };
+
+    // Crops are top, right, bottom, left; a crop leaving nothing is ignored
+    let [top, right, bottom, left] = raw.crops;
+    let (top, left, out_width, out_height) = if left + right < width && top + bottom < height {
+        (top, left, width - left - right, height - top - bottom)
+    } else {
+        (0, 0, width, height)
+    };
+
+    let mut pixels = vec![0u8; out_width * out_height * 3];
+    pixels.par_chunks_mut(out_width * 3).enumerate().for_each(|(out_row, row_pixels)| {
+        let row = out_row + top;
+        for (out_col, pixel) in row_pixels.chunks_mut(3).enumerate() {
+            let col = out_col + left;
+            let rgb: [f32; 3] = if cpp == 3 {
+                let index = (row * width + col) * 3;
+                std::array::from_fn(|c| level(values[index + c], c))
+            } else if monochrome {
+                [level(values[row * width + col], 1); 3]
+            } else {
+                // Colors a photosite lacks are the mean of its 3x3 neighbours that have them
+                let (mut sums, mut counts) = ([0.0f32; 3], [0u32; 3]);
+                for y in row.saturating_sub(1)..(row + 2).min(height) {
+                    for x in col.saturating_sub(1)..(col + 2).min(width) {
+                        let c = color_at(y, x);
+                        sums[c] += level(values[y * width + x], c);
+                        counts[c] += 1;
+                    }
+                }
+                let own = color_at(row, col);
+                std::array::from_fn(|c| {
+                    if c == own {
+                        level(values[row * width + col], c)
+                    } else if counts[c] > 0 {
+                        sums[c] / counts[c] as f32
+                    } else {
+                        0.0
+                    }
+                })
+            };
+            for (out, value) in pixel.iter_mut().zip(rgb) {
+                *out = (srgb_gamma(value.min(1.0)) * 255.0).round() as u8;
+            }
+        }
+    });
+
+    let mut img = image::RgbImage::from_raw(out_width as u32, out_height as u32, pixels)
+        .map(DynamicImage::ImageRgb8)
+        .ok_or_else(|| failed("truncated image data".to_string()))?;
+    if load.exif_rotate {
+        // Flips come before the transpose
+        let (transpose, flip_h, flip_v) = raw.orientation.to_flips();
+        if flip_h {
+            img = img.fliph();
+        }
+        if flip_v {
+            img = img.flipv();
+        }
+        if transpose {
+            img = img.rotate90().fliph();
+        }
+    }
+
+    Ok(img)
+}
+
+/// The sRGB transfer curve of a linear value in 0-1
+#[cfg(feature = "raw")]
+fn srgb_gamma(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Which files a directory walk finds, by default every image at any depth
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    /// Globs of the files to take, relative to the walked directory (all images when empty)
+    pub include: Vec<String>,
+    /// Globs of files and directories to leave out, directories matching are not entered
+    pub exclude: Vec<String>,
+    /// Directory levels to descend (1: only the directory's own files), unlimited when None
+    pub max_depth: Option<usize>,
+    /// Follow symbolic links to files and directories
+    pub follow_symlinks: bool,
+}
+
+impl WalkOptions {
+    fn glob_set(patterns: &[String]) -> Result<Option<GlobSet>> {
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob = Glob::new(pattern).map_err(|err| Error::Config(format!("Invalid glob {:?}: {}", pattern, err)))?;
+            builder.add(glob);
+        }
+        builder
+            .build()
+            .map(Some)
+            .map_err(|err| Error::Config(format!("Invalid globs: {}", err)))
+    }
+}
+
+/// Find all image files under a directory
+pub fn find_images(input_dir: &Path) -> Vec<PathBuf> {
+    walk_images(input_dir).collect()
+}
+
+/// Image files under a directory, yielded as the walk finds them
+pub fn walk_images(input_dir: &Path) -> impl Iterator<Item = PathBuf> + Send + use<> {
+    WalkDir::new(input_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|e| is_image(e.path()))
+        .map(|e| e.into_path())
+}
+
+/// Image files under a directory that the walk options take, yielded as the walk finds them
+pub fn walk_images_with(input_dir: &Path, walk: &WalkOptions) -> Result<impl Iterator<Item = PathBuf> + Send + use<>> {
+    let include = WalkOptions::glob_set(&walk.include)?;
+    let exclude = WalkOptions::glob_set(&walk.exclude)?;
+    let root = input_dir.to_path_buf();
+    let relative = move |path: &Path| path.strip_prefix(&root).unwrap_or(path).to_path_buf();
+    let relative_files = relative.clone();
+
+    let mut walker = WalkDir::new(input_dir).follow_links(walk.follow_symlinks);
+    if let Some(max_depth) = walk.max_depth {
+        walker = walker.max_depth(max_depth);
+    }
+
+    let prune = exclude.clone();
+    Ok(walker
+        .into_iter()
+        .filter_entry(move |entry| {
+            // The walked directory itself is always entered
+            entry.depth() == 0
+                || !entry.file_type().is_dir()
+                || !prune.as_ref().is_some_and(|exclude| exclude.is_match(relative(entry.path())))
+        })
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !entry.file_type().is_dir() && is_image(entry.path()))
+        .filter(move |entry| {
+            let path = relative_files(entry.path());
+            include.as_ref().is_none_or(|include| include.is_match(&path))
+                && !exclude.as_ref().is_some_and(|exclude| exclude.is_match(&path))
+        })
+        .map(|entry| entry.into_path()))
+}
+
+/// Extensions of the formats every build decodes
+const IMAGE_EXTENSIONS: [&str; 8] = ["jpg", "jpeg", "png", "bmp", "webp", "tif", "tiff", "gif"];
+
+/// Extensions of the camera RAW formats decoded with the `raw` feature
+const RAW_EXTENSIONS: [&str; 9] = ["cr2", "nef", "arw", "dng", "orf", "rw2", "raf", "pef", "srw"];
+
+/// Whether a path has one of the supported image extensions
+pub(crate) fn is_image(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        let ext_str = ext.to_string_lossy().to_lowercase();
+        IMAGE_EXTENSIONS.contains(&ext_str.as_str())
+            || (cfg!(feature = "avif") && ext_str == "avif")
+            || (cfg!(feature = "heic") && ["heic", "heif"].contains(&ext_str.as_str()))
+            || (cfg!(feature = "raw") && RAW_EXTENSIONS.contains(&ext_str.as_str()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_selection_parses_first_all_and_every() {
+        assert_eq!("first".parse(), Ok(FrameSelection::First));
+        assert_eq!("all".parse(), Ok(FrameSelection::All));
+        assert_eq!("every=5".parse(), Ok(FrameSelection::Every(5)));
+        for invalid in ["every=0", "every=", "every=two", "second", ""] {
+            assert!(invalid.parse::<FrameSelection>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn every_nth_frame_starts_with_the_first() {
+        let taken: Vec<usize> = (0..7).filter(|&i| FrameSelection::Every(3).takes(i)).collect();
+        assert_eq!(taken, vec![0, 3, 6]);
+        assert!(FrameSelection::First.takes(0) && !FrameSelection::First.takes(1));
+    }
+
+    #[test]
+    fn kept_animation_frames_count_against_the_pixel_limit() {
+        use image::codecs::gif::GifEncoder;
+        use image::{Frame, RgbaImage};
+
+        let mut data = Vec::new();
+        let frames = (0..3).map(|i| Frame::new(RgbaImage::from_pixel(10, 10, image::Rgba([i * 80, 0, 0, 255]))));
+        GifEncoder::new(&mut data).encode_frames(frames).unwrap();
+        let load = |frames, max_pixels| LoadOptions { frames, max_pixels: Some(max_pixels), ..LoadOptions::default() };
+
+        // One 10x10 canvas is within the limit, three of them aren't
+        assert!(decode_loaded_frames(Path::new("a.gif"), &data, &load(FrameSelection::All, 299)).is_err());
+        assert_eq!(decode_loaded_frames(Path::new("a.gif"), &data, &load(FrameSelection::All, 300)).unwrap().len(), 3);
+        assert_eq!(decode_loaded_frames(Path::new("a.gif"), &data, &load(FrameSelection::Every(2), 200)).unwrap().len(), 2);
+    }
+}
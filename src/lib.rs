@@ -1,4 +1,51 @@
+pub mod annotate;
+pub mod archive;
+pub mod attributes;
+pub mod coco;
+pub mod cropping;
+pub mod detections;
 pub mod detector;
+pub mod download;
+#[cfg(feature = "onnx")]
+pub mod embedding;
+pub mod error;
+pub mod extractor;
+pub mod input;
+#[cfg(feature = "lmdb")]
+pub mod lmdb;
+pub mod pipeline;
+pub mod pool;
+pub mod quality;
+#[cfg(feature = "onnx")]
+pub mod upscale;
+pub mod webdataset;
+#[cfg(feature = "s3")]
+pub mod s3;
 
 // Re-export commonly used items
-pub use detector::{FaceBox, FaceDetector, create_detector};
\ No newline at end of file
+pub use annotate::Annotator;
+pub use archive::{ArchiveKind, ArchiveReader};
+#[cfg(feature = "onnx")]
+pub use attributes::{AgeGenderEstimator, ExpressionClassifier, NsfwClassifier};
+pub use attributes::{Expression, FaceAttributes, Gender, NsfwScope};
+pub use coco::CocoDataset;
+pub use cropping::{CropOptions, FaceCrop, FaceRank, IdentityCap, ManifestEntry, Margins, OutputFormat, PadFill, ProcessedImage, ResizeFilter, crop_face, encode_crop, save_faces, save_faces_with, write_atomic};
+pub use download::{HttpFetcher, read_input_list};
+pub use detections::{DetectedBox, ImageDetections};
+pub use detector::{DetectorConfig, DetectorError, DetectorFactory, Device, FaceBox, FaceDetector, Fusion, Landmarks, Roi, available_detectors, create_detector, model_cache_dir, non_max_suppression, register_detector};
+#[cfg(feature = "onnx")]
+pub use embedding::FaceEmbedder;
+pub use error::{Error, Result};
+pub use extractor::{ExtractedFace, FaceExtractor};
+pub use input::{FrameSelection, Frames, LoadOptions, WalkOptions, decode_image, find_images, load_frames, load_image, walk_images, walk_images_with};
+#[cfg(feature = "lmdb")]
+pub use lmdb::LmdbWriter;
+pub use pipeline::{
+    ErrorPolicy, ExtractionObserver, ExtractionSummary, FaceExtractionPipeline, FaceExtractionPipelineBuilder, InputSource, OutputSink,
+};
+pub use pool::DetectorPool;
+#[cfg(feature = "s3")]
+pub use s3::{S3Location, S3Store};
+#[cfg(feature = "onnx")]
+pub use upscale::SuperResolution;
+pub use webdataset::ShardWriter;
@@ -0,0 +1,84 @@
+use crate::cropping::ManifestEntry;
+use crate::error::{Error, Result};
+use heed::types::{Bytes, Str};
+use heed::{Database, Env, EnvFlags, EnvOpenOptions};
+use std::path::{Path, PathBuf};
+
+/// Largest size the environment may grow to, address space rather than disk
+const MAP_SIZE: usize = 1 << 40;
+
+/// Writes crops and their manifest entries into an LMDB environment
+///
+/// Crops are stored encoded under their key, the crop path without its extension,
+/// in the `crops` database, and their manifest entries as JSON under the same key
+/// in the `metadata` database. Each image is committed in one transaction without
+/// syncing, [`LmdbWriter::finish`] flushes everything to disk.
+pub struct LmdbWriter {
+    path: PathBuf,
+    env: Env,
+    crops: Database<Str, Bytes>,
+    metadata: Database<Str, Bytes>,
+}
+
+impl LmdbWriter {
+    /// Open or create the environment directory at `path`
+    pub fn create(path: &Path) -> Result<Self> {
+        std::fs::create_dir_all(path).map_err(|source| Error::Io {
+            context: format!("Failed to create LMDB environment: {:?}", path),
+            source,
+        })?;
+        let failed = |err: heed::Error| Error::Storage(format!("Failed to open LMDB environment {:?}: {}", path, err));
+
+        // SAFETY: the environment is only opened once per process, and syncing
+        // is left to finish(), losing at most the last images on a crash
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .map_size(MAP_SIZE)
+                .max_dbs(2)
+                .flags(EnvFlags::NO_SYNC)
+                .open(path)
+        }
+        .map_err(failed)?;
+
+        let mut txn = env.write_txn().map_err(failed)?;
+        let crops = env.create_database(&mut txn, Some("crops")).map_err(failed)?;
+        let metadata = env.create_database(&mut txn, Some("metadata")).map_err(failed)?;
+        txn.commit().map_err(failed)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            env,
+            crops,
+            metadata,
+        })
+    }
+
+    /// Store the crops of an image with their manifest entries in one transaction,
+    /// pointing each entry's output at its key
+    pub fn append(&self, entries: &mut [ManifestEntry], crops: Vec<Vec<u8>>) -> Result<()> {
+        let failed = |err: heed::Error| Error::Storage(format!("Failed to write to LMDB environment {:?}: {}", self.path, err));
+
+        let mut txn = self.env.write_txn().map_err(failed)?;
+        for (entry, data) in entries.iter_mut().zip(crops) {
+            entry.output = Path::new(&entry.output)
+                .with_extension("")
+                .to_string_lossy()
+                .replace('\\', "/");
+            let json = serde_json::to_vec(entry).map_err(|err| Error::Io {
+                context: format!("Failed to encode metadata of {}", entry.output),
+                source: err.into(),
+            })?;
+            self.crops.put(&mut txn, &entry.output, &data).map_err(failed)?;
+            self.metadata.put(&mut txn, &entry.output, &json).map_err(failed)?;
+        }
+
+        txn.commit().map_err(failed)
+    }
+
+    /// Flush the environment to disk
+    pub fn finish(&self) -> Result<()> {
+        self.env
+            .force_sync()
+            .map_err(|err| Error::Storage(format!("Failed to sync LMDB environment {:?}: {}", self.path, err)))
+    }
+}
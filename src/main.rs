@@ -1,230 +1,1236 @@
-mod detector;
-
 use anyhow::{Context, Result};
-use clap::Parser;
-use detector::{create_detector, FaceDetector};
-use log::{debug, error, info, warn};
-use std::fs;
+use clap::{Parser, Subcommand, ValueEnum};
+use checkpoint::{CheckpointWriter, Fingerprint, RunState, STATE_FILE};
+use face_cropper::quality::PhashIndex;
+use progress::ProgressWriter;
+use face_cropper::{
+    create_detector, find_images, walk_images_with, write_atomic, read_input_list, ArchiveKind, CocoDataset, CropOptions, DetectorConfig, Device, ErrorPolicy, ExtractionObserver, FaceRank, FrameSelection, Fusion, FaceDetector, IdentityCap,
+    Expression, FaceExtractionPipeline, Gender, HttpFetcher, InputSource, NsfwScope, LoadOptions, ManifestEntry, Margins, OutputFormat, OutputSink, PadFill, ProcessedImage, ResizeFilter, Roi, WalkOptions,
+};
+#[cfg(feature = "onnx")]
+use face_cropper::{AgeGenderEstimator, ExpressionClassifier, NsfwClassifier, SuperResolution};
+use log::{info, warn};
+use rand::SeedableRng;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Seek, Write};
 use std::path::{Path, PathBuf};
-use std::time::Instant;
-use walkdir::WalkDir;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+mod anonymize;
+mod bench;
+#[cfg(feature = "camera")]
+mod camera;
+mod checkpoint;
+mod cluster;
+mod compare;
+mod crop;
+#[cfg(feature = "sqlite")]
+mod database;
+mod detect;
+mod gallery;
+mod metrics;
+mod mosaic;
+mod pairs;
+mod progress;
+#[cfg(feature = "review")]
+mod review;
+mod serve;
+mod stats;
+mod sweep;
+#[cfg(feature = "parquet")]
+mod table;
 
 /// Command line arguments
 #[derive(Parser, Debug)]
-#[clap(author, version, about = "Extract and crop faces from images using face detection")]
+#[clap(
+    author,
+    version,
+    about = "Extract and crop faces from images using face detection",
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    /// Input directory containing images
-    #[clap(short, long, value_parser)]
-    input_dir: PathBuf,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Input directory containing images, a .zip/.tar/.tar.gz archive of them, s3://bucket/prefix,
+    /// or camera:<N> to capture from a webcam
+    #[clap(short, long, visible_alias = "input", value_parser, required_unless_present = "input_list")]
+    input_dir: Option<PathBuf>,
+
+    /// Text file listing image URLs (or paths relative to it), one per line, instead of an input directory
+    #[clap(long, value_parser, conflicts_with = "input_dir")]
+    input_list: Option<PathBuf>,
+
+    /// Listed URLs downloaded at the same time
+    #[clap(long, default_value = "8")]
+    download_concurrency: usize,
+
+    /// Retries of a failed download (network errors, timeouts, 429 and 5xx responses),
+    /// 0.5s after the first failure and doubling up to 30s between further ones
+    #[clap(long, default_value = "3")]
+    download_retries: u32,
+
+    /// Output directory for cropped faces, or s3://bucket/prefix to upload them
+    #[clap(short, long, value_parser, required_unless_present = "output_archive")]
+    output_dir: Option<PathBuf>,
+
+    /// Write the crops into a .tar, .tar.gz or .zip file instead of single files; the manifest
+    /// goes to the output directory, next to the archive by default
+    #[clap(long, value_parser)]
+    output_archive: Option<PathBuf>,
+
+    /// Layout of the saved crops: single files, WebDataset .tar shards of crop + JSON samples
+    /// with an index.json, or an LMDB environment (lmdb), next to the manifest
+    #[clap(long, value_enum, default_value = "files", conflicts_with = "output_archive")]
+    output_format: OutputLayout,
 
-    /// Output directory for cropped faces
-    #[clap(short, long, value_parser)]
-    output_dir: PathBuf,
+    /// Samples per WebDataset shard
+    #[clap(long, default_value_t = face_cropper::webdataset::DEFAULT_SHARD_SAMPLES)]
+    shard_samples: usize,
 
     /// Confidence threshold for face detection (0.0-1.0)
-    #[clap(short, long, default_value = "0.5")]
+    #[clap(short, long, default_value = "0.5", global = true)]
     threshold: f32,
 
     /// Maximum number of faces to extract (0 for unlimited)
     #[clap(short, long, default_value = "10000")]
     max_faces: usize,
 
-    /// Batch size for processing
+    /// Only take files matching one of these globs, relative to the input directory
+    /// (e.g. '**/*.jpg', repeatable)
+    #[clap(long, global = true)]
+    include: Vec<String>,
+
+    /// Leave out files and directories matching these globs (e.g. '**/thumbnails'
+    /// skips the directories, '**/thumbnails/**' only their files; repeatable)
+    #[clap(long, global = true)]
+    exclude: Vec<String>,
+
+    /// Directory levels to scan below the input directory (1: only its own files)
+    #[clap(long, global = true)]
+    max_depth: Option<usize>,
+
+    /// Follow symbolic links while scanning input directories
+    #[clap(long, global = true)]
+    follow_symlinks: bool,
+
+    /// Process a random subset of this many of the images found, the same subset
+    /// for the same --seed (not for S3 input)
+    #[clap(long)]
+    sample: Option<usize>,
+
+    /// Seed choosing the --sample subset
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Stop after this long (e.g. 90m, 2h), the batch in flight is saved and the run
+    /// can be continued with --resume
+    #[clap(long, value_parser = humantime::parse_duration)]
+    max_duration: Option<Duration>,
+
+    /// What to do when images fail: skip (log and continue), fail (stop at the first)
+    /// or fail-after=N (stop once N failed), stopping after the batch in flight is saved
+    #[clap(long, default_value = "skip")]
+    error_policy: ErrorPolicy,
+
+    /// Frames of animated GIF, WebP and PNG images to process: first, all or every=N;
+    /// crops and manifest entries of animated images record their frame index
+    #[clap(long, default_value = "first")]
+    frames: FrameSelection,
+
+    /// Skip images whose shorter side is below this (px), such as thumbnails and icons, telling
+    /// them from their header before decoding them
+    #[clap(long)]
+    min_image_dim: Option<u32>,
+
+    /// Fail images whose header declares more pixels than this (e.g. 100000000) before decoding
+    /// them, so a decompression bomb can't run a long job out of memory; the frames kept of an
+    /// animation (--frames) count together
+    #[clap(long, global = true)]
+    max_image_pixels: Option<u64>,
+
+    /// Batch size for processing (images handed to the detector in one call)
     #[clap(short, long, default_value = "16")]
     batch_size: usize,
 
-    /// Square size for output faces (px)
-    #[clap(short, long, default_value = "128")]
+    /// Square size for output faces (px), 0 saves crops at their native resolution
+    #[clap(short, long, default_value = "128", global = true)]
     size: u32,
 
-    /// Face detector to use (rustface, etc.)
-    #[clap(long, default_value = "rustface")]
+    /// Save every face at each of these sizes (e.g. 128,256,512) into subdirectories
+    /// named after the size; filters such as --min-sharpness look at the first one
+    #[clap(long, value_delimiter = ',', conflicts_with = "size", global = true)]
+    sizes: Vec<u32>,
+
+    /// Filter crops are resized with: lanczos3 (the sharpest), bilinear, box or nearest (the fastest)
+    #[clap(long, default_value = "lanczos3", global = true)]
+    filter: ResizeFilter,
+
+    /// How crops smaller than the output size are enlarged (sr: a Real-ESRGAN model, onnx)
+    #[clap(long, value_enum, default_value = "resize", global = true)]
+    upscale: Upscale,
+
+    /// Super-resolution model for --upscale sr (Real-ESRGAN x4 as ONNX, default model/realesrgan_x4.onnx)
+    #[clap(long, global = true)]
+    upscale_model: Option<PathBuf>,
+
+    /// Image format of the saved crops: jpg, png or webp
+    #[clap(long, default_value = "jpg", global = true)]
+    format: OutputFormat,
+
+    /// Quality of jpg and webp crops (1-100, webp at 100 is lossless)
+    #[clap(long, default_value = "75", value_parser = clap::value_parser!(u8).range(1..=100), global = true)]
+    quality: u8,
+
+    /// Face detector to use (rustface, onnx, mtcnn, blazeface, yunet, rekognition, google-vision, mock),
+    /// ensemble:<detector>,<detector>,... to run several and fuse their boxes, or
+    /// plugin:<library> to load one from a shared library
+    #[clap(long, default_value = "rustface", global = true)]
     detector: String,
 
-    /// Optional detector-specific parameters (JSON string)
-    #[clap(long, default_value = "")]
-    detector_params: String,
+    /// Smallest face the detector looks for (px, rustface: at least 20, mtcnn: at least 12)
+    #[clap(long, global = true)]
+    min_face_size: Option<u32>,
+
+    /// Scale step between image pyramid levels (rustface, mtcnn, 0.01-0.99)
+    #[clap(long, global = true)]
+    pyramid_scale_factor: Option<f32>,
+
+    /// Sliding window step (px, rustface)
+    #[clap(long, global = true)]
+    slide_window_step: Option<u32>,
+
+    /// Detector-internal score threshold, applied before --threshold (rustface)
+    #[clap(long, global = true)]
+    score_threshold: Option<f64>,
+
+    /// Model file (onnx, or a SeetaFace .bin for rustface) or directory with pnet/rnet/onet.onnx (mtcnn)
+    /// to load instead of the default, or JSON file of boxes the mock detector returns
+    #[clap(long, global = true)]
+    model_path: Option<PathBuf>,
+
+    /// Further SeetaFace models (e.g. a profile-face model) rustface runs after the frontal one or
+    /// --model-path, their boxes merged with NMS; repeat for more
+    #[clap(long, global = true)]
+    extra_model: Vec<PathBuf>,
+
+    /// Network input size (px, multiple of 32, onnx)
+    #[clap(long, global = true)]
+    input_size: Option<u32>,
+
+    /// IoU above which overlapping detections are merged, keeping the most confident (default 0.4);
+    /// lower it when a face is saved twice, e.g. found at two pyramid scales or by two ensemble members
+    #[clap(long, global = true)]
+    nms_iou: Option<f32>,
+
+    /// Also detect on mirrored and 90° rotated copies and merge the boxes (finds tilted faces, 4x slower)
+    #[clap(long, global = true)]
+    tta: bool,
+
+    /// Also detect on copies turned clockwise by these angles (degrees, e.g. 0,90,180,270 or
+    /// -30,-15,0,15,30) and merge the boxes, for sideways faces in scans and screenshots;
+    /// leave 0 out to skip the upright pass
+    #[clap(long, value_delimiter = ',', allow_hyphen_values = true, global = true)]
+    rotations: Vec<f32>,
+
+    /// Only detect inside this region of each image, as x,y,w,h in pixels or as fractions of the
+    /// image size when every value is at most 1 (e.g. 0.25,0,0.5,1 for the middle half)
+    #[clap(long, global = true)]
+    roi: Option<Roi>,
+
+    /// Detect on overlapping tiles of images larger than this many megapixels (for panoramas and scans)
+    #[clap(long, global = true)]
+    tile_megapixels: Option<f32>,
+
+    /// Shrink images to this longest side (px) for detection, crops still come from the full image
+    #[clap(long, global = true)]
+    detect_max_dim: Option<u32>,
+
+    /// Run ONNX detectors on this device (cpu, cuda, coreml or directml), falling back
+    /// to the CPU with a warning when ONNX Runtime can't use it
+    #[clap(long, default_value = "cpu", global = true)]
+    device: Device,
+
+    /// GPU to run on with --device cuda or directml
+    #[clap(long, default_value_t = 0, global = true)]
+    gpu_id: u32,
+
+    /// Requests per second sent to cloud detectors (rekognition, google-vision), 5 by default
+    #[clap(long, global = true)]
+    cloud_rate: Option<f32>,
+
+    /// How ensemble detectors merge overlapping boxes, wbf (weighted box fusion) or nms
+    /// (overlap threshold: --nms-iou)
+    #[clap(long, default_value = "wbf", global = true)]
+    fusion: Fusion,
+
+    /// Number of worker threads, each with its own detector instance
+    #[clap(short, long, default_value = "1", global = true)]
+    jobs: usize,
+
+    /// Rotate crops so the eyes are horizontal (needs a detector that provides landmarks)
+    #[clap(long, global = true)]
+    align: bool,
+
+    /// Padding added around the detected box, as a fraction of its size
+    #[clap(long, default_value = "0.5", global = true)]
+    padding: f32,
+
+    /// Margin above the detected box, as a fraction of its height; unset sides get half the --padding
+    #[clap(long, global = true)]
+    margin_top: Option<f32>,
+
+    /// Margin below the detected box, as a fraction of its height
+    #[clap(long, global = true)]
+    margin_bottom: Option<f32>,
+
+    /// Margin left of the detected box, as a fraction of its width
+    #[clap(long, global = true)]
+    margin_left: Option<f32>,
+
+    /// Margin right of the detected box, as a fraction of its width
+    #[clap(long, global = true)]
+    margin_right: Option<f32>,
+
+    /// Center crops of faces with landmarks on the eyes, with the eye line this far down
+    /// the crop (fraction of its height, e.g. 0.4), instead of on the detected box
+    #[clap(long, global = true)]
+    eye_y: Option<f32>,
+
+    /// Keep crops near the image border full size, filling the missing pixels
+    /// (clamp: repeat the edge, black, reflect: mirror the image); they shrink to fit otherwise
+    #[clap(long, global = true)]
+    pad_fill: Option<PadFill>,
+
+    /// Save crops in subdirectories mirroring where their source image is under the input directory
+    #[clap(long, global = true)]
+    mirror_structure: bool,
+
+    /// Use the name of each image's directory as an identity label: crops go to <output>/<label>/
+    /// and the manifest records the label
+    #[clap(long, conflicts_with = "mirror_structure", global = true)]
+    label_from_dir: bool,
+
+    /// Save at most this many crops per identity (--label-from-dir label or cluster), so no
+    /// single subject dominates the dataset
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    max_per_identity: Option<u64>,
+
+    /// Spread crops over numbered subdirectories (00000/, 00001/, ...) of this many crops each
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    shard_size: Option<u64>,
+
+    /// Don't crop faces smaller than this in the source image (px, shorter side of the box)
+    #[clap(long, global = true)]
+    min_face_px: Option<u32>,
+
+    /// Don't crop faces larger than this in the source image (px, shorter side of the box)
+    #[clap(long, global = true)]
+    max_face_px: Option<u32>,
+
+    /// Crop at most this many faces per image, the largest ones
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    max_faces_per_image: Option<u64>,
+
+    /// Crop only the largest face of every image (same as --max-faces-per-image 1)
+    #[clap(long, conflicts_with = "max_faces_per_image", global = true)]
+    largest_only: bool,
+
+    /// Crop only the most confident face of every image, the larger one on ties
+    #[clap(long, conflicts_with_all = ["max_faces_per_image", "largest_only"], global = true)]
+    best_face: bool,
+
+    /// Drop blurry faces whose crop sharpness (variance of the Laplacian) is below this
+    #[clap(long, global = true)]
+    min_sharpness: Option<f64>,
+
+    /// Drop near-black faces whose crop brightness (mean luminance, 0-255) is below this
+    #[clap(long, global = true)]
+    min_brightness: Option<f64>,
+
+    /// Drop blown-out faces whose crop brightness (mean luminance, 0-255) is above this
+    #[clap(long, global = true)]
+    max_brightness: Option<f64>,
+
+    /// Drop flat, washed-out faces whose crop contrast (standard deviation of the luminance) is below this
+    #[clap(long, global = true)]
+    min_contrast: Option<f64>,
+
+    /// Skip faces whose perceptual hash is within this Hamming distance (0-64) of an already saved face
+    #[clap(long, value_parser = clap::value_parser!(u32).range(0..=64), global = true)]
+    dedupe_phash: Option<u32>,
+
+    /// Estimate age and gender of every face and record them in the manifest (onnx)
+    #[clap(long, global = true)]
+    age_gender: bool,
+
+    /// Age/gender model (insightface genderage.onnx, default model/genderage.onnx)
+    #[clap(long, global = true)]
+    age_gender_model: Option<PathBuf>,
+
+    /// Keep only faces estimated to be 18 or older (implies --age-gender)
+    #[clap(long, global = true)]
+    only_adults: bool,
+
+    /// Keep only faces of this estimated gender, female or male (implies --age-gender)
+    #[clap(long, global = true)]
+    gender: Option<Gender>,
+
+    /// Classify the facial expression of every face and record it in the manifest (onnx)
+    #[clap(long, global = true)]
+    expression: bool,
+
+    /// Expression model (FER+ emotion-ferplus-8.onnx, default model/emotion-ferplus-8.onnx)
+    #[clap(long, global = true)]
+    expression_model: Option<PathBuf>,
+
+    /// Keep only faces with one of these expressions, comma separated (neutral, happy, surprised,
+    /// sad, angry, disgusted, fearful, contemptuous; implies --expression)
+    #[clap(long, value_delimiter = ',', global = true)]
+    filter_expression: Vec<Expression>,
+
+    /// Reject explicit content before anything is written, with an NSFW classifier (onnx)
+    #[clap(long, global = true)]
+    nsfw: bool,
+
+    /// NSFW model (nsfw_model MobileNet v2 as ONNX, default model/nsfw_mobilenet2.onnx)
+    #[clap(long, global = true)]
+    nsfw_model: Option<PathBuf>,
+
+    /// NSFW score (0-1) above which crops or images are rejected
+    #[clap(long, default_value = "0.8", global = true)]
+    nsfw_threshold: f32,
+
+    /// Score each face crop or the whole source image: crop or image
+    #[clap(long, default_value = "crop", global = true)]
+    nsfw_scope: NsfwScope,
+
+    /// Don't rotate images upright according to their EXIF orientation tag
+    #[clap(long, global = true)]
+    no_exif_rotate: bool,
+
+    /// Also write detection annotations for the source images in this format
+    #[clap(long, value_enum)]
+    annotations: Option<AnnotationFormat>,
+
+    /// Report progress as log lines or as JSON events on stdout (run_started, batch_started,
+    /// face_saved, image_done, error, run_finished)
+    #[clap(long, value_enum, default_value = "text")]
+    progress_format: ProgressFormat,
+
+    /// Write log lines as text or as JSON objects with the fields of their image span
+    /// (path, faces, saved, duration_ms), filtered by RUST_LOG either way
+    #[clap(long, value_enum, default_value = "text", global = true)]
+    log_format: LogFormat,
+
+    /// Also record the run, its images, detections and crops in this SQLite database
+    #[clap(long, value_parser)]
+    db: Option<PathBuf>,
+
+    /// Also write the manifest as a Parquet table, manifest.parquet next to it
+    #[clap(long)]
+    parquet: bool,
+
+    /// Also write an index.html to the output directory showing the crops as thumbnails
+    /// (written to thumbs/), grouped by label (--label-from-dir) or else by source image
+    #[clap(long)]
+    gallery: bool,
+
+    /// Also write copies of the source images with the detected boxes drawn on them to this directory
+    #[clap(long, global = true)]
+    save_annotated: Option<PathBuf>,
+
+    /// Don't write face crops (useful with --annotations)
+    #[clap(long)]
+    no_crops: bool,
+
+    /// Continue an interrupted run in the same output directory, skipping finished images
+    #[clap(long)]
+    resume: bool,
+
+    /// Continue a finished or interrupted run, processing only images that are new or whose
+    /// content changed (recognized by mtime and SHA-256); face numbers continue where it stopped,
+    /// the earlier crops and manifest entries of changed images are removed
+    #[clap(long, conflicts_with = "resume")]
+    incremental: bool,
 }
 
-/// Process an image file and save cropped faces
-fn process_image(
-    path: &Path,
-    detector: &mut Box<dyn FaceDetector>,
-    output_dir: &Path,
-    threshold: f32,
-    size: u32,
-    face_counter: &mut usize
-) -> Result<usize> {
-    // Load image
-    let img = image::open(path)
-        .with_context(|| format!("Failed to open image: {:?}", path))?;
-
-    // Detect faces
-    let faces = detector.detect_faces(&img, threshold)?;
-
-    // Process each detected face
-    let mut faces_found = 0;
-
-    for face in faces {
-        // Crop face with some padding
-        let padding_factor = 0.5; // 50% extra padding around face
-        let padding_w = (face.width as f32 * padding_factor) as i32;
-        let padding_h = (face.height as f32 * padding_factor) as i32;
-
-        let x = (face.x - padding_w / 2).max(0);
-        let y = (face.y - padding_h / 2).max(0);
-        let width = (face.width + padding_w).min(img.width() as i32 - x);
-        let height = (face.height + padding_h).min(img.height() as i32 - y);
-
-        // Ensure we have a valid crop region
-        if width <= 0 || height <= 0 {
-            continue;
+/// Subcommands, running without one extracts faces from --input-dir
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run an HTTP service that detects faces in uploaded images
+    Serve(serve::ServeArgs),
+    /// Detect faces and write their bounding boxes (JSON/CSV) without saving crops
+    Detect(detect::DetectArgs),
+    /// Crop faces listed in a `detect` output or COCO file, without running detection
+    Crop(crop::CropArgs),
+    /// Blur or pixelate every detected face and write the redacted images
+    Anonymize(anonymize::AnonymizeArgs),
+    /// Group face crops by identity (ArcFace embeddings) into one directory per person
+    Cluster(cluster::ClusterArgs),
+    /// Write positive and negative crop pairs (LFW style CSVs) from a directory of labeled crops
+    Pairs(pairs::PairsArgs),
+    /// Tile a random sample of face crops into one contact-sheet image
+    Mosaic(mosaic::MosaicArgs),
+    /// Step through the crops of a run in a terminal UI to keep, reject or relabel them
+    #[cfg(feature = "review")]
+    Review(review::ReviewArgs),
+    /// Time each detector over sample images: images/sec, faces found and per-stage timings
+    Bench(bench::BenchArgs),
+    /// Run two detectors over the same images and report the faces only one of them found
+    Compare(compare::CompareArgs),
+    /// Detect once per image and report how many faces each confidence threshold keeps
+    Sweep(sweep::SweepArgs),
+    /// Report distributions over the manifest of a run: faces per image, confidence, sharpness, failures
+    Stats(stats::StatsArgs),
+}
+
+impl Args {
+    /// Detector configuration from the detector flags
+    fn detector_config(&self) -> DetectorConfig {
+        DetectorConfig {
+            min_face_size: self.min_face_size,
+            pyramid_scale_factor: self.pyramid_scale_factor,
+            slide_window_step: self.slide_window_step,
+            score_threshold: self.score_threshold,
+            model_path: self.model_path.clone(),
+            extra_models: self.extra_model.clone(),
+            input_size: self.input_size,
+            nms_iou: self.nms_iou,
+            tta: self.tta,
+            rotations: self.rotations.clone(),
+            roi: self.roi,
+            tile_megapixels: self.tile_megapixels,
+            detect_max_dim: self.detect_max_dim,
+            device: self.device,
+            gpu_id: self.gpu_id,
+            requests_per_second: self.cloud_rate,
+            fusion: self.fusion,
         }
+    }
 
-        // Get square crop (use the smaller dimension)
-        let size_to_use = width.min(height);
-        let x_center = x + width / 2;
-        let y_center = y + height / 2;
-        let x_crop = (x_center - size_to_use / 2).max(0);
-        let y_crop = (y_center - size_to_use / 2).max(0);
+    /// Which files of input directories are processed, shared by all modes
+    fn walk_options(&self) -> WalkOptions {
+        WalkOptions {
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            max_depth: self.max_depth,
+            follow_symlinks: self.follow_symlinks,
+        }
+    }
 
-        // Create the crop
-        let cropped = img.crop_imm(
-            x_crop as u32,
-            y_crop as u32,
-            size_to_use as u32,
-            size_to_use as u32
-        );
+    /// The images under a directory (or the file itself) that the walk options take
+    fn find_images(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        Ok(walk_images_with(dir, &self.walk_options())?.collect())
+    }
 
-        // Resize to the requested size
-        let resized = cropped.resize_exact(
-            size,
-            size,
-            image::imageops::FilterType::Lanczos3
-        );
+    /// Image loading options shared by all modes
+    fn load_options(&self) -> LoadOptions {
+        LoadOptions {
+            exif_rotate: !self.no_exif_rotate,
+            frames: self.frames,
+            min_dim: self.min_image_dim,
+            max_pixels: self.max_image_pixels,
+        }
+    }
 
-        // Generate output filename with face index and confidence
-        let filename = format!(
-            "face_{:06}_{:.3}.jpg",
-            face_counter,
-            face.confidence
-        );
-        let output_path = output_dir.join(filename);
+    /// Crop options shared by all modes, loading the classification models they need
+    fn crop_options(&self) -> Result<CropOptions> {
+        #[cfg(not(feature = "onnx"))]
+        if self.wants_age_gender() || self.wants_expression() || self.wants_nsfw() {
+            return Err(anyhow::anyhow!(
+                "Age, gender, expression and NSFW classification run on ONNX Runtime, which needs a build with `--features onnx`"
+            ));
+        }
+        if self.max_per_identity.is_some() && !self.label_from_dir {
+            return Err(anyhow::anyhow!("--max-per-identity needs labels, from --label-from-dir or the cluster subcommand"));
+        }
+        #[cfg(not(feature = "onnx"))]
+        if self.wants_upscaler() {
+            return Err(anyhow::anyhow!("Super-resolution runs on ONNX Runtime, which needs a build with `--features onnx`"));
+        }
+
+        Ok(CropOptions {
+            size: self.sizes.first().copied().unwrap_or(self.size),
+            sizes: self.sizes.clone(),
+            filter: self.filter,
+            #[cfg(feature = "onnx")]
+            upscaler: self
+                .wants_upscaler()
+                .then(|| SuperResolution::new(self.upscale_model.as_deref()))
+                .transpose()
+                .context("Failed to load super-resolution model")?,
+            format: self.format,
+            quality: self.quality,
+            align: self.align,
+            padding: self.padding,
+            margins: self.margins(),
+            eye_y: self.eye_y,
+            pad_fill: self.pad_fill,
+            mirror_root: self.input_dir.clone().filter(|_| self.mirror_structure),
+            label_from_dir: self.label_from_dir,
+            identity_cap: self.max_per_identity.map(|max| IdentityCap::new(max as usize)),
+            shard_size: self.shard_size.map(|size| size as usize),
+            min_face_px: self.min_face_px,
+            max_face_px: self.max_face_px,
+            min_sharpness: self.min_sharpness,
+            min_brightness: self.min_brightness,
+            max_brightness: self.max_brightness,
+            min_contrast: self.min_contrast,
+            max_faces: if self.largest_only || self.best_face {
+                Some(1)
+            } else {
+                self.max_faces_per_image.map(|max| max as usize)
+            },
+            rank: if self.best_face { FaceRank::Confidence } else { FaceRank::Area },
+            dedupe: self.dedupe_phash.map(PhashIndex::new),
+            #[cfg(feature = "onnx")]
+            age_gender: self
+                .wants_age_gender()
+                .then(|| AgeGenderEstimator::new(self.age_gender_model.as_deref()))
+                .transpose()
+                .context("Failed to load age/gender model")?,
+            min_age: self.only_adults.then_some(18),
+            gender: self.gender,
+            #[cfg(feature = "onnx")]
+            expression: self
+                .wants_expression()
+                .then(|| ExpressionClassifier::new(self.expression_model.as_deref()))
+                .transpose()
+                .context("Failed to load expression model")?,
+            expressions: self.filter_expression.clone(),
+            #[cfg(feature = "onnx")]
+            nsfw: self
+                .wants_nsfw()
+                .then(|| NsfwClassifier::new(self.nsfw_model.as_deref()))
+                .transpose()
+                .context("Failed to load NSFW model")?,
+            nsfw_threshold: self.nsfw_threshold,
+            nsfw_scope: self.nsfw_scope,
+            skip: self.no_crops,
+        })
+    }
+
+    /// Per-side margins when any is set, the others taking their share of the padding
+    fn margins(&self) -> Option<Margins> {
+        let sides = [self.margin_top, self.margin_bottom, self.margin_left, self.margin_right];
+        if sides.iter().all(Option::is_none) {
+            return None;
+        }
+
+        let side = |margin: Option<f32>| margin.unwrap_or(self.padding / 2.0);
+        Some(Margins {
+            top: side(self.margin_top),
+            bottom: side(self.margin_bottom),
+            left: side(self.margin_left),
+            right: side(self.margin_right),
+        })
+    }
+
+    /// Whether age and gender are estimated, directly or for a filter
+    fn wants_age_gender(&self) -> bool {
+        self.age_gender || self.age_gender_model.is_some() || self.only_adults || self.gender.is_some()
+    }
+
+    /// Whether expressions are classified, directly or for a filter
+    fn wants_expression(&self) -> bool {
+        self.expression || self.expression_model.is_some() || !self.filter_expression.is_empty()
+    }
+
+    /// Whether the NSFW filter runs
+    fn wants_nsfw(&self) -> bool {
+        self.nsfw || self.nsfw_model.is_some()
+    }
+
+    /// Whether small crops are upscaled with the super-resolution model
+    fn wants_upscaler(&self) -> bool {
+        self.upscale == Upscale::Sr
+    }
+}
+
+/// How the saved crops are laid out in the output
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputLayout {
+    /// One file per crop
+    Files,
+    /// WebDataset .tar shards (shard-000000.tar, ...) with a {key}.jpg and {key}.json per sample
+    Webdataset,
+    /// An LMDB environment (faces.lmdb/) with the crops and their metadata, keyed alike
+    Lmdb,
+}
+
+/// How crops smaller than the output size are enlarged
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Upscale {
+    /// Resample with the --filter
+    Resize,
+    /// Run a super-resolution model first, then resample to the output size
+    Sr,
+}
+
+/// How run progress is reported
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ProgressFormat {
+    /// Log lines only (RUST_LOG=info)
+    Text,
+    /// Newline-delimited JSON events on stdout, next to the log lines
+    Json,
+}
+
+/// How log lines are written to stderr
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line, for log pipelines
+    Json,
+}
+
+/// Supported annotation export formats
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum AnnotationFormat {
+    /// COCO detection JSON (annotations.json)
+    Coco,
+}
+
+/// Create the detector selected on the command line
+fn init_detector(args: &Args) -> Result<Box<dyn FaceDetector>> {
+    create_detector(&args.detector, &args.detector_config())
+        .context("Failed to initialize face detector")
+}
+
+/// Random numbers for sampling, the same for the same seed
+///
+/// ChaCha gives the same numbers on every platform and rand version, so a seed
+/// picks the same images, pairs or mosaic crops wherever the tool runs.
+fn seeded_rng(seed: u64) -> rand_chacha::ChaCha8Rng {
+    rand_chacha::ChaCha8Rng::seed_from_u64(seed)
+}
 
-        // Save the cropped and resized face
-        resized.save(&output_path)
-            .with_context(|| format!("Failed to save cropped face to: {:?}", output_path))?;
+/// A random subset of `count` images in their original order, the same for the same seed
+fn sample_images(image_paths: Vec<PathBuf>, count: usize, seed: u64) -> Vec<PathBuf> {
+    let mut rng = seeded_rng(seed);
+    let mut picked = rand::seq::index::sample(&mut rng, image_paths.len(), count).into_vec();
+    picked.sort_unstable();
 
-        debug!("Saved face from {:?} to {:?}", path, output_path);
+    let mut image_paths: Vec<Option<PathBuf>> = image_paths.into_iter().map(Some).collect();
+    picked.into_iter().filter_map(|i| image_paths[i].take()).collect()
+}
+
+/// S3 location of a path given on the command line, None for local paths
+#[cfg(feature = "s3")]
+fn s3_location(path: &Path) -> Result<Option<face_cropper::S3Location>> {
+    match path.to_str() {
+        Some(url) if url.starts_with("s3://") => face_cropper::S3Location::parse(url)
+            .map(Some)
+            .with_context(|| format!("Invalid S3 location: {}", url)),
+        _ => Ok(None),
+    }
+}
+
+/// Without S3 support every path is local, `s3://` ones are rejected
+#[cfg(not(feature = "s3"))]
+fn s3_location(path: &Path) -> Result<Option<std::convert::Infallible>> {
+    match path.to_str() {
+        Some(url) if url.starts_with("s3://") => Err(anyhow::anyhow!(
+            "{} requested, but S3 input and output need a build with `--features s3`",
+            url
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Path of an image relative to the input directory, as recorded in state and annotations
+fn relative_path(path: &Path, input_dir: &Path) -> String {
+    path.strip_prefix(input_dir)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
 
-        *face_counter += 1;
-        faces_found += 1;
+/// Directory of an output directory that review moves rejected crops to
+const REJECTED_DIR: &str = "rejected";
+
+/// Face crops under a directory of crops, without the ones review rejected or
+/// the thumbnails of a gallery
+fn find_crops(dir: &Path) -> Vec<PathBuf> {
+    let mut crops = find_images(dir);
+    crops.retain(|crop| {
+        !crop
+            .strip_prefix(dir)
+            .is_ok_and(|relative| relative.starts_with(REJECTED_DIR) || relative.starts_with(gallery::THUMBS_DIR))
+    });
+    crops
+}
+
+/// Every entry of a manifest written by an earlier or the current run
+fn read_manifest(path: &Path) -> Result<Vec<ManifestEntry>> {
+    let manifest = File::open(path).with_context(|| format!("Failed to open manifest: {:?}", path))?;
+    let mut entries = Vec::new();
+    for line in BufReader::new(manifest).lines() {
+        let line = line.with_context(|| format!("Failed to read manifest: {:?}", path))?;
+        if !line.trim().is_empty() {
+            entries.push(serde_json::from_str(&line).with_context(|| format!("Invalid manifest line: {}", line))?);
+        }
     }
 
-    Ok(faces_found)
+    Ok(entries)
+}
+
+/// Remove the manifest entries and crops of changed images an incremental run
+/// processes again, returning the new length of the manifest
+///
+/// Only the first `manifest_bytes` of the manifest are kept, the rest is from an
+/// unfinished batch.
+fn drop_changed_entries(
+    manifest_path: &Path,
+    manifest_bytes: u64,
+    output_dir: &Path,
+    input_dir: &Path,
+    changed: &HashSet<String>
+) -> Result<u64> {
+    if !manifest_path.exists() {
+        return Ok(0);
+    }
+    let data = fs::read(manifest_path).with_context(|| format!("Failed to open manifest: {:?}", manifest_path))?;
+    let finished = &data[..data.len().min(manifest_bytes as usize)];
+
+    let mut kept = Vec::with_capacity(finished.len());
+    let mut dropped = 0;
+    for line in finished.split_inclusive(|&byte| byte == b'\n') {
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+        let entry: ManifestEntry = serde_json::from_slice(line)
+            .with_context(|| format!("Invalid manifest line: {}", String::from_utf8_lossy(line)))?;
+        if !changed.contains(&relative_path(Path::new(&entry.source), input_dir)) {
+            kept.extend_from_slice(line);
+            continue;
+        }
+
+        let crop = output_dir.join(&entry.output);
+        match fs::remove_file(&crop) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                warn!("Failed to remove the crop of a changed image {:?}: {}", crop, err);
+            }
+            _ => {}
+        }
+        dropped += 1;
+    }
+
+    // Replaced in one step, the old entries stay until the new manifest is complete
+    write_atomic(manifest_path, &kept).with_context(|| format!("Failed to write manifest: {:?}", manifest_path))?;
+    info!("Dropped {} manifest entries of {} changed images", dropped, changed.len());
+
+    Ok(kept.len() as u64)
 }
 
 /// Main program logic
 fn run(args: Args) -> Result<()> {
-    // Initialize logger
-    env_logger::init();
+    // Paths in an input list are relative to the list
+    let input_dir = match &args.input_list {
+        Some(list) => list.parent().unwrap_or(Path::new("")).to_path_buf(),
+        None => args.input_dir.clone().expect("clap requires --input-dir or --input-list"),
+    };
+    let output_dir = match (&args.output_dir, &args.output_archive) {
+        (Some(dir), _) => dir.clone(),
+        (None, Some(archive)) => archive.parent().unwrap_or(Path::new("")).to_path_buf(),
+        (None, None) => unreachable!("clap requires --output-dir or --output-archive"),
+    };
+
+    #[cfg(not(feature = "parquet"))]
+    if args.parquet {
+        return Err(anyhow::anyhow!("--parquet needs a build with `--features parquet`"));
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    if let Some(db) = &args.db {
+        return Err(anyhow::anyhow!(
+            "Database {:?} requested, but --db needs a build with `--features sqlite`",
+            db
+        ));
+    }
+
+    // `s3://bucket/prefix` reads from or writes to a bucket instead of a directory
+    let remote_input = s3_location(&input_dir)?;
+
+    // An incremental run resumes the last one, unchanged images count as finished
+    let resume = args.resume || args.incremental;
+    let remote_output = s3_location(&output_dir)?;
+    if resume && (remote_input.is_some() || remote_output.is_some()) {
+        return Err(anyhow::anyhow!("--resume and --incremental aren't supported with S3 input or output"));
+    }
+    if resume && args.output_archive.is_some() {
+        return Err(anyhow::anyhow!("--resume and --incremental can't append to an --output-archive"));
+    }
+    #[cfg(not(feature = "lmdb"))]
+    if args.output_format == OutputLayout::Lmdb {
+        return Err(anyhow::anyhow!("LMDB output needs a build with `--features lmdb`"));
+    }
+    let webdataset = args.output_format == OutputLayout::Webdataset;
+    let lmdb = args.output_format == OutputLayout::Lmdb;
+    if resume && webdataset {
+        return Err(anyhow::anyhow!("--resume and --incremental can't append to WebDataset shards"));
+    }
+    if (webdataset || lmdb) && remote_output.is_some() {
+        return Err(anyhow::anyhow!("WebDataset and LMDB output need a local output directory"));
+    }
+    if args.gallery && (args.no_crops || args.output_archive.is_some() || webdataset || lmdb || remote_output.is_some()) {
+        return Err(anyhow::anyhow!("--gallery needs the crops written as files to a local output directory"));
+    }
+    if args.sample.is_some() && remote_input.is_some() {
+        return Err(anyhow::anyhow!("--sample isn't supported with S3 input"));
+    }
+
+    // The manifest and annotations for S3 are written locally and uploaded at the end
+    let output_dir = match &remote_output {
+        Some(_) => std::env::temp_dir().join(format!("face_cropper-{}", std::process::id())),
+        None => output_dir,
+    };
 
     // Create output directory if it doesn't exist
-    fs::create_dir_all(&args.output_dir)
+    fs::create_dir_all(&output_dir)
         .context("Failed to create output directory")?;
 
-    // Initialize face detector
-    info!("Initializing face detector: {}", args.detector);
-    let mut detector = create_detector(&args.detector)
-        .context("Failed to initialize face detector")?;
-
-    // Set detector params if provided
-    if !args.detector_params.is_empty() {
-        detector.set_params(&args.detector_params)?;
-    }
-
-    // Find all image files in input directory
-    info!("Scanning input directory for images: {:?}", args.input_dir);
-    let image_paths: Vec<PathBuf> = WalkDir::new(&args.input_dir)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|e| {
-            if let Some(ext) = e.path().extension() {
-                let ext_str = ext.to_string_lossy().to_lowercase();
-                return ["jpg", "jpeg", "png", "bmp"].contains(&ext_str.as_str());
+    // `camera:<N>` captures from a webcam instead of reading a directory
+    if let Some(index) = input_dir.to_str().and_then(|input| input.strip_prefix("camera:")) {
+        let index: u32 = index
+            .parse()
+            .with_context(|| format!("Invalid camera index: {}", index))?;
+
+        if remote_output.is_some() || args.output_archive.is_some() || webdataset || lmdb {
+            return Err(anyhow::anyhow!("Camera capture needs a local output directory"));
+        }
+
+        #[cfg(feature = "camera")]
+        return camera::capture(&args, index, &output_dir);
+        #[cfg(not(feature = "camera"))]
+        return Err(anyhow::anyhow!(
+            "Camera {} requested, but camera input needs a build with `--features camera`",
+            index
+        ));
+    }
+
+    // Pick up where an interrupted run left off
+    let state_path = output_dir.join(STATE_FILE);
+    let mut state = if resume {
+        RunState::load(&state_path)?
+    } else {
+        RunState::default()
+    };
+    // Changed images are processed again, their earlier crops make way for the new ones
+    let changed = if args.incremental {
+        state.take_changed(&input_dir)
+    } else {
+        HashSet::new()
+    };
+    let state = Arc::new(state);
+
+    let input = match remote_input {
+        #[cfg(feature = "s3")]
+        Some(location) => InputSource::S3(location),
+        #[cfg(not(feature = "s3"))]
+        Some(never) => match never {},
+        // Directories are processed while they are scanned, a resumed run skips
+        // the finished images on the way
+        None if args.input_list.is_none()
+            && args.sample.is_none()
+            && !(ArchiveKind::of(&input_dir).is_some() && input_dir.is_file()) =>
+        {
+            info!("Scanning input directory for images: {:?}", input_dir);
+            if resume {
+                info!(
+                    "Resuming: {} images already processed, next face index {}",
+                    state.processed.len(),
+                    state.face_counter
+                );
             }
-            false
-        })
-        .map(|e| e.path().to_owned())
-        .collect();
+            InputSource::Directory(input_dir.clone())
+        }
+        None => {
+            // Find all image files in input directory
+            let mut image_paths = match &args.input_list {
+                Some(list) => {
+                    info!("Reading input list: {:?}", list);
+                    read_input_list(list)?
+                }
+                None if ArchiveKind::of(&input_dir).is_some() && input_dir.is_file() => {
+                    info!("Listing images in archive: {:?}", input_dir);
+                    InputSource::Archive(input_dir.clone()).images()?
+                }
+                None => {
+                    info!("Scanning input directory for images: {:?}", input_dir);
+                    args.find_images(&input_dir)?
+                }
+            };
+
+            info!("Found {} images", image_paths.len());
 
-    info!("Found {} images", image_paths.len());
+            if image_paths.is_empty() {
+                warn!("No images found in input");
+                return Ok(());
+            }
+
+            if let Some(sample) = args.sample
+                && sample < image_paths.len()
+            {
+                image_paths = sample_images(image_paths, sample, args.seed);
+                info!("Sampled {} images (seed {})", image_paths.len(), args.seed);
+            }
+
+            if resume {
+                image_paths.retain(|path| !state.is_current(&relative_path(path, &input_dir), path));
+                info!(
+                    "Resuming: {} images already processed, {} new or changed, next face index {}",
+                    state.processed.len(),
+                    image_paths.len(),
+                    state.face_counter
+                );
+            }
+
+            InputSource::Files { root: input_dir.clone(), paths: image_paths }
+        }
+    };
 
-    if image_paths.is_empty() {
-        warn!("No images found in input directory");
-        return Ok(());
+    let streamed = matches!(input, InputSource::Directory(_));
+
+    let output = match &remote_output {
+        _ if args.no_crops => OutputSink::Discard,
+        _ if let Some(archive) = &args.output_archive => OutputSink::Archive(archive.clone()),
+        _ if webdataset => OutputSink::WebDataset {
+            dir: output_dir.clone(),
+            samples_per_shard: args.shard_samples,
+        },
+        #[cfg(feature = "lmdb")]
+        _ if lmdb => OutputSink::Lmdb(output_dir.join("faces.lmdb")),
+        #[cfg(feature = "s3")]
+        Some(location) => OutputSink::S3(location.clone()),
+        #[cfg(not(feature = "s3"))]
+        Some(never) => match *never {},
+        None => OutputSink::Directory(output_dir.clone()),
+    };
+
+    let mut builder = FaceExtractionPipeline::builder()
+        .detector(&args.detector, args.detector_config())
+        .threshold(args.threshold)
+        .input(input)
+        .output(output)
+        .walk_options(args.walk_options())
+        .load_options(args.load_options())
+        .fetcher(HttpFetcher::new(args.download_concurrency, args.download_retries))
+        .crop_options(args.crop_options()?)
+        .max_faces(args.max_faces)
+        .error_policy(args.error_policy)
+        .first_face_index(state.face_counter)
+        .hash_sources(args.incremental)
+        .batch_size(args.batch_size)
+        .jobs(args.jobs);
+    if let Some(dir) = &args.save_annotated {
+        builder = builder.annotated_dir(dir);
     }
+    if resume && streamed {
+        let (state, input_dir) = (Arc::clone(&state), input_dir.clone());
+        builder = builder.skip_images(move |path| state.is_current(&relative_path(path, &input_dir), path));
+    }
+
+    // The first Ctrl-C lets the batch in flight finish and the results get written,
+    // the second one quits right away
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = Arc::clone(&stop);
+    ctrlc::set_handler(move || {
+        if handler_stop.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        eprintln!("Stopping after the current batch, press Ctrl-C again to quit immediately");
+    })
+    .context("Failed to install the Ctrl-C handler")?;
 
-    // Process images in chunks
-    let mut face_counter = 0;
-    let mut processed_counter = 0;
-    let start_time = Instant::now();
+    // The time budget stops the run like a Ctrl-C
+    let budget_used = Arc::new(AtomicBool::new(false));
+    if let Some(max_duration) = args.max_duration {
+        let (stop, budget_used) = (Arc::clone(&stop), Arc::clone(&budget_used));
+        std::thread::spawn(move || {
+            std::thread::sleep(max_duration);
+            budget_used.store(true, Ordering::SeqCst);
+            if !stop.swap(true, Ordering::SeqCst) {
+                warn!(
+                    "Time budget of {} used up, stopping after the current batch",
+                    humantime::format_duration(max_duration)
+                );
+            }
+        });
+    }
+    let pipeline = builder.stop_flag(stop).build()?;
 
-    for (batch_idx, chunk) in image_paths.chunks(args.batch_size).enumerate() {
-        // Check if we've reached the maximum number of faces
-        if args.max_faces > 0 && face_counter >= args.max_faces {
-            info!("Reached maximum number of faces ({}), stopping", args.max_faces);
-            break;
+    // Every saved face gets a line in the manifest, dropping entries of an unfinished batch
+    let manifest_path = output_dir.join("manifest.jsonl");
+    let manifest_bytes = if changed.is_empty() {
+        state.manifest_bytes
+    } else {
+        drop_changed_entries(&manifest_path, state.manifest_bytes, &output_dir, &input_dir, &changed)?
+    };
+    let manifest_file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(!resume)
+        .open(&manifest_path)
+        .with_context(|| format!("Failed to create manifest: {:?}", manifest_path))?;
+    if resume {
+        // Shorter when a run was stopped right after dropping changed images' entries
+        let length = manifest_file.metadata()?.len();
+        manifest_file.set_len(manifest_bytes.min(length))?;
+    }
+    let mut manifest = BufWriter::new(manifest_file);
+    manifest.seek(std::io::SeekFrom::End(0))?;
+
+    // Rewritten every run, a resumed run tries the earlier failures again
+    let failures_path = output_dir.join("failures.txt");
+    let failures = BufWriter::new(
+        File::create(&failures_path).with_context(|| format!("Failed to create failures list: {:?}", failures_path))?,
+    );
+
+    let mut checkpoint = CheckpointWriter::open(&state_path, resume.then_some(&*state))?;
+    if !changed.is_empty() {
+        checkpoint.record(Vec::new(), HashMap::new(), state.face_counter, manifest_bytes)?;
+    }
+
+    // Annotations are collected in memory and written once at the end
+    let coco_path = output_dir.join("annotations.json");
+    let coco = match args.annotations {
+        Some(AnnotationFormat::Coco) if resume && coco_path.exists() => {
+            let file = File::open(&coco_path)
+                .with_context(|| format!("Failed to open annotations file: {:?}", coco_path))?;
+            let mut coco: CocoDataset = serde_json::from_reader(BufReader::new(file))
+                .with_context(|| format!("Failed to read annotations from: {:?}", coco_path))?;
+            coco.remove_images(&changed);
+            Some(coco)
         }
+        Some(AnnotationFormat::Coco) => {
+            if resume && !state.processed.is_empty() {
+                warn!("No annotations from the interrupted run, {:?} will only cover the remaining images", coco_path);
+            }
+            Some(CocoDataset::new())
+        }
+        None => None,
+    };
+
+    #[cfg(feature = "sqlite")]
+    let db = args
+        .db
+        .as_deref()
+        .map(|path| {
+            database::Database::open(path, &database::RunInfo {
+                input: &input_dir,
+                output: args.output_dir.as_deref().unwrap_or(&output_dir),
+                detector: &args.detector,
+                threshold: args.threshold,
+            })
+        })
+        .transpose()?;
 
+    let mut recorder = RunRecorder {
+        input_dir: &input_dir,
+        manifest,
+        failures,
+        checkpoint,
+        coco,
+        #[cfg(feature = "sqlite")]
+        db,
+        finished: Vec::new(),
+        fingerprints: args.incremental.then(HashMap::new),
+        run_started: SystemTime::now(),
+        progress: (args.progress_format == ProgressFormat::Json).then(ProgressWriter::new),
+    };
+    let summary = pipeline.run_with(&mut recorder)?;
+    recorder.manifest.flush().context("Failed to write manifest")?;
+    recorder.failures.flush().context("Failed to write failures list")?;
+    if summary.failed > 0 {
+        warn!(
+            "{} images failed, listed in {:?}; retry them with --input-list={:?} --resume",
+            summary.failed, failures_path, failures_path
+        );
+    }
+    if let Some(progress) = recorder.progress.as_mut() {
+        progress.run_finished(&summary)?;
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(db) = recorder.db.take() {
+        db.finish(&summary)?;
+    }
+
+    if let Some(coco) = &recorder.coco {
+        // Replaced in one step, a resumed run reads the previous annotations from it
+        write_atomic(&coco_path, &serde_json::to_vec(coco)?)
+            .with_context(|| format!("Failed to write annotations to: {:?}", coco_path))?;
         info!(
-            "Processing batch {}/{} ({} images)",
-            batch_idx + 1,
-            (image_paths.len() + args.batch_size - 1) / args.batch_size,
-            chunk.len()
+            "Wrote {} face annotations for {} images to {:?}",
+            coco.annotations.len(),
+            coco.images.len(),
+            coco_path
         );
+    }
 
-        // Process each image in the batch
-        for path in chunk {
-            match process_image(path, &mut detector, &args.output_dir, args.threshold, args.size, &mut face_counter) { 
-                Ok(_faces_found) => {
-                    processed_counter += 1;
-                    if processed_counter % 10 == 0 {
-                        let elapsed = start_time.elapsed().as_secs();
-                        if elapsed > 0 {
-                            let images_per_sec = processed_counter as f64 / elapsed as f64;
-                            info!(
-                                "Processed {}/{} images ({:.2} images/sec), found {} faces",
-                                processed_counter,
-                                image_paths.len(),
-                                images_per_sec,
-                                face_counter
-                            );
-                        }
-                    }
-                },
-                Err(err) => {
-                    error!("Failed to process {:?}: {}", path, err);
-                    processed_counter += 1;
-                }
+    // Converted from the manifest, so a resumed run's table has the earlier crops too
+    #[cfg(feature = "parquet")]
+    if args.parquet {
+        let table_path = output_dir.join("manifest.parquet");
+        let rows = table::write_manifest_table(&manifest_path, &table_path)?;
+        info!("Wrote {} rows of face metadata to {:?}", rows, table_path);
+    }
+
+    if args.gallery {
+        let entries = read_manifest(&manifest_path)?;
+        let gallery_path = gallery::write_gallery(&output_dir, "Extracted faces", &gallery::manifest_groups(&entries))?;
+        info!("Wrote a gallery of {} faces to {:?}", entries.len(), gallery_path);
+    }
+
+    #[cfg(feature = "s3")]
+    if let Some(location) = &remote_output {
+        let store = face_cropper::S3Store::new()?;
+        for path in [manifest_path, coco_path, failures_path, output_dir.join("manifest.parquet")] {
+            if let Some(name) = path.file_name().and_then(|name| name.to_str())
+                && path.exists()
+            {
+                store.move_file(&path, &location.bucket, &location.key(name))?;
             }
         }
+        let _ = fs::remove_dir_all(&output_dir);
+        info!("Uploaded results to {:?}", location.url());
+    }
 
-        info!(
-            "Processed {} faces so far",
+    let face_counter = summary.next_face_index;
+    if summary.stopped && budget_used.load(Ordering::SeqCst) {
+        eprintln!(
+            "Time budget used up after {} images ({} failed), {} faces saved; continue with --resume",
+            summary.images,
+            summary.failed,
             face_counter
         );
+        // The exit status of timeout(1)
+        std::process::exit(124);
+    }
+    if summary.stopped {
+        eprintln!(
+            "Interrupted after {} images ({} failed), {} faces saved in {} seconds; continue with --resume",
+            summary.images,
+            summary.failed,
+            face_counter,
+            summary.elapsed.as_secs()
+        );
+        std::process::exit(130);
     }
-
-    let elapsed = start_time.elapsed().as_secs();
 
     info!(
         "Finished processing. Extracted {} faces in {} seconds",
         face_counter,
-        elapsed
+        summary.elapsed.as_secs()
     );
 
-    if face_counter < 4000 {
+    if !args.no_crops && face_counter < 4000 {
         warn!(
             "Only extracted {} faces, which is less than the recommended minimum of 4,000",
             face_counter
@@ -234,7 +1240,163 @@ fn run(args: Args) -> Result<()> {
     Ok(())
 }
 
+/// Writes the manifest, annotations and checkpoints of a run as the pipeline reports results
+struct RunRecorder<'a> {
+    input_dir: &'a Path,
+    manifest: BufWriter<File>,
+    /// Images that failed with the kind of error, a list for --input-list
+    failures: BufWriter<File>,
+    checkpoint: CheckpointWriter,
+    coco: Option<CocoDataset>,
+    #[cfg(feature = "sqlite")]
+    db: Option<database::Database>,
+    /// Images finished since the last checkpoint, relative to the input directory
+    finished: Vec<String>,
+    /// Fingerprints of the finished images, when running incrementally
+    fingerprints: Option<HashMap<String, Fingerprint>>,
+    /// When the pipeline started reading images, files modified later get no times in their fingerprint
+    run_started: SystemTime,
+    progress: Option<ProgressWriter>,
+}
+
+impl RunRecorder<'_> {
+    /// Add an image's crops to the manifest and its faces to the annotations
+    fn record(&mut self, path: &Path, processed: &ProcessedImage) -> Result<()> {
+        for entry in &processed.entries {
+            serde_json::to_writer(&mut self.manifest, entry)?;
+            writeln!(self.manifest)?;
+        }
+
+        // Images skipped for their size weren't decoded, so have no size to record
+        if let Some(coco) = self.coco.as_mut()
+            && processed.width > 0
+        {
+            let image_id = coco.add_image(&relative_path(path, self.input_dir), processed.width, processed.height);
+            for face in &processed.faces {
+                coco.add_face(image_id, face);
+            }
+        }
+
+        if let Some(progress) = self.progress.as_mut() {
+            progress.image_done(path, processed)?;
+        }
+
+        Ok(())
+    }
+
+    /// Report an image that failed (it is logged already)
+    fn record_error(&mut self, path: &Path, category: &str, message: &str) -> Result<()> {
+        // Absolute paths, failures.txt is read relative to its own directory
+        let listed = match face_cropper::download::url_of(path) {
+            Some(_) => path.to_path_buf(),
+            None => std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+        };
+        let message = message.replace(['\t', '\n', '\r'], " ");
+        writeln!(self.failures, "{}\t{}\t{}", listed.display(), category, message)?;
+
+        match self.progress.as_mut() {
+            Some(progress) => progress.error(path, &message),
+            None => Ok(()),
+        }
+    }
+
+    /// Checkpoint once the batch's manifest entries and database rows are on disk
+    fn checkpoint(&mut self, next_face_index: usize) -> Result<()> {
+        self.manifest.flush().context("Failed to write manifest")?;
+        self.failures.flush().context("Failed to write failures list")?;
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = self.db.as_mut() {
+            db.commit()?;
+        }
+        let manifest_bytes = self.manifest.get_mut().stream_position()?;
+        let fingerprints = self.fingerprints.as_mut().map(std::mem::take).unwrap_or_default();
+        self.checkpoint.record(std::mem::take(&mut self.finished), fingerprints, next_face_index, manifest_bytes)
+    }
+}
+
+impl ExtractionObserver for RunRecorder<'_> {
+    fn run_started(&mut self, images: usize) -> face_cropper::Result<()> {
+        match self.progress.as_mut() {
+            Some(progress) => progress
+                .run_started(images)
+                .map_err(|err| face_cropper::Error::Observer(err.into())),
+            None => Ok(()),
+        }
+    }
+
+    fn batch_started(&mut self, batch: usize, batches: usize, images: usize) -> face_cropper::Result<()> {
+        match self.progress.as_mut() {
+            Some(progress) => progress
+                .batch_started(batch, batches, images)
+                .map_err(|err| face_cropper::Error::Observer(err.into())),
+            None => Ok(()),
+        }
+    }
+
+    fn image_done(&mut self, path: &Path, result: face_cropper::Result<ProcessedImage>) -> face_cropper::Result<()> {
+        // Failed images don't count as finished, a resumed run tries them again
+        if let Ok(processed) = &result {
+            let relative = relative_path(path, self.input_dir);
+            if let Some(fingerprints) = self.fingerprints.as_mut() {
+                let fingerprint = Fingerprint::of_processed(path, processed.source_sha256.clone(), self.run_started);
+                fingerprints.insert(relative.clone(), fingerprint);
+            }
+            self.finished.push(relative);
+        }
+
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = self.db.as_mut() {
+            db.record_image(path, result.as_ref().map_err(|err| err.to_string()))
+                .map_err(|err| face_cropper::Error::Observer(err.into()))?;
+        }
+
+        match result {
+            Ok(processed) => self.record(path, &processed),
+            Err(err) => {
+                let category = err.category();
+                self.record_error(path, category, &format!("{:#}", anyhow::Error::new(err)))
+            }
+        }
+        .map_err(|err| face_cropper::Error::Observer(err.into()))
+    }
+
+    fn batch_done(&mut self, next_face_index: usize) -> face_cropper::Result<()> {
+        self.checkpoint(next_face_index)
+            .map_err(|err| face_cropper::Error::Observer(err.into()))
+    }
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
-    run(args)
+
+    // Initialize logger, the JSON one also takes the `log` records
+    match args.log_format {
+        LogFormat::Text => env_logger::init(),
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_env_filter(
+                tracing_subscriber::EnvFilter::try_from_default_env()
+                    .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("error")),
+            )
+            .with_writer(std::io::stderr)
+            .init(),
+    }
+    match &args.command {
+        Some(Command::Serve(serve_args)) => serve::serve(&args, serve_args),
+        Some(Command::Detect(detect_args)) => detect::detect(&args, detect_args),
+        Some(Command::Crop(crop_args)) => crop::crop(&args, crop_args),
+        Some(Command::Anonymize(anonymize_args)) => anonymize::anonymize(&args, anonymize_args),
+        Some(Command::Cluster(cluster_args)) => cluster::cluster(&args, cluster_args),
+        Some(Command::Pairs(pairs_args)) => pairs::pairs(pairs_args),
+        Some(Command::Mosaic(mosaic_args)) => mosaic::mosaic(&args, mosaic_args),
+        #[cfg(feature = "review")]
+        Some(Command::Review(review_args)) => review::review(&args, review_args),
+        Some(Command::Bench(bench_args)) => bench::bench(&args, bench_args),
+        Some(Command::Compare(compare_args)) => compare::compare(&args, compare_args),
+        Some(Command::Sweep(sweep_args)) => sweep::sweep(&args, sweep_args),
+        Some(Command::Stats(stats_args)) => stats::stats(stats_args),
+        None => run(args),
+    }
 }
@@ -0,0 +1,102 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Upper bounds of the detection latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Counters of a long-running mode, rendered in the Prometheus text format
+///
+/// `last_success` holds the time of the last processed image so alerts can fire
+/// on stalled ingestion, not only on failures.
+pub struct Metrics {
+    images: AtomicU64,
+    faces: AtomicU64,
+    failures: AtomicU64,
+    last_success: AtomicU64,
+    /// Detections per bucket of LATENCY_BUCKETS, not cumulative
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_count: AtomicU64,
+    latency_sum_micros: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            images: AtomicU64::new(0),
+            faces: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            last_success: AtomicU64::new(0),
+            latency_buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            latency_count: AtomicU64::new(0),
+            latency_sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    /// Count a processed image with the faces found in it
+    pub fn image_processed(&self, faces: usize) {
+        self.images.fetch_add(1, Ordering::Relaxed);
+        self.faces.fetch_add(faces as u64, Ordering::Relaxed);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        self.last_success.store(now, Ordering::Relaxed);
+    }
+
+    pub fn failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record how long one detection took
+    pub fn detection_latency(&self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counter = |out: &mut String, name: &str, help: &str, value: &AtomicU64| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+        };
+
+        counter(&mut out, "face_cropper_images_processed_total", "Images processed.", &self.images);
+        counter(&mut out, "face_cropper_faces_extracted_total", "Faces found in processed images.", &self.faces);
+        counter(&mut out, "face_cropper_failures_total", "Images that failed to process.", &self.failures);
+
+        let _ = writeln!(out, "# HELP face_cropper_last_success_timestamp_seconds Unix time of the last processed image.");
+        let _ = writeln!(out, "# TYPE face_cropper_last_success_timestamp_seconds gauge");
+        let _ = writeln!(
+            out,
+            "face_cropper_last_success_timestamp_seconds {}",
+            self.last_success.load(Ordering::Relaxed)
+        );
+
+        let name = "face_cropper_detection_duration_seconds";
+        let _ = writeln!(out, "# HELP {} Time spent detecting faces in an image.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&self.latency_buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let count = self.latency_count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(
+            out,
+            "{}_sum {}",
+            name,
+            self.latency_sum_micros.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(out, "{}_count {}", name, count);
+
+        out
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{find_crops, seeded_rng, Args};
+use anyhow::{Context, Result};
+use face_cropper::load_image;
+use image::imageops::{self, FilterType};
+use image::{Rgb, RgbImage};
+use log::{info, warn};
+use rayon::prelude::*;
+use std::path::PathBuf;
+
+/// Gray the cells are filled with around crops that aren't square, and where there are no crops
+const BACKGROUND: Rgb<u8> = Rgb([32, 32, 32]);
+
+/// Arguments of the `mosaic` subcommand
+#[derive(clap::Args, Debug)]
+pub struct MosaicArgs {
+    /// Directory of face crops, searched recursively (crops review rejected are left out)
+    pub crops: PathBuf,
+
+    /// Image the contact sheet is written to, its format from the extension
+    #[clap(short, long, default_value = "mosaic.jpg")]
+    pub output: PathBuf,
+
+    /// Grid as `COLUMNSxROWS`, as many random crops as it has cells are tiled into it
+    #[clap(long, default_value = "8x8", value_parser = parse_grid)]
+    pub grid: Grid,
+
+    /// Square size every crop is fitted into (px)
+    #[clap(long, default_value = "128")]
+    pub cell_size: u32,
+
+    /// Gap between the cells (px)
+    #[clap(long, default_value = "2")]
+    pub spacing: u32,
+
+    /// Seed choosing the crops, the same sheet for the same seed and directory
+    #[clap(long, default_value_t = 0)]
+    pub seed: u64,
+}
+
+/// Columns and rows of a contact sheet
+#[derive(Debug, Clone, Copy)]
+pub struct Grid {
+    pub columns: u32,
+    pub rows: u32,
+}
+
+/// Parse `COLUMNSxROWS`, both above 0
+fn parse_grid(value: &str) -> std::result::Result<Grid, String> {
+    let invalid = || format!("Invalid grid: {} (expected COLUMNSxROWS, e.g. 8x8)", value);
+    let (columns, rows) = value.split_once(['x', 'X']).ok_or_else(invalid)?;
+    let columns: u32 = columns.trim().parse().map_err(|_| invalid())?;
+    let rows: u32 = rows.trim().parse().map_err(|_| invalid())?;
+    if columns == 0 || rows == 0 {
+        return Err(invalid());
+    }
+
+    Ok(Grid { columns, rows })
+}
+
+/// Tile a random sample of the crops into one preview image
+pub fn mosaic(args: &Args, mosaic_args: &MosaicArgs) -> Result<()> {
+    if mosaic_args.cell_size == 0 {
+        return Err(anyhow::anyhow!("--cell-size must be above 0"));
+    }
+
+    // Sorted so the same seed picks the same crops whatever order the directory lists them in
+    let mut crops = find_crops(&mosaic_args.crops);
+    crops.sort();
+    if crops.is_empty() {
+        warn!("No face crops found at {:?}", mosaic_args.crops);
+        return Ok(());
+    }
+
+    let Grid { columns, rows } = mosaic_args.grid;
+    let cells = (columns as usize * rows as usize).min(crops.len());
+    let mut rng = seeded_rng(mosaic_args.seed);
+    let mut picked = rand::seq::index::sample(&mut rng, crops.len(), cells).into_vec();
+    picked.sort_unstable();
+    info!("Tiling {} of {} face crops", picked.len(), crops.len());
+
+    let cell_size = mosaic_args.cell_size;
+    let load = args.load_options();
+    let thumbnails: Vec<RgbImage> = picked
+        .par_iter()
+        .filter_map(|&i| match load_image(&crops[i], &load) {
+            Ok(img) => Some(img.resize(cell_size, cell_size, FilterType::Triangle).to_rgb8()),
+            Err(e) => {
+                warn!("Skipping {:?}: {}", crops[i], e);
+                None
+            }
+        })
+        .collect();
+
+    // A sample smaller than the grid leaves out the empty rows at the bottom
+    let rows = (thumbnails.len() as u32).div_ceil(columns).clamp(1, rows);
+    let step = cell_size + mosaic_args.spacing;
+    let mut sheet = RgbImage::from_pixel(
+        columns * step + mosaic_args.spacing,
+        rows * step + mosaic_args.spacing,
+        BACKGROUND,
+    );
+    for (i, thumbnail) in thumbnails.iter().enumerate() {
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        // Crops that aren't square are centered in their cell
+        let x = mosaic_args.spacing + column * step + (cell_size - thumbnail.width()) / 2;
+        let y = mosaic_args.spacing + row * step + (cell_size - thumbnail.height()) / 2;
+        imageops::replace(&mut sheet, thumbnail, x as i64, y as i64);
+    }
+
+    if let Some(parent) = mosaic_args.output.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+    sheet
+        .save(&mosaic_args.output)
+        .with_context(|| format!("Failed to save mosaic to: {:?}", mosaic_args.output))?;
+    info!(
+        "Wrote a {}x{} mosaic of {} crops to {:?}",
+        columns,
+        rows,
+        thumbnails.len(),
+        mosaic_args.output
+    );
+
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use crate::detect::csv_field;
+use crate::{gallery, relative_path, seeded_rng, REJECTED_DIR};
+use anyhow::{Context, Result};
+use face_cropper::find_images;
+use log::{info, warn};
+use rand::Rng;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Header of both pair CSVs, paths relative to the labeled directory
+const CSV_HEADER: &str = "left,right,left_identity,right_identity";
+
+/// Draws per pair wanted before giving up on finding new ones
+const ATTEMPTS_PER_PAIR: usize = 20;
+
+/// Arguments of the `pairs` subcommand
+#[derive(clap::Args, Debug)]
+pub struct PairsArgs {
+    /// Labeled directory with one subdirectory of crops per identity, e.g. the output
+    /// of --label-from-dir or of the cluster subcommand (`unclustered/`, the `rejected/`
+    /// crops of review and gallery `thumbs/` are skipped)
+    pub dir: PathBuf,
+
+    /// Directory positive_pairs.csv and negative_pairs.csv are written to, the labeled directory by default
+    #[clap(short, long)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Positive and negative pairs generated each (LFW has 3000 of both)
+    #[clap(long, default_value = "3000")]
+    pub pairs: usize,
+
+    /// Seed choosing the pairs, the same pairs for the same seed and directory
+    #[clap(long, default_value_t = 0)]
+    pub seed: u64,
+}
+
+/// A pair of crops by their indices: (identity, crop) for both sides
+type Pair = ((usize, usize), (usize, usize));
+
+/// Write random same-identity and different-identity crop pairs for verification benchmarks
+pub fn pairs(pairs_args: &PairsArgs) -> Result<()> {
+    let identities = labeled_crops(&pairs_args.dir)?;
+    let crop_count: usize = identities.iter().map(|(_, crops)| crops.len()).sum();
+    info!("Found {} crops of {} identities", crop_count, identities.len());
+
+    let mut rng = seeded_rng(pairs_args.seed);
+
+    // Positive pairs: two different crops of an identity with at least two
+    let repeated: Vec<usize> = (0..identities.len()).filter(|&i| identities[i].1.len() >= 2).collect();
+    let positive = draw_pairs(pairs_args.pairs, !repeated.is_empty(), || {
+        let identity = repeated[rng.gen_range(0..repeated.len())];
+        let count = identities[identity].1.len();
+        let (a, b) = distinct(&mut rng, count);
+        ((identity, a), (identity, b))
+    });
+
+    // Negative pairs: a crop each of two different identities
+    let negative = draw_pairs(pairs_args.pairs, identities.len() >= 2, || {
+        let (a, b) = distinct(&mut rng, identities.len());
+        let left = rng.gen_range(0..identities[a].1.len());
+        let right = rng.gen_range(0..identities[b].1.len());
+        ((a, left), (b, right))
+    });
+
+    let output_dir = pairs_args.output_dir.as_deref().unwrap_or(&pairs_args.dir);
+    fs::create_dir_all(output_dir).context("Failed to create output directory")?;
+    for (name, pairs) in [("positive_pairs.csv", &positive), ("negative_pairs.csv", &negative)] {
+        if pairs.len() < pairs_args.pairs {
+            warn!("Only found {} of {} pairs for {}", pairs.len(), pairs_args.pairs, name);
+        }
+        write_pairs(&output_dir.join(name), pairs, &identities, &pairs_args.dir)?;
+    }
+
+    info!(
+        "Wrote {} positive and {} negative pairs to {:?}",
+        positive.len(),
+        negative.len(),
+        output_dir
+    );
+
+    Ok(())
+}
+
+/// The crops of every identity directory, both sorted so the same seed gives the same pairs
+fn labeled_crops(dir: &Path) -> Result<Vec<(String, Vec<PathBuf>)>> {
+    let mut identities = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read directory: {:?}", dir))? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().map(|name| name.to_string_lossy().into_owned()) else {
+            continue;
+        };
+        if !path.is_dir() || name == "unclustered" || name == REJECTED_DIR || name == gallery::THUMBS_DIR {
+            continue;
+        }
+
+        let mut crops = find_images(&path);
+        crops.sort();
+        if !crops.is_empty() {
+            identities.push((name, crops));
+        }
+    }
+    identities.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(identities)
+}
+
+/// Up to `count` different pairs from `draw`, none when `possible` is false
+///
+/// Gives up after a number of draws that found nothing new, when there are fewer
+/// pairs to be had than asked for.
+fn draw_pairs(count: usize, possible: bool, mut draw: impl FnMut() -> Pair) -> Vec<Pair> {
+    if !possible {
+        return Vec::new();
+    }
+
+    let mut seen = HashSet::new();
+    let mut pairs = Vec::with_capacity(count);
+    let mut attempts = 0;
+    while pairs.len() < count && attempts < count * ATTEMPTS_PER_PAIR {
+        attempts += 1;
+        let (left, right) = draw();
+        // A pair and its swapped sides are the same pair
+        if seen.insert((left.min(right), left.max(right))) {
+            pairs.push((left, right));
+        }
+    }
+
+    pairs
+}
+
+/// Two different indices below `count`, which is at least 2
+fn distinct(rng: &mut impl Rng, count: usize) -> (usize, usize) {
+    let a = rng.gen_range(0..count);
+    let b = (a + rng.gen_range(1..count)) % count;
+    (a, b)
+}
+
+/// Write pairs to a CSV with the crop paths relative to the labeled directory
+fn write_pairs(path: &Path, pairs: &[Pair], identities: &[(String, Vec<PathBuf>)], dir: &Path) -> Result<()> {
+    let mut output = BufWriter::new(File::create(path).with_context(|| format!("Failed to create {:?}", path))?);
+    writeln!(output, "{}", CSV_HEADER)?;
+    for &((a, left), (b, right)) in pairs {
+        writeln!(
+            output,
+            "{},{},{},{}",
+            csv_field(&relative_path(&identities[a].1[left], dir)),
+            csv_field(&relative_path(&identities[b].1[right], dir)),
+            csv_field(&identities[a].0),
+            csv_field(&identities[b].0)
+        )?;
+    }
+    output.flush().with_context(|| format!("Failed to write {:?}", path))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labeled_crops_skip_unclustered_rejected_and_thumbnails() {
+        let dir = std::env::temp_dir().join(format!("face_cropper-pairs-{}", std::process::id()));
+        for label in ["alice", "bob", "unclustered", REJECTED_DIR, gallery::THUMBS_DIR] {
+            fs::create_dir_all(dir.join(label)).unwrap();
+            image::RgbImage::new(4, 4).save(dir.join(label).join("face.png")).unwrap();
+        }
+
+        let identities = labeled_crops(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<String> = identities.unwrap().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["alice", "bob"]);
+    }
+}
@@ -0,0 +1,1125 @@
+use crate::annotate::Annotator;
+use crate::archive::{ArchiveKind, ArchiveReader, ArchiveWriter};
+use crate::cropping::{save_faces, save_faces_with, CropOptions, ManifestEntry, ProcessedImage};
+use crate::detector::{DetectorConfig, DetectorError, FaceBox};
+use crate::error::{Error, Result};
+use crate::download::{url_of, HttpFetcher};
+#[cfg(feature = "lmdb")]
+use crate::lmdb::LmdbWriter;
+use crate::input::{decode_loaded_frames, find_images, read_image, walk_images_with, Frames, LoadOptions, WalkOptions};
+use crate::pool::DetectorPool;
+use crate::webdataset::ShardWriter;
+#[cfg(feature = "s3")]
+use crate::s3::{S3Location, S3Store};
+use image::DynamicImage;
+use log::{error, info, warn};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+/// Chunks that can queue up between two pipeline stages
+const PIPELINE_DEPTH: usize = 2;
+
+/// A chunk of image files after decoding, with the digests of their bytes when sources are hashed
+type DecodedChunk = Vec<(PathBuf, Option<String>, Result<Frames>)>;
+
+/// A frame of a decoded image, its index when animated and the faces found in it
+type DetectedFrame = (Option<u32>, DynamicImage, Vec<FaceBox>);
+
+/// A decoded image with the faces found in its frames, and the digest of its bytes
+type DetectedImage = (PathBuf, Option<String>, Result<Vec<DetectedFrame>>);
+
+/// A chunk of decoded images with the faces found in their frames
+type DetectedChunk = Vec<DetectedImage>;
+
+/// Decides for an image path whether the pipeline leaves it out
+type SkipFilter = Box<dyn Fn(&Path) -> bool + Send + Sync>;
+
+/// Where the images of a pipeline come from
+#[derive(Debug, Clone)]
+pub enum InputSource {
+    /// Every image found under a directory, processed while the directory is walked
+    Directory(PathBuf),
+    /// Every image in a `.zip`, `.tar` or `.tar.gz` archive, read without extracting it
+    Archive(PathBuf),
+    /// Image files or http(s) URLs, with the directory their paths are relative to
+    /// (for mirrored output and annotated copies)
+    Files { root: PathBuf, paths: Vec<PathBuf> },
+    /// Every image under an S3 prefix, downloaded a chunk ahead of detection
+    #[cfg(feature = "s3")]
+    S3(S3Location),
+}
+
+impl InputSource {
+    /// Directory the source images are relative to
+    pub fn root(&self) -> &Path {
+        match self {
+            InputSource::Directory(dir) | InputSource::Archive(dir) => dir,
+            InputSource::Files { root, .. } => root,
+            #[cfg(feature = "s3")]
+            InputSource::S3(location) => location.url(),
+        }
+    }
+
+    /// Paths of the source images, `s3://` URLs for objects
+    pub fn images(&self) -> Result<Vec<PathBuf>> {
+        Ok(match self {
+            InputSource::Directory(dir) => find_images(dir),
+            InputSource::Archive(path) => ArchiveReader::open(path)?
+                .images()?
+                .iter()
+                .map(|member| path.join(member))
+                .collect(),
+            InputSource::Files { paths, .. } => paths.clone(),
+            #[cfg(feature = "s3")]
+            InputSource::S3(location) => S3Store::new()?
+                .list_images(location)?
+                .iter()
+                .map(|key| location.object_url(key))
+                .collect(),
+        })
+    }
+}
+
+/// Where the crops of a pipeline go
+#[derive(Debug, Clone)]
+pub enum OutputSink {
+    /// Encode crops in the configured format and write them to a directory
+    Directory(PathBuf),
+    /// Encode crops like [`OutputSink::Directory`] and append them to a new
+    /// `.zip`, `.tar` or `.tar.gz` archive, finished at the end of the run
+    Archive(PathBuf),
+    /// Encode crops like [`OutputSink::Directory`] and write them with their manifest
+    /// entries as WebDataset samples, into `.tar` shards of `samples_per_shard`
+    /// samples in `dir` and an `index.json` listing them, finished at the end of the run
+    WebDataset { dir: PathBuf, samples_per_shard: usize },
+    /// Encode crops like [`OutputSink::Directory`] and store them with their manifest
+    /// entries in an LMDB environment at this path
+    #[cfg(feature = "lmdb")]
+    Lmdb(PathBuf),
+    /// Encode crops like [`OutputSink::Directory`] and upload them under an S3 prefix
+    #[cfg(feature = "s3")]
+    S3(S3Location),
+    /// Detect only, the faces are still reported to the observer
+    Discard,
+}
+
+/// What a pipeline does when images fail
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Log failed images and carry on
+    #[default]
+    Skip,
+    /// Stop at the first failed image
+    Fail,
+    /// Stop once this many images failed
+    FailAfter(usize),
+}
+
+impl ErrorPolicy {
+    /// Failed images that stop the run, None for no limit
+    fn limit(self) -> Option<usize> {
+        match self {
+            ErrorPolicy::Skip => None,
+            ErrorPolicy::Fail => Some(1),
+            ErrorPolicy::FailAfter(failures) => Some(failures),
+        }
+    }
+}
+
+impl std::str::FromStr for ErrorPolicy {
+    type Err = String;
+
+    /// Parse `skip`, `fail` or `fail-after=N`
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "skip" => Ok(ErrorPolicy::Skip),
+            "fail" => Ok(ErrorPolicy::Fail),
+            _ => value
+                .strip_prefix("fail-after=")
+                .and_then(|failures| failures.parse().ok())
+                .filter(|&failures| failures > 0)
+                .map(ErrorPolicy::FailAfter)
+                .ok_or_else(|| format!("Invalid error policy: {} (expected skip, fail or fail-after=N with N > 0)", value)),
+        }
+    }
+}
+
+/// Receives the results of a pipeline run, in input order
+pub trait ExtractionObserver: Send {
+    /// Called once the input is listed, with the number of images to process
+    /// (0 for directories, they are walked while the images are processed)
+    fn run_started(&mut self, _images: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called before the results of a batch are saved, with its 1-based number,
+    /// the number of batches (0 when unknown, as for directories) and the images in it
+    fn batch_started(&mut self, _batch: usize, _batches: usize, _images: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called for every image after its crops were saved (failures are logged already)
+    ///
+    /// Errors of the observer's own are passed back as [`Error::Observer`].
+    fn image_done(&mut self, path: &Path, result: Result<ProcessedImage>) -> Result<()>;
+
+    /// Called after every batch of images, with the next free face index
+    fn batch_done(&mut self, _next_face_index: usize) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Observer that ignores all results
+impl ExtractionObserver for () {
+    fn image_done(&mut self, _path: &Path, _result: Result<ProcessedImage>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Totals of a pipeline run
+#[derive(Debug, Clone)]
+pub struct ExtractionSummary {
+    /// Images processed, including the ones that failed
+    pub images: usize,
+    /// Images that couldn't be loaded, detected or saved
+    pub failed: usize,
+    /// Next free face index, the first index plus the faces saved
+    pub next_face_index: usize,
+    /// The stop flag ended the run before all images were processed
+    pub stopped: bool,
+    /// Wall time of the run
+    pub elapsed: Duration,
+}
+
+/// The whole extraction flow: decode images, detect faces, crop and save them
+///
+/// Decoding, detection and saving run as three overlapping stages connected by
+/// bounded channels. With several jobs each stage spreads its chunk over a worker
+/// pool, every worker with its own detector, otherwise detection hands whole chunks
+/// to the detector as one batch.
+pub struct FaceExtractionPipeline {
+    input: InputSource,
+    /// The input root is an archive, the image paths are paths of its members
+    input_archive: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    output_archive: Option<ArchiveWriter>,
+    output_shards: Option<ShardWriter>,
+    #[cfg(feature = "lmdb")]
+    output_lmdb: Option<LmdbWriter>,
+    walk: WalkOptions,
+    load: LoadOptions,
+    fetcher: HttpFetcher,
+    crop: CropOptions,
+    annotator: Option<Annotator>,
+    detectors: DetectorPool,
+    pool: rayon::ThreadPool,
+    threshold: f32,
+    max_faces: usize,
+    /// Number of the next saved face, shared by runs and [`process_bytes`](Self::process_bytes)
+    face_counter: AtomicUsize,
+    batch_size: usize,
+    jobs: usize,
+    stop: Option<Arc<AtomicBool>>,
+    error_policy: ErrorPolicy,
+    skip: Option<SkipFilter>,
+    hash_sources: bool,
+    #[cfg(feature = "s3")]
+    s3: Option<S3Remote>,
+}
+
+/// The S3 ends of a pipeline and the client serving them
+#[cfg(feature = "s3")]
+struct S3Remote {
+    store: S3Store,
+    input: Option<S3Location>,
+    /// Crops are staged in the pipeline's output directory until they're uploaded here
+    output: Option<S3Location>,
+}
+
+/// Builder of a [`FaceExtractionPipeline`], the output is required
+///
+/// Without an input source the pipeline only processes images handed to
+/// [`process_bytes`](FaceExtractionPipeline::process_bytes).
+pub struct FaceExtractionPipelineBuilder {
+    detector: String,
+    detector_config: DetectorConfig,
+    threshold: f32,
+    input: Option<InputSource>,
+    output: Option<OutputSink>,
+    walk: WalkOptions,
+    load: LoadOptions,
+    fetcher: HttpFetcher,
+    crop: CropOptions,
+    annotated_dir: Option<PathBuf>,
+    max_faces: usize,
+    first_face_index: usize,
+    batch_size: usize,
+    jobs: usize,
+    stop: Option<Arc<AtomicBool>>,
+    error_policy: ErrorPolicy,
+    skip: Option<SkipFilter>,
+    hash_sources: bool,
+}
+
+impl Default for FaceExtractionPipelineBuilder {
+    fn default() -> Self {
+        Self {
+            detector: "rustface".to_string(),
+            detector_config: DetectorConfig::default(),
+            threshold: 0.5,
+            input: None,
+            output: None,
+            walk: WalkOptions::default(),
+            load: LoadOptions::default(),
+            fetcher: HttpFetcher::default(),
+            crop: CropOptions::default(),
+            annotated_dir: None,
+            max_faces: 0,
+            first_face_index: 0,
+            batch_size: 16,
+            jobs: 1,
+            stop: None,
+            error_policy: ErrorPolicy::Skip,
+            skip: None,
+            hash_sources: false,
+        }
+    }
+}
+
+impl FaceExtractionPipelineBuilder {
+    /// Detector to use (see [`create_detector`](crate::create_detector)), rustface by default
+    pub fn detector(mut self, name: &str, config: DetectorConfig) -> Self {
+        self.detector = name.to_string();
+        self.detector_config = config;
+        self
+    }
+
+    /// Confidence threshold for detected faces
+    pub fn threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Where the images come from
+    pub fn input(mut self, input: InputSource) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    /// Where the crops go
+    pub fn output(mut self, output: OutputSink) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    /// Which files of a directory input are processed
+    pub fn walk_options(mut self, walk: WalkOptions) -> Self {
+        self.walk = walk;
+        self
+    }
+
+    /// How source images are loaded
+    pub fn load_options(mut self, load: LoadOptions) -> Self {
+        self.load = load;
+        self
+    }
+
+    /// How input URLs are downloaded
+    pub fn fetcher(mut self, fetcher: HttpFetcher) -> Self {
+        self.fetcher = fetcher;
+        self
+    }
+
+    /// How faces are cropped and saved
+    pub fn crop_options(mut self, crop: CropOptions) -> Self {
+        self.crop = crop;
+        self
+    }
+
+    /// Also write copies of the source images with the detected boxes drawn on them
+    pub fn annotated_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.annotated_dir = Some(dir.into());
+        self
+    }
+
+    /// Stop once the face index reaches this (0 for unlimited), checked between batches
+    pub fn max_faces(mut self, max_faces: usize) -> Self {
+        self.max_faces = max_faces;
+        self
+    }
+
+    /// Index of the first saved face, to continue the numbering of an earlier run
+    pub fn first_face_index(mut self, index: usize) -> Self {
+        self.first_face_index = index;
+        self
+    }
+
+    /// Images handed to the detector in one call
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Number of worker threads, each with its own detector instance
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Stop the run once this flag is set (e.g. by a Ctrl-C handler), after the
+    /// chunk in flight is saved and reported
+    pub fn stop_flag(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Whether failed images stop the run, by default they are only logged and reported
+    ///
+    /// A run stopped by the policy finishes reporting the batch of the failure,
+    /// then returns [`Error::TooManyFailures`].
+    pub fn error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.error_policy = policy;
+        self
+    }
+
+    /// Leave out the images `skip` returns true for, e.g. the ones an earlier run
+    /// finished, checked as the input is listed or walked
+    pub fn skip_images(mut self, skip: impl Fn(&Path) -> bool + Send + Sync + 'static) -> Self {
+        self.skip = Some(Box::new(skip));
+        self
+    }
+
+    /// Record the SHA-256 digest of every source image's bytes in its
+    /// [`ProcessedImage`], e.g. to tell later whether the file changed
+    pub fn hash_sources(mut self, hash_sources: bool) -> Self {
+        self.hash_sources = hash_sources;
+        self
+    }
+
+    /// Create the output directory and the detectors
+    pub fn build(self) -> Result<FaceExtractionPipeline> {
+        let input = self.input.unwrap_or_else(|| InputSource::Files {
+            root: PathBuf::new(),
+            paths: Vec::new(),
+        });
+        let output = self
+            .output
+            .ok_or_else(|| Error::Config("The pipeline needs an output sink".to_string()))?;
+
+        // One client serves both ends, S3 input is listed up front like a directory
+        #[cfg(feature = "s3")]
+        let (input, s3) = {
+            let input_location = match &input {
+                InputSource::S3(location) => Some(location.clone()),
+                _ => None,
+            };
+            let output_location = match &output {
+                OutputSink::S3(location) => Some(location.clone()),
+                _ => None,
+            };
+
+            if input_location.is_some() || output_location.is_some() {
+                let store = S3Store::new()?;
+                let input = match input_location.as_ref() {
+                    Some(location) => {
+                        info!("Listing images in {:?}", location.url());
+                        let paths = store
+                            .list_images(location)?
+                            .iter()
+                            .map(|key| location.object_url(key))
+                            .collect();
+                        InputSource::Files { root: location.url().to_path_buf(), paths }
+                    }
+                    None => input,
+                };
+                (input, Some(S3Remote { store, input: input_location, output: output_location }))
+            } else {
+                (input, None)
+            }
+        };
+
+        let root = input.root();
+        let input_archive = (ArchiveKind::of(root).is_some() && root.is_file()).then(|| root.to_path_buf());
+
+        let mut crop = self.crop;
+        let output_archive = match &output {
+            OutputSink::Archive(path) => Some(ArchiveWriter::create(path)?),
+            _ => None,
+        };
+        let output_shards = match &output {
+            OutputSink::WebDataset { dir, samples_per_shard } => {
+                fs::create_dir_all(dir).map_err(|source| Error::Io {
+                    context: format!("Failed to create output directory: {:?}", dir),
+                    source,
+                })?;
+                Some(ShardWriter::create(dir, *samples_per_shard)?)
+            }
+            _ => None,
+        };
+        #[cfg(feature = "lmdb")]
+        let output_lmdb = match &output {
+            OutputSink::Lmdb(path) => Some(LmdbWriter::create(path)?),
+            _ => None,
+        };
+        let output_dir = match output {
+            OutputSink::Directory(dir) => {
+                fs::create_dir_all(&dir).map_err(|source| Error::Io {
+                    context: format!("Failed to create output directory: {:?}", dir),
+                    source,
+                })?;
+                Some(dir)
+            }
+            #[cfg(feature = "s3")]
+            OutputSink::S3(_) => {
+                let dir = std::env::temp_dir().join(format!("face_cropper-s3-{}", std::process::id()));
+                fs::create_dir_all(&dir).map_err(|source| Error::Io {
+                    context: format!("Failed to create staging directory: {:?}", dir),
+                    source,
+                })?;
+                Some(dir)
+            }
+            OutputSink::Archive(_) | OutputSink::WebDataset { .. } => None,
+            #[cfg(feature = "lmdb")]
+            OutputSink::Lmdb(_) => None,
+            OutputSink::Discard => {
+                crop.skip = true;
+                None
+            }
+        };
+
+        let annotator = self
+            .annotated_dir
+            .as_deref()
+            .map(|dir| Annotator::new(dir, input.root()))
+            .transpose()?;
+
+        info!("Initializing face detector: {}", self.detector);
+        let detectors = DetectorPool::new(&self.detector, self.detector_config)?;
+
+        // Spin up the worker pool, giving every thread its own detector
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.jobs.max(1))
+            .build()
+            .map_err(|err| Error::Config(format!("Failed to create worker thread pool: {}", err)))?;
+
+        if self.jobs > 1 {
+            info!("Initializing {} worker detectors", self.jobs);
+            pool.broadcast(|_| detectors.with_detector(|_| ()))
+                .into_iter()
+                .collect::<std::result::Result<Vec<_>, DetectorError>>()?;
+        }
+
+        Ok(FaceExtractionPipeline {
+            input,
+            input_archive,
+            output_dir,
+            output_archive,
+            output_shards,
+            #[cfg(feature = "lmdb")]
+            output_lmdb,
+            walk: self.walk,
+            load: self.load,
+            fetcher: self.fetcher,
+            crop,
+            annotator,
+            detectors,
+            pool,
+            threshold: self.threshold,
+            max_faces: self.max_faces,
+            face_counter: AtomicUsize::new(self.first_face_index),
+            batch_size: self.batch_size,
+            jobs: self.jobs,
+            stop: self.stop,
+            error_policy: self.error_policy,
+            skip: self.skip,
+            hash_sources: self.hash_sources,
+            #[cfg(feature = "s3")]
+            s3,
+        })
+    }
+}
+
+impl FaceExtractionPipeline {
+    /// Start building a pipeline
+    pub fn builder() -> FaceExtractionPipelineBuilder {
+        FaceExtractionPipelineBuilder::default()
+    }
+
+    /// Process every input image
+    pub fn run(&self) -> Result<ExtractionSummary> {
+        self.run_with(&mut ())
+    }
+
+    /// Process one encoded image held in memory (e.g. an upload) as if it were the
+    /// input file `name`, returning its result instead of reporting it
+    ///
+    /// The image is decoded with the load options and its faces are saved to the
+    /// output like those of a run, numbered on from the faces saved before. Archive
+    /// WebDataset and LMDB outputs are only finished by a run, so this needs a
+    /// directory or S3 output.
+    pub fn process_bytes(&self, name: &Path, data: &[u8]) -> Result<ProcessedImage> {
+        if self.output_archive.is_some() || self.collects_crops() {
+            return Err(Error::Config("Images in memory can't be saved to an archive output".to_string()));
+        }
+
+        let frames = decode_loaded_frames(name, data, &self.load)?;
+        let detected = self.detect_frames(frames)?;
+        self.save_image(name, self.digest(data), detected)
+    }
+
+    /// Process every input image, reporting each result to `observer`
+    ///
+    /// An error returned by the observer stops the run, as do failed images when
+    /// the error policy says so.
+    pub fn run_with(&self, observer: &mut impl ExtractionObserver) -> Result<ExtractionSummary> {
+        let start_time = Instant::now();
+        let skipped = |path: &Path| self.skip.as_ref().is_some_and(|skip| skip(path));
+
+        // Directories are processed as they are walked, so the number of images
+        // is only known for the other inputs
+        let (image_paths, total_images): (Box<dyn Iterator<Item = PathBuf> + Send + '_>, Option<usize>) =
+            match &self.input {
+                InputSource::Directory(dir) => {
+                    let paths = walk_images_with(dir, &self.walk)?;
+                    (Box::new(paths.filter(move |path| !skipped(path))), None)
+                }
+                input => {
+                    let mut paths = input.images()?;
+                    paths.retain(|path| !skipped(path));
+                    let total = paths.len();
+                    (Box::new(paths.into_iter()), Some(total))
+                }
+            };
+
+        // Process images in chunks, at least one image per worker
+        let chunk_size = self.batch_size.max(self.jobs).max(1);
+        let total_chunks = total_images.map(|total| total.div_ceil(chunk_size));
+        let face_counter = &self.face_counter;
+        // Images the input yielded so far, and whether it yielded all of them
+        let listed_images = AtomicUsize::new(0);
+        let input_exhausted = AtomicBool::new(false);
+        observer.run_started(total_images.unwrap_or(0))?;
+        let archive = self.input_archive.as_deref().map(ArchiveReader::open).transpose()?;
+
+        // Stop at max_faces, checked before a chunk's faces are saved
+        let limit_reached = || self.max_faces > 0 && face_counter.load(Ordering::SeqCst) >= self.max_faces;
+        let stop_requested = || self.stop.as_ref().is_some_and(|stop| stop.load(Ordering::SeqCst));
+
+        // Decoding runs on its own thread, detection on this one (detectors can't
+        // move between threads) and saving plus reporting on a third
+        let stages = std::thread::scope(|scope| -> Result<(usize, usize)> {
+            // Chunks travel with the time their decoding started, the start of their images
+            let (decoded_tx, decoded_rx) = mpsc::sync_channel::<(Instant, DecodedChunk)>(PIPELINE_DEPTH);
+            let (detected_tx, detected_rx) = mpsc::sync_channel::<(Instant, DetectedChunk)>(PIPELINE_DEPTH);
+            let (limit_reached, stop_requested) = (&limit_reached, &stop_requested);
+            let (listed_images, input_exhausted) = (&listed_images, &input_exhausted);
+            let archive = archive.as_ref();
+
+            scope.spawn(move || {
+                let mut paths = image_paths;
+                loop {
+                    let chunk: Vec<PathBuf> = paths.by_ref().take(chunk_size).collect();
+                    if chunk.is_empty() {
+                        input_exhausted.store(true, Ordering::SeqCst);
+                        break;
+                    }
+                    listed_images.fetch_add(chunk.len(), Ordering::SeqCst);
+
+                    // Sending fails once the detect stage has stopped
+                    let started = Instant::now();
+                    if decoded_tx.send((started, self.decode_chunk(&chunk, archive))).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let saver = scope.spawn(move || -> Result<(usize, usize)> {
+                let mut processed_counter = 0;
+                let mut failed_counter = 0;
+                // Set once the error policy stops the run, at the end of the batch
+                let mut policy_error = None;
+
+                for (batch_idx, (started, chunk)) in detected_rx.into_iter().enumerate() {
+                    if limit_reached() {
+                        info!("Reached maximum number of faces ({}), stopping", self.max_faces);
+                        break;
+                    }
+                    if stop_requested() {
+                        info!("Stop requested, not saving further batches");
+                        break;
+                    }
+                    observer.batch_started(batch_idx + 1, total_chunks.unwrap_or(0), chunk.len())?;
+
+                    // Results keep the input order, so log and report them from here
+                    for (path, result) in self.save_chunk(chunk) {
+                        // Everything logged about the image, the observer's too, is in its span
+                        let span = tracing::info_span!("image", path = %path.display());
+                        let _entered = span.enter();
+
+                        processed_counter += 1;
+                        match &result {
+                            Ok(processed) => tracing::info!(
+                                faces = processed.faces.len(),
+                                saved = processed.entries.len(),
+                                duration_ms = started.elapsed().as_millis() as u64,
+                                "Processed image"
+                            ),
+                            Err(err) => {
+                                error!("Failed to process {:?}: {}", path, err);
+                                failed_counter += 1;
+                                if policy_error.is_none()
+                                    && self.error_policy.limit().is_some_and(|limit| failed_counter >= limit)
+                                {
+                                    policy_error = Some(Error::TooManyFailures {
+                                        failed: failed_counter,
+                                        path: path.clone(),
+                                        reason: err.to_string(),
+                                    });
+                                }
+                            }
+                        }
+                        if result.is_ok() && processed_counter % 10 == 0 {
+                            let elapsed = start_time.elapsed().as_secs();
+                            if elapsed > 0 {
+                                let images_per_sec = processed_counter as f64 / elapsed as f64;
+                                info!(
+                                    "Processed {}{} images ({:.2} images/sec), found {} faces",
+                                    processed_counter,
+                                    total_images.map(|total| format!("/{}", total)).unwrap_or_default(),
+                                    images_per_sec,
+                                    face_counter.load(Ordering::SeqCst)
+                                );
+                            }
+                        }
+
+                        observer.image_done(&path, result)?;
+                    }
+
+                    observer.batch_done(face_counter.load(Ordering::SeqCst))?;
+                    info!(
+                        "Processed {} faces so far",
+                        face_counter.load(Ordering::SeqCst)
+                    );
+                    if let Some(err) = policy_error {
+                        return Err(err);
+                    }
+                }
+
+                Ok((processed_counter, failed_counter))
+            });
+
+            for (batch_idx, (started, chunk)) in decoded_rx.into_iter().enumerate() {
+                if limit_reached() || stop_requested() {
+                    break;
+                }
+
+                info!(
+                    "Processing batch {}{} ({} images)",
+                    batch_idx + 1,
+                    total_chunks.map(|total| format!("/{}", total)).unwrap_or_default(),
+                    chunk.len()
+                );
+
+                // Sending fails once the save stage has stopped, its result says why
+                if detected_tx.send((started, self.detect_chunk(chunk))).is_err() {
+                    break;
+                }
+            }
+
+            drop(detected_tx);
+            saver.join().expect("save stage panicked")
+        });
+
+        // A stopped run still leaves a readable archive of what was saved
+        if let Some(writer) = &self.output_archive {
+            writer.finish()?;
+        }
+        if let Some(writer) = &self.output_shards {
+            writer.finish()?;
+        }
+        #[cfg(feature = "lmdb")]
+        if let Some(writer) = &self.output_lmdb {
+            writer.finish()?;
+        }
+        let (images, failed) = stages?;
+        if images == 0 && input_exhausted.load(Ordering::SeqCst) {
+            warn!("No images to process in {:?}", self.input.root());
+        }
+
+        // Everything staged for S3 has been uploaded and removed by now
+        #[cfg(feature = "s3")]
+        if let (Some(S3Remote { output: Some(_), .. }), Some(dir)) = (&self.s3, &self.output_dir) {
+            let _ = fs::remove_dir_all(dir);
+        }
+
+        Ok(ExtractionSummary {
+            images,
+            failed,
+            next_face_index: face_counter.load(Ordering::SeqCst),
+            stopped: stop_requested()
+                && (!input_exhausted.load(Ordering::SeqCst) || images < listed_images.load(Ordering::SeqCst)),
+            elapsed: start_time.elapsed(),
+        })
+    }
+
+    /// Decode a chunk of image files, in parallel on the worker pool when there are several workers
+    fn decode_chunk(&self, paths: &[PathBuf], archive: Option<&ArchiveReader>) -> DecodedChunk {
+        if let Some(archive) = archive {
+            return self.unpack_chunk(archive, paths);
+        }
+
+        #[cfg(feature = "s3")]
+        if let Some(S3Remote { store, input: Some(location), .. }) = &self.s3 {
+            return self.fetch_chunk(store, location, paths);
+        }
+
+        // URLs of the chunk are downloaded together, before decoding
+        let urls: Vec<&str> = paths.iter().filter_map(|path| url_of(path)).collect();
+        let mut downloads = self.fetcher.fetch_all(&urls).into_iter();
+        let sources: Vec<_> = paths
+            .iter()
+            .map(|path| (path, url_of(path).and_then(|_| downloads.next())))
+            .collect();
+
+        let decode = |(path, download): (&PathBuf, Option<Result<Vec<u8>>>)| {
+            self.decode_source(path, download.unwrap_or_else(|| read_image(path)))
+        };
+
+        if self.jobs > 1 {
+            self.pool.install(|| sources.into_par_iter().map(decode).collect())
+        } else {
+            sources.into_iter().map(decode).collect()
+        }
+    }
+
+    /// Decode an image read or downloaded from `path`, hashing its bytes when sources are hashed
+    fn decode_source(&self, path: &Path, data: Result<Vec<u8>>) -> (PathBuf, Option<String>, Result<Frames>) {
+        let digest = data.as_deref().ok().and_then(|data| self.digest(data));
+        let frames = data.and_then(|data| decode_loaded_frames(path, &data, &self.load));
+        (path.to_path_buf(), digest, frames)
+    }
+
+    /// Hex SHA-256 digest of a source image's bytes, when sources are hashed
+    fn digest(&self, data: &[u8]) -> Option<String> {
+        self.hash_sources.then(|| format!("{:x}", Sha256::digest(data)))
+    }
+
+    /// Read a chunk of archive members in order, then decode them like files
+    fn unpack_chunk(&self, archive: &ArchiveReader, paths: &[PathBuf]) -> DecodedChunk {
+        let root = self.input.root();
+        let members: Vec<_> = paths
+            .iter()
+            .map(|path| {
+                let member = path.strip_prefix(root).unwrap_or(path);
+                archive.read(&member.to_string_lossy().replace('\\', "/"))
+            })
+            .collect();
+
+        let decode = |(path, data): (&PathBuf, Result<Vec<u8>>)| self.decode_source(path, data);
+
+        if self.jobs > 1 {
+            self.pool.install(|| paths.par_iter().zip(members).map(decode).collect())
+        } else {
+            paths.iter().zip(members).map(decode).collect()
+        }
+    }
+
+    /// Download a chunk of S3 objects concurrently, then decode them like files
+    #[cfg(feature = "s3")]
+    fn fetch_chunk(&self, store: &S3Store, location: &S3Location, paths: &[PathBuf]) -> DecodedChunk {
+        let keys: Vec<String> = paths
+            .iter()
+            .map(|path| location.key_of(path).unwrap_or_default())
+            .collect();
+        let downloads = store.get_all(&location.bucket, &keys);
+
+        let decode = |(path, data): (&PathBuf, Result<Vec<u8>>)| self.decode_source(path, data);
+
+        if self.jobs > 1 {
+            self.pool.install(|| paths.par_iter().zip(downloads).map(decode).collect())
+        } else {
+            paths.iter().zip(downloads).map(decode).collect()
+        }
+    }
+
+    /// Upload the saved crops of an image to the S3 output, removing the staged files
+    #[cfg(feature = "s3")]
+    fn upload_crops(&self, output_dir: &Path, processed: &ProcessedImage) -> Result<()> {
+        if let Some(S3Remote { store, output: Some(location), .. }) = &self.s3 {
+            for entry in &processed.entries {
+                let key = location.key(&entry.output.replace('\\', "/"));
+                store.move_file(&output_dir.join(&entry.output), &location.bucket, &key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Detect faces in a decoded chunk: in parallel across workers, or handing all
+    /// images to the detector at once when batching on a single thread
+    fn detect_chunk(&self, chunk: DecodedChunk) -> DetectedChunk {
+        let detect = |(path, digest, frames): (PathBuf, Option<String>, Result<Frames>)| {
+            (path, digest, frames.and_then(|frames| self.detect_frames(frames)))
+        };
+
+        if self.jobs > 1 {
+            return self.pool.install(|| chunk.into_par_iter().map(detect).collect());
+        }
+
+        if self.batch_size <= 1 {
+            return chunk.into_iter().map(detect).collect();
+        }
+
+        // Images that failed to decode are left out of the batch, the frames of the others
+        // all go into it; every image keeps its slot so results stay in input order
+        let mut slots: Vec<Option<DetectedImage>> = Vec::with_capacity(chunk.len());
+        let mut loaded = Vec::new();
+        let mut images = Vec::new();
+        for (position, (path, digest, frames)) in chunk.into_iter().enumerate() {
+            match frames {
+                Ok(frames) => {
+                    let (indices, frame_images): (Vec<_>, Vec<_>) = frames.into_iter().unzip();
+                    loaded.push((position, path, digest, indices));
+                    images.extend(frame_images);
+                    slots.push(None);
+                }
+                Err(err) => slots.push(Some((path, digest, Err(err)))),
+            }
+        }
+
+        match self.detectors.detect_faces_batch(&images, self.threshold) {
+            Ok(batch_faces) => {
+                let mut detected = images.into_iter().zip(batch_faces);
+                for (position, path, digest, indices) in loaded {
+                    let frames = indices
+                        .into_iter()
+                        .zip(detected.by_ref())
+                        .map(|(frame, (img, faces))| (frame, img, faces))
+                        .collect();
+                    slots[position] = Some((path, digest, Ok(frames)));
+                }
+            }
+            Err(err) => {
+                for (position, path, digest, _) in loaded {
+                    let err = DetectorError::Backend(format!("Batch detection failed: {}", err));
+                    slots[position] = Some((path, digest, Err(err.into())));
+                }
+            }
+        }
+
+        slots.into_iter().flatten().collect()
+    }
+
+    /// Crop, encode and save the faces of a detected chunk, keeping the chunk order
+    fn save_chunk(&self, chunk: DetectedChunk) -> Vec<(PathBuf, Result<ProcessedImage>)> {
+        let save = |(path, digest, detected): DetectedImage| {
+            let result = detected.and_then(|frames| self.save_image(&path, digest, frames));
+            (path, result)
+        };
+
+        if self.jobs > 1 {
+            self.pool.install(|| chunk.into_par_iter().map(save).collect())
+        } else {
+            chunk.into_iter().map(save).collect()
+        }
+    }
+
+    /// Detect faces in every frame of a decoded image
+    fn detect_frames(&self, frames: Frames) -> Result<Vec<DetectedFrame>> {
+        frames
+            .into_iter()
+            .map(|(frame, img)| {
+                let faces = self.detectors.detect_faces(&img, self.threshold)?;
+                Ok((frame, img, faces))
+            })
+            .collect()
+    }
+
+    /// Crop, encode and save the faces of every frame of an image, as one result
+    fn save_image(&self, path: &Path, digest: Option<String>, frames: Vec<DetectedFrame>) -> Result<ProcessedImage> {
+        let processed = frames
+            .into_iter()
+            .map(|(frame, img, faces)| self.save_frame(path, frame, &img, faces))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ProcessedImage { source_sha256: digest, ..merge_frames(processed) })
+    }
+
+    /// Crop, encode and save the faces of one frame of an image, and annotate it
+    fn save_frame(&self, path: &Path, frame: Option<u32>, img: &DynamicImage, faces: Vec<FaceBox>) -> Result<ProcessedImage> {
+        let output_dir = self.output_dir.as_deref().unwrap_or(Path::new(""));
+        let face_counter = &self.face_counter;
+        let processed = match &self.output_archive {
+            Some(writer) => save_faces_with(path, frame, img, faces, &self.crop, face_counter, |relative, data| {
+                writer.append(&relative.to_string_lossy().replace('\\', "/"), &data)
+            })?,
+            // Samples pair each crop with its manifest entry, which only exists once
+            // all crops of the frame are encoded
+            None if self.collects_crops() => {
+                let crops = std::cell::RefCell::new(Vec::new());
+                let mut processed = save_faces_with(path, frame, img, faces, &self.crop, face_counter, |_, data| {
+                    crops.borrow_mut().push(data);
+                    Ok(())
+                })?;
+                self.store_samples(&mut processed.entries, crops.into_inner())?;
+                processed
+            }
+            None => save_faces(path, frame, img, faces, output_dir, &self.crop, face_counter)?,
+        };
+        #[cfg(feature = "s3")]
+        self.upload_crops(output_dir, &processed)?;
+        if let Some(annotator) = &self.annotator
+            && !processed.rejected
+        {
+            // Annotated frames are saved next to each other as stills
+            match frame {
+                Some(frame) => {
+                    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                    let frame_path = path
+                        .with_file_name(format!("{}_frame{:04}", stem, frame))
+                        .with_extension(path.extension().unwrap_or_default());
+                    annotator.save(&frame_path, img, &processed.faces)?;
+                }
+                None => annotator.save(path, img, &processed.faces)?,
+            }
+        }
+        Ok(processed)
+    }
+
+    /// Whether crops are stored together with their manifest entries (WebDataset or LMDB)
+    fn collects_crops(&self) -> bool {
+        #[cfg(feature = "lmdb")]
+        if self.output_lmdb.is_some() {
+            return true;
+        }
+        self.output_shards.is_some()
+    }
+
+    /// Store the encoded crops of a frame with their manifest entries, in order
+    fn store_samples(&self, entries: &mut [ManifestEntry], crops: Vec<Vec<u8>>) -> Result<()> {
+        if let Some(writer) = &self.output_shards {
+            for (entry, data) in entries.iter_mut().zip(&crops) {
+                writer.append(entry, data)?;
+            }
+        }
+        #[cfg(feature = "lmdb")]
+        if let Some(writer) = &self.output_lmdb {
+            writer.append(entries, crops)?;
+        }
+        Ok(())
+    }
+}
+
+/// One result for all frames of an image: the faces and crops of every frame,
+/// rejected only when every frame was
+fn merge_frames(frames: Vec<ProcessedImage>) -> ProcessedImage {
+    let mut frames = frames.into_iter();
+    // Images skipped for their size have no frames, nor a size
+    let Some(mut merged) = frames.next() else {
+        return ProcessedImage {
+            width: 0,
+            height: 0,
+            faces: Vec::new(),
+            entries: Vec::new(),
+            rejected: true,
+            source_sha256: None,
+        };
+    };
+    for frame in frames {
+        merged.faces.extend(frame.faces);
+        merged.entries.extend(frame.entries);
+        merged.rejected &= frame.rejected;
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the source and crops of every reported image, in the order reported
+    struct Recorder(Vec<(PathBuf, Option<Vec<String>>)>);
+
+    impl ExtractionObserver for Recorder {
+        fn image_done(&mut self, path: &Path, result: Result<ProcessedImage>) -> Result<()> {
+            let crops = result.ok().map(|processed| processed.entries.into_iter().map(|entry| entry.output).collect());
+            self.0.push((path.to_path_buf(), crops));
+            Ok(())
+        }
+    }
+
+    /// Run the mock detector over the images with a batch size, returning what was reported
+    fn run_batched(dir: &Path, paths: &[PathBuf], batch_size: usize) -> Vec<(PathBuf, Option<Vec<String>>)> {
+        let pipeline = FaceExtractionPipeline::builder()
+            .detector("mock", DetectorConfig::default())
+            .input(InputSource::Files { root: dir.to_path_buf(), paths: paths.to_vec() })
+            .output(OutputSink::Directory(dir.join(format!("out-{}", batch_size))))
+            .batch_size(batch_size)
+            .build()
+            .unwrap();
+        let mut recorder = Recorder(Vec::new());
+        pipeline.run_with(&mut recorder).unwrap();
+        recorder.0
+    }
+
+    #[test]
+    fn error_policy_parses_skip_fail_and_fail_after() {
+        assert_eq!("skip".parse(), Ok(ErrorPolicy::Skip));
+        assert_eq!("fail".parse(), Ok(ErrorPolicy::Fail));
+        assert_eq!("fail-after=3".parse(), Ok(ErrorPolicy::FailAfter(3)));
+        for invalid in ["fail-after=0", "fail-after=", "fail-after=-1", "never"] {
+            assert!(invalid.parse::<ErrorPolicy>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn fail_after_stops_once_that_many_images_failed() {
+        let dir = std::env::temp_dir().join(format!("face_cropper-fail-after-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<PathBuf> = (0..4).map(|i| dir.join(format!("corrupt-{}.png", i))).collect();
+        for path in &paths {
+            fs::write(path, b"not an image").unwrap();
+        }
+
+        let pipeline = FaceExtractionPipeline::builder()
+            .detector("mock", DetectorConfig::default())
+            .input(InputSource::Files { root: dir.clone(), paths })
+            .output(OutputSink::Discard)
+            .batch_size(1)
+            .error_policy(ErrorPolicy::FailAfter(2))
+            .build()
+            .unwrap();
+        let mut recorder = Recorder(Vec::new());
+        let result = pipeline.run_with(&mut recorder);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(result, Err(Error::TooManyFailures { failed: 2, .. })), "{:?}", result.err());
+        // The batch with the second failure is still reported, the rest isn't processed
+        assert_eq!(recorder.0.len(), 2);
+    }
+
+    #[test]
+    fn batched_detection_keeps_input_order_around_corrupt_images() {
+        let dir = std::env::temp_dir().join(format!("face_cropper-batch-order-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<PathBuf> = ["first.png", "corrupt.png", "second.png"].iter().map(|name| dir.join(name)).collect();
+        for path in [&paths[0], &paths[2]] {
+            image::RgbImage::from_pixel(256, 256, image::Rgb([128, 128, 128])).save(path).unwrap();
+        }
+        fs::write(&paths[1], b"not an image").unwrap();
+
+        let batched = run_batched(&dir, &paths, 3);
+        let single = run_batched(&dir, &paths, 1);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let order: Vec<&PathBuf> = batched.iter().map(|(path, _)| path).collect();
+        assert_eq!(order, paths.iter().collect::<Vec<_>>());
+        let saved = |crops: &Option<Vec<String>>| crops.as_ref().is_some_and(|crops| !crops.is_empty());
+        assert!(saved(&batched[0].1) && batched[1].1.is_none() && saved(&batched[2].1));
+        // Faces are numbered the same whether the images were detected one by one or together
+        assert_eq!(batched, single);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::detector::{create_detector, DetectorConfig, FaceBox, FaceDetector, Result};
+use image::DynamicImage;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Source of pool ids, telling apart the detectors of different pools on one thread
+static NEXT_POOL_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Detectors of the current thread, by pool id
+    static THREAD_DETECTORS: RefCell<HashMap<usize, Box<dyn FaceDetector>>> = RefCell::new(HashMap::new());
+}
+
+/// Detectors for use from several worker threads, one instance per thread
+///
+/// Detectors can't move between threads (rustface keeps its model state behind `Rc`),
+/// so instead of handing out instances the pool creates one on every thread that uses
+/// it, on first use, and keeps it until the pool is dropped on that thread or the
+/// thread exits. The pool itself is `Sync` and can be shared with rayon or scoped
+/// threads by reference.
+///
+/// Dropping the pool frees the detector of the thread dropping it; the other threads
+/// can't be reached from there and free theirs when they exit. Pools used from
+/// long-lived threads (rayon's global pool) should be given a thread pool of their
+/// own that is dropped with them, as [`FaceExtractionPipeline`](crate::FaceExtractionPipeline) does.
+pub struct DetectorPool {
+    id: usize,
+    name: String,
+    config: DetectorConfig,
+    instances: AtomicUsize,
+}
+
+impl DetectorPool {
+    /// Create a pool of `name` detectors (see [`create_detector`])
+    ///
+    /// The calling thread's detector is created right away, so an invalid
+    /// configuration or a missing model is reported here rather than by the workers.
+    pub fn new(name: &str, config: DetectorConfig) -> Result<Self> {
+        let pool = Self {
+            id: NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed),
+            name: name.to_string(),
+            config,
+            instances: AtomicUsize::new(0),
+        };
+        pool.with_detector(|_| ())?;
+
+        Ok(pool)
+    }
+
+    /// Run `f` with the current thread's detector, creating it on first use
+    ///
+    /// The detector is taken out of the thread's detectors while `f` runs, so `f`
+    /// may use the pool again; such a nested call gets a second detector, and the
+    /// outer one is kept when both are put back.
+    pub fn with_detector<T>(&self, f: impl FnOnce(&mut dyn FaceDetector) -> T) -> Result<T> {
+        let mut detector = match THREAD_DETECTORS.with(|cell| cell.borrow_mut().remove(&self.id)) {
+            Some(detector) => detector,
+            None => {
+                let detector = create_detector(&self.name, &self.config)?;
+                self.instances.fetch_add(1, Ordering::Relaxed);
+                detector
+            }
+        };
+
+        let result = f(detector.as_mut());
+
+        let replaced = THREAD_DETECTORS.with(|cell| cell.borrow_mut().insert(self.id, detector));
+        // Dropped once the map is released, like in `drop`
+        drop(replaced);
+
+        Ok(result)
+    }
+
+    /// Detect faces with the current thread's detector
+    pub fn detect_faces(&self, image: &DynamicImage, threshold: f32) -> Result<Vec<FaceBox>> {
+        self.with_detector(|detector| detector.detect_faces(image, threshold))?
+    }
+
+    /// Detect faces in several images at once with the current thread's detector
+    pub fn detect_faces_batch(&self, images: &[DynamicImage], threshold: f32) -> Result<Vec<Vec<FaceBox>>> {
+        self.with_detector(|detector| detector.detect_faces_batch(images, threshold))?
+    }
+
+    /// Detect faces in a grayscale buffer with the current thread's detector
+    pub fn detect_luma8(&self, data: &[u8], width: u32, height: u32, threshold: f32) -> Result<Vec<FaceBox>> {
+        self.with_detector(|detector| detector.detect_luma8(data, width, height, threshold))?
+    }
+
+    /// Number of detectors created so far, one per thread that used the pool
+    pub fn instances(&self) -> usize {
+        self.instances.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for DetectorPool {
+    fn drop(&mut self) {
+        // Fails only while the thread's locals are being destroyed, which frees them anyway
+        let removed = THREAD_DETECTORS.try_with(|cell| cell.borrow_mut().remove(&self.id));
+        // Dropped once the map is released, in case the detector's own drop touches it
+        drop(removed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_calls_get_a_detector_of_their_own() {
+        let pool = DetectorPool::new("mock", DetectorConfig::default()).unwrap();
+        let nested = pool.with_detector(|_| pool.with_detector(|_| pool.instances()).unwrap()).unwrap();
+        assert_eq!(nested, 2);
+
+        // The outer detector is the one kept
+        pool.with_detector(|_| ()).unwrap();
+        assert_eq!(pool.instances(), 2);
+    }
+}
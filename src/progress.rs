@@ -0,0 +1,90 @@
+use anyhow::Result;
+use face_cropper::{ExtractionSummary, ProcessedImage};
+use serde::Serialize;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// One line of `--progress-format json` output
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum ProgressEvent<'a> {
+    /// The input was listed, `images` are left to process
+    RunStarted { images: usize },
+    /// A batch is about to be saved (1-based)
+    BatchStarted { batch: usize, batches: usize, images: usize },
+    /// A crop was written
+    FaceSaved { source: &'a str, output: &'a str, confidence: f32 },
+    /// An image was processed
+    ImageDone { path: &'a str, faces: usize, saved: usize },
+    /// An image couldn't be loaded, detected or saved
+    Error { path: &'a str, message: &'a str },
+    /// The run ended, `stopped` if it was interrupted
+    RunFinished {
+        images: usize,
+        failed: usize,
+        next_face_index: usize,
+        elapsed_secs: f64,
+        stopped: bool,
+    },
+}
+
+/// Writes progress events to stdout as newline-delimited JSON, one flush per event
+pub struct ProgressWriter {
+    out: io::Stdout,
+}
+
+impl ProgressWriter {
+    pub fn new() -> Self {
+        Self { out: io::stdout() }
+    }
+
+    pub fn run_started(&mut self, images: usize) -> Result<()> {
+        self.emit(&ProgressEvent::RunStarted { images })
+    }
+
+    pub fn batch_started(&mut self, batch: usize, batches: usize, images: usize) -> Result<()> {
+        self.emit(&ProgressEvent::BatchStarted { batch, batches, images })
+    }
+
+    /// Report a processed image and each of its saved crops
+    pub fn image_done(&mut self, path: &Path, processed: &ProcessedImage) -> Result<()> {
+        for entry in &processed.entries {
+            self.emit(&ProgressEvent::FaceSaved {
+                source: &entry.source,
+                output: &entry.output,
+                confidence: entry.confidence,
+            })?;
+        }
+
+        self.emit(&ProgressEvent::ImageDone {
+            path: &path.to_string_lossy(),
+            faces: processed.faces.len(),
+            saved: processed.entries.len(),
+        })
+    }
+
+    pub fn error(&mut self, path: &Path, message: &str) -> Result<()> {
+        self.emit(&ProgressEvent::Error {
+            path: &path.to_string_lossy(),
+            message,
+        })
+    }
+
+    pub fn run_finished(&mut self, summary: &ExtractionSummary) -> Result<()> {
+        self.emit(&ProgressEvent::RunFinished {
+            images: summary.images,
+            failed: summary.failed,
+            next_face_index: summary.next_face_index,
+            elapsed_secs: summary.elapsed.as_secs_f64(),
+            stopped: summary.stopped,
+        })
+    }
+
+    fn emit(&mut self, event: &ProgressEvent) -> Result<()> {
+        let mut out = self.out.lock();
+        serde_json::to_writer(&mut out, event)?;
+        writeln!(out)?;
+        out.flush()?;
+        Ok(())
+    }
+}
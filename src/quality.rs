@@ -0,0 +1,138 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+use std::sync::Mutex;
+
+/// Sharpness of an image as the variance of its Laplacian
+///
+/// Blurred images have few edges, so their Laplacian stays close to zero everywhere.
+/// Scores depend on the image size, compare crops of the same output size only.
+pub fn sharpness(img: &DynamicImage) -> f64 {
+    let gray = img.to_luma8();
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+
+    let at = |x: u32, y: u32| gray.get_pixel(x, y)[0] as f64;
+    let mut sum = 0.0;
+    let mut sum_sq = 0.0;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let laplacian = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y);
+            sum += laplacian;
+            sum_sq += laplacian * laplacian;
+        }
+    }
+
+    let count = ((width - 2) * (height - 2)) as f64;
+    let mean = sum / count;
+    sum_sq / count - mean * mean
+}
+
+/// Exposure of an image on its 0-255 grayscale
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Exposure {
+    /// Mean luminance, near 0 for underexposed images and near 255 for blown-out ones
+    pub brightness: f64,
+    /// RMS contrast, the standard deviation of the luminance, low for flat images
+    pub contrast: f64,
+}
+
+/// Mean luminance and RMS contrast of an image
+pub fn exposure(img: &DynamicImage) -> Exposure {
+    let gray = img.to_luma8();
+    let count = gray.pixels().len();
+    if count == 0 {
+        return Exposure { brightness: 0.0, contrast: 0.0 };
+    }
+
+    let (sum, sum_sq) = gray.pixels().fold((0.0, 0.0), |(sum, sum_sq), pixel| {
+        let luma = pixel[0] as f64;
+        (sum + luma, sum_sq + luma * luma)
+    });
+    let mean = sum / count as f64;
+
+    Exposure {
+        brightness: mean,
+        contrast: (sum_sq / count as f64 - mean * mean).max(0.0).sqrt(),
+    }
+}
+
+/// 64-bit perceptual hash (pHash) of an image
+///
+/// Bits are the low frequencies of the DCT of a 32x32 grayscale thumbnail, set where
+/// they are above their median, so near-identical images have hashes a few bits apart.
+pub fn phash(img: &DynamicImage) -> u64 {
+    const SIZE: usize = 32;
+    const LOW: usize = 8;
+
+    let thumbnail = img.resize_exact(SIZE as u32, SIZE as u32, FilterType::Triangle).to_luma8();
+    let pixels: Vec<f64> = thumbnail.pixels().map(|p| p[0] as f64).collect();
+
+    // Separable 2D DCT-II, only the LOW x LOW lowest frequencies are needed
+    let cosines: Vec<f64> = (0..LOW * SIZE)
+        .map(|i| {
+            let (u, x) = (i / SIZE, i % SIZE);
+            (std::f64::consts::PI * u as f64 * (2 * x + 1) as f64 / (2 * SIZE) as f64).cos()
+        })
+        .collect();
+    let mut rows = vec![0.0; SIZE * LOW];
+    for y in 0..SIZE {
+        for u in 0..LOW {
+            rows[y * LOW + u] = (0..SIZE).map(|x| pixels[y * SIZE + x] * cosines[u * SIZE + x]).sum();
+        }
+    }
+    let mut coefficients = Vec::with_capacity(LOW * LOW);
+    for v in 0..LOW {
+        for u in 0..LOW {
+            coefficients.push((0..SIZE).map(|y| rows[y * LOW + u] * cosines[v * SIZE + y]).sum::<f64>());
+        }
+    }
+
+    // The DC term only reflects the overall brightness, leave it out of the median
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+
+    coefficients
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| **c > median)
+        .fold(0, |hash, (i, _)| hash | 1 << i)
+}
+
+/// Perceptual hashes of the faces saved so far, to skip near-duplicates
+#[derive(Debug)]
+pub struct PhashIndex {
+    /// Largest Hamming distance at which two hashes count as the same face
+    max_distance: u32,
+    hashes: Mutex<Vec<u64>>,
+}
+
+impl PhashIndex {
+    pub fn new(max_distance: u32) -> Self {
+        Self {
+            max_distance,
+            hashes: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Add a hash unless it's within the distance of a known one, returns whether it was added
+    pub fn insert(&self, hash: u64) -> bool {
+        let mut hashes = self.hashes.lock().expect("phash index lock poisoned");
+        if hashes.iter().any(|known| (known ^ hash).count_ones() <= self.max_distance) {
+            return false;
+        }
+
+        hashes.push(hash);
+        true
+    }
+
+    /// Forget a hash added by [`insert`](Self::insert), for a face that wasn't saved after all
+    pub fn remove(&self, hash: u64) {
+        let mut hashes = self.hashes.lock().expect("phash index lock poisoned");
+        if let Some(index) = hashes.iter().rposition(|&known| known == hash) {
+            hashes.swap_remove(index);
+        }
+    }
+}
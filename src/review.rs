@@ -0,0 +1,382 @@
+use crate::{read_manifest, Args, REJECTED_DIR};
+use anyhow::{bail, Context, Result};
+use face_cropper::{load_image, write_atomic, LoadOptions, ManifestEntry};
+use image::imageops::FilterType;
+use image::DynamicImage;
+use log::{info, warn};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Alignment, Constraint, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Arguments of the `review` subcommand
+#[derive(clap::Args, Debug)]
+pub struct ReviewArgs {
+    /// Output directory of an extraction run, with its manifest.jsonl
+    pub dir: PathBuf,
+
+    /// Manifest entry to start at, 1 for the first
+    #[clap(long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+    pub start: u64,
+}
+
+/// What to do with a crop once the review is saved
+#[derive(Debug, Clone, PartialEq)]
+enum Decision {
+    /// Not looked at yet, the crop stays as it is
+    Undecided,
+    Keep,
+    /// Move the crop to `rejected/` and drop it from the manifest
+    Reject,
+    /// Move the crop to the directory of a new label
+    Relabel(String),
+}
+
+/// State of the review UI
+struct Review {
+    entries: Vec<ManifestEntry>,
+    decisions: Vec<Decision>,
+    current: usize,
+    /// Label being typed, while relabeling
+    input: Option<String>,
+    /// The current crop, decoded once rather than on every redraw
+    preview: Option<(usize, Option<DynamicImage>)>,
+}
+
+/// How the review loop ended
+enum Outcome {
+    Save,
+    Discard,
+}
+
+/// Step through the crops of a run in a terminal UI, then apply the decisions
+///
+/// Kept and undecided crops stay where they are. Rejected ones are moved to
+/// `rejected/` with their manifest entries in `rejected/manifest.jsonl`, and
+/// relabeled ones to the directory of their new label. The manifest is rewritten
+/// to match; coco.json and the other exports of the run aren't updated.
+pub fn review(args: &Args, review_args: &ReviewArgs) -> Result<()> {
+    let dir = &review_args.dir;
+    let manifest_path = dir.join("manifest.jsonl");
+    let entries = read_manifest(&manifest_path)?;
+    if entries.is_empty() {
+        warn!("No crops to review in {:?}", manifest_path);
+        return Ok(());
+    }
+
+    let mut review = Review {
+        decisions: vec![Decision::Undecided; entries.len()],
+        current: (review_args.start as usize - 1).min(entries.len() - 1),
+        entries,
+        input: None,
+        preview: None,
+    };
+
+    let load = args.load_options();
+    let mut terminal = ratatui::try_init().context("Failed to set up the terminal")?;
+    let outcome = run_ui(&mut terminal, &mut review, dir, &load);
+    ratatui::restore();
+
+    match outcome? {
+        Outcome::Save => apply(dir, &manifest_path, review.entries, review.decisions),
+        Outcome::Discard => {
+            info!("Discarded the review, nothing was changed");
+            Ok(())
+        }
+    }
+}
+
+/// Draw and handle keys until the review is saved or discarded
+fn run_ui(terminal: &mut DefaultTerminal, review: &mut Review, dir: &Path, load: &LoadOptions) -> Result<Outcome> {
+    loop {
+        if review.preview.as_ref().is_none_or(|(index, _)| *index != review.current) {
+            let path = dir.join(&review.entries[review.current].output);
+            review.preview = Some((review.current, load_image(&path, load).ok()));
+        }
+        terminal.draw(|frame| draw(frame, review)).context("Failed to draw the review")?;
+
+        let Event::Key(key) = event::read().context("Failed to read terminal input")? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        if let Some(outcome) = handle_key(review, key) {
+            return Ok(outcome);
+        }
+    }
+}
+
+/// Apply a key press, returns how the review ended if it did
+fn handle_key(review: &mut Review, key: KeyEvent) -> Option<Outcome> {
+    if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+        return Some(Outcome::Discard);
+    }
+
+    // Typing a new label
+    if let Some(input) = &mut review.input {
+        match key.code {
+            KeyCode::Enter => {
+                let label = input.trim().to_string();
+                if valid_label(&label) {
+                    review.input = None;
+                    decide(review, Decision::Relabel(label));
+                }
+            }
+            KeyCode::Esc => review.input = None,
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) => input.push(c),
+            _ => {}
+        }
+        return None;
+    }
+
+    let last = review.entries.len() - 1;
+    match key.code {
+        KeyCode::Char('k') | KeyCode::Enter => decide(review, Decision::Keep),
+        KeyCode::Char('x') | KeyCode::Delete => decide(review, Decision::Reject),
+        KeyCode::Char('l') => {
+            let label = review.entries[review.current].label.clone().unwrap_or_default();
+            review.input = Some(label);
+        }
+        KeyCode::Char('u') => review.decisions[review.current] = Decision::Undecided,
+        KeyCode::Right | KeyCode::Char('n') => review.current = (review.current + 1).min(last),
+        KeyCode::Left | KeyCode::Char('p') => review.current = review.current.saturating_sub(1),
+        KeyCode::Home => review.current = 0,
+        KeyCode::End => review.current = last,
+        // Next crop nothing was decided for yet
+        KeyCode::Tab => {
+            if let Some(next) = (review.current + 1..=last).find(|&i| review.decisions[i] == Decision::Undecided) {
+                review.current = next;
+            }
+        }
+        KeyCode::Char('q') => return Some(Outcome::Save),
+        KeyCode::Esc => return Some(Outcome::Discard),
+        _ => {}
+    }
+    None
+}
+
+/// Record a decision for the current crop and move on to the next
+fn decide(review: &mut Review, decision: Decision) {
+    review.decisions[review.current] = decision;
+    review.current = (review.current + 1).min(review.entries.len() - 1);
+}
+
+/// Whether a label can name a directory of the output directory
+fn valid_label(label: &str) -> bool {
+    !label.is_empty()
+        && label != "."
+        && label != ".."
+        && label != REJECTED_DIR
+        && !label.contains(['/', '\\'])
+}
+
+/// Draw the current crop with its details, the decision counts and the keys
+fn draw(frame: &mut Frame, review: &Review) {
+    let [header, preview, footer] =
+        Layout::vertical([Constraint::Length(3), Constraint::Min(4), Constraint::Length(3)]).areas(frame.area());
+
+    let entry = &review.entries[review.current];
+    let decision = match &review.decisions[review.current] {
+        Decision::Undecided => Span::raw("undecided"),
+        Decision::Keep => "keep".green(),
+        Decision::Reject => "reject".red(),
+        Decision::Relabel(label) => format!("relabel to {}", label).yellow(),
+    };
+    let details = Line::from(vec![
+        Span::raw(format!(
+            "{}  label {}  confidence {:.2}  sharpness {:.0}  ",
+            entry.source,
+            entry.label.as_deref().unwrap_or("-"),
+            entry.confidence,
+            entry.sharpness
+        )),
+        decision,
+    ]);
+    let title = format!(" {} of {}: {} ", review.current + 1, review.entries.len(), entry.output);
+    frame.render_widget(Paragraph::new(details).block(Block::bordered().title(title)), header);
+
+    let block = Block::bordered();
+    let inner = block.inner(preview);
+    frame.render_widget(block, preview);
+    match review.preview.as_ref().and_then(|(_, img)| img.as_ref()) {
+        Some(img) => frame.render_widget(Paragraph::new(half_blocks(img, inner)).alignment(Alignment::Center), inner),
+        None => frame.render_widget(Paragraph::new("Failed to load the crop".red()).alignment(Alignment::Center), inner),
+    }
+
+    let counts = |wanted: fn(&Decision) -> bool| review.decisions.iter().filter(|&d| wanted(d)).count();
+    let status = format!(
+        " kept {}  rejected {}  relabeled {} ",
+        counts(|d| *d == Decision::Keep),
+        counts(|d| *d == Decision::Reject),
+        counts(|d| matches!(d, Decision::Relabel(_)))
+    );
+    let keys = match &review.input {
+        Some(input) => Line::from(vec![Span::raw("New label: "), input.clone().bold(), Span::raw("_  (enter to apply, esc to cancel)")]),
+        None => Line::raw(
+            "k/enter keep  x reject  l relabel  u undo  ←/→ move  tab next undecided  q save and quit  esc quit without saving",
+        ),
+    };
+    frame.render_widget(Paragraph::new(keys).block(Block::bordered().title(status)), footer);
+}
+
+/// An image fitted into an area as rows of half blocks, two pixels per cell
+/// in the foreground and background colors (needs a true-color terminal)
+fn half_blocks(img: &DynamicImage, area: Rect) -> Vec<Line<'static>> {
+    if area.width == 0 || area.height == 0 {
+        return Vec::new();
+    }
+    let fitted = img.resize(area.width as u32, area.height as u32 * 2, FilterType::Triangle).to_rgb8();
+    let (width, height) = fitted.dimensions();
+
+    (0..height.div_ceil(2))
+        .map(|row| {
+            let spans: Vec<Span> = (0..width)
+                .map(|x| {
+                    let rgb = |y: u32| {
+                        let [r, g, b] = fitted.get_pixel(x, y.min(height - 1)).0;
+                        Color::Rgb(r, g, b)
+                    };
+                    Span::styled("▀", Style::new().fg(rgb(row * 2)).bg(rgb(row * 2 + 1)))
+                })
+                .collect();
+            Line::from(spans)
+        })
+        .collect()
+}
+
+/// Move rejected and relabeled crops and rewrite the manifest without the rejected ones
+///
+/// Every move is planned and its target checked before any file is touched. A move
+/// or manifest write that fails anyway puts the crops moved so far back, so the
+/// manifests never point at files that aren't where they say.
+fn apply(dir: &Path, manifest_path: &Path, entries: Vec<ManifestEntry>, decisions: Vec<Decision>) -> Result<()> {
+    let rejected_dir = dir.join(REJECTED_DIR);
+    let (mut rejected, mut relabeled) = (Vec::new(), 0);
+    let mut kept = Vec::with_capacity(entries.len());
+    // Source and target of every crop to move, and the targets taken so far
+    let mut moves: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut targets = HashSet::new();
+
+    for (mut entry, decision) in entries.into_iter().zip(decisions) {
+        match decision {
+            Decision::Undecided | Decision::Keep => kept.push(entry),
+            Decision::Reject => {
+                let target = rejected_dir.join(&entry.output);
+                if target.exists() || !targets.insert(target.clone()) {
+                    bail!("{:?} already exists, nothing was moved", target);
+                }
+                moves.push((dir.join(&entry.output), target));
+                rejected.push(entry);
+            }
+            Decision::Relabel(label) => {
+                if entry.label.as_deref() == Some(label.as_str()) {
+                    kept.push(entry);
+                    continue;
+                }
+                let file_name = Path::new(&entry.output).file_name().context("Crop path has no file name")?;
+                let output = Path::new(&label).join(file_name);
+                let target = dir.join(&output);
+                if target.exists() || targets.contains(&target) {
+                    warn!("{:?} already exists, leaving {} labeled as it was", output, entry.output);
+                    kept.push(entry);
+                    continue;
+                }
+                targets.insert(target.clone());
+                moves.push((dir.join(&entry.output), target));
+                entry.output = output.to_string_lossy().replace('\\', "/");
+                entry.label = Some(label);
+                relabeled += 1;
+                kept.push(entry);
+            }
+        }
+    }
+    if let Some((from, _)) = moves.iter().find(|(from, _)| !from.is_file()) {
+        bail!("Crop {:?} is missing, nothing was moved", from);
+    }
+
+    let mut moved = 0;
+    let result = moves
+        .iter()
+        .try_for_each(|(from, to)| move_crop(from, to).map(|_| moved += 1))
+        .and_then(|_| write_manifests(manifest_path, &rejected_dir, &kept, &rejected));
+    if let Err(err) = result {
+        for (from, to) in moves[..moved].iter().rev() {
+            if let Err(restore_err) = fs::rename(to, from) {
+                warn!("Failed to move {:?} back to {:?}: {}", to, from, restore_err);
+            }
+        }
+        return Err(err.context("Review wasn't applied, the crops moved so far were put back"));
+    }
+
+    info!(
+        "Kept {} crops ({} relabeled) and moved {} rejected ones to {:?}",
+        kept.len(),
+        relabeled,
+        rejected.len(),
+        rejected_dir
+    );
+
+    Ok(())
+}
+
+/// Add the rejected entries to `rejected/manifest.jsonl` and write the kept ones as
+/// the manifest, leaving the rejected manifest as it was if the manifest fails
+fn write_manifests(manifest_path: &Path, rejected_dir: &Path, kept: &[ManifestEntry], rejected: &[ManifestEntry]) -> Result<()> {
+    let jsonl = |entries: &[ManifestEntry]| -> Result<String> {
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        Ok(lines)
+    };
+
+    let rejected_manifest = rejected_dir.join("manifest.jsonl");
+    let previous = if rejected.is_empty() {
+        None
+    } else {
+        let previous = match fs::read_to_string(&rejected_manifest) {
+            Ok(previous) => Some(previous),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err).with_context(|| format!("Failed to read {:?}", rejected_manifest)),
+        };
+        let lines = previous.clone().unwrap_or_default() + &jsonl(rejected)?;
+        write_atomic(&rejected_manifest, lines.as_bytes())
+            .with_context(|| format!("Failed to write manifest: {:?}", rejected_manifest))?;
+        Some(previous)
+    };
+
+    let written = jsonl(kept).and_then(|manifest| {
+        write_atomic(manifest_path, manifest.as_bytes())
+            .with_context(|| format!("Failed to write manifest: {:?}", manifest_path))
+    });
+    if written.is_err() {
+        let restored = match &previous {
+            Some(Some(previous)) => write_atomic(&rejected_manifest, previous.as_bytes()),
+            Some(None) => fs::remove_file(&rejected_manifest),
+            None => Ok(()),
+        };
+        if let Err(err) = restored {
+            warn!("Failed to restore {:?}: {}", rejected_manifest, err);
+        }
+    }
+
+    written
+}
+
+/// Move a crop, creating the directory it goes to
+fn move_crop(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+    fs::rename(from, to).with_context(|| format!("Failed to move {:?} to {:?}", from, to))
+}
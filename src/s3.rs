@@ -0,0 +1,196 @@
+use crate::error::{Error, Result};
+use crate::input::is_image;
+use aws_sdk_s3::Client;
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::primitives::ByteStream;
+use std::path::{Path, PathBuf};
+
+/// An `s3://bucket/prefix` location
+#[derive(Debug, Clone)]
+pub struct S3Location {
+    pub bucket: String,
+    /// Key prefix without a trailing slash, empty for the whole bucket
+    pub prefix: String,
+    url: PathBuf,
+}
+
+impl S3Location {
+    /// Parse an `s3://bucket/prefix` URL, None if it isn't one
+    pub fn parse(url: &str) -> Option<Self> {
+        let rest = url.strip_prefix("s3://")?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return None;
+        }
+
+        let prefix = prefix.trim_matches('/').to_string();
+        Some(Self {
+            url: PathBuf::from(format!("s3://{}/{}", bucket, prefix)),
+            bucket: bucket.to_string(),
+            prefix,
+        })
+    }
+
+    /// Parse a path given on the command line as an S3 URL
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.to_str().and_then(Self::parse)
+    }
+
+    /// The location as a URL, used as the root path of its objects
+    pub fn url(&self) -> &Path {
+        &self.url
+    }
+
+    /// Key of a path relative to the prefix
+    pub fn key(&self, relative: &str) -> String {
+        if self.prefix.is_empty() {
+            relative.to_string()
+        } else {
+            format!("{}/{}", self.prefix, relative)
+        }
+    }
+
+    /// URL path of an object in the bucket
+    pub fn object_url(&self, key: &str) -> PathBuf {
+        PathBuf::from(format!("s3://{}/{}", self.bucket, key))
+    }
+
+    /// Key of an object URL in this bucket, None for other paths
+    pub fn key_of(&self, path: &Path) -> Option<String> {
+        path.to_str()?
+            .strip_prefix("s3://")?
+            .strip_prefix(self.bucket.as_str())?
+            .strip_prefix('/')
+            .map(str::to_string)
+    }
+}
+
+/// Blocking access to S3, running the SDK on a runtime of its own
+///
+/// Credentials and region come from the standard AWS environment variables,
+/// config files and instance metadata, `AWS_ENDPOINT_URL` points it at an
+/// S3-compatible server instead.
+pub struct S3Store {
+    runtime: tokio::runtime::Runtime,
+    client: Client,
+}
+
+impl S3Store {
+    /// Load the AWS configuration and create a client
+    pub fn new() -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(|source| Error::Io {
+                context: "Failed to start the S3 runtime".to_string(),
+                source,
+            })?;
+        let config = runtime.block_on(aws_config::load_defaults(aws_config::BehaviorVersion::latest()));
+
+        // S3-compatible servers set through AWS_ENDPOINT_URL (MinIO and the like)
+        // rarely support bucket subdomains
+        let client_config = aws_sdk_s3::config::Builder::from(&config)
+            .force_path_style(config.endpoint_url().is_some())
+            .build();
+
+        Ok(Self {
+            client: Client::from_conf(client_config),
+            runtime,
+        })
+    }
+
+    /// Keys of all images under a location, in key order
+    pub fn list_images(&self, location: &S3Location) -> Result<Vec<String>> {
+        let prefix = if location.prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", location.prefix)
+        };
+
+        self.runtime.block_on(async {
+            let mut pages = self
+                .client
+                .list_objects_v2()
+                .bucket(&location.bucket)
+                .prefix(prefix)
+                .into_paginator()
+                .send();
+
+            let mut keys = Vec::new();
+            while let Some(page) = pages.next().await {
+                let page = page.map_err(|err| {
+                    Error::Storage(format!("Failed to list {:?}: {}", location.url(), DisplayErrorContext(err)))
+                })?;
+                keys.extend(
+                    page.contents()
+                        .iter()
+                        .filter_map(|object| object.key())
+                        .filter(|key| is_image(Path::new(key)))
+                        .map(str::to_string),
+                );
+            }
+
+            Ok(keys)
+        })
+    }
+
+    /// Download objects concurrently, in the order of `keys`
+    pub fn get_all(&self, bucket: &str, keys: &[String]) -> Vec<Result<Vec<u8>>> {
+        self.runtime.block_on(async {
+            let downloads: Vec<_> = keys
+                .iter()
+                .map(|key| {
+                    let request = self.client.get_object().bucket(bucket).key(key).send();
+                    let context = format!("Failed to download s3://{}/{}", bucket, key);
+                    self.runtime.spawn(async move {
+                        let object = request
+                            .await
+                            .map_err(|err| Error::Storage(format!("{}: {}", context, DisplayErrorContext(err))))?;
+                        let data = object
+                            .body
+                            .collect()
+                            .await
+                            .map_err(|err| Error::Storage(format!("{}: {}", context, err)))?;
+                        Ok(data.into_bytes().to_vec())
+                    })
+                })
+                .collect();
+
+            let mut results = Vec::with_capacity(downloads.len());
+            for download in downloads {
+                results.push(download.await.unwrap_or_else(|err| Err(Error::Storage(err.to_string()))));
+            }
+            results
+        })
+    }
+
+    /// Upload an object
+    pub fn put(&self, bucket: &str, key: &str, data: Vec<u8>) -> Result<()> {
+        self.runtime.block_on(async {
+            self.client
+                .put_object()
+                .bucket(bucket)
+                .key(key)
+                .body(ByteStream::from(data))
+                .send()
+                .await
+                .map_err(|err| {
+                    Error::Storage(format!("Failed to upload s3://{}/{}: {}", bucket, key, DisplayErrorContext(err)))
+                })?;
+            Ok(())
+        })
+    }
+
+    /// Upload a local file and delete it
+    pub fn move_file(&self, path: &Path, bucket: &str, key: &str) -> Result<()> {
+        let data = std::fs::read(path).map_err(|source| Error::Io {
+            context: format!("Failed to read {:?}", path),
+            source,
+        })?;
+        self.put(bucket, key, data)?;
+        std::fs::remove_file(path).map_err(|source| Error::Io {
+            context: format!("Failed to remove {:?}", path),
+            source,
+        })
+    }
+}
@@ -0,0 +1,400 @@
+use crate::metrics::Metrics;
+use crate::{init_detector, Args};
+use anyhow::{Context, Result};
+use base64::Engine;
+use face_cropper::{crop_face, decode_image, encode_crop, CropOptions, DetectedBox, FaceDetector, LoadOptions};
+use image::DynamicImage;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::io::{Cursor, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Barrier};
+use std::time::Instant;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Largest upload accepted by the service
+const MAX_UPLOAD_BYTES: usize = 32 * 1024 * 1024;
+
+/// Arguments of the `serve` subcommand
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+    /// Address to listen on
+    #[clap(long, default_value = "127.0.0.1:8080")]
+    pub bind: String,
+}
+
+/// How crops are returned by `POST /detect`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CropMode {
+    None,
+    Base64,
+    Zip,
+}
+
+/// Response body of `POST /detect`
+#[derive(Debug, Serialize)]
+struct DetectResponse {
+    width: u32,
+    height: u32,
+    faces: Vec<DetectedFace>,
+}
+
+/// A detected face as returned by the service
+#[derive(Debug, Serialize)]
+struct DetectedFace {
+    #[serde(flatten)]
+    face: DetectedBox,
+    /// Base64 encoded crop in the --format image format, with `crops=base64`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crop: Option<String>,
+}
+
+/// Why a request failed, with the status it is answered with: 4xx for requests
+/// that can't be served as sent, 500 for failures of the service itself
+#[derive(Debug)]
+struct RequestError {
+    status: u16,
+    error: anyhow::Error,
+}
+
+impl RequestError {
+    fn new(status: u16, error: impl Into<anyhow::Error>) -> Self {
+        Self { status, error: error.into() }
+    }
+}
+
+/// Errors passed on with `?` are the service's own
+impl From<anyhow::Error> for RequestError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::new(500, error)
+    }
+}
+
+/// Run the HTTP service until the process is killed
+///
+/// Endpoints:
+/// - `GET /health` returns `ok`
+/// - `GET /metrics` returns Prometheus metrics: images processed, faces
+///   extracted, failures and a histogram of detection latency
+/// - `POST /detect[?threshold=T&crops=base64|zip]` takes an image as the raw
+///   request body or as the first part of a multipart form, and returns the
+///   detected faces as JSON (or a zip of crops plus `faces.json`)
+///
+/// Failed requests get a JSON `error`: 400 for bad queries and uploads, 413 for
+/// uploads over the size or pixel limit, 500 when detection or encoding fails.
+pub fn serve(args: &Args, serve_args: &ServeArgs) -> Result<()> {
+    // Create one detector up front so a model download happens once, before the
+    // workers create theirs
+    info!("Initializing face detector: {}", args.detector);
+    init_detector(args)?;
+
+    let server = Server::http(&serve_args.bind)
+        .map_err(|err| anyhow::anyhow!("Failed to listen on {}: {}", serve_args.bind, err))?;
+    let load = args.load_options();
+    let crop = args.crop_options()?;
+    let workers = args.jobs.max(1);
+    let metrics = Metrics::new();
+
+    // Detectors can't move between threads, so every worker creates its own and
+    // reports whether it could; requests are only served once all of them have one
+    let (ready_tx, ready_rx) = mpsc::channel::<Result<()>>();
+    let start = Barrier::new(workers + 1);
+    let failed = AtomicBool::new(false);
+    let (server, load, crop, metrics, start, failed) = (&server, &load, &crop, &metrics, &start, &failed);
+
+    // Each worker owns a detector and pulls requests off the shared server
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                let ready_tx = ready_tx.clone();
+                scope.spawn(move || -> Result<()> {
+                    let detector = match init_detector(args) {
+                        Ok(detector) => {
+                            let _ = ready_tx.send(Ok(()));
+                            Some(detector)
+                        }
+                        Err(err) => {
+                            let _ = ready_tx.send(Err(err));
+                            None
+                        }
+                    };
+                    start.wait();
+                    let Some(mut detector) = detector.filter(|_| !failed.load(Ordering::SeqCst)) else {
+                        return Ok(());
+                    };
+
+                    loop {
+                        let request = server.recv().context("Failed to receive request")?;
+                        handle_request(request, &mut detector, args.threshold, load, crop, metrics);
+                    }
+                })
+            })
+            .collect();
+
+        // Every worker reports before waiting to start, so the first failure is
+        // enough to tell them all to stop
+        let ready = ready_rx.iter().take(workers).collect::<Result<Vec<()>>>();
+        failed.store(ready.is_err(), Ordering::SeqCst);
+        start.wait();
+        ready.context("A server worker failed to start")?;
+        info!("Listening on http://{} with {} worker(s)", serve_args.bind, workers);
+
+        for handle in handles {
+            handle.join().expect("server worker panicked")?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Route a request and send the response
+fn handle_request(
+    mut request: Request,
+    detector: &mut Box<dyn FaceDetector>,
+    threshold: f32,
+    load: &LoadOptions,
+    crop: &CropOptions,
+    metrics: &Metrics
+) {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+
+    let response = match (request.method(), path) {
+        (Method::Get, "/health") => Ok(text_response(200, "ok")),
+        (Method::Get, "/metrics") => Ok(Response::from_string(metrics.render())
+            .with_header(content_type("text/plain; version=0.0.4"))),
+        (Method::Post, "/detect") => {
+            let response = detect(&mut request, query, detector, threshold, load, crop, metrics);
+            if response.is_err() {
+                metrics.failure();
+            }
+            response
+        }
+        _ => Ok(json_error(404, "Not found")),
+    };
+
+    let response = response.unwrap_or_else(|err| {
+        if err.status >= 500 {
+            error!("Request to {} failed: {:#}", url, err.error);
+        } else {
+            warn!("Request to {} failed: {:#}", url, err.error);
+        }
+        json_error(err.status, &format!("{:#}", err.error))
+    });
+
+    if let Err(err) = request.respond(response) {
+        error!("Failed to send response for {}: {}", url, err);
+    }
+}
+
+/// Handle `POST /detect`
+fn detect(
+    request: &mut Request,
+    query: &str,
+    detector: &mut Box<dyn FaceDetector>,
+    threshold: f32,
+    load: &LoadOptions,
+    crop: &CropOptions,
+    metrics: &Metrics
+) -> std::result::Result<Response<Cursor<Vec<u8>>>, RequestError> {
+    // Query parameters override the command line defaults
+    let mut threshold = threshold;
+    let mut crop_mode = CropMode::None;
+    for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+        match key {
+            "threshold" => {
+                threshold = value
+                    .parse()
+                    .with_context(|| format!("Invalid threshold: {}", value))
+                    .map_err(|err| RequestError::new(400, err))?;
+            }
+            "crops" => {
+                crop_mode = match value {
+                    "none" => CropMode::None,
+                    "base64" => CropMode::Base64,
+                    "zip" => CropMode::Zip,
+                    _ => {
+                        let err = anyhow::anyhow!("Invalid crops mode: {} (none, base64, zip)", value);
+                        return Err(RequestError::new(400, err));
+                    }
+                };
+            }
+            _ => {}
+        }
+    }
+
+    let img = read_upload(request, load)?;
+    let started = Instant::now();
+    let faces = detector.detect_faces(&img, threshold).context("Face detection failed")?;
+    metrics.detection_latency(started.elapsed());
+    info!("Detected {} faces in uploaded {}x{} image", faces.len(), img.width(), img.height());
+
+    let mut crops = Vec::new();
+    if crop_mode != CropMode::None {
+        for face in &faces {
+            let encoded = match crop_face(&img, face, crop) {
+                Some(face_crop) => Some(encode_crop(&face_crop.image, crop).context("Failed to encode crop")?),
+                None => None,
+            };
+            crops.push(encoded);
+        }
+    }
+
+    let body = DetectResponse {
+        width: img.width(),
+        height: img.height(),
+        faces: faces
+            .iter()
+            .enumerate()
+            .map(|(i, face)| DetectedFace {
+                face: DetectedBox::from(face),
+                // Base64 mode embeds the crop, zip mode packs it next to faces.json
+                crop: crops
+                    .get(i)
+                    .cloned()
+                    .flatten()
+                    .filter(|_| crop_mode == CropMode::Base64)
+                    .map(|encoded| base64::engine::general_purpose::STANDARD.encode(encoded)),
+            })
+            .collect(),
+    };
+
+    if crop_mode == CropMode::Zip {
+        let response = zip_response(&body, &crops, crop.format.extension())?;
+        metrics.image_processed(body.faces.len());
+        return Ok(response);
+    }
+
+    let json = serde_json::to_vec(&body).context("Failed to encode response")?;
+    metrics.image_processed(body.faces.len());
+    Ok(Response::from_data(json).with_header(content_type("application/json")))
+}
+
+/// Read and decode the uploaded image from the request body
+fn read_upload(request: &mut Request, load: &LoadOptions) -> std::result::Result<DynamicImage, RequestError> {
+    let too_large = || RequestError::new(413, anyhow::anyhow!("Upload larger than {} bytes", MAX_UPLOAD_BYTES));
+    if request.body_length().is_some_and(|length| length > MAX_UPLOAD_BYTES) {
+        return Err(too_large());
+    }
+
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_UPLOAD_BYTES as u64 + 1)
+        .read_to_end(&mut body)
+        .context("Failed to read request body")
+        .map_err(|err| RequestError::new(400, err))?;
+    if body.len() > MAX_UPLOAD_BYTES {
+        return Err(too_large());
+    }
+
+    // Accept both raw uploads and `curl -F image=@photo.jpg` style forms
+    let boundary = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Content-Type"))
+        .and_then(|header| multipart_boundary(header.value.as_str()));
+    let data = match boundary {
+        Some(boundary) => first_multipart_part(&body, &boundary)
+            .context("Malformed multipart upload")
+            .map_err(|err| RequestError::new(400, err))?,
+        None => &body[..],
+    };
+
+    // Images over --max-image-pixels are too large as well
+    decode_image(data, load).map_err(|err| {
+        let status = if matches!(err, image::ImageError::Limits(_)) { 413 } else { 400 };
+        RequestError::new(status, anyhow::Error::new(err).context("Failed to decode uploaded image"))
+    })
+}
+
+/// Extract the boundary from a `multipart/form-data` content type
+fn multipart_boundary(content_type: &str) -> Option<String> {
+    if !content_type.starts_with("multipart/form-data") {
+        return None;
+    }
+
+    content_type
+        .split(';')
+        .filter_map(|param| param.trim().strip_prefix("boundary="))
+        .map(|boundary| boundary.trim_matches('"').to_string())
+        .next()
+}
+
+/// Body of the first part of a multipart message
+fn first_multipart_part<'a>(body: &'a [u8], boundary: &str) -> Option<&'a [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let start = find(body, delimiter.as_bytes())? + delimiter.len();
+    let part = &body[start..];
+
+    // Part headers end with an empty line
+    let data_start = find(part, b"\r\n\r\n")? + 4;
+    let data = &part[data_start..];
+    let data_end = find(data, format!("\r\n{}", delimiter).as_bytes())?;
+
+    Some(&data[..data_end])
+}
+
+/// Position of the first occurrence of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Package the detections and crops as a zip archive
+fn zip_response(body: &DetectResponse, crops: &[Option<Vec<u8>>], extension: &str) -> Result<Response<Cursor<Vec<u8>>>> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = zip::write::FileOptions::default();
+
+    zip.start_file("faces.json", options)?;
+    zip.write_all(&serde_json::to_vec_pretty(body)?)?;
+
+    for (i, encoded) in crops.iter().enumerate() {
+        if let Some(encoded) = encoded {
+            zip.start_file(format!("face_{:03}.{}", i, extension), options)?;
+            zip.write_all(encoded)?;
+        }
+    }
+
+    let data = zip.finish()?.into_inner();
+    Ok(Response::from_data(data).with_header(content_type("application/zip")))
+}
+
+fn text_response(status: u16, text: &str) -> Response<Cursor<Vec<u8>>> {
+    Response::from_string(text)
+        .with_status_code(status)
+        .with_header(content_type("text/plain"))
+}
+
+fn json_error(status: u16, message: &str) -> Response<Cursor<Vec<u8>>> {
+    let json = serde_json::json!({ "error": message }).to_string();
+    Response::from_string(json)
+        .with_status_code(status)
+        .with_header(content_type("application/json"))
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes("Content-Type", value).expect("static header is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multipart_boundary_is_read_from_the_content_type() {
+        assert_eq!(multipart_boundary("multipart/form-data; boundary=abc123").as_deref(), Some("abc123"));
+        assert_eq!(multipart_boundary("multipart/form-data; charset=utf-8; boundary=\"a b\"").as_deref(), Some("a b"));
+        assert_eq!(multipart_boundary("multipart/form-data"), None);
+        assert_eq!(multipart_boundary("image/jpeg; boundary=abc"), None);
+    }
+
+    #[test]
+    fn first_multipart_part_returns_the_body_of_the_first_part() {
+        let body = b"--xyz\r\nContent-Disposition: form-data; name=\"image\"; filename=\"a.jpg\"\r\n\
+            Content-Type: image/jpeg\r\n\r\nfirst\r\nbody\r\n--xyz\r\n\r\nsecond\r\n--xyz--\r\n";
+        assert_eq!(first_multipart_part(body, "xyz"), Some(&b"first\r\nbody"[..]));
+        assert_eq!(first_multipart_part(body, "other"), None);
+        // A part without its closing delimiter is incomplete
+        assert_eq!(first_multipart_part(b"--xyz\r\n\r\ndata", "xyz"), None);
+    }
+}
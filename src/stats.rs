@@ -0,0 +1,194 @@
+use crate::read_manifest;
+use anyhow::{Context, Result};
+use face_cropper::{write_atomic, ManifestEntry};
+use log::warn;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Upper bounds of the source resolution buckets, on the shorter side of the image (px)
+const RESOLUTION_BUCKETS: [u32; 5] = [240, 480, 720, 1080, 2160];
+
+/// Width of the longest histogram bar (characters)
+const BAR_WIDTH: usize = 40;
+
+/// Arguments of the `stats` subcommand
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+    /// Output directory of an extraction run, with its manifest.jsonl (and failures.txt)
+    pub dir: PathBuf,
+
+    /// Also save the report as JSON to this file
+    #[clap(long)]
+    pub json: Option<PathBuf>,
+
+    /// Bins of the confidence and sharpness histograms
+    #[clap(long, default_value = "10", value_parser = clap::value_parser!(u64).range(1..))]
+    pub bins: u64,
+}
+
+/// Distributions over the crops of a run
+#[derive(Serialize, Debug)]
+struct Report {
+    crops: usize,
+    /// Source images (frames of animated ones counted separately) with at least one crop
+    images: usize,
+    labels: usize,
+    /// Images by the number of crops taken from them
+    faces_per_image: BTreeMap<usize, usize>,
+    confidence: Distribution,
+    sharpness: Distribution,
+    /// Images by the shorter side of the source, keyed by the bucket's upper bound (`>2160` above the last)
+    source_resolution: Vec<(String, usize)>,
+    /// Failed images by error category, from failures.txt
+    failures: BTreeMap<String, usize>,
+}
+
+/// Summary and histogram of a value
+#[derive(Serialize, Debug, Default)]
+struct Distribution {
+    min: f64,
+    max: f64,
+    mean: f64,
+    median: f64,
+    /// Values per bin of equal width from min to max: (lower bound, count)
+    histogram: Vec<(f64, usize)>,
+}
+
+impl Distribution {
+    fn new(mut values: Vec<f64>, bins: usize) -> Self {
+        if values.is_empty() {
+            return Self::default();
+        }
+        values.sort_by(f64::total_cmp);
+
+        let (min, max) = (values[0], values[values.len() - 1]);
+        let width = (max - min) / bins as f64;
+        let mut histogram: Vec<(f64, usize)> = (0..bins).map(|i| (min + i as f64 * width, 0)).collect();
+        for &value in &values {
+            // The max falls into the last bin, and every value into the first when they're all equal
+            let bin = if width > 0.0 { (((value - min) / width) as usize).min(bins - 1) } else { 0 };
+            histogram[bin].1 += 1;
+        }
+
+        Self {
+            min,
+            max,
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            median: values[values.len() / 2],
+            histogram,
+        }
+    }
+}
+
+/// Print distributions over the manifest and failures of a run, optionally saving them as JSON
+pub fn stats(stats_args: &StatsArgs) -> Result<()> {
+    let entries = read_manifest(&stats_args.dir.join("manifest.jsonl"))?;
+    let failures = failure_categories(&stats_args.dir.join("failures.txt"))?;
+    let report = report(&entries, failures, stats_args.bins as usize);
+
+    print_report(&report);
+    if let Some(path) = &stats_args.json {
+        write_atomic(path, serde_json::to_string_pretty(&report)?.as_bytes())
+            .with_context(|| format!("Failed to write report: {:?}", path))?;
+    }
+
+    Ok(())
+}
+
+/// Distributions over the manifest entries of a run
+fn report(entries: &[ManifestEntry], failures: BTreeMap<String, usize>, bins: usize) -> Report {
+    // Crops and shorter side of every source image
+    let mut images: HashMap<(&str, Option<u32>), (usize, u32)> = HashMap::new();
+    for entry in entries {
+        let image = images
+            .entry((entry.source.as_str(), entry.frame))
+            .or_insert((0, entry.image_width.min(entry.image_height)));
+        image.0 += 1;
+    }
+
+    let mut faces_per_image = BTreeMap::new();
+    let mut resolutions = [0; RESOLUTION_BUCKETS.len() + 1];
+    for &(crops, short_side) in images.values() {
+        *faces_per_image.entry(crops).or_insert(0) += 1;
+        let bucket = RESOLUTION_BUCKETS.iter().position(|&bound| short_side <= bound);
+        resolutions[bucket.unwrap_or(RESOLUTION_BUCKETS.len())] += 1;
+    }
+    let source_resolution = RESOLUTION_BUCKETS
+        .iter()
+        .map(|bound| format!("<={}", bound))
+        .chain([format!(">{}", RESOLUTION_BUCKETS[RESOLUTION_BUCKETS.len() - 1])])
+        .zip(resolutions)
+        .collect();
+
+    let mut labels: Vec<&str> = entries.iter().filter_map(|entry| entry.label.as_deref()).collect();
+    labels.sort_unstable();
+    labels.dedup();
+
+    Report {
+        crops: entries.len(),
+        images: images.len(),
+        labels: labels.len(),
+        faces_per_image,
+        confidence: Distribution::new(entries.iter().map(|entry| entry.confidence as f64).collect(), bins),
+        sharpness: Distribution::new(entries.iter().map(|entry| entry.sharpness).collect(), bins),
+        source_resolution,
+        failures,
+    }
+}
+
+/// Failed images per category of a failures.txt, none when the run had no list
+fn failure_categories(path: &Path) -> Result<BTreeMap<String, usize>> {
+    let mut categories = BTreeMap::new();
+    if !path.exists() {
+        warn!("No failures list at {:?}", path);
+        return Ok(categories);
+    }
+
+    let list = fs::read_to_string(path).with_context(|| format!("Failed to read failures list: {:?}", path))?;
+    for line in list.lines().filter(|line| !line.trim().is_empty()) {
+        let category = line.split('\t').nth(1).unwrap_or("unknown");
+        *categories.entry(category.to_string()).or_insert(0) += 1;
+    }
+
+    Ok(categories)
+}
+
+/// Print the report as a bar chart per distribution
+fn print_report(report: &Report) {
+    println!("{} crops from {} images, {} labels", report.crops, report.images, report.labels);
+
+    println!("\nFaces per image");
+    let faces: Vec<(String, usize)> = report.faces_per_image.iter().map(|(faces, &images)| (faces.to_string(), images)).collect();
+    print_bars(&faces);
+
+    for (name, distribution) in [("Confidence", &report.confidence), ("Sharpness", &report.sharpness)] {
+        println!(
+            "\n{} (min {:.2}, median {:.2}, mean {:.2}, max {:.2})",
+            name, distribution.min, distribution.median, distribution.mean, distribution.max
+        );
+        let bins: Vec<(String, usize)> = distribution.histogram.iter().map(|&(lower, count)| (format!("{:.2}", lower), count)).collect();
+        print_bars(&bins);
+    }
+
+    println!("\nSource resolution (shorter side, px)");
+    print_bars(&report.source_resolution);
+
+    println!("\nFailures");
+    if report.failures.is_empty() {
+        println!("  none");
+    }
+    let failures: Vec<(String, usize)> = report.failures.iter().map(|(category, &count)| (category.clone(), count)).collect();
+    print_bars(&failures);
+}
+
+/// Print a row per key with its count and a bar scaled to the largest count
+fn print_bars(rows: &[(String, usize)]) {
+    let largest = rows.iter().map(|&(_, count)| count).max().unwrap_or(0).max(1);
+    let key_width = rows.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+    for (key, count) in rows {
+        let bar = "#".repeat((count * BAR_WIDTH).div_ceil(largest));
+        println!("  {:>key_width$} {:>7} {}", key, count, bar, key_width = key_width);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{init_detector, Args};
+use anyhow::Result;
+use face_cropper::load_image;
+use log::{error, info, warn};
+use std::path::PathBuf;
+
+/// Arguments of the `sweep` subcommand
+#[derive(clap::Args, Debug)]
+pub struct SweepArgs {
+    /// Image file, or directory to scan for images
+    pub input: PathBuf,
+
+    /// Thresholds to report as `start..end:step`, both ends included
+    #[clap(long, default_value = "0.3..0.9:0.05", value_parser = parse_thresholds)]
+    pub thresholds: Thresholds,
+}
+
+/// The thresholds of a sweep, in increasing order
+#[derive(Debug, Clone)]
+pub struct Thresholds(Vec<f32>);
+
+/// Parse `start..end:step` into the thresholds it covers
+fn parse_thresholds(value: &str) -> std::result::Result<Thresholds, String> {
+    let invalid = || format!("Invalid thresholds: {} (expected start..end:step, e.g. 0.3..0.9:0.05)", value);
+    let (range, step) = value.split_once(':').ok_or_else(invalid)?;
+    let (start, end) = range.split_once("..").ok_or_else(invalid)?;
+    let [start, end, step] = [start, end, step].map(|number| number.trim().parse::<f32>());
+    let (start, end, step) = (start.map_err(|_| invalid())?, end.map_err(|_| invalid())?, step.map_err(|_| invalid())?);
+    if step <= 0.0 || start > end {
+        return Err(invalid());
+    }
+
+    // Steps are counted rather than added up so 0.05 steps don't drift past the end
+    let steps = ((end - start) / step + 1e-4).floor() as usize;
+    Ok(Thresholds((0..=steps).map(|i| start + i as f32 * step).collect()))
+}
+
+/// Detect once per image at the lowest threshold and print the faces kept at each threshold
+pub fn sweep(args: &Args, sweep_args: &SweepArgs) -> Result<()> {
+    let thresholds = &sweep_args.thresholds.0;
+    let lowest = thresholds.first().copied().unwrap_or(args.threshold);
+    info!("Initializing face detector: {}", args.detector);
+    let mut detector = init_detector(args)?;
+    let load = args.load_options();
+
+    let image_paths = args.find_images(&sweep_args.input)?;
+    if image_paths.is_empty() {
+        warn!("No images found at {:?}", sweep_args.input);
+        return Ok(());
+    }
+    info!("Detecting faces in {} images at threshold {:.2}", image_paths.len(), lowest);
+
+    // Confidences of the faces of every image
+    let mut confidences: Vec<Vec<f32>> = Vec::with_capacity(image_paths.len());
+    for path in &image_paths {
+        let faces = load_image(path, &load)
+            .and_then(|img| Ok(detector.detect_faces(&img, lowest)?));
+        match faces {
+            Ok(faces) => confidences.push(faces.iter().map(|face| face.confidence).collect()),
+            Err(err) => error!("Failed to process {:?}: {}", path, err),
+        }
+    }
+
+    println!("{:>9} {:>7} {:>16} {:>15}", "threshold", "faces", "images w/ faces", "faces per image");
+    for &threshold in thresholds {
+        let per_image: Vec<usize> = confidences
+            .iter()
+            .map(|image| image.iter().filter(|&&confidence| confidence >= threshold).count())
+            .collect();
+        let faces: usize = per_image.iter().sum();
+        println!(
+            "{:>9.2} {:>7} {:>16} {:>15.2}",
+            threshold,
+            faces,
+            per_image.iter().filter(|&&count| count > 0).count(),
+            faces as f64 / confidences.len().max(1) as f64
+        );
+    }
+
+    Ok(())
+}
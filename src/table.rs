@@ -0,0 +1,73 @@
+use crate::read_manifest;
+use anyhow::{Context, Result};
+use arrow_array::{ArrayRef, Float32Array, Float64Array, Int32Array, RecordBatch, StringArray, UInt32Array};
+use face_cropper::{write_atomic, Expression, Gender, ManifestEntry};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Write every entry of a manifest as a row of a Parquet table, returns the row count
+///
+/// One column per manifest field, with the box and crop rectangles split into
+/// x, y, width and height columns and the landmarks left out. Fields a crop
+/// doesn't have (label, frame, attributes) are null.
+pub fn write_manifest_table(manifest_path: &Path, table_path: &Path) -> Result<usize> {
+    let entries = read_manifest(manifest_path)?;
+
+    let strings = |value: fn(&ManifestEntry) -> Option<&str>| -> ArrayRef {
+        Arc::new(entries.iter().map(value).collect::<StringArray>())
+    };
+    let ints = |value: fn(&ManifestEntry) -> i32| -> ArrayRef { Arc::new(entries.iter().map(value).collect::<Int32Array>()) };
+    let counts = |value: fn(&ManifestEntry) -> Option<u32>| -> ArrayRef {
+        Arc::new(entries.iter().map(value).collect::<UInt32Array>())
+    };
+    let floats = |value: fn(&ManifestEntry) -> Option<f32>| -> ArrayRef {
+        Arc::new(entries.iter().map(value).collect::<Float32Array>())
+    };
+
+    let batch = RecordBatch::try_from_iter_with_nullable([
+        ("source", strings(|entry| Some(&entry.source)), false),
+        ("label", strings(|entry| entry.label.as_deref()), true),
+        ("frame", counts(|entry| entry.frame), true),
+        ("image_width", counts(|entry| Some(entry.image_width)), false),
+        ("image_height", counts(|entry| Some(entry.image_height)), false),
+        ("bbox_x", ints(|entry| entry.bbox[0]), false),
+        ("bbox_y", ints(|entry| entry.bbox[1]), false),
+        ("bbox_width", ints(|entry| entry.bbox[2]), false),
+        ("bbox_height", ints(|entry| entry.bbox[3]), false),
+        ("confidence", floats(|entry| Some(entry.confidence)), false),
+        ("crop_x", ints(|entry| entry.crop[0]), false),
+        ("crop_y", ints(|entry| entry.crop[1]), false),
+        ("crop_width", ints(|entry| entry.crop[2]), false),
+        ("crop_height", ints(|entry| entry.crop[3]), false),
+        ("crop_angle", floats(|entry| Some(entry.crop_angle)), false),
+        ("sharpness", Arc::new(entries.iter().map(|entry| Some(entry.sharpness)).collect::<Float64Array>()), false),
+        ("brightness", Arc::new(entries.iter().map(|entry| Some(entry.brightness)).collect::<Float64Array>()), false),
+        ("contrast", Arc::new(entries.iter().map(|entry| Some(entry.contrast)).collect::<Float64Array>()), false),
+        ("output", strings(|entry| Some(&entry.output)), false),
+        ("output_size", counts(|entry| Some(entry.output_size)), false),
+        ("age", counts(|entry| entry.attributes.age), true),
+        ("age_bucket", strings(|entry| entry.attributes.age_bucket.as_deref()), true),
+        ("gender", names(entries.iter().map(|entry| entry.attributes.gender.map(Gender::name))), true),
+        ("expression", names(entries.iter().map(|entry| entry.attributes.expression.map(Expression::name))), true),
+        ("nsfw_score", floats(|entry| entry.attributes.nsfw_score), true),
+    ])
+    .context("Failed to build the metadata table")?;
+
+    // Written in memory first so the table is replaced in one step
+    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    let mut data = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut data, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    write_atomic(table_path, &data).with_context(|| format!("Failed to write metadata table: {:?}", table_path))?;
+
+    Ok(entries.len())
+}
+
+/// Column of the names of enum values, as the manifest writes them
+fn names(values: impl Iterator<Item = Option<&'static str>>) -> ArrayRef {
+    Arc::new(values.collect::<StringArray>())
+}
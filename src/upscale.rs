@@ -0,0 +1,78 @@
+use crate::detector::{load_session, DetectorError, Result};
+use image::{DynamicImage, RgbImage};
+use ort::session::Session;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Default super-resolution model, relative to the working directory
+pub const DEFAULT_UPSCALE_MODEL: &str = "model/realesrgan_x4.onnx";
+
+/// Super-resolution of small crops with a Real-ESRGAN model run through ONNX Runtime
+///
+/// Expects an NCHW RGB input scaled to 0-1 with dynamic height and width, and a
+/// single output of the same layout at the model's scale (4x for `RealESRGAN_x4plus`).
+/// The session is shared between worker threads behind a lock.
+#[derive(Debug)]
+pub struct SuperResolution {
+    session: Mutex<Session>,
+}
+
+impl SuperResolution {
+    /// Load the model, or the default one when no path is given
+    pub fn new(model_path: Option<&Path>) -> Result<Self> {
+        let model_path = model_path.unwrap_or(Path::new(DEFAULT_UPSCALE_MODEL));
+        if !model_path.exists() {
+            return Err(DetectorError::ModelNotFound {
+                path: model_path.to_path_buf(),
+                hint: "Export RealESRGAN_x4plus to ONNX (dynamic input size) and place it there, \
+                    or pass --upscale-model <path>",
+            });
+        }
+
+        Ok(Self {
+            session: Mutex::new(load_session(model_path)?),
+        })
+    }
+
+    /// The image upscaled by the model's scale factor
+    pub fn upscale(&self, image: &DynamicImage) -> Result<DynamicImage> {
+        let rgb = image.to_rgb8();
+        let (width, height) = rgb.dimensions();
+
+        let plane = (width * height) as usize;
+        let mut input = vec![0.0f32; 3 * plane];
+        for (i, pixel) in rgb.pixels().enumerate() {
+            for channel in 0..3 {
+                input[channel * plane + i] = pixel[channel] as f32 / 255.0;
+            }
+        }
+
+        let tensor = ort::value::Tensor::from_array(([1, 3, height as usize, width as usize], input))
+            .map_err(|err| DetectorError::Backend(format!("Failed to build ONNX input tensor: {}", err)))?;
+
+        let mut session = self.session.lock().expect("upscaler lock poisoned");
+        let input_name = session.inputs()[0].name().to_string();
+        let outputs = session
+            .run(ort::inputs![input_name => tensor])
+            .map_err(|err| DetectorError::Backend(format!("ONNX inference failed: {}", err)))?;
+        let (shape, values) = outputs[0]
+            .try_extract_tensor::<f32>()
+            .map_err(|err| DetectorError::Backend(format!("Failed to read upscaler output: {}", err)))?;
+
+        let &[1, 3, out_height, out_width] = &shape[..] else {
+            return Err(DetectorError::Backend(format!(
+                "Unsupported upscaler model: expected a 1x3xHxW output, got {:?}",
+                &shape[..]
+            )));
+        };
+        let (out_width, out_height) = (out_width as u32, out_height as u32);
+        let plane = (out_width * out_height) as usize;
+        let upscaled = RgbImage::from_fn(out_width, out_height, |x, y| {
+            let i = (y * out_width + x) as usize;
+            let value = |channel: usize| (values[channel * plane + i].clamp(0.0, 1.0) * 255.0).round() as u8;
+            image::Rgb([value(0), value(1), value(2)])
+        });
+
+        Ok(DynamicImage::ImageRgb8(upscaled))
+    }
+}
@@ -0,0 +1,126 @@
+use crate::archive::ArchiveWriter;
+use crate::cropping::{write_atomic, ManifestEntry};
+use crate::error::{Error, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Samples per shard when none is given
+pub const DEFAULT_SHARD_SAMPLES: usize = 10_000;
+
+/// Name of the shard index written next to the shards
+pub const INDEX_FILE: &str = "index.json";
+
+/// Writes crops as WebDataset samples into numbered `.tar` shards
+///
+/// Every sample is a `{key}.{ext}` crop and a `{key}.json` with its manifest
+/// entry, both in the same shard. Keys are the crop paths without their extension
+/// and with dots replaced, as WebDataset splits member names at the first dot.
+/// [`ShardWriter::finish`] closes the last shard and writes `index.json` in the
+/// `wids` shard index format, listing every shard with its sample count.
+pub struct ShardWriter {
+    dir: PathBuf,
+    samples_per_shard: usize,
+    shards: Mutex<Shards>,
+}
+
+/// The shard being written and the ones finished before it
+struct Shards {
+    current: Option<ArchiveWriter>,
+    /// Name and sample count of every shard so far, the current one last
+    written: Vec<(String, usize)>,
+}
+
+/// Body of `index.json`
+#[derive(Serialize)]
+struct ShardIndex<'a> {
+    #[serde(rename = "__kind__")]
+    kind: &'static str,
+    wids_version: u32,
+    shardlist: Vec<ShardEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct ShardEntry<'a> {
+    url: &'a str,
+    nsamples: usize,
+}
+
+impl ShardWriter {
+    /// Write shards of `samples_per_shard` samples into a directory, which has to exist
+    pub fn create(dir: &Path, samples_per_shard: usize) -> Result<Self> {
+        if samples_per_shard == 0 {
+            return Err(Error::Config("WebDataset shards need room for at least one sample".to_string()));
+        }
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            samples_per_shard,
+            shards: Mutex::new(Shards {
+                current: None,
+                written: Vec::new(),
+            }),
+        })
+    }
+
+    /// Add a crop with its manifest entry as one sample, pointing the entry's
+    /// output at the crop's member name
+    pub fn append(&self, entry: &mut ManifestEntry, data: &[u8]) -> Result<()> {
+        let output = Path::new(&entry.output);
+        let extension = output.extension().unwrap_or_default().to_string_lossy().into_owned();
+        let key = output
+            .with_extension("")
+            .to_string_lossy()
+            .replace('\\', "/")
+            .replace('.', "_");
+        entry.output = format!("{}.{}", key, extension);
+        let json = serde_json::to_vec(entry).map_err(|err| Error::Io {
+            context: format!("Failed to encode sample {}", key),
+            source: err.into(),
+        })?;
+
+        let mut shards = self.shards.lock().expect("shard writer lock poisoned");
+        let full = shards.written.last().is_some_and(|&(_, samples)| samples >= self.samples_per_shard);
+        if shards.current.is_none() || full {
+            if let Some(shard) = shards.current.take() {
+                shard.finish()?;
+            }
+            let name = format!("shard-{:06}.tar", shards.written.len());
+            shards.current = Some(ArchiveWriter::create(&self.dir.join(&name))?);
+            shards.written.push((name, 0));
+        }
+
+        let shard = shards.current.as_ref().expect("a shard was just opened");
+        shard.append(&entry.output, data)?;
+        shard.append(&format!("{}.json", key), &json)?;
+        if let Some((_, samples)) = shards.written.last_mut() {
+            *samples += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Finish the last shard and write the shard index, later appends start a new shard
+    pub fn finish(&self) -> Result<()> {
+        let mut shards = self.shards.lock().expect("shard writer lock poisoned");
+        if let Some(shard) = shards.current.take() {
+            shard.finish()?;
+        }
+
+        let index = ShardIndex {
+            kind: "wids-shard-index-v1",
+            wids_version: 1,
+            shardlist: shards
+                .written
+                .iter()
+                .map(|(name, samples)| ShardEntry { url: name, nsamples: *samples })
+                .collect(),
+        };
+        let index_path = self.dir.join(INDEX_FILE);
+        let data = serde_json::to_vec_pretty(&index).map_err(std::io::Error::from);
+        data.and_then(|data| write_atomic(&index_path, &data)).map_err(|source| Error::Io {
+            context: format!("Failed to write shard index: {:?}", index_path),
+            source,
+        })
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Run the binary with the mock detector on a directory
+fn run_mock(input: &Path, output: &Path, args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_face_cropper"))
+        .arg("--input-dir")
+        .arg(input)
+        .arg("--output-dir")
+        .arg(output)
+        .args(["--detector", "mock", "--size", "64"])
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// Entries of a run's manifest
+fn manifest(output: &Path) -> Vec<serde_json::Value> {
+    let manifest = fs::read_to_string(output.join("manifest.jsonl")).unwrap();
+    manifest.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+}
+
+/// Number of jpg crops in an output directory
+fn crop_files(output: &Path) -> usize {
+    fs::read_dir(output)
+        .unwrap()
+        .filter(|file| file.as_ref().unwrap().path().extension().is_some_and(|extension| extension == "jpg"))
+        .count()
+}
+
+/// A full run of the binary with the mock detector, which finds four faces per image
+#[test]
+fn mock_detector_run_writes_crops_and_manifest() {
+    let dir = std::env::temp_dir().join(format!("face_cropper-mock-run-{}", std::process::id()));
+    let (input, output) = (dir.join("in"), dir.join("out"));
+    fs::create_dir_all(&input).unwrap();
+    image::RgbImage::from_pixel(256, 128, image::Rgb([128, 128, 128])).save(input.join("wide.png")).unwrap();
+    image::RgbImage::from_pixel(200, 200, image::Rgb([90, 90, 90])).save(input.join("square.jpg")).unwrap();
+
+    run_mock(&input, &output, &[]);
+
+    let entries = manifest(&output);
+    assert_eq!(entries.len(), 8);
+
+    let mut sources: Vec<&str> = entries.iter().map(|entry| entry["source"].as_str().unwrap()).collect();
+    sources.dedup();
+    assert_eq!(sources.len(), 2);
+    for entry in &entries {
+        let crop = image::open(output.join(entry["output"].as_str().unwrap())).unwrap();
+        assert_eq!((crop.width(), crop.height()), (64, 64));
+        assert_eq!(entry["output_size"], 64);
+        assert_eq!(entry["confidence"], 1.0);
+    }
+
+    let crops = crop_files(&output);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(crops, 8);
+}
+
+/// An incremental run processes a changed image again, replacing its earlier crops
+#[test]
+fn incremental_run_replaces_the_crops_of_changed_images() {
+    let dir = std::env::temp_dir().join(format!("face_cropper-incremental-{}", std::process::id()));
+    let (input, output) = (dir.join("in"), dir.join("out"));
+    fs::create_dir_all(&input).unwrap();
+    image::RgbImage::from_pixel(256, 128, image::Rgb([128, 128, 128])).save(input.join("wide.png")).unwrap();
+    image::RgbImage::from_pixel(200, 200, image::Rgb([90, 90, 90])).save(input.join("square.png")).unwrap();
+    run_mock(&input, &output, &["--incremental"]);
+    let first = manifest(&output);
+
+    image::RgbImage::from_pixel(200, 200, image::Rgb([200, 200, 200])).save(input.join("square.png")).unwrap();
+    run_mock(&input, &output, &["--incremental"]);
+    let entries = manifest(&output);
+    let crops = crop_files(&output);
+
+    // Nothing changed since, nothing is processed again
+    run_mock(&input, &output, &["--incremental"]);
+    let unchanged = manifest(&output);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(entries.len(), 8);
+    assert_eq!(crops, 8);
+    let square = |entries: &[serde_json::Value]| -> Vec<String> {
+        entries
+            .iter()
+            .filter(|entry| entry["source"].as_str().unwrap().ends_with("square.png"))
+            .map(|entry| entry["output"].as_str().unwrap().to_string())
+            .collect()
+    };
+    let (old, new) = (square(&first), square(&entries));
+    assert_eq!(new.len(), 4);
+    assert!(new.iter().all(|output| !old.contains(output)));
+    assert_eq!(unchanged, entries);
+}